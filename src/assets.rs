@@ -0,0 +1,330 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::{AssetsConfig, ConvertRule, GenerateRule};
+
+/// Converter and arguments used for a source extension when a rule names no tool
+fn default_converter(extension: &str) -> Option<(&'static str, &'static [&'static str])> {
+    match extension.to_ascii_lowercase().as_str() {
+        "svg" => Some(("inkscape", &["${INPUT}", "--export-type=pdf", "--export-filename=${OUTPUT}"])),
+        "eps" => Some(("epstopdf", &["${INPUT}", "--outfile=${OUTPUT}"])),
+        _ => None,
+    }
+}
+
+fn extension_of(path: &Path) -> String {
+    path.extension().map(|ext| ext.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+impl ConvertRule {
+    /// Why the rule cannot be used, if it cannot
+    pub fn problem(&self) -> Option<String> {
+        if let Err(e) = glob::Pattern::new(&self.from) {
+            return Some(format!("invalid glob pattern \"{}\": {}", self.from, e.msg));
+        }
+        if self.tool.is_none() && default_converter(&extension_of(Path::new(&self.from))).is_none() {
+            return Some(format!("no default converter for \"{}\"; set tool and args", self.from));
+        }
+        None
+    }
+
+    /// The converted file for `source`; both are relative to the project root
+    pub fn output_for(&self, source: &Path) -> PathBuf {
+        let dir = source.parent().map(|dir| dir.to_string_lossy().replace('\\', "/")).unwrap_or_default();
+        let name = source.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let mut template = self.to.clone().unwrap_or_else(|| "${DIR}/${NAME}.pdf".to_string());
+        if dir.is_empty() {
+            template = template.replace("${DIR}/", "");
+        }
+        PathBuf::from(template.replace("${DIR}", &dir).replace("${NAME}", &name))
+    }
+
+    /// The command converting `input` into `output`
+    pub fn command_line(&self, input: &Path, output: &Path) -> Vec<String> {
+        let (tool, args): (String, Vec<String>) = match &self.tool {
+            Some(tool) => (tool.clone(), self.args.clone()),
+            None => match default_converter(&extension_of(input)) {
+                Some((tool, args)) => (tool.to_string(), args.iter().map(|arg| arg.to_string()).collect()),
+                None => (String::new(), Vec::new()),
+            },
+        };
+        let input = input.to_string_lossy().to_string();
+        let output = output.to_string_lossy().to_string();
+        let mut command = vec![tool];
+        command.extend(args.iter().map(|arg| arg.replace("${INPUT}", &input).replace("${OUTPUT}", &output)));
+        command
+    }
+}
+
+impl GenerateRule {
+    /// Why the rule cannot be used, if it cannot
+    pub fn problem(&self) -> Option<String> {
+        if self.tool.trim().is_empty() {
+            return Some("a generate rule has an empty tool".to_string());
+        }
+        if self.outputs.is_empty() {
+            return Some(format!("the generate rule running {} lists no outputs", self.tool));
+        }
+        self.inputs.iter().find_map(|pattern| {
+            glob::Pattern::new(pattern)
+                .err()
+                .map(|e| format!("invalid glob pattern \"{}\": {}", pattern, e.msg))
+        })
+    }
+
+    /// The key the rule's last run is recorded under
+    fn key(&self) -> String {
+        self.outputs.join(", ")
+    }
+
+    /// Hash of the command and of the path and contents of every input
+    pub fn input_hash(&self, project_root: &Path) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for part in std::iter::once(&self.tool).chain(&self.args) {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        let root = glob::Pattern::escape(&project_root.to_string_lossy());
+        for pattern in &self.inputs {
+            hasher.update(pattern.as_bytes());
+            let mut files: Vec<PathBuf> = glob::glob(&format!("{}/{}", root, pattern))?
+                .flatten()
+                .filter(|path| path.is_file())
+                .collect();
+            files.sort();
+            for file in files {
+                hasher.update(file.strip_prefix(project_root).unwrap_or(&file).to_string_lossy().as_bytes());
+                hasher.update([0]);
+                hasher.update(Sha256::digest(std::fs::read(&file)?));
+            }
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// Input hashes of the last successful run of each generate rule
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct GenerateState {
+    runs: BTreeMap<String, String>,
+}
+
+impl GenerateState {
+    fn path(project_root: &Path) -> PathBuf {
+        project_root.join(".tpmgr").join("asset-state.json")
+    }
+
+    fn load(project_root: &Path) -> Self {
+        std::fs::read_to_string(Self::path(project_root))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, project_root: &Path) -> Result<()> {
+        let path = Self::path(project_root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Run the generate rules whose outputs are missing or whose inputs changed
+/// since their last run; the number run
+pub fn generate_assets(rules: &[GenerateRule], project_root: &Path, environment: &[(String, String)]) -> Result<usize> {
+    let mut state = GenerateState::load(project_root);
+    let mut generated = 0;
+    for rule in rules {
+        if let Some(problem) = rule.problem() {
+            anyhow::bail!("[[assets.generate]]: {}", problem);
+        }
+        let hash = rule.input_hash(project_root)?;
+        let outputs_exist = rule.outputs.iter().all(|output| project_root.join(output).is_file());
+        if outputs_exist && state.runs.get(&rule.key()) == Some(&hash) {
+            continue;
+        }
+
+        println!("🛠️  Generating {} with {}", rule.key(), rule.tool);
+        let result = Command::new(&rule.tool)
+            .args(&rule.args)
+            .current_dir(project_root)
+            .envs(environment.iter().cloned())
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to execute {}: {}", rule.tool, e))?;
+        if !result.status.success() {
+            print!("{}", String::from_utf8_lossy(&result.stdout));
+            print!("{}", String::from_utf8_lossy(&result.stderr));
+            anyhow::bail!("Generating {} failed with exit code: {:?}", rule.key(), result.status.code());
+        }
+        if let Some(missing) = rule.outputs.iter().find(|output| !project_root.join(output).is_file()) {
+            anyhow::bail!("{} {} did not write {}", rule.tool, rule.args.join(" "), missing);
+        }
+        state.runs.insert(rule.key(), hash);
+        // Recorded after each run, so a later failure does not repeat this one
+        state.save(project_root)?;
+        generated += 1;
+    }
+    Ok(generated)
+}
+
+/// Generate, then convert, the figures declared in `[assets]`
+pub fn prepare_assets(assets: &AssetsConfig, project_root: &Path, environment: &[(String, String)]) -> Result<()> {
+    generate_assets(&assets.generate, project_root, environment)?;
+    convert_assets(&assets.convert, project_root, environment)?;
+    Ok(())
+}
+
+/// Whether `output` is missing or older than `source`
+pub fn is_stale(source: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    match (modified(source), modified(output)) {
+        (Some(source), Some(output)) => source > output,
+        _ => true,
+    }
+}
+
+/// The sources matched by `rules` with their converted files, relative to the
+/// project root; a file matched by several rules is converted by the first
+pub fn conversions<'a>(rules: &'a [ConvertRule], project_root: &Path) -> Result<Vec<(PathBuf, PathBuf, &'a ConvertRule)>> {
+    let mut conversions: Vec<(PathBuf, PathBuf, &ConvertRule)> = Vec::new();
+    let root = glob::Pattern::escape(&project_root.to_string_lossy());
+    for rule in rules {
+        if let Some(problem) = rule.problem() {
+            anyhow::bail!("[[assets.convert]]: {}", problem);
+        }
+        let mut sources: Vec<PathBuf> = glob::glob(&format!("{}/{}", root, rule.from))?
+            .flatten()
+            .filter(|path| path.is_file())
+            .filter_map(|path| path.strip_prefix(project_root).ok().map(Path::to_path_buf))
+            .collect();
+        sources.sort();
+        for source in sources {
+            let output = rule.output_for(&source);
+            if output != source && !conversions.iter().any(|(known, _, _)| *known == source) {
+                conversions.push((source, output, rule));
+            }
+        }
+    }
+    Ok(conversions)
+}
+
+/// Convert every source whose converted file is missing or out of date;
+/// the number converted
+pub fn convert_assets(rules: &[ConvertRule], project_root: &Path, environment: &[(String, String)]) -> Result<usize> {
+    let mut converted = 0;
+    for (source, output, rule) in conversions(rules, project_root)? {
+        if !is_stale(&project_root.join(&source), &project_root.join(&output)) {
+            continue;
+        }
+        if let Some(parent) = project_root.join(&output).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let command = rule.command_line(&source, &output);
+        println!("🖼️  Converting {} → {}", source.display(), output.display());
+        let result = Command::new(&command[0])
+            .args(&command[1..])
+            .current_dir(project_root)
+            .envs(environment.iter().cloned())
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| anyhow::anyhow!("Failed to execute {}: {}", command[0], e))?;
+        if !result.status.success() {
+            print!("{}", String::from_utf8_lossy(&result.stdout));
+            print!("{}", String::from_utf8_lossy(&result.stderr));
+            anyhow::bail!("Converting {} failed with exit code: {:?}", source.display(), result.status.code());
+        }
+        converted += 1;
+    }
+    Ok(converted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: &str, to: Option<&str>, tool: Option<&str>, args: &[&str]) -> ConvertRule {
+        ConvertRule {
+            from: from.to_string(),
+            to: to.map(str::to_string),
+            tool: tool.map(str::to_string),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_convert_rules() {
+        let svg = rule("figures/*.svg", None, None, &[]);
+        assert_eq!(svg.problem(), None);
+        assert_eq!(svg.output_for(Path::new("figures/plot.svg")), PathBuf::from("figures/plot.pdf"));
+        assert_eq!(svg.output_for(Path::new("logo.svg")), PathBuf::from("logo.pdf"));
+        assert_eq!(
+            svg.command_line(Path::new("figures/plot.svg"), Path::new("figures/plot.pdf")),
+            vec!["inkscape", "figures/plot.svg", "--export-type=pdf", "--export-filename=figures/plot.pdf"]
+        );
+        let eps = rule("**/*.EPS", Some("build/${NAME}-eps.pdf"), None, &[]);
+        assert_eq!(eps.output_for(Path::new("a/b.EPS")), PathBuf::from("build/b-eps.pdf"));
+        assert_eq!(eps.command_line(Path::new("a/b.EPS"), Path::new("build/b-eps.pdf"))[0], "epstopdf");
+        assert!(rule("figures/*.dot", None, None, &[]).problem().is_some());
+        assert!(rule("figures/[*.svg", None, None, &[]).problem().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_generate_assets_when_inputs_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("data")).unwrap();
+        std::fs::write(root.join("plot.sh"), "cat data/*.csv > results.pdf; echo run >> runs.log").unwrap();
+        std::fs::write(root.join("data/a.csv"), "1,2\n").unwrap();
+        let rule = GenerateRule {
+            tool: "sh".to_string(),
+            args: vec!["plot.sh".to_string()],
+            inputs: vec!["plot.sh".to_string(), "data/*.csv".to_string()],
+            outputs: vec!["results.pdf".to_string()],
+        };
+        let rules = std::slice::from_ref(&rule);
+
+        assert_eq!(generate_assets(rules, root, &[]).unwrap(), 1);
+        assert_eq!(generate_assets(rules, root, &[]).unwrap(), 0);
+        // New data, a deleted output and an edited command each cause a run
+        std::fs::write(root.join("data/b.csv"), "3,4\n").unwrap();
+        assert_eq!(generate_assets(rules, root, &[]).unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(root.join("results.pdf")).unwrap(), "1,2\n3,4\n");
+        std::fs::remove_file(root.join("results.pdf")).unwrap();
+        assert_eq!(generate_assets(rules, root, &[]).unwrap(), 1);
+        let mut edited = rule.clone();
+        edited.args.push("--dpi=300".to_string());
+        assert_eq!(generate_assets(&[edited], root, &[]).unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(root.join("runs.log")).unwrap().lines().count(), 4);
+
+        let mut missing = rule.clone();
+        missing.outputs = vec!["other.pdf".to_string()];
+        assert!(generate_assets(&[missing], root, &[]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_convert_assets_when_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("figures")).unwrap();
+        std::fs::write(root.join("figures/plot.dot"), "digraph").unwrap();
+        std::fs::write(root.join("figures/flow.dot"), "digraph").unwrap();
+        let rules = vec![rule("figures/*.dot", Some("${DIR}/out/${NAME}.pdf"), Some("cp"), &["${INPUT}", "${OUTPUT}"])];
+
+        assert_eq!(convert_assets(&rules, root, &[]).unwrap(), 2);
+        assert_eq!(std::fs::read_to_string(root.join("figures/out/plot.pdf")).unwrap(), "digraph");
+        assert_eq!(convert_assets(&rules, root, &[]).unwrap(), 0);
+
+        // An edited source is converted again
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        let source = std::fs::OpenOptions::new().write(true).open(root.join("figures/plot.dot")).unwrap();
+        source.set_modified(later).unwrap();
+        assert_eq!(convert_assets(&rules, root, &[]).unwrap(), 1);
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// How often a running step's memory use is sampled
+const MEMORY_SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Wall-clock timings of repeated runs of a compile chain
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BenchResult {
+    /// The resolved commands of the chain, one per step
+    pub steps: Vec<String>,
+    /// Whether intermediate files were removed before each run
+    pub cold: bool,
+    /// Seconds taken by each step, one entry per run
+    pub runs: Vec<Vec<f64>>,
+}
+
+/// Summary statistics of a set of samples, in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub mean: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    pub stddev: f64,
+}
+
+impl Stats {
+    /// None when there are no samples
+    pub fn of(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / count;
+        let middle = sorted.len() / 2;
+        let median = if sorted.len().is_multiple_of(2) { (sorted[middle - 1] + sorted[middle]) / 2.0 } else { sorted[middle] };
+        let variance = sorted.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / count;
+        Some(Self {
+            mean,
+            median,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            stddev: variance.sqrt(),
+        })
+    }
+}
+
+impl BenchResult {
+    pub fn step_stats(&self, step: usize) -> Option<Stats> {
+        let samples: Vec<f64> = self.runs.iter().filter_map(|run| run.get(step).copied()).collect();
+        Stats::of(&samples)
+    }
+
+    /// Statistics of whole runs of the chain
+    pub fn total_stats(&self) -> Option<Stats> {
+        let totals: Vec<f64> = self.runs.iter().map(|run| run.iter().sum()).collect();
+        Stats::of(&totals)
+    }
+
+    /// The baseline saved with `bench compile --save-baseline`, if any
+    pub fn load_baseline(project_root: &Path) -> Result<Option<Self>> {
+        let path = baseline_path(project_root);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let baseline = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid benchmark baseline {}: {}", path.display(), e))?;
+        Ok(Some(baseline))
+    }
+
+    pub fn save_baseline(&self, project_root: &Path) -> Result<()> {
+        let path = baseline_path(project_root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Where a project's benchmark baseline is kept
+pub fn baseline_path(project_root: &Path) -> PathBuf {
+    project_root.join(".tpmgr").join("bench-baseline.json")
+}
+
+/// Duration and peak memory of one run of a compile step
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StepTiming {
+    pub command: String,
+    pub seconds: f64,
+    /// Peak resident memory of the tool's process, where the platform reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory_kb: Option<u64>,
+}
+
+/// The steps run by the latest `tpmgr compile`, retries included
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct CompileTimings {
+    pub steps: Vec<StepTiming>,
+}
+
+impl CompileTimings {
+    pub fn path(project_root: &Path) -> PathBuf {
+        project_root.join(".tpmgr").join("compile-timings.json")
+    }
+
+    /// The timings of the latest compile, if one was recorded
+    pub fn load(project_root: &Path) -> Result<Option<Self>> {
+        let path = Self::path(project_root);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        let timings = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Invalid compile timings {}: {}", path.display(), e))?;
+        Ok(Some(timings))
+    }
+
+    pub fn save(&self, project_root: &Path) -> Result<()> {
+        let path = Self::path(project_root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn total_seconds(&self) -> f64 {
+        self.steps.iter().map(|step| step.seconds).sum()
+    }
+}
+
+/// A finished command with its wall-clock time and peak memory
+#[derive(Debug)]
+pub struct MeasuredRun {
+    pub status: ExitStatus,
+    /// stdout followed by stderr when captured, else empty
+    pub output: String,
+    pub seconds: f64,
+    pub peak_memory_kb: Option<u64>,
+}
+
+/// Run `command` to completion, sampling its memory use while it runs. With
+/// `capture` its stdout and stderr are collected instead of going wherever
+/// `command` sends them.
+pub fn run_measured(command: &mut Command, capture: bool) -> std::io::Result<MeasuredRun> {
+    if capture {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let started = Instant::now();
+    let mut child = command.spawn()?;
+    let readers = [
+        child.stdout.take().map(|stream| read_in_background(Box::new(stream))),
+        child.stderr.take().map(|stream| read_in_background(Box::new(stream))),
+    ];
+
+    let mut peak_memory_kb = None;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        // The high-water mark only grows, so the last reading is the peak
+        peak_memory_kb = peak_memory_kb_of(child.id()).or(peak_memory_kb);
+        std::thread::sleep(MEMORY_SAMPLE_INTERVAL);
+    };
+    let seconds = started.elapsed().as_secs_f64();
+
+    let output: Vec<String> = readers
+        .into_iter()
+        .flatten()
+        .map(|reader| reader.join().unwrap_or_default())
+        .collect();
+    Ok(MeasuredRun { status, output: output.join("\n"), seconds, peak_memory_kb })
+}
+
+fn read_in_background(mut stream: Box<dyn Read + Send>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = stream.read_to_end(&mut bytes);
+        String::from_utf8_lossy(&bytes).to_string()
+    })
+}
+
+/// The peak resident memory of a running process (VmHWM on Linux)
+#[cfg(target_os = "linux")]
+fn peak_memory_kb_of(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peak_memory_kb_of(_pid: u32) -> Option<u64> {
+    None
+}
+
+/// Run each command of a chain once in `dir` with the tools' output discarded;
+/// the seconds each step took. A failing step stops the run.
+pub fn time_chain(commands: &[Vec<String>], dir: &Path, environment: &[(String, String)]) -> Result<Vec<f64>> {
+    let mut timings = Vec::new();
+    for (i, command) in commands.iter().enumerate() {
+        let Some((tool, args)) = command.split_first() else {
+            timings.push(0.0);
+            continue;
+        };
+        let mut command = Command::new(tool);
+        command
+            .args(args)
+            .current_dir(dir)
+            .envs(environment.iter().cloned())
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let run = run_measured(&mut command, false).map_err(|e| anyhow::anyhow!("Failed to execute {}: {}", tool, e))?;
+        if !run.status.success() {
+            anyhow::bail!("Step {}/{} ({}) failed with exit code: {:?}", i + 1, commands.len(), tool, run.status.code());
+        }
+        timings.push(run.seconds);
+    }
+    Ok(timings)
+}
+
+/// Change from `baseline` to `current` in percent; negative is faster
+pub fn change_percent(baseline: f64, current: f64) -> f64 {
+    if baseline == 0.0 {
+        0.0
+    } else {
+        (current - baseline) / baseline * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_statistics_and_baseline() {
+        let stats = Stats::of(&[3.0, 1.0, 2.0, 6.0]).unwrap();
+        assert_eq!(stats.mean, 3.0);
+        assert_eq!(stats.median, 2.5);
+        assert_eq!((stats.min, stats.max), (1.0, 6.0));
+        assert!((stats.stddev - 1.870_828_693).abs() < 1e-6);
+        assert!(Stats::of(&[]).is_none());
+
+        let result = BenchResult {
+            steps: vec!["pdflatex main.tex".to_string(), "bibtex main".to_string()],
+            cold: false,
+            runs: vec![vec![1.0, 0.25], vec![3.0, 0.75]],
+        };
+        assert_eq!(result.step_stats(1).unwrap().mean, 0.5);
+        assert_eq!(result.total_stats().unwrap().median, 2.5);
+        assert_eq!(change_percent(2.0, 1.5), -25.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(BenchResult::load_baseline(dir.path()).unwrap(), None);
+        result.save_baseline(dir.path()).unwrap();
+        assert_eq!(BenchResult::load_baseline(dir.path()).unwrap(), Some(result));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_measured() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2; exit 3"]);
+        let run = run_measured(&mut command, true).unwrap();
+        assert_eq!(run.status.code(), Some(3));
+        assert_eq!(run.output, "out\n\nerr\n");
+        assert!(run.seconds > 0.0);
+    }
+}
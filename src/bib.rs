@@ -0,0 +1,589 @@
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::tex_parser::{DependencyType, TeXDependency, TeXParser};
+
+/// Fields naming another entry that BibTeX or biber pulls in with the citing one
+const REFERENCE_FIELDS: &[&str] = &["crossref", "xref", "xdata"];
+
+/// One `@type{...}` block of a .bib file. @string, @preamble and @comment
+/// blocks have no key and no parsed fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BibEntry {
+    /// Lowercase entry type, e.g. "article"
+    pub entry_type: String,
+    pub key: String,
+    /// Lowercase field names with their values as written (braces kept)
+    pub fields: Vec<(String, String)>,
+    /// The block as it appears in the file
+    pub text: String,
+}
+
+impl BibEntry {
+    /// Whether this is a citable entry rather than @string, @preamble or @comment
+    pub fn is_citable(&self) -> bool {
+        !matches!(self.entry_type.as_str(), "string" | "preamble" | "comment")
+    }
+
+    /// A field's value without its outer braces or quotes
+    pub fn field(&self, name: &str) -> Option<&str> {
+        let value = self.fields.iter().find(|(field, _)| field == name)?.1.trim();
+        Some(single_part(value).map(str::trim).unwrap_or(value))
+    }
+
+    /// The DOI in lowercase without a resolver prefix
+    pub fn doi(&self) -> Option<String> {
+        self.field("doi").map(normalize_doi).filter(|doi| !doi.is_empty())
+    }
+
+    /// The arXiv ID without its version
+    pub fn arxiv_id(&self) -> Option<String> {
+        let archive = self.field("archiveprefix").or_else(|| self.field("eprinttype")).unwrap_or("arxiv");
+        if !archive.eq_ignore_ascii_case("arxiv") {
+            return None;
+        }
+        let eprint = self.field("eprint")?;
+        Some(strip_arxiv_version(eprint.trim_start_matches("arXiv:")).to_string())
+    }
+
+    /// The title in lowercase letters and digits only, for comparisons
+    pub fn normalized_title(&self) -> Option<String> {
+        let title: String = self
+            .field("title")?
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect();
+        Some(title).filter(|title| !title.is_empty())
+    }
+
+    /// Whether both entries describe the same work: same DOI, same arXiv ID,
+    /// or the same key and title
+    pub fn same_work(&self, other: &BibEntry) -> bool {
+        let same = |a: Option<String>, b: Option<String>| a.is_some() && a == b;
+        same(self.doi(), other.doi())
+            || same(self.arxiv_id(), other.arxiv_id())
+            || (self.key.eq_ignore_ascii_case(&other.key) && same(self.normalized_title(), other.normalized_title()))
+    }
+
+    /// The entry written with one field per line
+    pub fn to_bibtex(&self) -> String {
+        if !self.is_citable() {
+            return self.text.clone();
+        }
+        let mut text = format!("@{}{{{},\n", self.entry_type, self.key);
+        for (name, value) in &self.fields {
+            text.push_str(&format!("  {} = {},\n", name, value.trim()));
+        }
+        text.push('}');
+        text
+    }
+
+    /// Tidy the fields: values on one line, braces instead of quotes, bare
+    /// DOIs, and no empty or repeated fields
+    pub fn normalize(&mut self) {
+        if !self.is_citable() {
+            return;
+        }
+        let line_break = Regex::new(r"\s*\n\s*").unwrap();
+        let doi = self.doi().filter(|doi| doi.starts_with("10."));
+        let mut seen = HashSet::new();
+        let mut fields = Vec::new();
+        for (name, value) in std::mem::take(&mut self.fields) {
+            // BibTeX rejects a repeated field and biber keeps the first
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let mut value = line_break.replace_all(value.trim(), " ").to_string();
+            if value.starts_with('"') {
+                if let Some(inner) = single_part(&value) {
+                    value = format!("{{{}}}", inner);
+                }
+            }
+            if name == "doi" {
+                if let Some(doi) = &doi {
+                    value = format!("{{{}}}", doi);
+                }
+            }
+            if !matches!(value.as_str(), "" | "{}") {
+                fields.push((name, value));
+            }
+        }
+        self.fields = fields;
+    }
+}
+
+/// The inside of a value that is a single braced or quoted part, not `{a} # {b}`
+fn single_part(value: &str) -> Option<&str> {
+    let bytes = value.as_bytes();
+    let whole = match bytes.first() {
+        Some(b'{') => closing_delimiter(bytes, 0) == Some(bytes.len() - 1),
+        Some(b'"') => value[1..].find('"') == Some(value.len() - 2),
+        _ => false,
+    };
+    whole.then(|| &value[1..value.len() - 1])
+}
+
+/// The blocks of a .bib file in order
+pub fn parse_bib(content: &str) -> Vec<BibEntry> {
+    let bytes = content.as_bytes();
+    let mut entries = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = content[position..].find('@') {
+        let start = position + offset;
+        let mut cursor = start + 1;
+        while cursor < bytes.len() && (bytes[cursor].is_ascii_alphanumeric() || bytes[cursor] == b'_') {
+            cursor += 1;
+        }
+        let entry_type = content[start + 1..cursor].to_lowercase();
+        while cursor < bytes.len() && bytes[cursor].is_ascii_whitespace() {
+            cursor += 1;
+        }
+        if entry_type.is_empty() || cursor >= bytes.len() || !matches!(bytes[cursor], b'{' | b'(') {
+            position = start + 1;
+            continue;
+        }
+        let Some(end) = closing_delimiter(bytes, cursor) else {
+            break;
+        };
+        position = end + 1;
+        let text = content[start..=end].to_string();
+        let body = &content[cursor + 1..end];
+        match entry_type.as_str() {
+            "string" | "preamble" | "comment" => entries.push(BibEntry {
+                entry_type,
+                key: String::new(),
+                fields: Vec::new(),
+                text,
+            }),
+            _ => {
+                let (key, rest) = body.split_once(',').unwrap_or((body, ""));
+                entries.push(BibEntry {
+                    entry_type,
+                    key: key.trim().to_string(),
+                    fields: parse_fields(rest),
+                    text,
+                });
+            }
+        }
+    }
+    entries
+}
+
+/// Index of the `}` or `)` closing the block opened at `open`
+fn closing_delimiter(bytes: &[u8], open: usize) -> Option<usize> {
+    let parenthesized = bytes[open] == b'(';
+    let mut depth = 0usize;
+    for (index, byte) in bytes.iter().enumerate().skip(open + 1) {
+        match byte {
+            b'{' => depth += 1,
+            b'}' if depth == 0 && !parenthesized => return Some(index),
+            b'}' => depth = depth.saturating_sub(1),
+            b')' if depth == 0 && parenthesized => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `name = value` pairs; a value may be braced, quoted, a bare word or
+/// number, or parts joined with `#`
+fn parse_fields(body: &str) -> Vec<(String, String)> {
+    let bytes = body.as_bytes();
+    let mut fields = Vec::new();
+    let mut cursor = 0;
+    loop {
+        while cursor < bytes.len() && (bytes[cursor].is_ascii_whitespace() || bytes[cursor] == b',') {
+            cursor += 1;
+        }
+        let Some(equals) = body[cursor..].find('=').map(|offset| cursor + offset) else {
+            break;
+        };
+        let name = body[cursor..equals].trim().to_lowercase();
+        let value_start = equals + 1;
+        cursor = value_start;
+        let mut depth = 0usize;
+        let mut quoted = false;
+        while cursor < bytes.len() {
+            match bytes[cursor] {
+                b'{' => depth += 1,
+                b'}' => depth = depth.saturating_sub(1),
+                b'"' if depth == 0 => quoted = !quoted,
+                b',' if depth == 0 && !quoted => break,
+                _ => {}
+            }
+            cursor += 1;
+        }
+        if !name.is_empty() {
+            fields.push((name, body[value_start..cursor].trim().to_string()));
+        }
+    }
+    fields
+}
+
+/// What `bib add` fetches an entry for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Identifier {
+    Doi(String),
+    Arxiv(String),
+}
+
+impl Identifier {
+    /// A DOI (`10.xxxx/...`, `doi:...` or a doi.org link) or an arXiv ID
+    /// (`2101.00001`, `arXiv:hep-th/9901001v2` or an arxiv.org link)
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let doi = normalize_doi(input);
+        if doi.starts_with("10.") && doi.contains('/') {
+            return Ok(Self::Doi(doi));
+        }
+
+        let lower = input.to_lowercase();
+        let mut id = input;
+        for prefix in ["https://arxiv.org/abs/", "https://arxiv.org/pdf/", "http://arxiv.org/abs/", "http://arxiv.org/pdf/", "arxiv:"] {
+            if lower.starts_with(prefix) {
+                id = &input[prefix.len()..];
+                break;
+            }
+        }
+        let id = id.trim_end_matches(".pdf");
+        let arxiv = Regex::new(r"^(\d{4}\.\d{4,5}|[a-z-]+(\.[A-Z]{2})?/\d{7})(v\d+)?$").unwrap();
+        if arxiv.is_match(id) {
+            return Ok(Self::Arxiv(id.to_string()));
+        }
+        anyhow::bail!("'{}' is neither a DOI nor an arXiv ID", input)
+    }
+
+    /// The entry for this DOI or arXiv ID among `entries`, if there is one
+    pub fn find_in<'a>(&self, entries: &'a [BibEntry]) -> Option<&'a BibEntry> {
+        entries.iter().find(|entry| match self {
+            Self::Doi(doi) => entry.doi().as_ref() == Some(doi),
+            Self::Arxiv(id) => entry.arxiv_id().as_deref() == Some(strip_arxiv_version(id)),
+        })
+    }
+
+    /// Where the BibTeX entry is fetched, and the Accept header to send
+    pub fn bibtex_url(&self) -> (String, &'static str) {
+        match self {
+            // doi.org content negotiation redirects to the registration agency
+            Self::Doi(doi) => (format!("https://doi.org/{}", doi), "application/x-bibtex"),
+            Self::Arxiv(id) => (format!("https://arxiv.org/bibtex/{}", id), "text/plain"),
+        }
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Doi(doi) => write!(f, "doi:{}", doi),
+            Self::Arxiv(id) => write!(f, "arXiv:{}", id),
+        }
+    }
+}
+
+/// Lowercase DOI without `doi:` or a resolver URL in front
+fn normalize_doi(doi: &str) -> String {
+    let doi = doi.trim().to_lowercase();
+    let prefixes = ["https://doi.org/", "http://doi.org/", "https://dx.doi.org/", "http://dx.doi.org/", "doi:"];
+    let doi = prefixes.iter().find_map(|prefix| doi.strip_prefix(prefix)).unwrap_or(&doi);
+    doi.trim().to_string()
+}
+
+fn strip_arxiv_version(id: &str) -> &str {
+    match id.rfind('v') {
+        Some(index) if index + 1 < id.len() && id[index + 1..].bytes().all(|byte| byte.is_ascii_digit()) => &id[..index],
+        _ => id,
+    }
+}
+
+/// Download the BibTeX entry of `identifier`
+pub async fn fetch_entry(identifier: &Identifier) -> Result<BibEntry> {
+    let (url, accept) = identifier.bibtex_url();
+    let response = crate::download::http_client()?
+        .get(&url)
+        .header(reqwest::header::ACCEPT, accept)
+        .send()
+        .await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        anyhow::bail!("{} was not found", identifier);
+    }
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to fetch {}: {}", identifier, response.status());
+    }
+    let text = response.text().await?;
+    parse_bib(&text)
+        .into_iter()
+        .find(BibEntry::is_citable)
+        .ok_or_else(|| anyhow::anyhow!("No BibTeX entry in the response for {}", identifier))
+}
+
+/// What `add_entry` did with an entry
+#[derive(Debug, PartialEq)]
+pub enum Added {
+    /// Added under this key (changed when the fetched key was taken)
+    New(String),
+    /// The file already has the work under this key
+    Duplicate(String),
+}
+
+/// Add `entry` to `entries` unless it is already there
+pub fn add_entry(entries: &mut Vec<BibEntry>, mut entry: BibEntry) -> Added {
+    if let Some(existing) = entries.iter().find(|existing| existing.is_citable() && existing.same_work(&entry)) {
+        return Added::Duplicate(existing.key.clone());
+    }
+    let base = entry.key.clone();
+    let taken = |key: &str| entries.iter().any(|existing| existing.key.eq_ignore_ascii_case(key));
+    let mut suffix = b'b';
+    while taken(&entry.key) && suffix <= b'z' {
+        entry.key = format!("{}{}", base, suffix as char);
+        suffix += 1;
+    }
+    let key = entry.key.clone();
+    entries.push(entry);
+    Added::New(key)
+}
+
+/// Append entries to a .bib file, creating it if needed
+pub fn append_entries(path: &Path, entries: &[BibEntry]) -> Result<()> {
+    let mut content = std::fs::read_to_string(path).unwrap_or_default();
+    for entry in entries {
+        if !content.is_empty() {
+            content.truncate(content.trim_end().len());
+            content.push_str("\n\n");
+        }
+        content.push_str(&entry.to_bibtex());
+        content.push('\n');
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// What `tidy` changed, and what it found
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+pub struct TidyReport {
+    /// Keys whose case changed to match the citations: (old, new)
+    pub renamed: Vec<(String, String)>,
+    /// Removed duplicates: (removed key, key of the entry kept)
+    pub removed: Vec<(String, String)>,
+    /// Entries for the same work that are both cited, so neither was removed
+    pub cited_duplicates: Vec<(String, String)>,
+    /// Keys no document cites
+    pub uncited: Vec<String>,
+}
+
+/// Normalize, deduplicate and sort `entries`. `citations` are the keys the
+/// documents cite; `*` (from \nocite{*}) uses every entry.
+pub fn tidy(entries: Vec<BibEntry>, citations: &[String]) -> (Vec<BibEntry>, TidyReport) {
+    let mut report = TidyReport::default();
+    let cited: HashSet<&str> = citations.iter().map(String::as_str).collect();
+    let keys: HashSet<String> = entries.iter().map(|entry| entry.key.clone()).collect();
+
+    let mut kept: Vec<BibEntry> = Vec::new();
+    for mut entry in entries {
+        entry.normalize();
+        if !entry.is_citable() {
+            kept.push(entry);
+            continue;
+        }
+        // BibTeX matches keys ignoring case but biber does not: use the cited spelling
+        if !cited.contains(entry.key.as_str()) {
+            let spellings: Vec<&str> = cited.iter().copied().filter(|key| key.eq_ignore_ascii_case(&entry.key)).collect();
+            if let [spelling] = spellings[..] {
+                if !keys.contains(spelling) {
+                    report.renamed.push((entry.key.clone(), spelling.to_string()));
+                    entry.key = spelling.to_string();
+                }
+            }
+        }
+
+        let Some(index) = kept.iter().position(|other| other.is_citable() && other.same_work(&entry)) else {
+            kept.push(entry);
+            continue;
+        };
+        let (first_cited, entry_cited) = (cited.contains(kept[index].key.as_str()), cited.contains(entry.key.as_str()));
+        if first_cited && entry_cited && kept[index].key != entry.key {
+            report.cited_duplicates.push((kept[index].key.clone(), entry.key.clone()));
+            kept.push(entry);
+            continue;
+        }
+        // Keep the cited entry, with the fields only the other one has
+        let (mut keep, removed) = if entry_cited && !first_cited {
+            (entry, kept[index].clone())
+        } else {
+            (kept[index].clone(), entry)
+        };
+        for (name, value) in removed.fields {
+            if keep.field(&name).is_none() {
+                keep.fields.push((name, value));
+            }
+        }
+        report.removed.push((removed.key, keep.key.clone()));
+        kept[index] = keep;
+    }
+
+    // @string and @preamble must stay ahead of the entries using them
+    kept.sort_by_cached_key(|entry| match entry.entry_type.as_str() {
+        "string" | "preamble" => (0, String::new()),
+        "comment" => (2, String::new()),
+        _ => (1, entry.key.to_lowercase()),
+    });
+
+    if !cited.contains("*") {
+        let mut used: HashSet<String> = citations.iter().map(|key| key.to_lowercase()).collect();
+        for entry in &kept {
+            for value in REFERENCE_FIELDS.iter().filter_map(|field| entry.field(field)) {
+                used.extend(value.split(',').map(|key| key.trim().to_lowercase()));
+            }
+        }
+        report.uncited = kept
+            .iter()
+            .filter(|entry| entry.is_citable() && !used.contains(&entry.key.to_lowercase()))
+            .map(|entry| entry.key.clone())
+            .collect();
+    }
+    (kept, report)
+}
+
+/// The entries as the content of a .bib file
+pub fn format_bib(entries: &[BibEntry]) -> String {
+    let blocks: Vec<String> = entries.iter().map(BibEntry::to_bibtex).collect();
+    format!("{}\n", blocks.join("\n\n"))
+}
+
+/// The keys cited in `dependencies`, in order of first use
+pub fn cited_keys(dependencies: &[TeXDependency]) -> Vec<String> {
+    let mut keys: Vec<String> = Vec::new();
+    for dependency in dependencies {
+        if dependency.dependency_type == DependencyType::Citation && !keys.contains(&dependency.package_name) {
+            keys.push(dependency.package_name.clone());
+        }
+    }
+    keys
+}
+
+/// The .bib files the project's documents name in \bibliography or
+/// \addbibresource, or else the .bib files in the project
+pub fn project_bib_files(project_root: &Path, dependencies: &[TeXDependency]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dependency in dependencies {
+        if dependency.dependency_type == DependencyType::Bibliography {
+            let file = project_root.join(format!("{}.bib", dependency.package_name));
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    if files.is_empty() {
+        TeXParser::collect_project_files(project_root, &mut files)?;
+        files.retain(|file| file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bib")));
+        files.sort();
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIB: &str = r#"% Papers
+@String{jacm = "Journal of the ACM"}
+@comment{ignored @article{fake, title={Fake}} }
+@Article{knuth1974,
+  author    = {Donald E. Knuth},
+  title     = {{Computer Programming} as an Art},
+  journal   = jacm # " 17",
+  year      = 1974,
+  doi       = {https://doi.org/10.1145/361604.361612},
+}
+@misc(vaswani2017, title = "Attention Is All You Need", eprint = {1706.03762v7}, archivePrefix = {arXiv})
+"#;
+
+    #[test]
+    fn test_parse_bib() {
+        let entries = parse_bib(BIB);
+        assert_eq!(entries.len(), 4);
+        assert!(!entries[0].is_citable());
+        assert_eq!(entries[1].entry_type, "comment");
+        let knuth = &entries[2];
+        assert_eq!((knuth.entry_type.as_str(), knuth.key.as_str()), ("article", "knuth1974"));
+        assert_eq!(knuth.field("title"), Some("{Computer Programming} as an Art"));
+        assert_eq!(knuth.field("journal"), Some("jacm # \" 17\""));
+        assert_eq!(knuth.field("year"), Some("1974"));
+        let joined = parse_bib("@misc{x, note = {a} # {b}}").remove(0);
+        assert_eq!(joined.field("note"), Some("{a} # {b}"));
+        assert_eq!(knuth.doi().as_deref(), Some("10.1145/361604.361612"));
+        assert_eq!(entries[3].arxiv_id().as_deref(), Some("1706.03762"));
+        assert_eq!(entries[3].field("title"), Some("Attention Is All You Need"));
+        assert!(knuth.to_bibtex().starts_with("@article{knuth1974,\n  author = {Donald E. Knuth},\n"));
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(Identifier::parse("https://doi.org/10.1145/361604.361612").unwrap(), Identifier::Doi("10.1145/361604.361612".to_string()));
+        assert_eq!(Identifier::parse("doi:10.1000/XYZ").unwrap(), Identifier::Doi("10.1000/xyz".to_string()));
+        assert_eq!(Identifier::parse("arXiv:1706.03762v7").unwrap(), Identifier::Arxiv("1706.03762v7".to_string()));
+        assert_eq!(Identifier::parse("https://arxiv.org/pdf/hep-th/9901001.pdf").unwrap(), Identifier::Arxiv("hep-th/9901001".to_string()));
+        assert!(Identifier::parse("knuth1974").is_err());
+        let entries = parse_bib(BIB);
+        assert_eq!(Identifier::parse("10.1145/361604.361612").unwrap().find_in(&entries).unwrap().key, "knuth1974");
+        assert_eq!(Identifier::parse("1706.03762v2").unwrap().find_in(&entries).unwrap().key, "vaswani2017");
+        assert_eq!(Identifier::Arxiv("1706.03762".to_string()).bibtex_url().0, "https://arxiv.org/bibtex/1706.03762");
+    }
+
+    #[test]
+    fn test_add_entry_detects_duplicates() {
+        let mut entries = parse_bib(BIB);
+        let fetched = |text: &str| parse_bib(text).remove(0);
+        let same_doi = fetched("@article{Knuth_1974, title={Computer programming as an art}, doi={10.1145/361604.361612}}");
+        assert_eq!(add_entry(&mut entries, same_doi), Added::Duplicate("knuth1974".to_string()));
+        let same_arxiv = fetched("@misc{vaswani2017attention, eprint={1706.03762}, archivePrefix={arXiv}}");
+        assert_eq!(add_entry(&mut entries, same_arxiv), Added::Duplicate("vaswani2017".to_string()));
+        let other = fetched("@article{knuth1974, title={The Art of Computer Programming}, doi={10.5555/260999}}");
+        assert_eq!(add_entry(&mut entries, other), Added::New("knuth1974b".to_string()));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("refs.bib");
+        std::fs::write(&path, "@book{a, title={A}}").unwrap();
+        append_entries(&path, &entries[4..]).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("@book{a, title={A}}\n\n@article{knuth1974b,\n"));
+        assert_eq!(parse_bib(&content).len(), 2);
+    }
+
+    #[test]
+    fn test_tidy() {
+        let content = format!(
+            "{}\n@article{{Knuth_1974,\n  title = \"Computer programming as an art\",\n  doi = {{10.1145/361604.361612}},\n  pages = {{667--673}},\n  note = {{}}}}\n@book{{Lamport1994, title={{LaTeX:\n    A Document Preparation System}}, crossref={{series}}}}\n@misc{{series, title={{Series}}}}\n@misc{{unused, title={{Unused}}, title={{Again}}}}\n",
+            BIB
+        );
+        let citations = vec!["Knuth_1974".to_string(), "lamport1994".to_string()];
+        let (entries, report) = tidy(parse_bib(&content), &citations);
+
+        let keys: Vec<&str> = entries.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, vec!["", "Knuth_1974", "lamport1994", "series", "unused", "vaswani2017", ""]);
+        assert_eq!(report.removed, vec![("knuth1974".to_string(), "Knuth_1974".to_string())]);
+        assert_eq!(report.renamed, vec![("Lamport1994".to_string(), "lamport1994".to_string())]);
+        assert_eq!(report.uncited, vec!["unused", "vaswani2017"]);
+
+        let knuth = &entries[1];
+        assert_eq!(knuth.field("title"), Some("Computer programming as an art"));
+        assert_eq!(knuth.field("author"), Some("Donald E. Knuth"));
+        assert_eq!(knuth.field("note"), None);
+        assert_eq!(entries[2].field("title"), Some("LaTeX: A Document Preparation System"));
+        assert_eq!(entries[4].fields.len(), 1);
+        let formatted = format_bib(&entries);
+        assert!(formatted.contains("  title = {Computer programming as an art},\n"));
+        assert!(formatted.starts_with("@String{jacm"));
+        assert_eq!(format_bib(&tidy(parse_bib(&formatted), &citations).0), formatted);
+
+        let both = vec!["Knuth_1974".to_string(), "knuth1974".to_string()];
+        assert_eq!(tidy(parse_bib(&content), &both).1.cited_duplicates.len(), 1);
+        assert!(tidy(parse_bib(&content), &["*".to_string()]).1.uncited.is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::config::BuildCacheConfig;
+use crate::tex_parser::TeXParser;
+
+/// Files besides TeX sources that a build reads when no .fls records its inputs
+const ASSET_EXTENSIONS: &[&str] = &["bib", "bst", "bbx", "cbx", "png", "jpg", "jpeg", "eps", "svg", "pdf"];
+
+/// What a cached build was made from and what it produced; paths are
+/// relative to the project root
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Manifest {
+    pub chain: Vec<String>,
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+/// Outputs of earlier builds, stored under a hash of the compile chain and
+/// the contents of every input file
+pub struct BuildCache {
+    dir: PathBuf,
+    project_root: PathBuf,
+    max_entries: usize,
+}
+
+impl BuildCache {
+    pub fn new(config: &BuildCacheConfig, project_root: &Path) -> Self {
+        let dir = match &config.dir {
+            Some(dir) => project_root.join(dir),
+            None => project_root.join(".tpmgr").join("build-cache"),
+        };
+        Self {
+            dir,
+            project_root: project_root.to_path_buf(),
+            max_entries: config.max_entries.max(1),
+        }
+    }
+
+    /// The files the build reads: the project files recorded in the .fls
+    /// files next to `outputs` (written by `-recorder`), else the TeX
+    /// sources, bibliographies and figures of the project
+    pub fn input_files(&self, outputs: &[PathBuf], package_dir: &str) -> Result<Vec<String>> {
+        let mut inputs = BTreeSet::new();
+        for output in outputs {
+            if let Ok(content) = std::fs::read_to_string(output.with_extension("fls")) {
+                inputs.extend(recorded_inputs(&content, &self.project_root));
+            }
+        }
+        if !inputs.is_empty() {
+            return Ok(inputs.into_iter().collect());
+        }
+
+        let mut files = Vec::new();
+        TeXParser::collect_source_files(&self.project_root, &mut files)?;
+        let mut assets = Vec::new();
+        TeXParser::collect_project_files(&self.project_root, &mut assets)?;
+        files.extend(assets.into_iter().filter(|file| {
+            let is_asset = file
+                .extension()
+                .is_some_and(|ext| ASSET_EXTENSIONS.iter().any(|asset| ext.eq_ignore_ascii_case(asset)));
+            is_asset && !outputs.contains(file) && !file.starts_with(&self.dir)
+        }));
+        // Installing or removing a project package changes the build too
+        files.push(self.project_root.join(package_dir).join("registry.json"));
+        for file in files {
+            if file.is_file() {
+                if let Some(relative) = self.relative(&file) {
+                    inputs.insert(relative);
+                }
+            }
+        }
+        Ok(inputs.into_iter().collect())
+    }
+
+    /// The cached build whose chain and inputs match the files on disk now.
+    /// Each stored input list is checked, so a build made on another branch
+    /// with a different set of inputs is found as well.
+    pub fn lookup(&self, chain: &[String], inputs: &[String]) -> Result<Option<(String, Manifest)>> {
+        let mut input_lists = vec![inputs.to_vec()];
+        for (_, manifest) in self.entries() {
+            if manifest.chain == chain && !input_lists.contains(&manifest.inputs) {
+                input_lists.push(manifest.inputs);
+            }
+        }
+        for inputs in input_lists {
+            let key = self.key(chain, &inputs)?;
+            if let Ok(manifest) = self.read_manifest(&key) {
+                if manifest.chain == chain && manifest.inputs == inputs {
+                    return Ok(Some((key, manifest)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Copy the outputs of a cached build into the project; the files that
+    /// were different
+    pub fn restore(&self, key: &str, manifest: &Manifest) -> Result<Vec<String>> {
+        let entry = self.dir.join(key);
+        let mut restored = Vec::new();
+        for output in &manifest.outputs {
+            let cached = entry.join("files").join(output);
+            let target = self.project_root.join(output);
+            if std::fs::read(&cached)? == std::fs::read(&target).unwrap_or_default() {
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(&cached, &target)?;
+            restored.push(output.clone());
+        }
+        // The most recently used builds are the ones kept
+        std::fs::write(entry.join("manifest.json"), serde_json::to_string_pretty(manifest)?)?;
+        Ok(restored)
+    }
+
+    /// Keep the outputs of a successful build under the hash of its inputs
+    pub fn store(&self, chain: &[String], inputs: &[String], outputs: &[PathBuf]) -> Result<()> {
+        let outputs: Vec<String> = outputs
+            .iter()
+            .filter(|output| output.is_file())
+            .filter_map(|output| self.relative(output))
+            .collect();
+        if outputs.is_empty() {
+            return Ok(());
+        }
+        let key = self.key(chain, inputs)?;
+        let entry = self.dir.join(&key);
+        for output in &outputs {
+            let cached = entry.join("files").join(output);
+            if let Some(parent) = cached.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(self.project_root.join(output), cached)?;
+        }
+        let manifest = Manifest {
+            chain: chain.to_vec(),
+            inputs: inputs.to_vec(),
+            outputs,
+        };
+        std::fs::write(entry.join("manifest.json"), serde_json::to_string_pretty(&manifest)?)?;
+        self.evict()
+    }
+
+    /// Hash of the chain and of each input's path and contents; a missing
+    /// input hashes differently from any existing file
+    fn key(&self, chain: &[String], inputs: &[String]) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for command in chain {
+            hasher.update(command.as_bytes());
+            hasher.update([0]);
+        }
+        for input in inputs {
+            hasher.update(input.as_bytes());
+            hasher.update([0]);
+            match std::fs::read(self.project_root.join(input)) {
+                Ok(content) => hasher.update(Sha256::digest(&content)),
+                Err(_) => hasher.update(b"missing"),
+            }
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    fn read_manifest(&self, key: &str) -> Result<Manifest> {
+        let content = std::fs::read_to_string(self.dir.join(key).join("manifest.json"))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Cached builds, most recently used first
+    fn entries(&self) -> Vec<(PathBuf, Manifest)> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut entries: Vec<_> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let manifest_path = entry.path().join("manifest.json");
+                let used = std::fs::metadata(&manifest_path).and_then(|metadata| metadata.modified()).ok()?;
+                let manifest = serde_json::from_str(&std::fs::read_to_string(&manifest_path).ok()?).ok()?;
+                Some((used, entry.path(), manifest))
+            })
+            .collect();
+        entries.sort_by_key(|(used, _, _)| std::cmp::Reverse(*used));
+        entries.into_iter().map(|(_, path, manifest)| (path, manifest)).collect()
+    }
+
+    /// Remove the least recently used builds beyond `max_entries`
+    fn evict(&self) -> Result<()> {
+        for (path, _) in self.entries().into_iter().skip(self.max_entries) {
+            std::fs::remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+
+    fn relative(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.project_root).ok()?;
+        Some(relative.to_string_lossy().replace('\\', "/"))
+    }
+}
+
+/// The project files a LaTeX run read according to its .fls recording:
+/// INPUT lines inside `project_root` that the run did not also write
+pub fn recorded_inputs(fls: &str, project_root: &Path) -> Vec<String> {
+    let mut working_dir = project_root.to_path_buf();
+    let mut inputs = Vec::new();
+    let mut outputs = HashSet::new();
+    let root = project_root.canonicalize().unwrap_or_else(|_| project_root.to_path_buf());
+    for line in fls.lines() {
+        if let Some(pwd) = line.strip_prefix("PWD ") {
+            working_dir = PathBuf::from(pwd.trim());
+        } else if let Some(input) = line.strip_prefix("INPUT ") {
+            inputs.push(working_dir.join(input.trim()));
+        } else if let Some(output) = line.strip_prefix("OUTPUT ") {
+            outputs.insert(working_dir.join(output.trim()));
+        }
+    }
+
+    let mut recorded: Vec<String> = inputs
+        .into_iter()
+        .filter(|input| !outputs.contains(input))
+        .filter_map(|input| {
+            let input = input.canonicalize().ok()?;
+            let relative = input.strip_prefix(&root).ok()?;
+            Some(relative.to_string_lossy().replace('\\', "/"))
+        })
+        .collect();
+    recorded.sort();
+    recorded.dedup();
+    recorded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recorded_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::create_dir(root.join("chapters")).unwrap();
+        for file in ["main.tex", "main.aux", "chapters/intro.tex", "figure.png"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let fls = format!(
+            "PWD {}\nINPUT /usr/share/texmf/tex/latex/base/article.cls\nINPUT main.tex\nOUTPUT main.aux\nINPUT main.aux\n\
+             INPUT ./chapters/intro.tex\nINPUT ./figure.png\nINPUT ./figure.png\nINPUT ./deleted.tex\n",
+            root.display()
+        );
+        assert_eq!(recorded_inputs(&fls, &root), vec!["chapters/intro.tex", "figure.png", "main.tex"]);
+    }
+
+    #[test]
+    fn test_build_cache_reuses_outputs_across_branches() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let cache = BuildCache::new(&BuildCacheConfig::default(), root);
+        let chain = vec!["pdflatex main.tex".to_string()];
+        let pdf = root.join("main.pdf");
+
+        // Build on one branch
+        std::fs::write(root.join("main.tex"), "branch a").unwrap();
+        let inputs_a = cache.input_files(std::slice::from_ref(&pdf), "packages").unwrap();
+        assert_eq!(inputs_a, vec!["main.tex"]);
+        assert_eq!(cache.lookup(&chain, &inputs_a).unwrap(), None);
+        std::fs::write(&pdf, "pdf a").unwrap();
+        cache.store(&chain, &inputs_a, std::slice::from_ref(&pdf)).unwrap();
+
+        // Another branch with an extra input builds separately
+        std::fs::write(root.join("main.tex"), "branch b").unwrap();
+        std::fs::write(root.join("extra.tex"), "b only").unwrap();
+        let inputs_b = cache.input_files(std::slice::from_ref(&pdf), "packages").unwrap();
+        assert_eq!(cache.lookup(&chain, &inputs_b).unwrap(), None);
+        std::fs::write(&pdf, "pdf b").unwrap();
+        cache.store(&chain, &inputs_b, std::slice::from_ref(&pdf)).unwrap();
+
+        // Back on the first branch the first build is found and restored
+        std::fs::write(root.join("main.tex"), "branch a").unwrap();
+        let (key, manifest) = cache.lookup(&chain, &inputs_b).unwrap().unwrap();
+        assert_eq!(manifest.inputs, vec!["main.tex"]);
+        assert_eq!(cache.restore(&key, &manifest).unwrap(), vec!["main.pdf"]);
+        assert_eq!(std::fs::read_to_string(&pdf).unwrap(), "pdf a");
+        assert_eq!(cache.restore(&key, &manifest).unwrap(), Vec::<String>::new());
+
+        // A different chain never matches
+        assert_eq!(cache.lookup(&["lualatex main.tex".to_string()], &inputs_a).unwrap(), None);
+    }
+}
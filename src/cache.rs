@@ -0,0 +1,142 @@
+use crate::config::CacheConfig;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// What a garbage collection run removed from the cache
+#[derive(Debug, Default, PartialEq)]
+pub struct GcReport {
+    pub removed_files: usize,
+    pub freed_bytes: u64,
+}
+
+/// Apply the cache policy: remove archives unused for `max_age_days`, then
+/// the least recently used ones until the cache fits in `max_size_mb`.
+/// An archive counts as used when it is downloaded or reused for an install.
+pub fn collect_garbage(cache_dir: &Path, policy: &CacheConfig) -> Result<GcReport> {
+    collect_garbage_at(cache_dir, policy, SystemTime::now())
+}
+
+fn collect_garbage_at(cache_dir: &Path, policy: &CacheConfig, now: SystemTime) -> Result<GcReport> {
+    let mut report = GcReport::default();
+    if !cache_dir.is_dir() {
+        return Ok(report);
+    }
+
+    let mut entries: Vec<(PathBuf, u64, SystemTime)> = Vec::new();
+    for entry in std::fs::read_dir(cache_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            entries.push((entry.path(), metadata.len(), metadata.modified()?));
+        }
+    }
+    // Oldest first
+    entries.sort_by_key(|(_, _, used)| *used);
+
+    let mut remove = |path: &Path, size: u64| -> Result<()> {
+        std::fs::remove_file(path)?;
+        report.removed_files += 1;
+        report.freed_bytes += size;
+        Ok(())
+    };
+
+    if let Some(days) = policy.max_age_days {
+        let max_age = Duration::from_secs(days * 24 * 60 * 60);
+        entries.retain(|(path, size, used)| {
+            let expired = now.duration_since(*used).map(|age| age > max_age).unwrap_or(false);
+            !(expired && remove(path, *size).is_ok())
+        });
+    }
+
+    if let Some(max_size_mb) = policy.max_size_mb {
+        let max_size = max_size_mb * 1024 * 1024;
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        for (path, size, _) in &entries {
+            if total <= max_size {
+                break;
+            }
+            remove(path, *size)?;
+            total -= size;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Number of files in the cache and their total size
+pub fn usage(cache_dir: &Path) -> Result<(usize, u64)> {
+    if !cache_dir.is_dir() {
+        return Ok((0, 0));
+    }
+    let mut files = 0;
+    let mut bytes = 0;
+    for entry in std::fs::read_dir(cache_dir)? {
+        let metadata = entry?.metadata()?;
+        if metadata.is_file() {
+            files += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((files, bytes))
+}
+
+/// Mark a cached archive as used so garbage collection keeps it longer
+pub fn touch(path: &Path) -> Result<()> {
+    std::fs::File::options().write(true).open(path)?.set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+/// Human-readable size, e.g. "3.2 MB"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_collect_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        for (name, size, age_days) in [("old.tar.xz", 100, 40), ("mid.tar.xz", 2 * 1024 * 1024, 5), ("new.tar.xz", 1024 * 1024, 0)] {
+            let path = dir.path().join(name);
+            fs::write(&path, vec![0u8; size]).unwrap();
+            fs::File::options().write(true).open(&path).unwrap().set_modified(now - day * age_days).unwrap();
+        }
+
+        let keep_everything = CacheConfig::default();
+        assert_eq!(collect_garbage_at(dir.path(), &keep_everything, now).unwrap(), GcReport::default());
+
+        let policy = CacheConfig {
+            keep: true,
+            max_size_mb: Some(2),
+            max_age_days: Some(30),
+        };
+        let report = collect_garbage_at(dir.path(), &policy, now).unwrap();
+        assert_eq!(report.removed_files, 2);
+        assert_eq!(report.freed_bytes, 100 + 2 * 1024 * 1024);
+        assert!(dir.path().join("new.tar.xz").exists());
+        assert!(!dir.path().join("mid.tar.xz").exists());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(3 * 1024 * 1024 + 200 * 1024), "3.2 MB");
+    }
+}
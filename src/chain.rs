@@ -0,0 +1,631 @@
+use anyhow::Result;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::config::{CompileCommand, CompileStep, IndexConfig, IndexTool, InteractionMode, TEX_ENGINES};
+use crate::tex_parser::{DependencyType, TeXDependency};
+
+/// Command-line options of `compile` that change the chain
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChainOptions {
+    /// --interaction, taking precedence over the configured mode
+    pub interaction: Option<InteractionMode>,
+    pub halt_on_error: bool,
+    /// --draft: a quick build without bibliography and index runs
+    pub draft: bool,
+    /// --graph: show the stages of the chain instead of compiling
+    pub graph: bool,
+}
+
+/// latexmk options that select the engine
+const LATEXMK_ENGINE_OPTIONS: &[&str] = &["-pdf", "-pdflatex", "-xelatex", "-pdfxe", "-xe", "-lualatex", "-pdflua", "-lua", "-dvi", "-ps"];
+
+/// Give the steps that have no file argument `document`: engine runs compile
+/// it, and other tools get the job of the engine run before them
+pub fn fill_document(steps: &mut [CompileStep], document: &str) {
+    let mut job = Path::new(document).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    for step in steps {
+        if step.args.iter().all(|arg| arg.starts_with('-')) {
+            step.args.push(if step.is_engine_run() { document.to_string() } else { job.clone() });
+        }
+        if let Some(engine_job) = step.is_tex_engine().then(|| step.job_path()).flatten() {
+            job = engine_job;
+        }
+    }
+}
+
+/// Tools that build bibliographies, indexes and glossaries
+const AUXILIARY_TOOLS: &[&str] = &[
+    "bibtex", "bibtex8", "bibtexu", "pbibtex", "upbibtex", "biber", "makeindex", "texindy", "xindy", "upmendex", "mendex",
+    "makeglossaries", "makeglossaries-lite", "bib2gls",
+];
+
+impl CompileStep {
+    /// Whether the step runs a TeX engine directly (not through latexmk)
+    pub fn is_tex_engine(&self) -> bool {
+        Path::new(&self.tool)
+            .file_stem()
+            .is_some_and(|stem| TEX_ENGINES.contains(&stem.to_string_lossy().as_ref()))
+    }
+
+    /// Whether the step runs an engine directly or through latexmk, which
+    /// takes the same -interaction and -halt-on-error options
+    pub fn is_engine_run(&self) -> bool {
+        self.is_tex_engine() || Path::new(&self.tool).file_stem().is_some_and(|stem| stem == "latexmk")
+    }
+
+    /// Run `engine` instead of the step's engine; a latexmk step gets the
+    /// option selecting it (pdflatex, xelatex and lualatex)
+    pub fn set_engine(&mut self, engine: &str) {
+        if self.is_tex_engine() {
+            self.tool = engine.to_string();
+            return;
+        }
+        let option = match engine {
+            "pdflatex" => "-pdf",
+            "xelatex" => "-xelatex",
+            "lualatex" => "-lualatex",
+            _ => return,
+        };
+        if self.is_engine_run() {
+            self.args.retain(|arg| !LATEXMK_ENGINE_OPTIONS.contains(&arg.as_str()));
+            self.args.insert(0, option.to_string());
+        }
+    }
+
+    /// The job an engine step compiles, with its output directory: `main`
+    /// for `pdflatex main.tex`, `build/main` with `-output-directory=build`
+    pub fn job_path(&self) -> Option<String> {
+        let mut job = None;
+        let mut output_dir = None;
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            let option = arg.trim_start_matches('-');
+            if let Some(name) = option.strip_prefix("jobname=") {
+                job = Some(name.to_string());
+            } else if let Some(dir) = option.strip_prefix("output-directory=") {
+                output_dir = Some(dir.to_string());
+            } else if option == "output-directory" {
+                output_dir = args.next().cloned();
+            } else if option == "jobname" {
+                job = args.next().cloned();
+            } else if !arg.starts_with('-') && job.is_none() {
+                let stem = Path::new(arg).file_stem()?.to_string_lossy().to_string();
+                job = Some(stem);
+            }
+        }
+        let job = job?;
+        Some(match output_dir {
+            Some(dir) => format!("{}/{}", dir.trim_end_matches('/'), job),
+            None => job,
+        })
+    }
+}
+
+/// The makeindex run nomencl needs: the .nlo file sorted with nomencl.ist
+/// into the .nls file the next engine run reads
+pub fn nomencl_step(job: &str) -> CompileStep {
+    CompileStep {
+        tool: "makeindex".to_string(),
+        args: vec![
+            format!("{}.nlo", job),
+            "-s".to_string(),
+            "nomencl.ist".to_string(),
+            "-o".to_string(),
+            format!("{}.nls", job),
+        ],
+        ..Default::default()
+    }
+}
+
+/// The engine option that skips writing the PDF, for runs whose PDF a later
+/// run replaces
+fn no_pdf_option(engine: &str) -> Option<&'static str> {
+    match engine {
+        "pdflatex" | "lualatex" => Some("-draftmode"),
+        "xelatex" => Some("-no-pdf"),
+        _ => None,
+    }
+}
+
+/// The class of the document an engine step compiles: the one declared in
+/// the file named like its job, else the first one found
+fn document_class<'a>(step: &CompileStep, dependencies: &'a [TeXDependency]) -> Option<&'a str> {
+    let job = step.job_path()?;
+    let job = Path::new(&job).file_name()?;
+    let classes: Vec<&TeXDependency> = dependencies
+        .iter()
+        .filter(|dependency| dependency.dependency_type == DependencyType::DocumentClass)
+        .collect();
+    classes
+        .iter()
+        .find(|dependency| dependency.source_file.as_deref().and_then(Path::file_stem) == Some(job))
+        .or(classes.first())
+        .map(|dependency| dependency.package_name.as_str())
+}
+
+/// Turn the chain into a draft build for a quick look at the document:
+/// bibliography and index runs are dropped, repeated engine runs become one
+/// (reruns follow the auxiliary files), the document class gets the `draft`
+/// option, and engine runs before the last one skip writing the PDF.
+/// Returns the tools that were dropped.
+pub fn make_draft(compile: &mut CompileCommand, dependencies: &[TeXDependency]) -> Vec<String> {
+    let mut skipped = Vec::new();
+    compile.steps.retain(|step| {
+        let stem = Path::new(&step.tool).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        if !AUXILIARY_TOOLS.contains(&stem.as_str()) {
+            return true;
+        }
+        if !skipped.contains(&stem) {
+            skipped.push(stem);
+        }
+        false
+    });
+    compile.steps.dedup();
+
+    let last_engine = compile.steps.iter().rposition(CompileStep::is_tex_engine);
+    for (i, step) in compile.steps.iter_mut().enumerate().filter(|(_, step)| step.is_engine_run()) {
+        let class = document_class(step, dependencies).map(|class| format!("\\PassOptionsToClass{{draft}}{{{}}}", class));
+        if !step.is_tex_engine() {
+            // latexmk puts the code before \input of each document itself
+            if let Some(class) = class {
+                step.args.insert(0, format!("-usepretex={}", class));
+            }
+            continue;
+        }
+        // The engine reads its remaining arguments as the first input line:
+        // `\PassOptionsToClass{draft}{article}\input main.tex`
+        if let (Some(class), Some(file)) = (class, step.args.iter().rposition(|arg| !arg.starts_with('-'))) {
+            let has_jobname = step.args.iter().any(|arg| arg.trim_start_matches('-').starts_with("jobname"));
+            let job = Path::new(&step.args[file]).file_stem().map(|stem| stem.to_string_lossy().to_string());
+            step.args.insert(file, format!("{}\\input", class));
+            if let (false, Some(job)) = (has_jobname, job) {
+                step.args.insert(file, format!("-jobname={}", job));
+            }
+        }
+        let engine = Path::new(&step.tool).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        if let Some(option) = no_pdf_option(&engine).filter(|_| Some(i) != last_engine) {
+            step.args.insert(0, option.to_string());
+        }
+    }
+    skipped
+}
+
+/// Give every engine run the chain's `-interaction=<mode>`, replacing a mode
+/// set in its args, and `-halt-on-error` and `-synctex=1` unless it has them
+pub fn apply_engine_options(compile: &mut CompileCommand) {
+    let (interaction, halt_on_error, synctex) = (compile.interaction, compile.halt_on_error, compile.synctex);
+    for step in compile.steps.iter_mut().filter(|step| step.is_engine_run()) {
+        if let Some(mode) = interaction {
+            let mut args = Vec::with_capacity(step.args.len() + 1);
+            let mut old = std::mem::take(&mut step.args).into_iter();
+            while let Some(arg) = old.next() {
+                let option = arg.trim_start_matches('-');
+                if option == "interaction" {
+                    old.next();
+                } else if !(arg.starts_with('-') && option.starts_with("interaction=")) {
+                    args.push(arg);
+                }
+            }
+            args.insert(0, format!("-interaction={}", mode.as_str()));
+            step.args = args;
+        }
+        if halt_on_error && !step.args.iter().any(|arg| arg.trim_start_matches('-') == "halt-on-error") {
+            let position = usize::from(interaction.is_some());
+            step.args.insert(position, "-halt-on-error".to_string());
+        }
+        if synctex && !step.args.iter().any(|arg| arg.trim_start_matches('-').starts_with("synctex")) {
+            let position = usize::from(interaction.is_some()) + usize::from(halt_on_error);
+            step.args.insert(position, "-synctex=1".to_string());
+        }
+    }
+}
+
+/// The steps each step waits for: the ones named in its `needs`, or the step
+/// before it when it has no `needs`
+pub fn step_dependencies(steps: &[CompileStep]) -> Result<Vec<Vec<usize>>> {
+    let mut dependencies = Vec::with_capacity(steps.len());
+    for (i, step) in steps.iter().enumerate() {
+        let Some(needs) = &step.needs else {
+            dependencies.push(i.checked_sub(1).into_iter().collect());
+            continue;
+        };
+        let mut needed = Vec::new();
+        for name in needs {
+            let Some(index) = steps.iter().position(|other| other.name.as_deref() == Some(name.as_str())) else {
+                anyhow::bail!("Step {} ({}) needs \"{}\", but no step has that name", i + 1, step.tool, name);
+            };
+            needed.push(index);
+        }
+        dependencies.push(needed);
+    }
+    Ok(dependencies)
+}
+
+/// Group the steps into stages that run one after another, each holding the
+/// steps whose dependencies ran in earlier stages
+pub fn step_stages(dependencies: &[Vec<usize>]) -> Result<Vec<Vec<usize>>> {
+    let mut done = vec![false; dependencies.len()];
+    let mut stages: Vec<Vec<usize>> = Vec::new();
+    while done.contains(&false) {
+        let stage: Vec<usize> = (0..dependencies.len())
+            .filter(|&i| !done[i] && dependencies[i].iter().all(|&needed| done[needed]))
+            .collect();
+        if stage.is_empty() {
+            let waiting: Vec<String> = (0..dependencies.len()).filter(|&i| !done[i]).map(|i| (i + 1).to_string()).collect();
+            anyhow::bail!("The compile steps {} wait for each other", waiting.join(", "));
+        }
+        for &i in &stage {
+            done[i] = true;
+        }
+        stages.push(stage);
+    }
+    Ok(stages)
+}
+
+/// Whether the steps of a stage run at once: stages with engine runs, which
+/// share auxiliary files, run one step at a time
+pub fn runs_in_parallel(stage: &[usize], steps: &[CompileStep]) -> bool {
+    stage.len() > 1 && !stage.iter().any(|&i| steps[i].is_engine_run())
+}
+
+/// Files besides the .aux files whose contents the next engine pass reads
+const PASS_EXTENSIONS: &[&str] = &["toc", "lof", "lot", "bcf"];
+
+/// The job of each resolved command that runs a TeX engine
+pub fn engine_jobs(commands: &[Vec<String>]) -> Vec<Option<String>> {
+    commands
+        .iter()
+        .map(|command| {
+            let (tool, args) = command.split_first()?;
+            let step = CompileStep { tool: tool.clone(), args: args.to_vec(), ..Default::default() };
+            step.is_tex_engine().then(|| step.job_path()).flatten()
+        })
+        .collect()
+}
+
+/// Checksum of what an engine pass of `job` leaves for the next one: its
+/// .toc/.lof/.lot/.bcf files and every .aux file below its directory, which
+/// includes those of \include'd chapters. Equal checksums before and after a
+/// pass mean another pass would produce the same document.
+pub fn pass_checksum(project_root: &Path, job: &str) -> String {
+    let directory = project_root.join(job);
+    let directory = directory.parent().unwrap_or(project_root);
+    let mut files: Vec<_> = glob::glob(&directory.join("**").join("*.aux").to_string_lossy())
+        .map(|paths| paths.flatten().collect())
+        .unwrap_or_default();
+    files.sort();
+    files.extend(PASS_EXTENSIONS.iter().map(|extension| project_root.join(format!("{}.{}", job, extension))));
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        if let Ok(content) = std::fs::read(&file) {
+            hasher.update(file.to_string_lossy().as_bytes());
+            hasher.update(Sha256::digest(&content));
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Programs that sort an index
+const INDEX_TOOLS: &[&str] = &["makeindex", "texindy", "xindy", "upmendex", "mendex"];
+
+/// The run that sorts the index written to `index.idx` into `index.ind`
+pub fn index_step(config: &IndexConfig, index: &str) -> CompileStep {
+    let (tool, style_flag) = match config.tool {
+        IndexTool::Makeindex => ("makeindex", "-s"),
+        IndexTool::Xindy => ("texindy", "-M"),
+    };
+    let mut args = Vec::new();
+    if let Some(style) = &config.style {
+        args.push(style_flag.to_string());
+        args.push(style.clone());
+    }
+    args.extend(config.args.iter().cloned());
+    args.push("-o".to_string());
+    args.push(format!("{}.ind", index));
+    args.push(format!("{}.idx", index));
+    CompileStep { tool: tool.to_string(), args, ..Default::default() }
+}
+
+/// Whether a step of the chain already sorts `index` (`makeindex main`
+/// names the .idx file without its extension)
+fn sorts_index(compile: &CompileCommand, index: &str) -> bool {
+    let file = format!("{}.idx", index);
+    compile.steps.iter().any(|step| {
+        let index_tool = Path::new(&step.tool)
+            .file_stem()
+            .is_some_and(|stem| INDEX_TOOLS.contains(&stem.to_string_lossy().as_ref()));
+        step.args.iter().any(|arg| *arg == file || (index_tool && arg == index))
+    })
+}
+
+/// Whether a step of the chain already reads a file with `extension`
+fn reads_extension(compile: &CompileCommand, extension: &str) -> bool {
+    compile
+        .steps
+        .iter()
+        .any(|step| step.args.iter().any(|arg| arg.ends_with(&format!(".{}", extension))))
+}
+
+pub fn uses_package(dependencies: &[TeXDependency], package: &str) -> bool {
+    dependencies.iter().any(|dependency| {
+        matches!(dependency.dependency_type, DependencyType::UsePackage | DependencyType::RequirePackage)
+            && dependency.package_name == package
+    })
+}
+
+/// Insert `steps` after the first engine run, followed by another engine
+/// run unless the chain already has one later
+fn insert_after_engine(compile: &mut CompileCommand, steps: Vec<CompileStep>) {
+    let Some(engine) = compile.steps.iter().position(CompileStep::is_tex_engine) else {
+        return;
+    };
+    let rerun = compile.steps[engine].clone();
+    let after = engine + 1 + steps.len();
+    compile.steps.splice(engine + 1..engine + 1, steps);
+    if !compile.steps[after..].iter().any(CompileStep::is_tex_engine) {
+        compile.steps.push(rerun);
+    }
+}
+
+/// Add the tool runs the documents' packages need to a chain that runs the
+/// engine directly (latexmk chains are left alone), returning what was added
+pub fn add_package_steps(compile: &mut CompileCommand, dependencies: &[TeXDependency]) -> Vec<String> {
+    let mut added = Vec::new();
+    let Some(job) = compile.steps.iter().find(|step| step.is_tex_engine()).and_then(CompileStep::job_path) else {
+        return added;
+    };
+    if uses_package(dependencies, "nomencl") && !reads_extension(compile, "nlo") {
+        insert_after_engine(compile, vec![nomencl_step(&job)]);
+        added.push("makeindex for the nomencl nomenclature".to_string());
+    }
+
+    // Named indexes are written next to the job's own files
+    let directory = Path::new(&job).parent().filter(|dir| !dir.as_os_str().is_empty());
+    let mut indexes: Vec<String> = Vec::new();
+    for dependency in dependencies.iter().filter(|dependency| dependency.dependency_type == DependencyType::Index) {
+        let index = match (dependency.package_name.as_str(), directory) {
+            ("\\jobname", _) => job.clone(),
+            (name, Some(dir)) => format!("{}/{}", dir.display(), name),
+            (name, None) => name.to_string(),
+        };
+        if !indexes.contains(&index) && !sorts_index(compile, &index) {
+            indexes.push(index);
+        }
+    }
+    if !indexes.is_empty() {
+        let steps: Vec<CompileStep> = indexes.iter().map(|index| index_step(&compile.index, index)).collect();
+        let tool = steps[0].tool.clone();
+        insert_after_engine(compile, steps);
+        added.push(match indexes.len() {
+            1 => format!("{} for the index", tool),
+            count => format!("{} for {} indexes", tool, count),
+        });
+    }
+    added
+}
+
+/// The `% tpmgr:` directives at the top of a document: `step` lines replace
+/// the configured chain for it, `append` lines run after the chain
+#[derive(Debug, Default, PartialEq)]
+pub struct FileDirectives {
+    pub steps: Vec<CompileStep>,
+    pub append: Vec<CompileStep>,
+}
+
+impl FileDirectives {
+    /// Read the directives from the comment lines that start `content` (the
+    /// document `document`, relative to the project root)
+    pub fn read(content: &str, document: &str) -> Result<Self> {
+        let mut directives = Self::default();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some(comment) = line.strip_prefix('%') else {
+                break;
+            };
+            let Some(directive) = comment.trim_start().strip_prefix("tpmgr:") else {
+                continue;
+            };
+            let mut words = crate::import::split_command(directive).into_iter();
+            let (Some(kind), Some(tool)) = (words.next(), words.next()) else {
+                anyhow::bail!("{}:{}: a tpmgr directive needs a kind and a tool, e.g. `% tpmgr: step lualatex`", document, number + 1);
+            };
+            let step = CompileStep { tool, args: words.collect(), ..Default::default() };
+            match kind.as_str() {
+                "step" => directives.steps.push(step),
+                "append" => directives.append.push(step),
+                _ => anyhow::bail!("{}:{}: unknown tpmgr directive `{}` (use step or append)", document, number + 1, kind),
+            }
+        }
+        fill_document(&mut directives.steps, document);
+        fill_document(&mut directives.append, document);
+        Ok(directives)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty() && self.append.is_empty()
+    }
+
+    pub fn apply(&self, compile: &mut CompileCommand) {
+        if !self.steps.is_empty() {
+            compile.steps = self.steps.clone();
+        }
+        compile.steps.extend(self.append.iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::InteractionMode;
+    use crate::tex_parser::TeXParser;
+
+    fn dependency(package: &str) -> TeXDependency {
+        TeXParser::new().unwrap().parse_content(&format!("\\usepackage{{{}}}", package)).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_nomencl_step() {
+        let mut compile = CompileCommand::from_string("pdflatex -interaction=nonstopmode ${PROJECT_ROOT}/main.tex").unwrap();
+        let added = add_package_steps(&mut compile, &[dependency("nomencl")]);
+        assert_eq!(added.len(), 1);
+        assert_eq!(
+            compile.to_string(),
+            "pdflatex -interaction=nonstopmode ${PROJECT_ROOT}/main.tex | makeindex main.nlo -s nomencl.ist -o main.nls | pdflatex -interaction=nonstopmode ${PROJECT_ROOT}/main.tex"
+        );
+        // Already there: nothing is added twice
+        assert!(add_package_steps(&mut compile, &[dependency("nomencl")]).is_empty());
+
+        let mut chain = CompileCommand::from_string("xelatex -output-directory build thesis.tex | biber build/thesis | xelatex -output-directory build thesis.tex").unwrap();
+        add_package_steps(&mut chain, &[dependency("nomencl")]);
+        assert_eq!(chain.steps.len(), 4);
+        assert_eq!(chain.steps[1].args[0], "build/thesis.nlo");
+
+        let mut latexmk = CompileCommand::from_string("latexmk -pdf main.tex").unwrap();
+        assert!(add_package_steps(&mut latexmk, &[dependency("nomencl")]).is_empty());
+        assert!(add_package_steps(&mut CompileCommand::new(), &[dependency("amsmath")]).is_empty());
+    }
+
+    #[test]
+    fn test_engine_options() {
+        let mut compile = CompileCommand::from_string("pdflatex -interaction nonstopmode main.tex | bibtex main | latexmk --interaction=batchmode -halt-on-error -pdf main.tex").unwrap();
+        compile.interaction = Some(InteractionMode::Errorstopmode);
+        compile.halt_on_error = true;
+        apply_engine_options(&mut compile);
+        assert_eq!(
+            compile.to_string(),
+            "pdflatex -interaction=errorstopmode -halt-on-error main.tex | bibtex main | latexmk -interaction=errorstopmode -halt-on-error -pdf main.tex"
+        );
+
+        let mut unchanged = CompileCommand::from_string("xelatex -interaction=nonstopmode main.tex").unwrap();
+        apply_engine_options(&mut unchanged);
+        assert_eq!(unchanged.to_string(), "xelatex -interaction=nonstopmode main.tex");
+        unchanged.halt_on_error = true;
+        apply_engine_options(&mut unchanged);
+        assert_eq!(unchanged.to_string(), "xelatex -halt-on-error -interaction=nonstopmode main.tex");
+
+        let mut synctex = CompileCommand::from_string("lualatex main.tex | lualatex -synctex=-1 main.tex").unwrap();
+        synctex.synctex = true;
+        apply_engine_options(&mut synctex);
+        assert_eq!(synctex.to_string(), "lualatex -synctex=1 main.tex | lualatex -synctex=-1 main.tex");
+    }
+
+    #[test]
+    fn test_draft() {
+        let dependencies = TeXParser::new().unwrap().parse_content("\\documentclass[a4paper]{book}\n\\makeindex").unwrap();
+        let mut compile = CompileCommand::from_string("pdflatex -output-directory build main.tex | biber build/main | makeindex build/main | pdflatex -output-directory build main.tex | pdflatex -output-directory build main.tex").unwrap();
+        assert_eq!(make_draft(&mut compile, &dependencies), vec!["biber", "makeindex"]);
+        assert_eq!(compile.to_string(), "pdflatex -output-directory build -jobname=main \\PassOptionsToClass{draft}{book}\\input main.tex");
+        assert_eq!(compile.steps[0].job_path().as_deref(), Some("build/main"));
+
+        // Runs before the last one only write the auxiliary files
+        let mut chain = CompileCommand::from_string("xelatex -jobname=a main.tex | xelatex -jobname=b main.tex | latexmk -pdf main.tex").unwrap();
+        assert!(make_draft(&mut chain, &dependencies).is_empty());
+        assert_eq!(chain.steps[0].args, vec!["-no-pdf", "-jobname=a", "\\PassOptionsToClass{draft}{book}\\input", "main.tex"]);
+        assert_eq!(chain.steps[1].args[0], "-jobname=b");
+        assert_eq!(chain.steps[2].args[0], "-usepretex=\\PassOptionsToClass{draft}{book}");
+    }
+
+    #[test]
+    fn test_pass_checksum() {
+        let commands = CompileCommand::from_string("pdflatex -output-directory=build main.tex | biber build/main | pdflatex -output-directory=build main.tex")
+            .unwrap()
+            .resolve_variables(&crate::config::Config::new().chain_variables(Path::new(".")))
+            .unwrap();
+        assert_eq!(engine_jobs(&commands), vec![Some("build/main".to_string()), None, Some("build/main".to_string())]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let empty = pass_checksum(dir.path(), "build/main");
+        std::fs::create_dir_all(dir.path().join("build/chapters")).unwrap();
+        std::fs::write(dir.path().join("build/main.aux"), "\\relax").unwrap();
+        let first = pass_checksum(dir.path(), "build/main");
+        assert_ne!(first, empty);
+        assert_eq!(pass_checksum(dir.path(), "build/main"), first);
+
+        // Chapters' .aux files and the table of contents count too
+        std::fs::write(dir.path().join("build/chapters/intro.aux"), "\\newlabel{sec}{{1}{1}}").unwrap();
+        let second = pass_checksum(dir.path(), "build/main");
+        assert_ne!(second, first);
+        std::fs::write(dir.path().join("build/main.toc"), "\\contentsline").unwrap();
+        assert_ne!(pass_checksum(dir.path(), "build/main"), second);
+    }
+
+    #[test]
+    fn test_file_directives() {
+        let content = "% !TEX program = lualatex\n% tpmgr: step lualatex --shell-escape\n%tpmgr: step biber\n% tpmgr: append lualatex --shell-escape\n\n\\documentclass{article}\n% tpmgr: step pdflatex\n";
+        let directives = FileDirectives::read(content, "talks/demo.tex").unwrap();
+        let mut compile = CompileCommand::from_string("pdflatex main.tex | bibtex main | pdflatex main.tex").unwrap();
+        directives.apply(&mut compile);
+        assert_eq!(
+            compile.to_string(),
+            "lualatex --shell-escape talks/demo.tex | biber demo | lualatex --shell-escape talks/demo.tex"
+        );
+
+        let mut compile = CompileCommand::from_string("pdflatex main.tex").unwrap();
+        FileDirectives::read("% tpmgr: append ps2pdf main.ps\n", "main.tex").unwrap().apply(&mut compile);
+        assert_eq!(compile.to_string(), "pdflatex main.tex | ps2pdf main.ps");
+        assert!(FileDirectives::read("\\documentclass{article}\n", "main.tex").unwrap().is_empty());
+        assert!(FileDirectives::read("% tpmgr: before pdflatex\n", "main.tex").is_err());
+    }
+
+    #[test]
+    fn test_step_stages() {
+        let step = |tool: &str, name: Option<&str>, needs: Option<&[&str]>| CompileStep {
+            tool: tool.to_string(),
+            args: Vec::new(),
+            name: name.map(str::to_string),
+            needs: needs.map(|needs| needs.iter().map(|need| need.to_string()).collect()),
+        };
+        let mut steps = vec![
+            step("python", Some("plots"), Some(&[])),
+            step("inkscape", Some("diagrams"), Some(&[])),
+            step("pdflatex", Some("latex"), Some(&["plots", "diagrams"])),
+            step("bibtex", None, None),
+            step("pdflatex", None, None),
+        ];
+        let dependencies = step_dependencies(&steps).unwrap();
+        assert_eq!(dependencies, vec![vec![], vec![], vec![0, 1], vec![2], vec![3]]);
+        assert_eq!(step_stages(&dependencies).unwrap(), vec![vec![0, 1], vec![2], vec![3], vec![4]]);
+
+        // A chain without `needs` stays a sequence
+        let linear = CompileCommand::from_string("pdflatex main.tex | bibtex main | pdflatex main.tex").unwrap();
+        assert_eq!(step_stages(&step_dependencies(&linear.steps).unwrap()).unwrap(), vec![vec![0], vec![1], vec![2]]);
+
+        steps[0].needs = Some(vec!["latex".to_string()]);
+        assert!(step_stages(&step_dependencies(&steps).unwrap()).unwrap_err().to_string().contains("1, 3, 4, 5"));
+        steps[0].needs = Some(vec!["figures".to_string()]);
+        assert!(step_dependencies(&steps).is_err());
+    }
+
+    #[test]
+    fn test_index_steps() {
+        let parser = TeXParser::new().unwrap();
+        let single = parser.parse_content("\\makeindex").unwrap();
+        let mut compile = CompileCommand::from_string("pdflatex main.tex").unwrap();
+        assert_eq!(add_package_steps(&mut compile, &single), vec!["makeindex for the index"]);
+        assert_eq!(compile.to_string(), "pdflatex main.tex | makeindex -o main.ind main.idx | pdflatex main.tex");
+        assert!(add_package_steps(&mut compile, &single).is_empty());
+
+        // A step the user wrote already sorts the index
+        let mut manual = CompileCommand::from_string("pdflatex main.tex | makeindex main | pdflatex main.tex").unwrap();
+        assert!(add_package_steps(&mut manual, &single).is_empty());
+
+        let multiple = parser.parse_content("\\makeindex\n\\makeindex[name=persons]\n\\makeindex[name=persons]").unwrap();
+        let mut xindy = CompileCommand::from_string("lualatex -output-directory=build main.tex").unwrap();
+        xindy.index = IndexConfig {
+            tool: IndexTool::Xindy,
+            style: Some("style.xdy".to_string()),
+            args: vec!["-L".to_string(), "german".to_string()],
+        };
+        assert_eq!(add_package_steps(&mut xindy, &multiple), vec!["texindy for 2 indexes"]);
+        assert_eq!(xindy.steps.len(), 4);
+        assert_eq!(xindy.steps[1].args, vec!["-M", "style.xdy", "-L", "german", "-o", "build/main.ind", "build/main.idx"]);
+        assert_eq!(xindy.steps[2].args.last().unwrap(), "build/persons.idx");
+        assert!(xindy.steps[3].is_tex_engine());
+    }
+}
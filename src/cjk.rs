@@ -0,0 +1,98 @@
+/// What a CJK typesetting package needs besides itself
+#[derive(Debug)]
+pub struct CjkSupport {
+    pub name: &'static str,
+    /// Packages and classes that select this kind of CJK support
+    pub triggers: &'static [&'static str],
+    /// Engines the package works with, preferred first
+    pub engines: &'static [&'static str],
+    /// Packages that must be installed with any engine
+    pub packages: &'static [&'static str],
+    /// Packages that must be installed only with a given engine
+    pub engine_packages: &'static [(&'static str, &'static str)],
+    /// Font packages of which at least one must be installed
+    pub font_packages: &'static [&'static str],
+}
+
+const CJK_SUPPORT: &[CjkSupport] = &[
+    CjkSupport {
+        name: "ctex",
+        triggers: &["ctex", "ctexart", "ctexrep", "ctexbook", "ctexbeamer"],
+        engines: &["xelatex", "lualatex", "pdflatex", "uplatex"],
+        packages: &["ctex", "zhnumber"],
+        engine_packages: &[("xelatex", "xecjk"), ("lualatex", "luatexja"), ("pdflatex", "cjk")],
+        font_packages: &["fandol"],
+    },
+    CjkSupport {
+        name: "xeCJK",
+        triggers: &["xeCJK"],
+        engines: &["xelatex"],
+        packages: &["xecjk"],
+        engine_packages: &[],
+        font_packages: &["fandol"],
+    },
+    CjkSupport {
+        name: "LuaTeX-ja",
+        triggers: &[
+            "luatexja", "luatexja-fontspec", "luatexja-preset", "ltjsarticle", "ltjsbook",
+            "ltjsreport", "ltjarticle", "ltjbook", "ltjreport",
+        ],
+        engines: &["lualatex"],
+        packages: &["luatexja"],
+        engine_packages: &[],
+        font_packages: &["haranoaji", "ipaex"],
+    },
+    CjkSupport {
+        name: "CJK",
+        triggers: &["CJK", "CJKutf8"],
+        engines: &["pdflatex", "latex"],
+        packages: &["cjk"],
+        engine_packages: &[],
+        font_packages: &["arphic", "wadalab"],
+    },
+];
+
+impl CjkSupport {
+    /// Packages that must be installed to compile with `engine`; an unknown
+    /// engine is taken to be the preferred one
+    pub fn required_packages(&self, engine: Option<&str>) -> Vec<&'static str> {
+        let engine = engine.unwrap_or(self.engines[0]);
+        self.packages
+            .iter()
+            .copied()
+            .chain(self.engine_packages.iter().filter(|(name, _)| *name == engine).map(|(_, package)| *package))
+            .collect()
+    }
+}
+
+/// Get the CJK support the given packages and classes select
+pub fn detect(packages: &[String]) -> Vec<&'static CjkSupport> {
+    CJK_SUPPORT
+        .iter()
+        .filter(|support| packages.iter().any(|pkg| support.triggers.contains(&pkg.as_str())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let detected = detect(&["ctexart".to_string(), "amsmath".to_string()]);
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].name, "ctex");
+
+        assert!(detect(&["amsmath".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_required_packages_by_engine() {
+        let ctex = &detect(&["ctexart".to_string()])[0];
+        assert_eq!(ctex.required_packages(Some("xelatex")), vec!["ctex", "zhnumber", "xecjk"]);
+        assert_eq!(ctex.required_packages(Some("lualatex")), vec!["ctex", "zhnumber", "luatexja"]);
+        assert_eq!(ctex.required_packages(Some("pdflatex")), vec!["ctex", "zhnumber", "cjk"]);
+        assert_eq!(ctex.required_packages(Some("uplatex")), vec!["ctex", "zhnumber"]);
+        assert_eq!(ctex.required_packages(None), vec!["ctex", "zhnumber", "xecjk"]);
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Built-in command/environment to package table, see src/data/command_packages.toml
+const EMBEDDED_COMMAND_PACKAGES: &str = include_str!("data/command_packages.toml");
+
+/// Mapping of LaTeX commands and environments to the packages that provide them.
+///
+/// Names are stored without the leading backslash. The first package of each
+/// entry is the one to install; any of them satisfies the command.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CommandPackageMap {
+    #[serde(default)]
+    pub commands: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub environments: HashMap<String, Vec<String>>,
+}
+
+impl CommandPackageMap {
+    /// Load the mapping shipped with tpmgr
+    pub fn embedded() -> Self {
+        toml::from_str(EMBEDDED_COMMAND_PACKAGES)
+            .expect("embedded command package table is valid TOML")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty() && self.environments.is_empty()
+    }
+
+    /// Add entries from another mapping, replacing existing ones
+    pub fn extend(&mut self, other: &CommandPackageMap) {
+        for (command, packages) in &other.commands {
+            self.commands.insert(normalize_command(command).to_string(), packages.clone());
+        }
+        for (environment, packages) in &other.environments {
+            self.environments.insert(environment.clone(), packages.clone());
+        }
+    }
+
+    /// Packages providing a command (with or without leading backslash)
+    pub fn packages_for_command(&self, command: &str) -> Option<&[String]> {
+        self.commands
+            .get(normalize_command(command))
+            .map(|packages| packages.as_slice())
+            .filter(|packages| !packages.is_empty())
+    }
+
+    /// Packages providing an environment
+    pub fn packages_for_environment(&self, environment: &str) -> Option<&[String]> {
+        self.environments
+            .get(environment)
+            .map(|packages| packages.as_slice())
+            .filter(|packages| !packages.is_empty())
+    }
+}
+
+fn normalize_command(command: &str) -> &str {
+    command.strip_prefix('\\').unwrap_or(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_table() {
+        let map = CommandPackageMap::embedded();
+        assert_eq!(map.packages_for_command("SI"), Some(&["siunitx".to_string()][..]));
+        assert_eq!(map.packages_for_command("\\toprule").unwrap()[0], "booktabs");
+        assert_eq!(map.packages_for_environment("align*").unwrap()[0], "amsmath");
+        assert!(map.packages_for_command("section").is_none());
+    }
+
+    #[test]
+    fn test_extend_overrides() {
+        let mut map = CommandPackageMap::embedded();
+        let mut overrides = CommandPackageMap::default();
+        overrides.commands.insert("\\SI".to_string(), vec!["mysiunits".to_string()]);
+        overrides.environments.insert("myenv".to_string(), vec!["mypkg".to_string()]);
+        map.extend(&overrides);
+
+        assert_eq!(map.packages_for_command("SI").unwrap()[0], "mysiunits");
+        assert_eq!(map.packages_for_environment("myenv").unwrap()[0], "mypkg");
+    }
+}
@@ -1,30 +1,59 @@
 ﻿use anyhow::Result;
 use crate::config::Config;
-use crate::package::PackageManager;
+use crate::package::{InstallStep, PackageManager};
 use crate::mirror::MirrorManager;
+use crate::mirror_health::MirrorHealth;
 use crate::texlive::TeXLiveManager;
 use crate::tex_parser::TeXParser;
-use crate::{MirrorAction, ConfigAction};
-use std::path::Path;
-use glob;
+use crate::{BenchAction, BibAction, CacheAction, ImportAction, MirrorAction, ConfigAction, SynctexAction, TexliveAction};
+use std::path::{Path, PathBuf};
 
-/// Initialize global configuration if it's the first run
+/// Create a TeX parser with the command/package mappings from the global and project config
+fn new_parser() -> Result<TeXParser> {
+    let mut parser = TeXParser::new()?;
+    
+    if let Ok(global_config) = crate::config::GlobalConfig::load() {
+        parser.extend_command_packages(&global_config.command_packages);
+    }
+    // Commands that merely scan sources keep working with a broken tpmgr.toml
+    match Config::load_project() {
+        Ok(Some(config)) => parser.extend_command_packages(&config.command_packages),
+        Ok(None) => {}
+        Err(e) => println!("Warning: Ignoring the project's command mappings: {}", e),
+    }
+    
+    Ok(parser)
+}
+
+/// Initialize global configuration if it's the first run. Detections that
+/// failed are not repeated for a day (see `AutoConfigState`).
 pub async fn ensure_global_config_initialized() -> Result<()> {
-    use crate::config::GlobalConfig;
+    use crate::config::{AutoConfigState, GlobalConfig};
     
     let global_config = GlobalConfig::load()?;
     let mut needs_save = false;
     let mut updated_config = global_config.clone();
     
+    let mut state = AutoConfigState::load();
+    let original_state = state.clone();
+    let now = crate::mirror::unix_timestamp();
+    let detect_texlive = global_config.texlive_path.is_none() && AutoConfigState::should_retry(state.texlive_failed_at, now);
+    let select_mirror = global_config.mirror_url.is_none() && AutoConfigState::should_retry(state.mirror_failed_at, now);
+    
     // Only show first run message if we actually need to configure something
-    let is_first_run = global_config.texlive_path.is_none() || global_config.mirror_url.is_none();
+    let is_first_run = detect_texlive || select_mirror;
+    
+    // CI jobs get the same result on every run; leave the global config alone
+    if is_first_run && crate::ui::is_non_interactive() {
+        return Ok(());
+    }
     
     if is_first_run {
         println!("🔍 First run detected - auto-configuring global settings...");
     }
     
     // Check if TeXLive path is not set
-    if global_config.texlive_path.is_none() {
+    if detect_texlive {
         // Try to detect TeXLive installation
         let mut texlive_manager = TeXLiveManager::new();
         match texlive_manager.detect_texlive() {
@@ -33,12 +62,14 @@ pub async fn ensure_global_config_initialized() -> Result<()> {
                     let texlive_path = info.install_path.to_string_lossy().to_string();
                     updated_config.texlive_path = Some(texlive_path.clone());
                     needs_save = true;
-                    println!("✅ Detected TeXLive installation: {}", texlive_path);
+                    state.texlive_failed_at = None;
+                    println!("✅ Detected {} installation: {}", info.distribution, texlive_path);
                 } else {
                     println!("⚠️  TeXLive installation detected but path information unavailable");
                 }
             }
             Err(e) => {
+                state.texlive_failed_at = Some(now);
                 println!("⚠️  Could not detect TeXLive installation: {}", e);
                 println!("   You can manually set it later with: tpmgr config set --global texlive_path <path>");
             }
@@ -46,28 +77,43 @@ pub async fn ensure_global_config_initialized() -> Result<()> {
     }
     
     // Check if mirror URL is not set
-    if global_config.mirror_url.is_none() {
+    if select_mirror {
         println!("🌐 Auto-selecting best mirror...");
         
         let mut mirror_manager = MirrorManager::new();
+        if mirror_manager.fetch_mirrors().await.is_ok() {
+            mirror_manager.add_custom_mirrors(&global_config.custom_mirrors);
+        }
         match mirror_manager.select_best_mirror().await {
+            // Without a measured latency no mirror answered, e.g. when offline;
+            // the default mirror is used until the next attempt
+            Ok(_) if mirror_manager.selection().and_then(|selection| selection.latency_ms).is_none() => {
+                state.mirror_failed_at = Some(now);
+                println!("⚠️  No mirror could be reached, using the default for now");
+            }
             Ok(_) => {
                 if let Some(mirror) = mirror_manager.get_selected_mirror() {
-                    let mirror_url = format!("{}/systems/texlive/tlnet/", mirror.url);
-                    updated_config.mirror_url = Some(mirror_url.clone());
+                    updated_config.mirror_url = Some(mirror.tlnet_url());
+                    updated_config.mirror = mirror_manager.selection();
                     needs_save = true;
+                    state.mirror_failed_at = None;
                     println!("✅ Selected mirror: {} ({})", mirror.name, mirror.country);
                 } else {
                     println!("⚠️  Could not select best mirror, using default");
                 }
             }
             Err(e) => {
+                state.mirror_failed_at = Some(now);
                 println!("⚠️  Could not fetch mirrors: {}", e);
                 println!("   You can manually set it later with: tpmgr config set --global mirror_url <url>");
             }
         }
     }
     
+    if state != original_state {
+        state.save()?;
+    }
+    
     if needs_save {
         updated_config.save()?;
         if is_first_run {
@@ -81,33 +127,46 @@ pub async fn ensure_global_config_initialized() -> Result<()> {
     Ok(())
 }
 
+/// Point out a newer TeX Live release installed next to the configured one
+pub fn report_newer_texlive() {
+    let Some(texlive_path) = crate::config::GlobalConfig::load().ok().and_then(|config| config.texlive_path) else {
+        return;
+    };
+    if let Some((year, _)) = TeXLiveManager::newer_releases(Path::new(&texlive_path)).first() {
+        println!("ℹ️  TeX Live {} is installed next to {}; switch with: tpmgr texlive migrate", year, texlive_path);
+    }
+}
+
 pub async fn init_command(name: Option<String>) -> Result<()> {
     if let Some(project_name) = name {
         // Create new project in a subdirectory
         println!("Initializing LaTeX project: {}", project_name);
         
-        std::fs::create_dir_all(&project_name)?;
-        std::env::set_current_dir(&project_name)?;
+        let project_dir = Path::new(&project_name);
+        std::fs::create_dir_all(project_dir)?;
         
         // Create tpmgr.toml configuration file
         let global_config = crate::config::GlobalConfig::load()?;
         let mut config = Config::new();
         config.project.name = project_name.clone();
         
-        // Apply global configuration as defaults
-        if let Some(texlive_path) = &global_config.texlive_path {
-            config.project.texlive_path = Some(texlive_path.clone());
-        }
-        if let Some(mirror_url) = &global_config.mirror_url {
-            config.project.mirror_url = Some(mirror_url.clone());
+        // Apply global configuration as defaults; inside a workspace these
+        // are inherited from the workspace instead
+        if crate::config::WorkspaceConfig::find(project_dir)?.is_none() {
+            if let Some(texlive_path) = &global_config.texlive_path {
+                config.project.texlive_path = Some(texlive_path.clone());
+            }
+            if let Some(mirror_url) = &global_config.mirror_url {
+                config.project.mirror_url = Some(mirror_url.clone());
+            }
+            config.project.install_global = Some(global_config.install_global);
         }
-        config.project.install_global = Some(global_config.install_global);
         config.project.compile = global_config.compile_command.clone();
         
-        config.save("tpmgr.toml")?;
+        config.save(project_dir.join(crate::config::PROJECT_CONFIG_FILE))?;
         
         // Create basic LaTeX project structure
-        std::fs::create_dir_all("packages")?;
+        std::fs::create_dir_all(project_dir.join("packages"))?;
         
         // Create main.tex file in project root
         let main_tex = r#"\documentclass{article}
@@ -126,7 +185,7 @@ Welcome to your new LaTeX project managed by tpmgr!
 
 \end{document}
 "#;
-        std::fs::write("main.tex", main_tex)?;
+        std::fs::write(project_dir.join("main.tex"), main_tex)?;
         
         println!("✓ Project initialized successfully!");
         println!("  - Configuration: tpmgr.toml");
@@ -147,14 +206,17 @@ Welcome to your new LaTeX project managed by tpmgr!
         // Set project name to current directory name
         config.project.name = dir_name.to_string();
         
-        // Apply global configuration as defaults
-        if let Some(texlive_path) = &global_config.texlive_path {
-            config.project.texlive_path = Some(texlive_path.clone());
-        }
-        if let Some(mirror_url) = &global_config.mirror_url {
-            config.project.mirror_url = Some(mirror_url.clone());
+        // Apply global configuration as defaults; inside a workspace these
+        // are inherited from the workspace instead
+        if crate::config::WorkspaceConfig::find(Path::new("."))?.is_none() {
+            if let Some(texlive_path) = &global_config.texlive_path {
+                config.project.texlive_path = Some(texlive_path.clone());
+            }
+            if let Some(mirror_url) = &global_config.mirror_url {
+                config.project.mirror_url = Some(mirror_url.clone());
+            }
+            config.project.install_global = Some(global_config.install_global);
         }
-        config.project.install_global = Some(global_config.install_global);
         config.project.compile = global_config.compile_command.clone();
         
         // Set default compile target to main.tex
@@ -162,9 +224,10 @@ Welcome to your new LaTeX project managed by tpmgr!
             crate::config::CompileStep {
                 tool: "pdflatex".to_string(),
                 args: vec!["-interaction=nonstopmode".to_string(), "main.tex".to_string()],
+                ..Default::default()
             },
         ];
-        config.save("tpmgr.toml")?;
+        config.save_project()?;
         
         // Create packages directory if it doesn't exist
         if !std::path::Path::new("packages").exists() {
@@ -196,7 +259,7 @@ Welcome to your LaTeX project managed by tpmgr!
         }
         
         println!("✓ Project initialized successfully!");
-        println!("  - Configuration: tpmgr.toml");
+        println!("  - Configuration: {}", crate::config::project_config_path().display());
         println!("  - Main document: main.tex");
         println!("  - Package directory: packages/");
     }
@@ -208,52 +271,110 @@ pub async fn install_command(
     packages: &[String], 
     global: bool, 
     path: &str, 
-    use_compile: bool
+    use_compile: bool,
+    dry_run: bool,
+    yes: bool,
 ) -> Result<()> {
     if packages.is_empty() {
         println!("No packages specified - scanning for missing dependencies...");
-        return auto_install_missing_packages(path, use_compile).await;
+        return auto_install_missing_packages(path, use_compile, dry_run, yes).await;
+    }
+    
+    if dry_run {
+        let manager = PackageManager::read_only(global)?;
+        print_install_plan(&manager, packages).await;
+        return Ok(());
     }
     
     let manager = PackageManager::new(global)?;
-    let mut any_installed = false;
+    if !confirm_install(&manager, packages, yes).await? {
+        println!("Installation cancelled.");
+        return Ok(());
+    }
+    let mut installed = Vec::new();
+    let mut failed = Vec::new();
     
     for package_name in packages {
         println!("Installing {}...", package_name);
         match manager.install(package_name).await {
-            Ok(_) => {
+            Ok(files) => {
                 println!("✓ {} installed successfully", package_name);
-                any_installed = true;
+                report_unfindable_files(&manager, &files);
+                installed.push(package_name.clone());
             },
-            Err(e) => println!("✗ Failed to install {}: {}", package_name, e),
+            Err(e) => {
+                println!("✗ Failed to install {}: {}", package_name, e);
+                failed.push(package_name.clone());
+            }
         }
     }
     
-    // Auto-clean cache after installation
-    if any_installed {
-        println!("Auto-cleaning package cache...");
-        if let Err(e) = manager.clean_cache().await {
+    report_shadowing(&installed).await;
+    
+    // Keep downloaded archives for reuse unless the cache policy says otherwise
+    if !installed.is_empty() {
+        if let Err(e) = manager.apply_cache_policy().await {
             println!("Warning: Failed to clean cache: {}", e);
-        } else {
-            println!("✓ Package cache cleaned");
         }
     }
     
+    finish_install(&installed, &failed)
+}
+
+/// Print the install summary and, in non-interactive mode, fail when any
+/// package could not be installed
+fn finish_install(installed: &[String], failed: &[String]) -> Result<()> {
+    crate::ui::summary(serde_json::json!({
+        "command": "install",
+        "installed": installed,
+        "failed": failed,
+    }));
+    if crate::ui::is_non_interactive() && !failed.is_empty() {
+        anyhow::bail!("Failed to install {} packages: {}", failed.len(), failed.join(", "));
+    }
     Ok(())
 }
 
-pub async fn remove_command(packages: &[String], global: bool) -> Result<()> {
-    if packages.is_empty() {
-        println!("No packages specified - auto-cleaning package cache...");
-        let manager = PackageManager::new(global)?;
-        manager.clean_cache().await?;
-        println!("Cache cleaned successfully.");
-        return Ok(());
+pub async fn remove_command(packages: &[String], global: bool, unused: bool, dry_run: bool) -> Result<()> {
+    if packages.is_empty() && !unused {
+        anyhow::bail!("No packages specified; pass package names or --unused (use 'tpmgr cache clean' to empty the package cache)");
     }
     
-    let manager = PackageManager::new(global)?;
+    let manager = if dry_run { PackageManager::read_only(global)? } else { PackageManager::new(global)? };
+    let packages = if unused {
+        let project_root = current_project_root()?;
+        let dependencies = new_parser()?.parse_project(&project_root)?;
+        let used = TeXParser::get_unique_packages(&dependencies);
+        let unused_packages = manager.find_unused(&used).await?;
+        if unused_packages.is_empty() {
+            println!("All installed packages are used by the project.");
+            return Ok(());
+        }
+        println!("Packages not used by any document: {}", unused_packages.join(", "));
+        unused_packages
+    } else {
+        packages.to_vec()
+    };
     
-    for package_name in packages {
+    if dry_run {
+        println!("📋 Remove plan:");
+        for package_name in &packages {
+            match manager.plan_remove(package_name).await? {
+                Some(files) if files.is_empty() => println!("  {} (registry entry only)", package_name),
+                Some(files) => {
+                    println!("  {}", package_name);
+                    for file in files {
+                        println!("      deletes: {}", file.display());
+                    }
+                }
+                None => println!("  {}: not installed", package_name),
+            }
+        }
+        println!("No changes made (--dry-run)");
+        return Ok(());
+    }
+    
+    for package_name in &packages {
         println!("Removing {}...", package_name);
         match manager.remove(package_name).await {
             Ok(_) => println!("✓ {} removed successfully", package_name),
@@ -264,7 +385,36 @@ pub async fn remove_command(packages: &[String], global: bool) -> Result<()> {
     Ok(())
 }
 
-pub async fn update_command(packages: &[String]) -> Result<()> {
+pub async fn update_command(packages: &[String], dry_run: bool) -> Result<()> {
+    if dry_run {
+        let manager = PackageManager::read_only(false)?;
+        let packages = if packages.is_empty() {
+            let mut installed: Vec<String> = manager.list_installed().await?.into_iter().map(|(name, _)| name).collect();
+            installed.sort();
+            installed
+        } else {
+            packages.to_vec()
+        };
+        
+        println!("📋 Update plan:");
+        let mut download_size = 0;
+        for package_name in &packages {
+            match manager.plan_update(package_name).await {
+                Ok((current, latest)) if current == latest.version => {
+                    println!("  {} {}: up to date", package_name, current);
+                }
+                Ok((current, latest)) => {
+                    println!("  {} {} → {} [{}]{}", package_name, current, latest.version, latest.repository, format_download_size(latest.size));
+                    download_size += latest.size;
+                }
+                Err(e) => println!("  ✗ {}: {}", package_name, e),
+            }
+        }
+        println!("Total download: {}", crate::cache::format_size(download_size));
+        println!("No changes made (--dry-run)");
+        return Ok(());
+    }
+    
     let manager = PackageManager::new(false)?;
     
     if packages.is_empty() {
@@ -283,34 +433,180 @@ pub async fn update_command(packages: &[String]) -> Result<()> {
     Ok(())
 }
 
-pub async fn list_command(global: bool) -> Result<()> {
+pub async fn list_command(global: bool, shadowed: bool, pattern: Option<&str>, tree: bool, outdated: bool, sort: &str) -> Result<()> {
+    if shadowed {
+        return list_shadowed_packages().await;
+    }
+    
+    let pattern = pattern
+        .map(glob::Pattern::new)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid package pattern: {}", e))?;
     let manager = PackageManager::new(global)?;
-    let packages = manager.list_installed().await?;
+    let installed = manager.installed_packages().await?;
+    let mut packages: Vec<_> = installed
+        .iter()
+        .filter(|package| pattern.as_ref().is_none_or(|pattern| pattern.matches(&package.name)))
+        .cloned()
+        .collect();
+    crate::package::sort_installed(&mut packages, sort);
     
     if packages.is_empty() {
-        println!("No packages installed.");
-    } else {
-        println!("Installed packages:");
-        for (name, version) in packages {
-            println!("  {} ({})", name, version);
+        match &pattern {
+            Some(pattern) => println!("No installed packages match '{}'.", pattern),
+            None => println!("No packages installed."),
+        }
+        return Ok(());
+    }
+    
+    if outdated {
+        return list_outdated_packages(&manager, &packages).await;
+    }
+    
+    if tree {
+        println!("Installed packages and the packages they load:");
+        for line in crate::package::dependency_tree(&packages, &installed) {
+            println!("  {}", line);
+        }
+        return Ok(());
+    }
+    
+    println!("Installed packages:");
+    let width = packages.iter().map(|package| package.name.len() + package.version.len()).max().unwrap_or(0) + 3;
+    for package in &packages {
+        let label = format!("{} ({})", package.name, package.version);
+        println!("  {:<width$} {:>9}", label, crate::cache::format_size(package.size), width = width);
+    }
+    let total: u64 = packages.iter().map(|package| package.size).sum();
+    println!("Total: {} packages, {}", packages.len(), crate::cache::format_size(total));
+    
+    Ok(())
+}
+
+async fn list_outdated_packages(manager: &PackageManager, packages: &[crate::package::InstalledPackage]) -> Result<()> {
+    let mut outdated = 0;
+    for package in packages {
+        match manager.plan_update(&package.name).await {
+            Ok((current, latest)) if current != latest.version => {
+                if outdated == 0 {
+                    println!("Packages with newer versions:");
+                }
+                outdated += 1;
+                println!("  {} {} → {} ({})", package.name, current, latest.version, latest.repository);
+            }
+            Ok(_) => {}
+            Err(e) => println!("⚠️  Could not check {}: {}", package.name, e),
         }
     }
     
+    if outdated == 0 {
+        println!("✅ All listed packages are up to date.");
+    } else {
+        println!("Run 'tpmgr update' to upgrade them.");
+    }
+    Ok(())
+}
+
+async fn list_shadowed_packages() -> Result<()> {
+    let shadowed = find_shadowed_packages().await?;
+    if shadowed.is_empty() {
+        println!("No package is installed in more than one place.");
+        return Ok(());
+    }
+    
+    println!("Packages installed in more than one place (the first copy is used):");
+    for shadowing in &shadowed {
+        let skew = if shadowing.has_version_skew() { "  ⚠️  versions differ" } else { "" };
+        println!("  {}: {}{}", shadowing.name, shadowing.describe(), skew);
+    }
+    
     Ok(())
 }
 
-pub async fn search_command(query: &str) -> Result<()> {
+/// Packages installed in more than one of the project, the global TEXMF tree
+/// and the configured TeX Live
+async fn find_shadowed_packages() -> Result<Vec<crate::shadowing::Shadowing>> {
+    let project = PackageManager::read_only(false)?.list_installed().await?;
+    let global = PackageManager::read_only(true)?.list_installed().await?;
+    let texlive = configured_texlive();
+    Ok(crate::shadowing::find_shadowed(&project, &global, |name| {
+        texlive
+            .as_ref()
+            .and_then(|texlive| texlive.get_installed_package(name))
+            .map(|package| package.version.clone())
+    }))
+}
+
+/// The configured TeX Live with its package database loaded, if there is one
+fn configured_texlive() -> Option<TeXLiveManager> {
+    let texlive_path = Config::load_project_or_default().ok()?.resolved_texlive_path()?;
+    let mut texlive = TeXLiveManager::at(&texlive_path).ok()?;
+    texlive.read_package_database().ok()?.then_some(texlive)
+}
+
+/// Warn when newly installed packages are also installed elsewhere, naming
+/// the copy TeX will load
+async fn report_shadowing(installed: &[String]) {
+    if installed.is_empty() {
+        return;
+    }
+    let Ok(shadowed) = find_shadowed_packages().await else {
+        return;
+    };
+    for shadowing in shadowed.iter().filter(|shadowing| installed.contains(&shadowing.name)) {
+        println!("⚠️  {} is installed in several places; TeX loads the {} copy ({})",
+            shadowing.name, shadowing.winner().0, shadowing.describe());
+    }
+}
+
+/// Search the repositories, best matches first; `limit` caps the number of
+/// results shown (None shows all)
+pub async fn search_command(query: &str, regex: bool, limit: Option<usize>, installed_only: bool) -> Result<()> {
+    let search_query = crate::query::SearchQuery::parse(query, regex)?;
     let manager = PackageManager::new(false)?;
-    let results = manager.search(query).await?;
+    let results = manager.search(&search_query).await?;
+    
+    let project: std::collections::HashSet<String> = manager.list_installed().await?.into_iter().map(|(name, _)| name).collect();
+    let global: std::collections::HashSet<String> = PackageManager::read_only(true)?.list_installed().await?.into_iter().map(|(name, _)| name).collect();
+    // Only scan for .sty/.cls files inside a project, not wherever search runs
+    let local: std::collections::HashSet<String> = match crate::config::project_root() {
+        Some(root) => TeXParser::find_local_packages(&root)?.into_keys().collect(),
+        None => Default::default(),
+    };
+    let texlive = configured_texlive();
+    let status_of = |name: &str| {
+        crate::package::InstallStatus::of(name, &project, &local, |name| {
+            global.contains(name) || texlive.as_ref().is_some_and(|texlive| texlive.is_package_installed(name))
+        })
+    };
+    let results: Vec<_> = results
+        .into_iter()
+        .map(|package| (status_of(&package.name), package))
+        .filter(|(status, _)| !installed_only || *status != crate::package::InstallStatus::NotInstalled)
+        .collect();
     
     if results.is_empty() {
-        println!("No packages found matching '{}'", query);
-    } else {
-        println!("Search results for '{}':", query);
-        for package in results {
-            println!("  {} - {}", package.name, package.description);
+        if installed_only {
+            println!("No installed packages found matching '{}'", query);
+        } else {
+            println!("No packages found matching '{}'", query);
+        }
+        return Ok(());
+    }
+    
+    let total = results.len();
+    let shown = limit.unwrap_or(total).min(total);
+    println!("Search results for '{}':", query);
+    for (status, package) in results.into_iter().take(shown) {
+        if status == crate::package::InstallStatus::NotInstalled {
+            println!("    {} - {} [{}]", package.name, package.description, package.repository);
+        } else {
+            println!("  ✓ {} - {} [{}] ({})", package.name, package.description, package.repository, status.label());
         }
     }
+    if shown < total {
+        println!("Showing {} of {} results; use --limit <N> or --all to see more", shown, total);
+    }
     
     Ok(())
 }
@@ -323,21 +619,296 @@ pub async fn info_command(package_name: &str) -> Result<()> {
     println!("Version: {}", info.version);
     println!("Description: {}", info.description);
     println!("Dependencies: {:?}", info.dependencies);
+    println!("Repository: {}", info.repository);
+    println!("Download: {}", info.download_url);
+    
+    Ok(())
+}
+
+pub async fn publish_command(repository: &str, path: &str, name: Option<&str>, version: Option<&str>) -> Result<()> {
+    let repository_path = Path::new(repository);
+    std::fs::create_dir_all(repository_path)?;
+    
+    let entry = crate::publish::publish(Path::new(path), repository_path, name, version)?;
+    
+    println!("✓ Published {} {} to {}", entry.name, entry.version, repository_path.display());
+    println!("  Archive: {} ({} bytes)", entry.archive, entry.size);
+    if !entry.dependencies.is_empty() {
+        println!("  Dependencies: {}", entry.dependencies.join(", "));
+    }
+    println!("\n💡 Use the repository in tpmgr.toml:");
+    println!("   [[repositories]]");
+    println!("   name = \"internal\"");
+    println!("   url = \"{}\"", repository_path.canonicalize()?.display());
+    println!("   priority = 0");
+    
+    Ok(())
+}
+
+pub async fn cache_command(action: &CacheAction) -> Result<()> {
+    match action {
+        CacheAction::Info { global } => {
+            let manager = PackageManager::new(*global)?;
+            let (cache_dir, files, bytes) = manager.cache_usage()?;
+            let policy = if *global {
+                crate::config::GlobalConfig::load()?.cache
+            } else {
+                Config::load_project_or_default()?.cache_config()
+            };
+            println!("Package cache: {}", cache_dir.display());
+            println!("  Archives: {} ({})", files, crate::cache::format_size(bytes));
+            println!("  keep: {}", policy.keep);
+            println!("  max_size_mb: {}", policy.max_size_mb.map(|size| size.to_string()).unwrap_or_else(|| "<not set>".to_string()));
+            println!("  max_age_days: {}", policy.max_age_days.map(|days| days.to_string()).unwrap_or_else(|| "<not set>".to_string()));
+        }
+        CacheAction::Clean { global } => {
+            let manager = PackageManager::new(*global)?;
+            let (_, files, bytes) = manager.cache_usage()?;
+            manager.clean_cache().await?;
+            println!("✓ Removed {} archives from the package cache ({})", files, crate::cache::format_size(bytes));
+        }
+        CacheAction::Gc { global } => {
+            let manager = PackageManager::new(*global)?;
+            let report = manager.collect_cache_garbage().await?;
+            println!("✓ Removed {} old archives from the package cache ({})",
+                report.removed_files, crate::cache::format_size(report.freed_bytes));
+        }
+    }
+    
+    Ok(())
+}
+
+pub async fn texlive_command(action: &TexliveAction) -> Result<()> {
+    match action {
+        TexliveAction::Migrate { to, dry_run } => migrate_texlive(to.as_deref(), *dry_run).await,
+    }
+}
+
+/// Point the global config, and a project pinned to the same release, at a
+/// newer TeX Live, and report installed packages the new release ships
+async fn migrate_texlive(to: Option<&Path>, dry_run: bool) -> Result<()> {
+    let mut global_config = crate::config::GlobalConfig::load()?;
+    let current = global_config
+        .texlive_path
+        .clone()
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!("No TeX Live configured; set it with: tpmgr config set --global texlive_path <path>"))?;
+    let target = match to {
+        Some(path) => path.to_path_buf(),
+        None => TeXLiveManager::newer_releases(&current)
+            .into_iter()
+            .next()
+            .map(|(_, path)| path)
+            .ok_or_else(|| anyhow::anyhow!("No newer TeX Live release found next to {}", current.display()))?,
+    };
+    if !target.join("texmf-dist").is_dir() {
+        anyhow::bail!("{} is not a TeX Live installation", target.display());
+    }
+    if target == current {
+        println!("Already using {}", current.display());
+        return Ok(());
+    }
+    let target_path = target.to_string_lossy().to_string();
+    
+    println!("🔀 Migrating from {} to {}", current.display(), target.display());
+    let mut texlive = TeXLiveManager::at(&target)?;
+    texlive.scan_installed_packages()?;
+    
+    let global_packages = PackageManager::read_only(true)?.list_installed().await?;
+    report_packages_in_texlive(&global_packages, &texlive, "tpmgr remove --global");
+    
+    if let Some(mut project) = Config::load_project_file()? {
+        let project_packages = PackageManager::read_only(false)?.list_installed().await?;
+        report_packages_in_texlive(&project_packages, &texlive, "tpmgr remove");
+        
+        if project.project.texlive_path.as_deref().map(Path::new) == Some(current.as_path()) {
+            project.project.texlive_path = Some(target_path.clone());
+            if dry_run {
+                println!("Project texlive_path would be set to {}", target_path);
+            } else {
+                project.save_project()?;
+                println!("✓ Project texlive_path set to {}", target_path);
+            }
+        }
+    }
+    
+    global_config.texlive_path = Some(target_path.clone());
+    if dry_run {
+        println!("Global texlive_path would be set to {}", target_path);
+        println!("No changes made (--dry-run)");
+        return Ok(());
+    }
+    global_config.save()?;
+    println!("✅ Global texlive_path set to {}", target_path);
     
     Ok(())
 }
 
+/// Let kpsewhich confirm the database's view of `packages`, warning about
+/// files the database lists but kpsewhich cannot find
+fn check_with_kpsewhich(texlive: &mut TeXLiveManager, packages: &[String]) {
+    for file in texlive.check_with_kpsewhich(packages) {
+        println!("⚠️  The TeX Live database lists {}, but kpsewhich cannot find it; run 'mktexlsr' if the filename database is stale", file.display());
+    }
+}
+
+/// Release, revision and date of a package index, for `mirror check`
+fn describe_snapshot(snapshot: &crate::mirror_health::IndexSnapshot) -> String {
+    let updated = snapshot
+        .updated_at
+        .map(|updated| format!(", updated {}", crate::release::date_from_timestamp(updated)))
+        .unwrap_or_default();
+    format!("TeX Live {} at revision {}{}", snapshot.release, snapshot.revision, updated)
+}
+
+/// Mirrors checked by `mirror check` before giving up on failing over
+const MAX_FAILOVER_ATTEMPTS: usize = 5;
+
+/// Select and save the first mirror other than `current_url` whose package
+/// index is fresh
+async fn fail_over(
+    mirror_manager: &mut MirrorManager,
+    global_config: &mut crate::config::GlobalConfig,
+    client: &reqwest::Client,
+    current_url: &str,
+    main: Option<&crate::mirror_health::IndexSnapshot>,
+) -> Result<()> {
+    let candidates = mirror_manager.failover_candidates(current_url);
+    let tried = candidates.len().min(MAX_FAILOVER_ATTEMPTS);
+    for mirror in candidates.into_iter().take(MAX_FAILOVER_ATTEMPTS) {
+        let health = match crate::mirror_health::fetch_snapshot(client, &mirror.tlnet_url()).await {
+            Ok(snapshot) => crate::mirror_health::assess(&snapshot, main).0,
+            Err(_) => MirrorHealth::Broken,
+        };
+        println!("  {} ({}) - {}", mirror.name, mirror.country, health.label());
+        if !matches!(health, MirrorHealth::Fresh | MirrorHealth::Unverified) {
+            continue;
+        }
+        
+        mirror_manager.select_mirror_by_name(&mirror.name)?;
+        global_config.mirror_url = Some(mirror.tlnet_url());
+        global_config.mirror = mirror_manager.selection();
+        global_config.save()?;
+        println!("💾 Saved mirror to global configuration: {}", mirror.tlnet_url());
+        return Ok(());
+    }
+    anyhow::bail!("None of the {} mirrors tried serves a fresh package index; try again later or run 'tpmgr mirror test'", tried)
+}
+
+/// List installed packages that a TeX Live release provides itself
+fn report_packages_in_texlive(packages: &[(String, String)], texlive: &TeXLiveManager, remove_command: &str) {
+    let provided: Vec<&str> = packages
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| texlive.is_package_installed(name))
+        .collect();
+    if provided.is_empty() {
+        return;
+    }
+    
+    println!("📦 Now included in TeX Live {}:", texlive.get_texlive_info().map(|info| info.version.as_str()).unwrap_or("Unknown"));
+    for package in &provided {
+        println!("  - {}", package);
+    }
+    println!("   Remove the copies with: {} {}", remove_command, provided.join(" "));
+}
+
 pub async fn mirror_command(action: &MirrorAction) -> Result<()> {
     let mut mirror_manager = MirrorManager::new();
+    let mut global_config = crate::config::GlobalConfig::load()?;
     
     match action {
-        MirrorAction::List => {
+        MirrorAction::Current => {
+            let (mirror_url, source) = Config::load_project_or_default()?.resolved_mirror();
+            let source = match source {
+                "project" => "project or workspace mirror_url",
+                "global" => "global mirror_url",
+                _ => "CTAN redirector; no mirror_url is set",
+            };
+            println!("Mirror: {}", mirror_url);
+            println!("  from: {}", source);
+        }
+        MirrorAction::Check { failover } => {
+            let (mirror_url, source) = Config::load_project_or_default()?.resolved_mirror();
+            let client = crate::download::http_client()?;
+            println!("🔍 Checking {}", mirror_url);
+            let main = crate::mirror_health::fetch_snapshot(&client, crate::mirror_health::CTAN_MAIN_TLNET_URL).await.ok();
+            if let Some(main) = &main {
+                println!("   CTAN main: {}", describe_snapshot(main));
+            }
+            
+            let (health, detail) = match crate::mirror_health::fetch_snapshot(&client, &mirror_url).await {
+                Ok(snapshot) => {
+                    println!("   Mirror:    {}", describe_snapshot(&snapshot));
+                    crate::mirror_health::assess(&snapshot, main.as_ref())
+                }
+                Err(e) => (MirrorHealth::Broken, e.to_string()),
+            };
+            match health {
+                MirrorHealth::Fresh => println!("✅ The mirror is fresh: {}", detail),
+                MirrorHealth::Unverified => println!("⚠️  The mirror answers, but {}", detail),
+                MirrorHealth::Stale => println!("⚠️  The mirror is stale: {}", detail),
+                MirrorHealth::Broken => println!("❌ The mirror is broken: {}", detail),
+            }
+            crate::ui::summary(serde_json::json!({
+                "command": "mirror check",
+                "mirror": mirror_url,
+                "health": health,
+                "detail": detail,
+            }));
+            if matches!(health, MirrorHealth::Fresh | MirrorHealth::Unverified) {
+                return Ok(());
+            }
+            
+            if source == "project" {
+                println!("   The mirror is set by the project's mirror_url; change it there or remove it to use the global mirror");
+            } else if *failover || (!crate::ui::is_non_interactive() && ask_yes_no("Switch to another mirror?", true)?) {
+                if let Err(e) = mirror_manager.fetch_mirrors().await {
+                    println!("Warning: Failed to fetch mirrors: {}", e);
+                }
+                mirror_manager.add_custom_mirrors(&global_config.custom_mirrors);
+                return fail_over(&mut mirror_manager, &mut global_config, &client, &mirror_url, main.as_ref()).await;
+            } else {
+                println!("   Switch with: tpmgr mirror check --failover");
+            }
+            if crate::ui::is_non_interactive() {
+                anyhow::bail!("The mirror {} is {}", mirror_url, health.label());
+            }
+        }
+        MirrorAction::List { continent, country, protocol, json } => {
+            if *json {
+                crate::ui::set_json_output(true);
+            }
             // Automatically update mirror list
             if let Err(e) = mirror_manager.fetch_mirrors().await {
                 println!("Warning: Failed to fetch mirrors: {}", e);
                 return Ok(());
             }
-            mirror_manager.list_mirrors();
+            mirror_manager.add_custom_mirrors(&global_config.custom_mirrors);
+            if let Some(selection) = &global_config.mirror {
+                mirror_manager.restore_selection(selection);
+            }
+            
+            let filter = crate::mirror::MirrorFilter {
+                continent: continent.clone(),
+                country: country.clone(),
+                protocol: protocol.clone(),
+            };
+            let entries = mirror_manager.list_mirrors(&filter, &MirrorManager::load_benchmark());
+            if *json {
+                crate::ui::print_json(&entries);
+            } else if entries.is_empty() {
+                println!("No mirrors match the given filters");
+            } else {
+                crate::mirror::print_mirror_list(&entries);
+                if entries.iter().all(|entry| entry.tested_at.is_none()) {
+                    println!("\n💡 Measure latencies with: tpmgr mirror test");
+                }
+            }
+        }
+        MirrorAction::Update => {
+            mirror_manager.update_mirrors().await?;
+            println!("✓ Mirror list updated");
         }
         MirrorAction::Use { name, auto } => {
             // Automatically update mirror list
@@ -345,6 +916,7 @@ pub async fn mirror_command(action: &MirrorAction) -> Result<()> {
                 println!("Warning: Failed to fetch mirrors: {}", e);
                 return Ok(());
             }
+            mirror_manager.add_custom_mirrors(&global_config.custom_mirrors);
             
             if *auto {
                 mirror_manager.select_best_mirror().await?;
@@ -356,39 +928,108 @@ pub async fn mirror_command(action: &MirrorAction) -> Result<()> {
                 println!("Error: Please specify a mirror name or use --auto");
                 return Ok(());
             }
+            
+            if let Some(mirror) = mirror_manager.get_selected_mirror() {
+                if crate::download::is_rsync_url(&mirror.url) && !crate::download::rsync_available() {
+                    println!("⚠️  {} is only reachable over rsync, but rsync was not found on PATH", mirror.name);
+                }
+                global_config.mirror_url = Some(mirror.tlnet_url());
+                global_config.mirror = mirror_manager.selection();
+                global_config.save()?;
+                println!("💾 Saved mirror to global configuration: {}", mirror.tlnet_url());
+                if !mirror.has_standard_archives() {
+                    println!("   Archives: {}", mirror.archive_template());
+                }
+            }
+        }
+        MirrorAction::Test { filter, sort } => {
+            if let Err(e) = mirror_manager.fetch_mirrors().await {
+                println!("Warning: Failed to fetch mirrors: {}", e);
+                return Ok(());
+            }
+            mirror_manager.add_custom_mirrors(&global_config.custom_mirrors);
+            
+            println!("Benchmarking mirrors...");
+            let mut results = mirror_manager.benchmark(filter.as_deref()).await;
+            if results.is_empty() {
+                anyhow::bail!("No mirrors match '{}'", filter.as_deref().unwrap_or_default());
+            }
+            
+            crate::mirror::sort_results(&mut results, sort);
+            crate::mirror::print_benchmark(&results);
+            MirrorManager::save_benchmark(&results)?;
+            
+            match crate::mirror::best_result(&results) {
+                Some(best) => {
+                    println!("\n✅ Fastest mirror: {} ({})", best.name, best.country);
+                    println!("   Select it with: tpmgr mirror use --auto");
+                }
+                None => println!("\n⚠️  No mirror could be reached"),
+            }
+        }
+        MirrorAction::Add { name, url, index_template, archive_template } => {
+            let is_builtin = !global_config.custom_mirrors.iter().any(|m| &m.name == name)
+                && mirror_manager.fetch_mirrors().await.is_ok()
+                && mirror_manager.has_mirror(name);
+            if is_builtin {
+                anyhow::bail!("'{}' is a built-in mirror; choose another name", name);
+            }
+            
+            let replaced = global_config.add_custom_mirror(name, url, index_template.as_deref(), archive_template.as_deref())?;
+            global_config.save()?;
+            if replaced {
+                println!("✓ Updated custom mirror: {}", name);
+            } else {
+                println!("✓ Added custom mirror: {}", name);
+            }
+            println!("   Select it with: tpmgr mirror use \"{}\"", name);
+        }
+        MirrorAction::Remove { name } => {
+            if !global_config.remove_custom_mirror(name) {
+                anyhow::bail!("Custom mirror '{}' not found", name);
+            }
+            
+            if global_config.mirror.as_ref().is_some_and(|mirror| &mirror.name == name) {
+                global_config.mirror = None;
+                global_config.mirror_url = None;
+                println!("⚠️  '{}' was the selected mirror; a new one will be selected automatically", name);
+            }
+            global_config.save()?;
+            println!("✓ Removed custom mirror: {}", name);
         }
     }
     
     Ok(())
 }
 
-pub async fn analyze_command(path: &str, verbose: bool, use_compile: bool) -> Result<()> {
-    let parser = TeXParser::new()?;
-    let path = Path::new(path);
+pub async fn analyze_command(path: &str, verbose: bool, use_compile: bool, respect_includeonly: bool, suggest: bool) -> Result<()> {
+    let path = resolve_project_path(path)?;
+    let path = path.as_path();
+    // A single file is parsed without following its \include commands, so there is nothing to filter
+    if respect_includeonly && path.is_file() {
+        anyhow::bail!("--respect-includeonly needs a project directory, but {} is a single file", path.display());
+    }
+    let parser = new_parser()?;
     
     println!("Analyzing TeX dependencies in: {}", path.display());
     
     if use_compile {
         // Read compile command from configuration
-        let config = if Path::new("tpmgr.toml").exists() {
-            Config::load("tpmgr.toml")?
-        } else {
-            Config::new()
-        };
+        let config = Config::load_project_or_default()?;
         
         let compile_cmd = &config.project.compile;
-        let project_root = std::env::current_dir()?;
+        let project_root = current_project_root()?;
         
         let missing_packages = if path.is_file() {
             parser.detect_missing_packages_by_compilation(path, compile_cmd, &project_root)?
         } else {
-            let resolved_commands = compile_cmd.resolve_variables(&project_root)?;
+            let resolved_commands = compile_cmd.resolve_variables(&config.chain_variables(&project_root))?;
             if !resolved_commands.is_empty() {
                 let last_command = resolved_commands.last().unwrap();
                 if let Some(potential_target) = last_command.last() {
-                    let target_path = Path::new(potential_target);
+                    let target_path = &project_root.join(potential_target);
                     if target_path.exists() {
-                        parser.detect_missing_packages_by_compilation(&target_path, compile_cmd, &project_root)?
+                        parser.detect_missing_packages_by_compilation(target_path, compile_cmd, &project_root)?
                     } else {
                         println!("Target file specified in compile command not found: {}", potential_target);
                         let mut result_packages = Vec::new();
@@ -396,28 +1037,24 @@ pub async fn analyze_command(path: &str, verbose: bool, use_compile: bool) -> Re
                         
                         let src_dir = path.join("src");
                         if src_dir.exists() {
-                            for entry in std::fs::read_dir(&src_dir)? {
-                                if let Ok(entry) = entry {
-                                    if let Some(ext) = entry.path().extension() {
-                                        if ext == "tex" {
-                                            result_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
-                                            found_tex = true;
-                                            break;
-                                        }
+                            for entry in std::fs::read_dir(&src_dir)?.flatten() {
+                                if let Some(ext) = entry.path().extension() {
+                                    if ext == "tex" {
+                                        result_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
+                                        found_tex = true;
+                                        break;
                                     }
                                 }
                             }
                         }
                         
                         if !found_tex {
-                            for entry in std::fs::read_dir(path)? {
-                                if let Ok(entry) = entry {
-                                    if let Some(ext) = entry.path().extension() {
-                                        if ext == "tex" {
-                                            result_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
-                                            found_tex = true;
-                                            break;
-                                        }
+                            for entry in std::fs::read_dir(path)?.flatten() {
+                                if let Some(ext) = entry.path().extension() {
+                                    if ext == "tex" {
+                                        result_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
+                                        found_tex = true;
+                                        break;
                                     }
                                 }
                             }
@@ -450,27 +1087,69 @@ pub async fn analyze_command(path: &str, verbose: bool, use_compile: bool) -> Re
         
         if config.project.compile.auto_clean {
             println!("🧹 Cleaning intermediate files...");
-            let project_root = std::env::current_dir()?;
-            clean_intermediate_files(&project_root)?;
+            let project_root = current_project_root()?;
+            clean_intermediate_files(&project_root, &config)?;
         }
         
-        return Ok(());
+        return finish_analyze(&missing_packages);
     }
     
     // Use regex parsing
-    let dependencies = if path.is_file() {
+    let project_dir = if path.is_file() {
+        path.parent().unwrap_or(Path::new("."))
+    } else {
+        path
+    };
+    let mut dependencies = if path.is_file() {
         parser.parse_file(path)?
     } else {
         parser.parse_project(path)?
     };
     
+    // Packages provided by .sty/.cls files in the project need no installation,
+    // but their own requirements do
+    let mut local_packages = TeXParser::find_local_packages(project_dir)?;
+    parser.add_local_package_dependencies(&mut dependencies, &mut local_packages);
+    
+    let dependencies = if respect_includeonly {
+        let active = TeXParser::filter_included_only(&dependencies, path);
+        if active.len() < dependencies.len() {
+            println!("Honoring \\includeonly: skipped {} dependencies from excluded chapters", dependencies.len() - active.len());
+        }
+        active
+    } else {
+        dependencies
+    };
+    
     if verbose {
         TeXParser::print_dependency_analysis(&dependencies);
+        
+        let child_documents = TeXParser::find_child_documents(&dependencies, None);
+        if !child_documents.is_empty() {
+            println!("\nChild documents (compile individually with 'tpmgr compile -p <file>'):");
+            for child in child_documents {
+                println!("  - {}", child.display());
+            }
+        }
     }
     
     let packages = TeXParser::get_unique_packages(&dependencies);
-    let filtered_packages = TeXParser::filter_core_packages(&packages);
+    let (local_provided, filtered_packages): (Vec<String>, Vec<String>) = TeXParser::filter_core_packages(&packages)
+        .into_iter()
+        .partition(|package| local_packages.contains_key(package));
+    
+    if !local_provided.is_empty() {
+        println!("\nProvided by project files:");
+        for package in &local_provided {
+            println!("  ✓ {} ({})", package, local_packages[package].display());
+            let required = TeXParser::packages_required_by(&dependencies, &local_packages[package]);
+            if !required.is_empty() {
+                println!("      requires: {}", required.join(", "));
+            }
+        }
+    }
     
+    let mut missing_packages = Vec::new();
     if !filtered_packages.is_empty() {
         println!("\nRequired packages:");
         for package in &filtered_packages {
@@ -481,11 +1160,11 @@ pub async fn analyze_command(path: &str, verbose: bool, use_compile: bool) -> Re
         let texlive_available = texlive.detect_texlive().is_ok();
         if texlive_available {
             texlive.scan_installed_packages()?;
+            check_with_kpsewhich(&mut texlive, &filtered_packages);
         }
         
         let local_manager = PackageManager::new(false)?;
         
-        let mut missing_packages = Vec::new();
         let mut installed_packages = Vec::new();
         
         for package in &filtered_packages {
@@ -506,7 +1185,7 @@ pub async fn analyze_command(path: &str, verbose: bool, use_compile: bool) -> Re
             if is_available {
                 installed_packages.push(package);
             } else {
-                missing_packages.push(package);
+                missing_packages.push(package.clone());
             }
         }
         
@@ -519,63 +1198,205 @@ pub async fn analyze_command(path: &str, verbose: bool, use_compile: bool) -> Re
         
         if !missing_packages.is_empty() {
             println!("\nMissing packages:");
-            for package in missing_packages {
+            for package in &missing_packages {
                 println!("  ✗ {}", package);
             }
             println!("\nRun 'tpmgr install' to install missing packages");
         } else {
             println!("\n✓ All required packages are already installed!");
         }
+        
+        let config = Config::load_project_or_default()?;
+        report_cjk_support(&packages, &config.project.compile, texlive_available.then_some(&texlive));
     } else {
         println!("No external packages required.");
     }
     
+    if !filtered_packages.is_empty() {
+        let config = Config::load_project_or_default()?;
+        report_external_tool_requirements(&filtered_packages, &config.project.compile);
+    }
+    
+    report_missing_figures(&dependencies, project_dir);
+    report_option_clashes(&dependencies, project_dir);
+    report_load_order(&dependencies, project_dir, Config::load_project_or_default()?.project.compile.tex_engine().as_deref());
+    
+    if suggest {
+        report_package_suggestions(&parser, path, &packages)?;
+    }
+    
     // Clean intermediate files if using compilation analysis
     if use_compile {
-        if let Ok(config) = Config::load("tpmgr.toml") {
+        if let Ok(Some(config)) = Config::load_project() {
             if config.project.compile.auto_clean {
                 println!("🧹 Cleaning intermediate files...");
-                let project_root = std::env::current_dir()?;
-                clean_intermediate_files(&project_root)?;
+                let project_root = current_project_root()?;
+                clean_intermediate_files(&project_root, &config)?;
             }
         }
     }
     
+    finish_analyze(&missing_packages)
+}
+
+/// Analyze an unsaved editor buffer read from stdin and print JSON
+/// diagnostics for the packages it loads that are not installed. `filename`
+/// locates the project; nothing is written to disk.
+pub async fn analyze_stdin_command(filename: &str) -> Result<()> {
+    crate::ui::set_json_output(true);
+    let mut content = Vec::new();
+    std::io::Read::read_to_end(&mut std::io::stdin(), &mut content)?;
+    let content = String::from_utf8_lossy(&content);
+    
+    let analyzer = crate::diagnostics::BufferAnalyzer::new(new_parser()?);
+    let report = analyzer.analyze(Path::new(filename), &content).await?;
+    crate::ui::print_json(&report);
     Ok(())
 }
 
-async fn auto_install_missing_packages(path: &str, use_compile: bool) -> Result<()> {
-    let parser = TeXParser::new()?;
-    let path = Path::new(path);
-    
-    println!("Auto-installing packages for: {}", path.display());
-    
-    let mut missing_packages = Vec::new();
-    
-    // If compile detection is enabled, use compile error detection
+/// Answer JSON-RPC requests from editor plugins on stdio, or on a Unix
+/// socket, until the client sends "shutdown"
+pub async fn serve_command(socket: Option<&str>) -> Result<()> {
+    crate::ui::set_json_output(true);
+    let mut server = crate::server::Server::new(crate::diagnostics::BufferAnalyzer::new(new_parser()?));
+    match socket {
+        Some(socket) => crate::server::serve_socket(&mut server, Path::new(socket)).await,
+        None => {
+            let reader = tokio::io::BufReader::new(tokio::io::stdin());
+            crate::server::serve_stream(&mut server, reader, tokio::io::stdout()).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Print the analyze summary and, in non-interactive mode, fail when packages are missing
+fn finish_analyze(missing_packages: &[String]) -> Result<()> {
+    crate::ui::summary(serde_json::json!({
+        "command": "analyze",
+        "missing": missing_packages,
+    }));
+    if crate::ui::is_non_interactive() && !missing_packages.is_empty() {
+        anyhow::bail!("{} packages are missing: {}", missing_packages.len(), missing_packages.join(", "));
+    }
+    Ok(())
+}
+
+/// Watch the project and install packages as soon as a saved file loads
+/// one that is not available; stops on Ctrl+C
+pub async fn daemon_command(path: &str, interval_ms: u64, notify: bool) -> Result<()> {
+    use crate::watch::{IncrementalAnalysis, Snapshot};
+    
+    let project_root = resolve_project_path(path)?;
+    let parser = new_parser()?;
+    let manager = PackageManager::new(false)?;
+    let mut texlive = TeXLiveManager::new();
+    let texlive_available = texlive.detect_texlive().is_ok() && texlive.scan_installed_packages().is_ok();
+    
+    println!("👀 Watching {} for new packages (Ctrl+C to stop)...", project_root.display());
+    let mut analysis = IncrementalAnalysis::default();
+    let mut previous = Snapshot::new();
+    // Packages already tried, retried only once they are dropped from the sources and used again
+    let mut attempted = std::collections::HashSet::new();
+    loop {
+        let current = crate::watch::snapshot(&project_root)?;
+        let changed = crate::watch::changed_files(&previous, &current);
+        if !changed.is_empty() {
+            analysis.update(&parser, &changed, &current);
+            let required = analysis.required_packages(&parser, &project_root)?;
+            attempted.retain(|package| required.contains(package));
+            if texlive_available {
+                check_with_kpsewhich(&mut texlive, &required);
+            }
+            
+            let mut missing = Vec::new();
+            for package in required {
+                let in_texlive = texlive_available && texlive.is_package_installed(&texlive.resolve_package_name(&package));
+                if !in_texlive && !attempted.contains(&package) && !manager.is_package_installed(&package).await? {
+                    missing.push(package);
+                }
+            }
+            if !missing.is_empty() {
+                install_for_daemon(&manager, &analysis, &project_root, &missing, notify).await;
+                attempted.extend(missing);
+            }
+            previous = current;
+        }
+        
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                println!("Stopped watching.");
+                return Ok(());
+            }
+            _ = tokio::time::sleep(std::time::Duration::from_millis(interval_ms)) => {}
+        }
+    }
+}
+
+/// Install packages the daemon found missing and post one line per package
+async fn install_for_daemon(
+    manager: &PackageManager,
+    analysis: &crate::watch::IncrementalAnalysis,
+    project_root: &Path,
+    missing: &[String],
+    notify: bool,
+) {
+    let mut installed = Vec::new();
+    for package in resolve_owning_packages(missing).await {
+        let used_in = analysis
+            .file_using(&package)
+            .map(|file| format!(" (used in {})", file.strip_prefix(project_root).unwrap_or(file).display()))
+            .unwrap_or_default();
+        let message = match manager.install(&package).await {
+            Ok(files) => {
+                report_unfindable_files(manager, &files);
+                installed.push(package.clone());
+                format!("📦 Installed {}{}", package, used_in)
+            }
+            Err(e) => format!("❌ Failed to install {}{}: {}", package, used_in, e),
+        };
+        println!("{}", message);
+        if notify {
+            crate::ui::desktop_notification(&crate::ui::plain_text(&message));
+        }
+    }
+    
+    if !installed.is_empty() {
+        report_shadowing(&installed).await;
+        if let Err(e) = manager.apply_cache_policy().await {
+            println!("Warning: Failed to clean cache: {}", e);
+        }
+    }
+}
+
+async fn auto_install_missing_packages(path: &str, use_compile: bool, dry_run: bool, yes: bool) -> Result<()> {
+    let parser = new_parser()?;
+    let path = resolve_project_path(path)?;
+    let path = path.as_path();
+    
+    println!("Auto-installing packages for: {}", path.display());
+    
+    let mut missing_packages = Vec::new();
+    
+    // If compile detection is enabled, use compile error detection
     if use_compile {
         // Read compile command from configuration
-        let config = if Path::new("tpmgr.toml").exists() {
-            Config::load("tpmgr.toml")?
-        } else {
-            Config::new()
-        };
+        let config = Config::load_project_or_default()?;
         
         let compile_cmd = &config.project.compile;
-        let project_root = std::env::current_dir()?;
+        let project_root = current_project_root()?;
         
         if path.is_file() {
             missing_packages = parser.detect_missing_packages_by_compilation(path, compile_cmd, &project_root)?;
         } else {
             // For directories, first try to extract target files from compile commands  
-            let resolved_commands = compile_cmd.resolve_variables(&project_root)?;
+            let resolved_commands = compile_cmd.resolve_variables(&config.chain_variables(&project_root))?;
             if !resolved_commands.is_empty() {
                 // Get target file from the last command
                 let last_command = resolved_commands.last().unwrap();
                 if let Some(potential_target) = last_command.last() {
-                    let target_path = Path::new(potential_target);
+                    let target_path = &project_root.join(potential_target);
                     if target_path.exists() {
-                        missing_packages = parser.detect_missing_packages_by_compilation(&target_path, compile_cmd, &project_root)?;
+                        missing_packages = parser.detect_missing_packages_by_compilation(target_path, compile_cmd, &project_root)?;
                     } else {
                         println!("Target file specified in compile command not found: {}", potential_target);
                         // As a fallback, try to find .tex files
@@ -584,14 +1405,12 @@ async fn auto_install_missing_packages(path: &str, use_compile: bool) -> Result<
                         // First check src directory
                         let src_dir = path.join("src");
                         if src_dir.exists() {
-                            for entry in std::fs::read_dir(&src_dir)? {
-                                if let Ok(entry) = entry {
-                                    if let Some(ext) = entry.path().extension() {
-                                        if ext == "tex" {
-                                            missing_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
-                                            found_tex = true;
-                                            break;
-                                        }
+                            for entry in std::fs::read_dir(&src_dir)?.flatten() {
+                                if let Some(ext) = entry.path().extension() {
+                                    if ext == "tex" {
+                                        missing_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
+                                        found_tex = true;
+                                        break;
                                     }
                                 }
                             }
@@ -599,14 +1418,12 @@ async fn auto_install_missing_packages(path: &str, use_compile: bool) -> Result<
                         
                         // If not found in src directory, check current directory
                         if !found_tex {
-                            for entry in std::fs::read_dir(path)? {
-                                if let Ok(entry) = entry {
-                                    if let Some(ext) = entry.path().extension() {
-                                        if ext == "tex" {
-                                            missing_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
-                                            found_tex = true;
-                                            break;
-                                        }
+                            for entry in std::fs::read_dir(path)?.flatten() {
+                                if let Some(ext) = entry.path().extension() {
+                                    if ext == "tex" {
+                                        missing_packages = parser.detect_missing_packages_by_compilation(&entry.path(), compile_cmd, &project_root)?;
+                                        found_tex = true;
+                                        break;
                                     }
                                 }
                             }
@@ -625,14 +1442,25 @@ async fn auto_install_missing_packages(path: &str, use_compile: bool) -> Result<
         }
     } else {
         // Use regex parsing
-        let dependencies = if path.is_file() {
+        let project_dir = if path.is_file() {
+            path.parent().unwrap_or(Path::new("."))
+        } else {
+            path
+        };
+        let mut dependencies = if path.is_file() {
             parser.parse_file(path)?
         } else {
             parser.parse_project(path)?
         };
         
+        let mut local_packages = TeXParser::find_local_packages(project_dir)?;
+        parser.add_local_package_dependencies(&mut dependencies, &mut local_packages);
+        
         let packages = TeXParser::get_unique_packages(&dependencies);
-        let filtered_packages = TeXParser::filter_core_packages(&packages);
+        let filtered_packages: Vec<String> = TeXParser::filter_core_packages(&packages)
+            .into_iter()
+            .filter(|package| !local_packages.contains_key(package))
+            .collect();
         
         if filtered_packages.is_empty() {
             println!("No packages need to be installed.");
@@ -643,18 +1471,23 @@ async fn auto_install_missing_packages(path: &str, use_compile: bool) -> Result<
         let mut texlive = TeXLiveManager::new();
         texlive.detect_texlive()?;
         texlive.scan_installed_packages()?;
+        check_with_kpsewhich(&mut texlive, &filtered_packages);
         
-        // Find missing packages
+        // Find missing packages, checking the package that actually ships each file
         for package in &filtered_packages {
-            if !texlive.is_package_installed(package) {
+            if !texlive.is_package_installed(&texlive.resolve_package_name(package)) {
                 missing_packages.push(package.clone());
             }
         }
     }
     
+    // Missing files are named after their .sty/.cls, which is not always the
+    // name of the package that ships them (bm.sty is in 'tools')
+    let missing_packages = resolve_owning_packages(&missing_packages).await;
+    
     if missing_packages.is_empty() {
         println!("✓ All required packages are already installed!");
-        return Ok(());
+        return finish_install(&[], &[]);
     }
     
     println!("Found {} missing packages:", missing_packages.len());
@@ -663,37 +1496,47 @@ async fn auto_install_missing_packages(path: &str, use_compile: bool) -> Result<
     }
     
     // Check configuration to determine installation location
-    let _config = if Path::new("tpmgr.toml").exists() {
-        Config::load("tpmgr.toml")?
-    } else {
-        Config::new()
-    };
+    let _config = Config::load_project_or_default()?;
     
     // Here can decide whether to install globally or locally based on configuration
     let global = false; // Default local project installation
     
+    if dry_run {
+        let manager = PackageManager::read_only(global)?;
+        print_install_plan(&manager, &missing_packages).await;
+        return Ok(());
+    }
+    
     // Install missing packages
     let manager = PackageManager::new(global)?;
-    let mut any_installed = false;
+    if !confirm_install(&manager, &missing_packages, yes).await? {
+        println!("Installation cancelled.");
+        return Ok(());
+    }
+    let mut installed = Vec::new();
+    let mut failed = Vec::new();
     
     for package in &missing_packages {
         println!("Installing {}...", package);
         match manager.install(package).await {
-            Ok(_) => {
+            Ok(files) => {
                 println!("  ✓ {} installed successfully", package);
-                any_installed = true;
+                report_unfindable_files(&manager, &files);
+                installed.push(package.clone());
             },
-            Err(e) => println!("  ✗ Failed to install {}: {}", package, e),
+            Err(e) => {
+                println!("  ✗ Failed to install {}: {}", package, e);
+                failed.push(package.clone());
+            }
         }
     }
     
-    // Auto-clean cache after installation
-    if any_installed {
-        println!("Auto-cleaning package cache...");
-        if let Err(e) = manager.clean_cache().await {
+    report_shadowing(&installed).await;
+    
+    // Keep downloaded archives for reuse unless the cache policy says otherwise
+    if !installed.is_empty() {
+        if let Err(e) = manager.apply_cache_policy().await {
             println!("Warning: Failed to clean cache: {}", e);
-        } else {
-            println!("✓ Package cache cleaned");
         }
     }
     
@@ -712,16 +1555,16 @@ async fn auto_install_missing_packages(path: &str, use_compile: bool) -> Result<
     
     // Clean intermediate files if using compilation and auto_clean is enabled
     if use_compile {
-        if let Ok(config) = Config::load("tpmgr.toml") {
+        if let Ok(Some(config)) = Config::load_project() {
             if config.project.compile.auto_clean {
                 println!("🧹 Cleaning intermediate files...");
-                let project_root = std::env::current_dir()?;
-                clean_intermediate_files(&project_root)?;
+                let project_root = current_project_root()?;
+                clean_intermediate_files(&project_root, &config)?;
             }
         }
     }
     
-    Ok(())
+    finish_install(&installed, &failed)
 }
 
 pub async fn config_command(action: &ConfigAction) -> Result<()> {
@@ -731,18 +1574,37 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
         ConfigAction::Show { global } => {
             // Display global configuration
             let global_config = GlobalConfig::load()?;
-            println!("Global Configuration:");
+            match crate::config::active_profile() {
+                Some(profile) => println!("Global Configuration (profile: {}):", profile),
+                None => println!("Global Configuration:"),
+            }
             println!("  texlive_path: {}", 
                 global_config.texlive_path.as_ref().unwrap_or(&"<not set>".to_string()));
             println!("  mirror_url: {}", 
                 global_config.mirror_url.as_ref().unwrap_or(&"<not set>".to_string()));
+            if let Some(mirror) = &global_config.mirror {
+                match mirror.latency_ms {
+                    Some(latency) => println!("  mirror: {} ({}ms)", mirror.name, latency),
+                    None => println!("  mirror: {}", mirror.name),
+                }
+            }
+            if let Some(proxy) = &global_config.proxy {
+                println!("  proxy: {}", proxy);
+            }
             println!("  compile_command: {}", global_config.compile_command);
             println!("  install_global: {}", global_config.install_global);
+            if !global_config.profiles.is_empty() {
+                let names: Vec<&str> = global_config.profiles.keys().map(String::as_str).collect();
+                println!("  profiles: {}", names.join(", "));
+            }
             
             // If project configuration exists and not global-only, also display project configuration
-            if !global && Path::new("tpmgr.toml").exists() {
-                let project_config = Config::load("tpmgr.toml")?;
+            let project_config = if *global { None } else { Config::load_project()? };
+            if let Some(project_config) = project_config {
                 println!("\nProject Configuration:");
+                if let Some((path, _)) = crate::config::WorkspaceConfig::find(&current_project_root()?)? {
+                    println!("  workspace: {}", path.display());
+                }
                 println!("  name: {}", project_config.project.name);
                 println!("  version: {}", project_config.project.version);
                 println!("  compile: {}", project_config.project.compile);
@@ -753,6 +1615,10 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
                     project_config.project.mirror_url.as_ref().unwrap_or(&"<not set>".to_string()));
                 println!("  install_global: {}", 
                     project_config.project.install_global.map(|b| b.to_string()).unwrap_or_else(|| "<not set>".to_string()));
+                println!("  output_dir: {}", 
+                    project_config.project.output_dir.as_ref().unwrap_or(&"<not set>".to_string()));
+                println!("  main: {}", 
+                    project_config.project.main.as_ref().unwrap_or(&"<not set>".to_string()));
             }
         }
         ConfigAction::Set { key, value, global } => {
@@ -764,10 +1630,11 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
                 println!("✓ Set global {} = {}", key, value);
             } else {
                 // If in project directory and key belongs to project config, set project config
-                if Path::new("tpmgr.toml").exists() && Config::list_project_keys().contains(&key.as_str()) {
-                    let mut config = Config::load("tpmgr.toml")?;
+                let project_config = Config::load_project_file()?
+                    .filter(|_| Config::list_project_keys().contains(&key.as_str()));
+                if let Some(mut config) = project_config {
                     config.set_project_config(key, value)?;
-                    config.save("tpmgr.toml")?;
+                    config.save_project()?;
                     println!("✓ Set project {} = {}", key, value);
                     
                     // If mirror URL, equivalent to executing mirror use
@@ -796,8 +1663,7 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
                 // Get from project config first, then from global config
                 let mut found = false;
                 
-                if Path::new("tpmgr.toml").exists() {
-                    let project_config = Config::load("tpmgr.toml")?;
+                if let Some(project_config) = Config::load_project()? {
                     if let Some(value) = project_config.get_project_config(key) {
                         println!("{}", value);
                         found = true;
@@ -814,6 +1680,53 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
                 }
             }
         }
+        ConfigAction::Unset { key, global } => {
+            let project_config = if *global {
+                None
+            } else {
+                Config::load_project_file()?.filter(|_| Config::list_project_keys().contains(&key.as_str()))
+            };
+            if let Some(mut config) = project_config {
+                config.unset_project_config(key)?;
+                config.save_project()?;
+                println!("✓ Unset project {}", key);
+            } else {
+                let mut global_config = GlobalConfig::load()?;
+                global_config.unset(key)?;
+                global_config.save()?;
+                println!("✓ Unset global {}", key);
+            }
+        }
+        ConfigAction::Add { key, value, global } | ConfigAction::Remove { key, value, global } => {
+            let adding = matches!(action, ConfigAction::Add { .. });
+            if !crate::config::list_value_keys().contains(&key.as_str()) {
+                anyhow::bail!(
+                    "'{}' is not a list (lists: {})",
+                    key,
+                    crate::config::list_value_keys().join(", ")
+                );
+            }
+            
+            let project_config = if *global { None } else { Config::load_project_file()? };
+            let (scope, changed) = if let Some(mut config) = project_config {
+                let changed = if adding { config.add_to_list(key, value)? } else { config.remove_from_list(key, value)? };
+                config.save_project()?;
+                ("project", changed)
+            } else {
+                let mut global_config = GlobalConfig::load()?;
+                let changed = if adding { global_config.add_to_list(key, value)? } else { global_config.remove_from_list(key, value)? };
+                global_config.save()?;
+                ("global", changed)
+            };
+            
+            match (adding, changed) {
+                (true, true) => println!("✓ Added {} to {} {}", value, scope, key),
+                (true, false) if key == "repositories" => println!("✓ Updated {} in {} {}", value, scope, key),
+                (true, false) => println!("{} is already in {} {}", value, scope, key),
+                (false, true) => println!("✓ Removed {} from {} {}", value, scope, key),
+                (false, false) => anyhow::bail!("{} is not in {} {}", value, scope, key),
+            }
+        }
         ConfigAction::List { global } => {
             if *global {
                 // Show global configuration keys only
@@ -827,7 +1740,7 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
                     println!("  - {}", key);
                 }
                 
-                if Path::new("tpmgr.toml").exists() {
+                if Config::load_project_file()?.is_some() {
                     println!("\nAvailable project configuration keys:");
                     for key in Config::list_project_keys() {
                         println!("  - {}", key);
@@ -837,6 +1750,41 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
                 }
             }
         }
+        ConfigAction::Check { global } => {
+            let path = config_file_path(*global)?;
+            let issues = check_config_file(&std::fs::read_to_string(&path)?, *global);
+            if issues.is_empty() {
+                println!("✓ {} is valid", path.display());
+                return Ok(());
+            }
+            
+            println!("Checked {}:", path.display());
+            print_config_issues(&issues);
+            if crate::config_check::has_errors(&issues) {
+                anyhow::bail!("Configuration has errors");
+            }
+        }
+        ConfigAction::Edit { global } => {
+            if crate::ui::is_non_interactive() {
+                anyhow::bail!("'tpmgr config edit' needs an interactive terminal; use 'tpmgr config set' instead");
+            }
+            let path = config_file_path(*global)?;
+            edit_config_file(&path, *global)?;
+        }
+        ConfigAction::Export => {
+            println!("# tpmgr settings; apply them with: tpmgr config import <file>");
+            print!("{}", GlobalConfig::load()?.export()?);
+        }
+        ConfigAction::Import { file } => {
+            let content = if file == "-" {
+                std::io::read_to_string(std::io::stdin())?
+            } else {
+                std::fs::read_to_string(file)?
+            };
+            let imported = content.parse::<toml::Table>().map(|table| table.len()).unwrap_or_default();
+            GlobalConfig::import(&content)?;
+            println!("✓ Imported {} settings into {}", imported, GlobalConfig::get_config_path()?.display());
+        }
         ConfigAction::Reset { global } => {
             if *global {
                 // Reset global configuration only
@@ -849,9 +1797,9 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
                 global_config.save()?;
                 println!("✓ Global configuration reset to defaults");
                 
-                if Path::new("tpmgr.toml").exists() {
+                if Config::load_project_file()?.is_some() {
                     let project_config = Config::new();
-                    project_config.save("tpmgr.toml")?;
+                    project_config.save_project()?;
                     println!("✓ Project configuration reset to defaults");
                 }
             }
@@ -860,23 +1808,42 @@ pub async fn config_command(action: &ConfigAction) -> Result<()> {
     Ok(())
 }
 
-pub async fn compile_command(path: &str, clean: bool, verbose: bool) -> Result<()> {
+/// Build the project; returns whether the build succeeded, which is also an
+/// error in non-interactive mode
+#[allow(clippy::too_many_arguments)]
+pub async fn compile_command(
+    path: &str,
+    clean: bool,
+    verbose: bool,
+    strict_refs: bool,
+    auto_install: bool,
+    force: bool,
+    remote: Option<&str>,
+    options: crate::chain::ChainOptions,
+) -> Result<bool> {
     use std::process::Command;
     
+    let started = std::time::Instant::now();
     let path = Path::new(path);
-    let project_root = if path.is_file() {
-        path.parent().unwrap_or(Path::new(".")).to_path_buf()
+    let start_dir = if path.is_file() {
+        path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."))
     } else {
-        path.to_path_buf()
+        path
     };
+    // Compile from the project root even when started in a subdirectory
+    let project_root = crate::config::project_root_for(start_dir).unwrap_or_else(|| start_dir.to_path_buf());
     
-    // Change to project directory
-    let original_dir = std::env::current_dir()?;
-    std::env::set_current_dir(&project_root)?;
+    let target_document = if path.is_file() && new_parser()?.is_document(path) {
+        let document = path.canonicalize()?;
+        let relative = document.strip_prefix(project_root.canonicalize()?).map(Path::to_path_buf);
+        Some(relative.unwrap_or(document))
+    } else {
+        None
+    };
     
     // Load configuration
-    let config = if Path::new("tpmgr.toml").exists() {
-        Config::load("tpmgr.toml")?
+    let mut config = if let Some(config) = Config::load_project_in(&project_root)? {
+        config
     } else {
         println!("⚠️  No tpmgr.toml found in {}. Using default compilation settings.", project_root.display());
         Config::new()
@@ -884,101 +1851,340 @@ pub async fn compile_command(path: &str, clean: bool, verbose: bool) -> Result<(
     
     println!("📄 Compiling LaTeX project in: {}", project_root.display());
     
-    // Setup TEXINPUTS environment variable for local packages
-    let package_manager = PackageManager::new(false)?;
-    let packages_dir = project_root.join("packages");
+    // --profile <name> adds the post-processing steps and validation of a compile profile
+    let profile = crate::config::active_profile()
+        .and_then(|name| config.project.compile.profile(name).map(|profile| (name, profile)));
+    let validation = match profile {
+        Some((name, profile)) => {
+            println!("🎛️  Compile profile: {}", name);
+            config.project.compile.post.extend(profile.post);
+            profile.validate
+        }
+        None => None,
+    };
     
-    if packages_dir.exists() {
-        let package_texinputs = package_manager.get_texinputs_path();
-        
-        // Add current directory and parent search paths
-        #[cfg(windows)]
-        let separator = ";";
-        #[cfg(unix)]
-        let separator = ":";
-        
-        // Get existing TEXINPUTS if any, preserve system paths
-        let existing_texinputs = std::env::var("TEXINPUTS").unwrap_or_default();
-        
-        // Construct TEXINPUTS: current dir + package dirs + existing paths
-        let texinputs = if existing_texinputs.is_empty() {
-            format!(".{}{}{}", separator, package_texinputs, separator)
+    // The tools run in the project root with the local packages on TEXINPUTS
+    // and BSTINPUTS; tpmgr's own working directory and environment stay untouched
+    let environment = crate::environment::project_environment(&config, &project_root)?;
+    if verbose {
+        for (name, value) in environment.iter().filter(|(name, _)| crate::environment::SEARCH_PATH_VARIABLES.contains(&name.as_str())) {
+            println!("📦 Setting {}: {}", name, value);
+        }
+    }
+    
+    // Figures declared in [assets] are generated and converted before the
+    // check below; on a remote, the remote's tpmgr prepares them
+    if remote.is_none() {
+        if let Err(e) = crate::assets::prepare_assets(&config.assets, &project_root, &environment) {
+            println!("❌ {}", e);
+            crate::ui::summary(serde_json::json!({ "command": "compile", "success": false }));
+            anyhow::bail!("Preparing figures failed");
+        }
+    }
+    
+    // Pre-compile check: warn about figures that the compiler will not find
+    let dependencies = new_parser().and_then(|parser| parser.parse_project(&project_root)).unwrap_or_default();
+    report_missing_figures(&dependencies, &project_root);
+    
+    // A child document (subfiles/standalone) passed directly is compiled as its own target
+    let mut compile_cmd = config.project.compile.clone();
+    // ${MAIN_FILE} is the document passed directly, if any
+    let mut variables = config.chain_variables(&project_root);
+    if let Some(document) = &target_document {
+        variables.main_file = document.to_string_lossy().replace('\\', "/");
+    }
+    let document = match &target_document {
+        Some(_) => Some(variables.main_file.clone()),
+        None => compile_cmd.document(&variables),
+    };
+    // [project.compile.overrides] can give documents matching a pattern their own engine or chain
+    if let Some(document) = &document {
+        if let Some((pattern, compile_override)) = compile_cmd.override_for(document) {
+            println!("🎛️  Compile override \"{}\" for {}", pattern, document);
+            compile_cmd = compile_cmd.with_override(compile_override, document);
+        }
+    }
+    if let Some(file_name) = &target_document {
+        compile_cmd = compile_cmd.with_target(file_name);
+    }
+    // `% tpmgr:` directives at the top of the document change its chain
+    if let Some(document) = document {
+        if let Ok(content) = std::fs::read_to_string(project_root.join(&document)) {
+            let directives = crate::chain::FileDirectives::read(&content, &document)?;
+            if !directives.is_empty() {
+                println!("📝 Using the build directives in {}", document);
+                directives.apply(&mut compile_cmd);
+            }
+        }
+    }
+    if options.draft {
+        let skipped = crate::chain::make_draft(&mut compile_cmd, &dependencies);
+        if skipped.is_empty() {
+            println!("✏️  Draft build");
         } else {
-            format!(".{}{}{}{}", separator, package_texinputs, separator, existing_texinputs)
-        };
-        
-        if verbose {
-            println!("📦 Setting TEXINPUTS: {}", texinputs);
+            println!("✏️  Draft build, skipping {}", skipped.join(", "));
         }
-        
-        // Set environment variable for all child processes
-        std::env::set_var("TEXINPUTS", &texinputs);
+    } else {
+        compile_cmd = with_package_steps(&compile_cmd, &dependencies);
     }
+    // --interaction and --halt-on-error take precedence over the config
+    let interaction = options.interaction.or(compile_cmd.interaction);
+    compile_cmd.interaction = interaction;
+    compile_cmd.halt_on_error |= options.halt_on_error;
+    crate::chain::apply_engine_options(&mut compile_cmd);
     
     // Resolve compilation commands
-    let resolved_commands = config.project.compile.resolve_variables(&project_root)?;
+    let resolved_commands = compile_cmd.resolve_variables(&variables)?;
     
     if resolved_commands.is_empty() {
         println!("❌ No compilation steps defined. Configure compilation chain in tpmgr.toml");
-        return Ok(());
+        if crate::ui::is_non_interactive() {
+            anyhow::bail!("No compilation steps defined");
+        }
+        return Ok(false);
+    }
+    
+    // Steps with `needs` wait for the named steps instead of the one before them
+    let dependencies = crate::chain::step_dependencies(&compile_cmd.steps)?;
+    let stages = crate::chain::step_stages(&dependencies)?;
+    let waits_for = |i: usize| dependencies[i].iter().map(|needed| (needed + 1).to_string()).collect::<Vec<_>>().join(", ");
+    if options.graph {
+        println!("🕸️  Compile graph ({} steps, {} stages):", resolved_commands.len(), stages.len());
+        for (number, stage) in stages.iter().enumerate() {
+            let parallel = if crate::chain::runs_in_parallel(stage, &compile_cmd.steps) { " (in parallel)" } else { "" };
+            println!("  Stage {}{}:", number + 1, parallel);
+            for &i in stage {
+                match dependencies[i].is_empty() {
+                    true => println!("    {}. {}", i + 1, resolved_commands[i].join(" ")),
+                    false => println!("    {}. {}  ← {}", i + 1, resolved_commands[i].join(" "), waits_for(i)),
+                }
+            }
+        }
+        crate::ui::summary(serde_json::json!({
+            "command": "compile",
+            "steps": resolved_commands.iter().map(|cmd| cmd.join(" ")).collect::<Vec<_>>(),
+            "dependencies": dependencies,
+            "stages": stages,
+        }));
+        return Ok(true);
     }
     
     println!("🔗 Compilation chain ({} steps):", resolved_commands.len());
     for (i, cmd) in resolved_commands.iter().enumerate() {
-        println!("  {}. {}", i + 1, cmd.join(" "));
+        match compile_cmd.steps[i].needs {
+            Some(_) => println!("  {}. {}  (waits for {})", i + 1, cmd.join(" "), if dependencies[i].is_empty() { "no other step".to_string() } else { waits_for(i) }),
+            None => println!("  {}. {}", i + 1, cmd.join(" ")),
+        }
     }
     println!();
     
+    if let Some(remote) = remote {
+        return compile_remotely(remote, &project_root, &config, &resolved_commands, target_document.as_deref(), validation.as_ref(), &environment).await;
+    }
+    
+    // With [build_cache], inputs identical to an earlier build reuse its outputs
+    let build_cache = config
+        .build_cache
+        .enabled
+        .then(|| crate::build_cache::BuildCache::new(&config.build_cache, &project_root));
+    let mut chain: Vec<String> = resolved_commands.iter().map(|cmd| cmd.join(" ")).collect();
+    // Post-processing changes the cached files too
+    for step in &config.project.compile.post {
+        chain.push(serde_json::to_string(step)?);
+    }
+    let outputs = built_documents(&resolved_commands, &config, &project_root);
+    if let Some(build_cache) = build_cache.as_ref().filter(|_| !force) {
+        match restore_cached_build(build_cache, &chain, &outputs, &config) {
+            Ok(true) => {
+                let reports = match &validation {
+                    Some(validation) => validate_documents(&outputs, &config, validation, &project_root, &environment)?,
+                    None => Vec::new(),
+                };
+                let success = reports.iter().all(|report| report.passed);
+                let manifest = success
+                    .then(|| write_build_manifest(&config, &project_root, &resolved_commands, &outputs, started, true, &environment))
+                    .flatten();
+                crate::ui::summary(serde_json::json!({
+                    "command": "compile",
+                    "success": success,
+                    "cached": true,
+                    "validation": reports,
+                    "manifest": manifest,
+                }));
+                if !success && crate::ui::is_non_interactive() {
+                    anyhow::bail!("The PDF did not pass validation");
+                }
+                return Ok(success);
+            }
+            Ok(false) => {}
+            Err(e) => println!("Warning: Failed to read the build cache: {}", e),
+        }
+    }
+    
     // Execute compilation steps
     let mut success = true;
-    for (i, cmd_args) in resolved_commands.iter().enumerate() {
-        if cmd_args.is_empty() {
+    // Packages installed (or found uninstallable) by --auto-install, so none is tried twice
+    let mut attempted_packages = std::collections::HashSet::new();
+    let mut timings = crate::bench::CompileTimings::default();
+    // Engine passes stop once their auxiliary files are stable: a repeated
+    // engine step is skipped after a pass that changed nothing, and the last
+    // one is rerun while they change, up to max_passes runs
+    let engine_jobs = crate::chain::engine_jobs(&resolved_commands);
+    let last_engine = stages.concat().into_iter().rev().find(|&i| engine_jobs[i].is_some());
+    let max_passes = compile_cmd.max_passes.max(1);
+    let mut last_engine_passes = 0;
+    let mut stable_step = None;
+    let mut previous = None;
+    'steps: for stage in &stages {
+        // Independent steps, e.g. figure generation before the engine, run at once
+        if crate::chain::runs_in_parallel(stage, &compile_cmd.steps) {
+            if !run_parallel_steps(stage, &resolved_commands, &project_root, &environment, verbose, &mut timings) {
+                success = false;
+                break;
+            }
+            previous = stage.last().copied();
             continue;
         }
-        
-        let tool = &cmd_args[0];
-        let args = &cmd_args[1..];
-        
-        println!("⚙️  Step {}/{}: Running {}", i + 1, resolved_commands.len(), tool);
-        
+        for &i in stage {
+            let cmd_args = &resolved_commands[i];
+            if cmd_args.is_empty() {
+                continue;
+            }
+            
+            let tool = &cmd_args[0];
+            let args = &cmd_args[1..];
+            let job = engine_jobs[i].as_deref();
+            if job.is_some() && stable_step.is_some_and(|stable| previous == Some(stable) && resolved_commands[stable] == *cmd_args) {
+                println!("⏭️  Step {}/{} skipped: the auxiliary files are stable", i + 1, resolved_commands.len());
+                stable_step = Some(i);
+                previous = Some(i);
+                continue;
+            }
+            previous = Some(i);
+            let mut checksum = job.map(|job| crate::chain::pass_checksum(&project_root, job));
+            
+            loop {
+                println!("⚙️  Step {}/{}: Running {}", i + 1, resolved_commands.len(), tool);
+                
+                if verbose {
+                    println!("   Command: {}", cmd_args.join(" "));
+                }
+                
+                let mut command = Command::new(tool);
+                command.args(args).current_dir(&project_root).envs(environment.iter().cloned());
+                
+                // --auto-install reads the missing packages from the tool's output;
+                // in errorstopmode the engine asks on the terminal what to do
+                let prompts = interaction == Some(crate::config::InteractionMode::Errorstopmode) && engine_jobs[i].is_some();
+                if !verbose && !auto_install && !prompts {
+                    command.stdout(std::process::Stdio::null());
+                    command.stderr(std::process::Stdio::null());
+                }
+                let output = crate::bench::run_measured(&mut command, auto_install).map(|run| {
+                    if verbose && auto_install {
+                        print!("{}", run.output);
+                    }
+                    timings.steps.push(crate::bench::StepTiming {
+                        command: cmd_args.join(" "),
+                        seconds: run.seconds,
+                        peak_memory_kb: run.peak_memory_kb,
+                    });
+                    (run.status, run.output)
+                });
+                
+                match output {
+                    Ok((status, _)) if status.success() => {
+                        println!("✅ Step {}/{} completed", i + 1, resolved_commands.len());
+                        let Some(job) = job else {
+                            break;
+                        };
+                        let after = crate::chain::pass_checksum(&project_root, job);
+                        if checksum.as_ref() == Some(&after) {
+                            stable_step = Some(i);
+                        } else if last_engine == Some(i) {
+                            last_engine_passes += 1;
+                            if last_engine_passes < max_passes {
+                                println!("🔄 Auxiliary files changed; running step {}/{} again", i + 1, resolved_commands.len());
+                                checksum = Some(after);
+                                continue;
+                            }
+                            println!("⚠️  Auxiliary files still change after {} runs of step {} (max_passes)", max_passes, i + 1);
+                        }
+                        break;
+                    }
+                    Ok((status, log)) => {
+                        println!("❌ Step {}/{} failed with exit code: {:?}", i + 1, resolved_commands.len(), status.code());
+                        if auto_install && install_packages_missing_in(&log, &mut attempted_packages).await? {
+                            println!("🔄 Running step {}/{} again", i + 1, resolved_commands.len());
+                            continue;
+                        }
+                        success = false;
+                        break 'steps;
+                    }
+                    Err(e) => {
+                        println!("❌ Failed to execute {}: {}", tool, e);
+                        println!("   Make sure {} is installed and available in PATH", tool);
+                        success = false;
+                        break 'steps;
+                    }
+                }
+            }
+        }
+    }
+    
+    if !timings.steps.is_empty() {
         if verbose {
-            println!("   Command: {}", cmd_args.join(" "));
+            print_compile_timings(&timings);
         }
-        
-        let mut command = Command::new(tool);
-        command.args(args);
-        
-        if !verbose {
-            command.stdout(std::process::Stdio::null());
-            command.stderr(std::process::Stdio::null());
+        if let Err(e) = timings.save(&project_root) {
+            println!("Warning: Failed to save step timings: {}", e);
         }
-        
-        match command.status() {
-            Ok(status) => {
-                if status.success() {
-                    println!("✅ Step {}/{} completed", i + 1, resolved_commands.len());
-                } else {
-                    println!("❌ Step {}/{} failed with exit code: {:?}", i + 1, resolved_commands.len(), status.code());
-                    success = false;
-                    break;
-                }
-            }
-            Err(e) => {
-                println!("❌ Failed to execute {}: {}", tool, e);
-                println!("   Make sure {} is installed and available in PATH", tool);
+    }
+    
+    let mut undefined_references = 0;
+    if success {
+        undefined_references = report_undefined_references(&project_root, &resolved_commands);
+        if strict_refs && undefined_references > 0 {
+            success = false;
+        }
+    }
+    
+    let post_steps = &config.project.compile.post;
+    if success && !post_steps.is_empty() {
+        println!("🪄 Post-processing:");
+        for pdf in outputs.iter().filter(|pdf| pdf.is_file()) {
+            if let Err(e) = crate::postprocess::run_post_steps(post_steps, pdf, &environment) {
+                println!("❌ {}", e);
                 success = false;
                 break;
             }
         }
     }
     
+    let mut reports = Vec::new();
+    if let Some(validation) = validation.as_ref().filter(|_| success) {
+        reports = validate_documents(&outputs, &config, validation, &project_root, &environment)?;
+        success = reports.iter().all(|report| report.passed);
+    }
+    
+    let mut manifest = None;
     if success {
         println!("🎉 Compilation completed successfully!");
+        manifest = write_build_manifest(&config, &project_root, &resolved_commands, &outputs, started, false, &environment);
+        
+        if let Some(build_cache) = &build_cache {
+            let stored = build_cache
+                .input_files(&outputs, &config.project.package_dir)
+                .and_then(|inputs| build_cache.store(&chain, &inputs, &produced_files(&outputs, &config)));
+            if let Err(e) = stored {
+                println!("Warning: Failed to store the build in the build cache: {}", e);
+            }
+        }
         
         // Clean intermediate files if requested via command line or config
         if clean || config.project.compile.auto_clean {
             println!("🧹 Cleaning intermediate files...");
-            clean_intermediate_files(&project_root)?;
+            clean_intermediate_files(&project_root, &config)?;
         }
     } else {
         println!("💥 Compilation failed!");
@@ -986,115 +2192,1719 @@ pub async fn compile_command(path: &str, clean: bool, verbose: bool) -> Result<(
         // Clean intermediate files if explicitly requested via command line
         if clean {
             println!("🧹 Cleaning intermediate files...");
-            clean_intermediate_files(&project_root)?;
+            clean_intermediate_files(&project_root, &config)?;
         }
     }
     
-    // Restore original directory
-    std::env::set_current_dir(original_dir)?;
-    
-    Ok(())
-}
-
-fn clean_intermediate_files(project_root: &Path) -> Result<()> {
-    // Try to load patterns from config, fall back to defaults
-    let patterns = if let Ok(config) = Config::load("tpmgr.toml") {
-        if config.project.compile.clean_patterns.is_empty() {
-            // Use default patterns if none specified
-            vec![
-                "*.aux".to_string(), "*.log".to_string(), "*.out".to_string(), 
-                "*.toc".to_string(), "*.lof".to_string(), "*.lot".to_string(), 
-                "*.bbl".to_string(), "*.blg".to_string(), "*.fls".to_string(), 
-                "*.fdb_latexmk".to_string(), "*.synctex.gz".to_string(), 
-                "*.nav".to_string(), "*.snm".to_string(), "*.vrb".to_string(),
-                "*.run.xml".to_string(), "*.bcf".to_string(), "*.idx".to_string(), 
-                "*.ind".to_string(), "*.ilg".to_string(), "*.glo".to_string(), 
-                "*.gls".to_string(), "*.glg".to_string(), "*.auxlock".to_string(),
-            ]
-        } else {
-            config.project.compile.clean_patterns
-        }
-    } else {
-        // Default patterns when no config file
-        vec![
-            "*.aux".to_string(), "*.log".to_string(), "*.out".to_string(), 
-            "*.toc".to_string(), "*.lof".to_string(), "*.lot".to_string(), 
-            "*.bbl".to_string(), "*.blg".to_string(), "*.fls".to_string(), 
-            "*.fdb_latexmk".to_string(), "*.synctex.gz".to_string(), 
-            "*.nav".to_string(), "*.snm".to_string(), "*.vrb".to_string(),
-            "*.run.xml".to_string(), "*.bcf".to_string(), "*.idx".to_string(), 
-            "*.ind".to_string(), "*.ilg".to_string(), "*.glo".to_string(), 
-            "*.gls".to_string(), "*.glg".to_string(), "*.auxlock".to_string(),
-        ]
-    };
+    crate::ui::summary(serde_json::json!({
+        "command": "compile",
+        "success": success,
+        "undefined_references": undefined_references,
+        "validation": reports,
+        "manifest": manifest,
+    }));
+    if strict_refs && undefined_references > 0 {
+        anyhow::bail!("{} undefined citations or references (--strict-refs)", undefined_references);
+    }
+    if !success && crate::ui::is_non_interactive() {
+        anyhow::bail!("Compilation failed");
+    }
     
-    clean_files_by_patterns(project_root, &patterns)
+    Ok(success)
 }
 
-fn clean_files_by_patterns(project_root: &Path, patterns: &[String]) -> Result<()> {
-    let mut cleaned_count = 0;
+/// Run the steps of a parallel stage at once with their output captured (and
+/// shown with --verbose); returns whether all of them succeeded
+fn run_parallel_steps(
+    stage: &[usize],
+    commands: &[Vec<String>],
+    project_root: &Path,
+    environment: &[(String, String)],
+    verbose: bool,
+    timings: &mut crate::bench::CompileTimings,
+) -> bool {
+    let names: Vec<String> = stage.iter().map(|&i| format!("{} ({})", i + 1, commands[i][0])).collect();
+    println!("⚙️  Steps {} of {} running in parallel", names.join(", "), commands.len());
+    let runs: Vec<std::io::Result<crate::bench::MeasuredRun>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = stage
+            .iter()
+            .map(|&i| {
+                scope.spawn(move || {
+                    let mut command = std::process::Command::new(&commands[i][0]);
+                    command.args(&commands[i][1..]).current_dir(project_root).envs(environment.iter().cloned());
+                    crate::bench::run_measured(&mut command, true)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err(std::io::Error::other("the step's thread panicked"))))
+            .collect()
+    });
     
-    for pattern in patterns {
-        // Convert pattern to absolute path relative to project root
-        let full_pattern = if pattern.starts_with('/') || pattern.contains(':') {
-            // Absolute pattern
-            pattern.clone()
-        } else {
-            // Relative pattern - make it relative to project root
-            project_root.join(pattern).to_string_lossy().to_string()
-        };
-        
-        // Use glob to find matching files
-        match glob::glob(&full_pattern) {
-            Ok(paths) => {
-                for path_result in paths {
-                    match path_result {
-                        Ok(path) => {
-                            if path.is_file() {
-                                match std::fs::remove_file(&path) {
-                                    Ok(_) => {
-                                        // Show relative path from project root
-                                        let relative_path = path.strip_prefix(project_root)
-                                            .unwrap_or(&path);
-                                        println!("   Removed: {}", relative_path.display());
-                                        cleaned_count += 1;
-                                    }
-                                    Err(e) => {
-                                        println!("   Warning: Failed to remove {}: {}", path.display(), e);
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            println!("   Warning: Pattern error for {}: {}", full_pattern, e);
-                        }
-                    }
+    let mut success = true;
+    for (&i, run) in stage.iter().zip(runs) {
+        match run {
+            Ok(run) => {
+                if verbose {
+                    print!("{}", run.output);
+                }
+                timings.steps.push(crate::bench::StepTiming {
+                    command: commands[i].join(" "),
+                    seconds: run.seconds,
+                    peak_memory_kb: run.peak_memory_kb,
+                });
+                if run.status.success() {
+                    println!("✅ Step {}/{} completed", i + 1, commands.len());
+                } else {
+                    println!("❌ Step {}/{} failed with exit code: {:?}", i + 1, commands.len(), run.status.code());
+                    success = false;
                 }
             }
             Err(e) => {
-                println!("   Warning: Invalid glob pattern '{}': {}", full_pattern, e);
+                println!("❌ Failed to execute {}: {}", commands[i][0], e);
+                println!("   Make sure {} is installed and available in PATH", commands[i][0]);
+                success = false;
             }
         }
     }
+    success
+}
+
+/// `tpmgr release`: build the project (with the release profile and
+/// --strict-refs) and store each PDF under its release name
+pub async fn release_command(path: &str, force: bool) -> Result<()> {
+    let project_root = resolve_project_path(path)?.canonicalize()?;
+    let Some(config) = Config::load_project_in(&project_root)? else {
+        anyhow::bail!("No tpmgr.toml found in {}; a release needs the project's name and version", project_root.display());
+    };
+    if let Some(profile) = &config.release.profile {
+        if crate::config::active_profile().is_none() {
+            crate::config::set_active_profile(profile)?;
+        }
+    }
     
-    if cleaned_count > 0 {
-        println!("✅ Cleaned {} intermediate files", cleaned_count);
-    } else {
-        println!("   No intermediate files to clean");
+    let info = crate::release::ReleaseInfo::collect(&config.project.name, &config.project.version, &project_root);
+    let commands = config.project.compile.resolve_variables(&config.chain_variables(&project_root))?;
+    let documents = built_documents(&commands, &config, &project_root);
+    // Fail on a bad or taken name before spending time on the build
+    for document in &documents {
+        let name = document.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let target = crate::release::target(&config.release, &project_root, &info.file_name(&config.release.name, &name)?);
+        if target.exists() && !force {
+            anyhow::bail!("{} already exists; use --force to replace it", target.display());
+        }
+    }
+    let built = compile_command(&project_root.to_string_lossy(), false, false, true, false, false, None, crate::chain::ChainOptions::default()).await?;
+    if !built {
+        anyhow::bail!("The release build failed");
     }
     
+    let mut post = config.project.compile.post.clone();
+    if let Some(profile) = crate::config::active_profile().and_then(|name| config.project.compile.profile(name)) {
+        post.extend(profile.post);
+    }
+    let registry = project_root.join(&config.project.package_dir).join("registry.json");
+    let mut released = Vec::new();
+    for document in documents.iter().filter(|document| document.is_file()) {
+        let pdf = crate::postprocess::final_output(&post, document);
+        let name = document.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let file_name = info.file_name(&config.release.name, &name)?;
+        let written = crate::release::store(&config.release, &project_root, &pdf, &file_name, Some(&registry), force)?;
+        println!("📦 Released {} as {}", pdf.strip_prefix(&project_root).unwrap_or(&pdf).display(), written[0].strip_prefix(&project_root).unwrap_or(&written[0]).display());
+        for file in &written[1..] {
+            println!("   {}", file.strip_prefix(&project_root).unwrap_or(file).display());
+        }
+        released.push(written);
+    }
+    if released.is_empty() {
+        anyhow::bail!("The build produced no PDF to release");
+    }
+    crate::ui::summary(serde_json::json!({
+        "command": "release",
+        "version": config.project.version,
+        "files": released,
+    }));
     Ok(())
 }
 
+/// The chain with the tool runs the documents' packages need (nomencl's
+/// makeindex, ...) added
+fn with_package_steps(compile: &crate::config::CompileCommand, dependencies: &[crate::tex_parser::TeXDependency]) -> crate::config::CompileCommand {
+    let mut compile = compile.clone();
+    for added in crate::chain::add_package_steps(&mut compile, dependencies) {
+        println!("📑 Added {}", added);
+    }
+    compile
+}
 
+/// `compile --remote`: send the project with its installed packages to a build
+/// server or SSH host, show the build log and fetch the PDFs
+async fn compile_remotely(
+    remote: &str,
+    project_root: &Path,
+    config: &Config,
+    resolved_commands: &[Vec<String>],
+    target: Option<&Path>,
+    validation: Option<&crate::config::Validation>,
+    environment: &[(String, String)],
+) -> Result<bool> {
+    use crate::remote::RemoteTarget;
+    
+    let remote_target = RemoteTarget::parse(remote)?;
+    let mut outputs = built_documents(resolved_commands, config, project_root);
+    outputs.extend(post_outputs(&outputs, config));
+    let mut excluded = find_clean_targets(project_root, &config.clean_patterns())?;
+    excluded.extend(outputs.iter().cloned());
+    let files = crate::remote::bundle_files(project_root, &config.project.package_dir, &excluded)?;
+    let mut bundle = Vec::new();
+    crate::remote::write_bundle(project_root, &files, &mut bundle)?;
+    println!("📤 Sending {} files ({}) to {}", files.len(), crate::cache::format_size(bundle.len() as u64), remote);
+    
+    let target = target.map(|target| target.to_string_lossy().replace('\\', "/"));
+    let profile = crate::config::active_profile().filter(|name| config.project.compile.profile(name).is_some());
+    let mut success = match remote_target {
+        RemoteTarget::Http(server) => crate::remote::compile_over_http(&server, bundle, target.as_deref(), profile, project_root).await?,
+        RemoteTarget::Ssh { host, dir } => {
+            let outputs: Vec<PathBuf> = outputs
+                .iter()
+                .filter_map(|output| output.strip_prefix(project_root).ok().map(Path::to_path_buf))
+                .collect();
+            let compile = crate::remote::remote_compile_command(target.as_deref(), profile);
+            crate::remote::compile_over_ssh(&host, dir.as_deref(), &config.project.name, &bundle, &compile, &outputs, project_root)?
+        }
+    };
+    
+    // The fetched PDFs are checked here as well, so the report is part of the summary
+    let mut reports = Vec::new();
+    if let Some(validation) = validation.filter(|_| success) {
+        let documents = built_documents(resolved_commands, config, project_root);
+        reports = validate_documents(&documents, config, validation, project_root, environment)?;
+        success = reports.iter().all(|report| report.passed);
+    }
+    
+    if success {
+        println!("🎉 Remote compilation completed successfully!");
+    } else {
+        println!("💥 Remote compilation failed!");
+    }
+    crate::ui::summary(serde_json::json!({
+        "command": "compile",
+        "success": success,
+        "remote": remote,
+        "validation": reports,
+    }));
+    if !success && crate::ui::is_non_interactive() {
+        anyhow::bail!("Compilation failed");
+    }
+    Ok(success)
+}
 
+/// Validate the final PDF of each built document (after its post-processing
+/// steps) and print the results
+fn validate_documents(
+    documents: &[PathBuf],
+    config: &Config,
+    validation: &crate::config::Validation,
+    project_root: &Path,
+    environment: &[(String, String)],
+) -> Result<Vec<crate::validate::ValidationReport>> {
+    let mut reports = Vec::new();
+    for document in documents.iter().filter(|document| document.is_file()) {
+        let pdf = crate::postprocess::final_output(&config.project.compile.post, document);
+        let name = pdf.strip_prefix(project_root).unwrap_or(&pdf).display().to_string();
+        println!("🔍 Validating {} against PDF/A-{}", name, validation.pdfa);
+        let report = crate::validate::validate_pdf(&pdf, validation, project_root, environment)?;
+        print_validation_report(&report);
+        reports.push(report);
+    }
+    Ok(reports)
+}
 
+fn print_validation_report(report: &crate::validate::ValidationReport) {
+    if report.passed {
+        println!("  ✅ Passed ({})", report.validator);
+    } else {
+        println!("  ❌ Failed ({}): {} rule(s) not met", report.validator, report.failures.len());
+    }
+    for failure in &report.failures {
+        if failure.count > 1 {
+            println!("     ✗ {} ({} times): {}", failure.rule, failure.count, failure.description);
+        } else {
+            println!("     ✗ {}: {}", failure.rule, failure.description);
+        }
+    }
+    for warning in &report.warnings {
+        println!("     ⚠️  {}", warning);
+    }
+}
+
+/// Restore the outputs of a cached build made from the same inputs; false
+/// when there is none and the chain has to run
+fn restore_cached_build(build_cache: &crate::build_cache::BuildCache, chain: &[String], outputs: &[PathBuf], config: &Config) -> Result<bool> {
+    let inputs = build_cache.input_files(outputs, &config.project.package_dir)?;
+    let Some((key, manifest)) = build_cache.lookup(chain, &inputs)? else {
+        return Ok(false);
+    };
+    let restored = build_cache.restore(&key, &manifest)?;
+    if restored.is_empty() {
+        println!("♻️  Nothing changed since the last build; skipping the compilation chain (--force runs it anyway)");
+    } else {
+        println!("♻️  Inputs match an earlier build; restored {} from the build cache", restored.join(", "));
+    }
+    Ok(true)
+}
+
+/// The compact per-step summary shown at the end of `compile --verbose`
+fn print_compile_timings(timings: &crate::bench::CompileTimings) {
+    let width = timings.steps.iter().map(|step| step.command.chars().count() + 4).max().unwrap_or(0).max(7);
+    println!();
+    println!("⏱️  Step timings:");
+    for (i, step) in timings.steps.iter().enumerate() {
+        let memory = step
+            .peak_memory_kb
+            .map(|kb| format!("  peak {}", crate::cache::format_size(kb * 1024)))
+            .unwrap_or_default();
+        println!("  {:<width$} {:>8.2}s{}", format!("{}. {}", i + 1, step.command), step.seconds, memory, width = width);
+    }
+    println!("  {:<width$} {:>8.2}s", "Total", timings.total_seconds(), width = width);
+}
+
+/// Install the packages a failed compile step reports missing in its output
+/// (skipping ones already tried); true if any was installed, so the step is
+/// worth running again
+async fn install_packages_missing_in(log: &str, attempted: &mut std::collections::HashSet<String>) -> Result<bool> {
+    let names: Vec<String> = new_parser()?
+        .parse_compilation_errors(log)
+        .into_iter()
+        .filter(|name| !attempted.contains(name))
+        .collect();
+    if names.is_empty() {
+        println!("   No new missing packages in the output; not retrying");
+        return Ok(false);
+    }
+    // A file may belong to a package tried for another file in an earlier round
+    let packages: Vec<String> = resolve_owning_packages(&names)
+        .await
+        .into_iter()
+        .filter(|package| names.contains(package) || !attempted.contains(package))
+        .collect();
+    attempted.extend(names);
+    attempted.extend(packages.iter().cloned());
+    
+    let manager = PackageManager::new(false)?;
+    let mut installed = Vec::new();
+    for package in packages {
+        println!("📦 Installing missing package {}...", package);
+        match manager.install(&package).await {
+            Ok(files) => {
+                println!("  ✓ {} installed successfully", package);
+                report_unfindable_files(&manager, &files);
+                installed.push(package);
+            }
+            Err(e) => println!("  ✗ Failed to install {}: {}", package, e),
+        }
+    }
+    
+    if !installed.is_empty() {
+        report_shadowing(&installed).await;
+        if let Err(e) = manager.apply_cache_policy().await {
+            println!("Warning: Failed to clean cache: {}", e);
+        }
+    }
+    Ok(!installed.is_empty())
+}
+
+pub async fn bench_command(action: &BenchAction) -> Result<()> {
+    match action {
+        BenchAction::Compile { path, runs, cold, save_baseline } => bench_compile(path, *runs, *cold, *save_baseline),
+    }
+}
+
+/// Time `runs` runs of the project's compile chain, print per-step statistics
+/// and compare them with the saved baseline
+fn bench_compile(path: &str, runs: u32, cold: bool, save_baseline: bool) -> Result<()> {
+    use crate::bench::{BenchResult, Stats};
+    
+    let project_root = resolve_project_path(path)?;
+    let config = Config::load_project_in(&project_root)?.unwrap_or_else(Config::new);
+    let environment = crate::environment::project_environment(&config, &project_root)?;
+    let dependencies = new_parser()?.parse_project(&project_root)?;
+    let mut compile = with_package_steps(&config.project.compile, &dependencies);
+    crate::chain::apply_engine_options(&mut compile);
+    // The steps are timed one at a time, in an order that respects their `needs`
+    let order = crate::chain::step_stages(&crate::chain::step_dependencies(&compile.steps)?)?.concat();
+    let mut commands = compile.resolve_variables(&config.chain_variables(&project_root))?;
+    commands = order.into_iter().map(|i| std::mem::take(&mut commands[i])).collect();
+    if commands.is_empty() {
+        anyhow::bail!("No compilation steps defined. Configure compilation chain in tpmgr.toml");
+    }
+    let steps: Vec<String> = commands.iter().map(|command| command.join(" ")).collect();
+    
+    println!("⏱️  Benchmarking {} {} runs of the compile chain in: {}", runs, if cold { "cold" } else { "warm" }, project_root.display());
+    for (i, step) in steps.iter().enumerate() {
+        println!("  {}. {}", i + 1, step);
+    }
+    println!();
+    
+    if !cold {
+        // The first build writes the .aux files that later runs reuse
+        println!("🔥 Warm-up run...");
+        crate::bench::time_chain(&commands, &project_root, &environment)?;
+    }
+    let mut result = BenchResult { steps, cold, runs: Vec::new() };
+    for run in 1..=runs {
+        if cold {
+            let targets = find_clean_targets(&project_root, &config.clean_patterns())?;
+            let (targets, _) = split_kept_files(&config, &project_root, targets);
+            remove_clean_targets(&project_root, &targets);
+        }
+        let timings = crate::bench::time_chain(&commands, &project_root, &environment)?;
+        println!("  Run {}/{}: {:.2}s", run, runs, timings.iter().sum::<f64>());
+        result.runs.push(timings);
+    }
+    
+    let width = result.steps.iter().map(|step| step.chars().count() + 4).max().unwrap_or(0).max(7);
+    let row = |label: &str, stats: Option<Stats>| {
+        if let Some(stats) = stats {
+            println!(
+                "  {:<width$} {:>8.2}s {:>8.2}s {:>8.2}s {:>8.2}s {:>8.2}s",
+                label, stats.mean, stats.median, stats.min, stats.max, stats.stddev, width = width
+            );
+        }
+    };
+    println!();
+    println!("  {:<width$} {:>9} {:>9} {:>9} {:>9} {:>9}", "Step", "mean", "median", "min", "max", "stddev", width = width);
+    for (i, step) in result.steps.iter().enumerate() {
+        row(&format!("{}. {}", i + 1, step), result.step_stats(i));
+    }
+    row("Total", result.total_stats());
+    
+    if let Some(baseline) = BenchResult::load_baseline(&project_root)? {
+        println!();
+        println!("📊 Compared with the baseline ({} {} runs):", baseline.runs.len(), if baseline.cold { "cold" } else { "warm" });
+        let compare = |label: &str, before: Option<Stats>, after: Option<Stats>| {
+            if let (Some(before), Some(after)) = (before, after) {
+                println!(
+                    "  {:<width$} {:>8.2}s → {:.2}s ({:+.1}%)",
+                    label, before.mean, after.mean, crate::bench::change_percent(before.mean, after.mean), width = width
+                );
+            }
+        };
+        if baseline.steps == result.steps {
+            for (i, step) in result.steps.iter().enumerate() {
+                compare(&format!("{}. {}", i + 1, step), baseline.step_stats(i), result.step_stats(i));
+            }
+        } else {
+            println!("  The baseline ran a different chain:");
+            for (i, step) in baseline.steps.iter().enumerate() {
+                println!("    {}. {}", i + 1, step);
+            }
+        }
+        compare("Total", baseline.total_stats(), result.total_stats());
+        if baseline.cold != result.cold {
+            println!("⚠️  The baseline was measured {}; compare like with like for meaningful numbers", if baseline.cold { "cold" } else { "warm" });
+        }
+    } else if let Some(last) = crate::bench::CompileTimings::load(&project_root)? {
+        // Without a baseline, the latest `tpmgr compile` of the same chain is the reference
+        let last_steps: Vec<&str> = last.steps.iter().map(|step| step.command.as_str()).collect();
+        if last_steps == result.steps {
+            if let Some(total) = result.total_stats() {
+                println!();
+                println!(
+                    "📊 The latest tpmgr compile took {:.2}s ({:+.1}% against the mean here)",
+                    last.total_seconds(),
+                    crate::bench::change_percent(total.mean, last.total_seconds())
+                );
+            }
+        }
+    }
+    
+    if save_baseline {
+        result.save_baseline(&project_root)?;
+        println!();
+        println!("💾 Saved as the baseline: {}", crate::bench::baseline_path(&project_root).display());
+    }
+    Ok(())
+}
+
+pub async fn bib_command(action: &BibAction) -> Result<()> {
+    match action {
+        BibAction::Add { ids, file, path } => bib_add(ids, file.as_deref(), path).await,
+        BibAction::Tidy { file, dry_run, path } => bib_tidy(file.as_deref(), *dry_run, path),
+    }
+}
+
+/// The .bib file `bib` commands work on: `--file`, else the first one the
+/// documents use, else references.bib in the project root
+fn bib_file(project_root: &Path, file: Option<&str>) -> Result<PathBuf> {
+    if let Some(file) = file {
+        return Ok(PathBuf::from(file));
+    }
+    let dependencies = TeXParser::new()?.parse_project(project_root)?;
+    let files = crate::bib::project_bib_files(project_root, &dependencies)?;
+    if files.len() > 1 {
+        let names: Vec<String> = files
+            .iter()
+            .map(|file| file.strip_prefix(project_root).unwrap_or(file).display().to_string())
+            .collect();
+        println!("ℹ️  The project uses {}; using the first (choose another with --file)", names.join(", "));
+    }
+    Ok(files.into_iter().next().unwrap_or_else(|| project_root.join("references.bib")))
+}
+
+/// Fetch entries for DOIs and arXiv IDs and append the new ones to the .bib file
+async fn bib_add(ids: &[String], file: Option<&str>, path: &str) -> Result<()> {
+    use crate::bib::{Added, Identifier};
+    
+    let project_root = resolve_project_path(path)?;
+    let bib_path = bib_file(&project_root, file)?;
+    let bib_name = bib_path.strip_prefix(&project_root).unwrap_or(&bib_path).display().to_string();
+    let content = if bib_path.is_file() { std::fs::read_to_string(&bib_path)? } else { String::new() };
+    let mut entries = crate::bib::parse_bib(&content);
+    let existing = entries.len();
+    
+    let mut added = Vec::new();
+    let mut duplicates = Vec::new();
+    let mut failed = Vec::new();
+    for id in ids {
+        let identifier = match Identifier::parse(id) {
+            Ok(identifier) => identifier,
+            Err(e) => {
+                println!("  ✗ {}", e);
+                failed.push(id.clone());
+                continue;
+            }
+        };
+        // Known works are not fetched again
+        if let Some(entry) = identifier.find_in(&entries) {
+            println!("↷ {} is already in {} as {}", identifier, bib_name, entry.key);
+            duplicates.push(entry.key.clone());
+            continue;
+        }
+        
+        println!("📚 Fetching {}...", identifier);
+        match crate::bib::fetch_entry(&identifier).await {
+            Ok(entry) => match crate::bib::add_entry(&mut entries, entry) {
+                Added::New(key) => {
+                    println!("  ✓ Added {}", key);
+                    added.push(key);
+                }
+                Added::Duplicate(key) => {
+                    println!("  ↷ Already in {} as {}", bib_name, key);
+                    duplicates.push(key);
+                }
+            },
+            Err(e) => {
+                println!("  ✗ {}", e);
+                failed.push(id.clone());
+            }
+        }
+    }
+    
+    if !added.is_empty() {
+        crate::bib::append_entries(&bib_path, &entries[existing..])?;
+        println!("\n✅ Added {} entr{} to {}", added.len(), if added.len() == 1 { "y" } else { "ies" }, bib_name);
+        if content.is_empty() {
+            println!("   Load it in the document with \\addbibresource{{{}}} or \\bibliography{{{}}}", bib_name, bib_name.trim_end_matches(".bib"));
+        }
+    }
+    crate::ui::summary(serde_json::json!({
+        "command": "bib add",
+        "file": bib_path,
+        "added": added,
+        "duplicates": duplicates,
+        "failed": failed,
+    }));
+    if !failed.is_empty() && crate::ui::is_non_interactive() {
+        anyhow::bail!("{} of {} entries could not be fetched", failed.len(), ids.len());
+    }
+    Ok(())
+}
+
+/// Normalize, deduplicate and sort the project's .bib files, and report the
+/// entries no document cites
+fn bib_tidy(file: Option<&str>, dry_run: bool, path: &str) -> Result<()> {
+    let project_root = resolve_project_path(path)?;
+    let dependencies = TeXParser::new()?.parse_project(&project_root)?;
+    let citations = crate::bib::cited_keys(&dependencies);
+    let files = match file {
+        Some(file) => vec![PathBuf::from(file)],
+        None => crate::bib::project_bib_files(&project_root, &dependencies)?,
+    };
+    let files: Vec<PathBuf> = files.into_iter().filter(|file| file.is_file()).collect();
+    if files.is_empty() {
+        println!("No .bib files found in {}", project_root.display());
+        return Ok(());
+    }
+    
+    let mut summaries = Vec::new();
+    for bib_path in &files {
+        let bib_name = bib_path.strip_prefix(&project_root).unwrap_or(bib_path).display().to_string();
+        let content = std::fs::read_to_string(bib_path)?;
+        let (entries, report) = crate::bib::tidy(crate::bib::parse_bib(&content), &citations);
+        let tidied = crate::bib::format_bib(&entries);
+        let changed = tidied != content;
+        
+        println!("🧹 {}", bib_name);
+        for (removed, kept) in &report.removed {
+            println!("  ✓ Removed {} (same work as {})", removed, kept);
+        }
+        for (old, new) in &report.renamed {
+            println!("  ✓ Renamed {} to {} to match the citations", old, new);
+        }
+        for (first, second) in &report.cited_duplicates {
+            println!("  ⚠️  {} and {} are the same work but both are cited; cite one and run tidy again", first, second);
+        }
+        if !report.uncited.is_empty() {
+            println!("  ⚠️  Never cited ({}): {}", report.uncited.len(), report.uncited.join(", "));
+        }
+        let citable = entries.iter().filter(|entry| entry.is_citable()).count();
+        let count = format!("{} entr{}", citable, if citable == 1 { "y" } else { "ies" });
+        match (changed, dry_run) {
+            (false, _) => println!("  Already tidy ({})", count),
+            (true, true) => println!("  Would rewrite {} ({})", bib_name, count),
+            (true, false) => {
+                std::fs::write(bib_path, &tidied)?;
+                println!("  ✅ Tidied {}", count);
+            }
+        }
+        summaries.push(serde_json::json!({
+            "file": bib_path,
+            "entries": citable,
+            "changed": changed,
+            "report": report,
+        }));
+    }
+    crate::ui::summary(serde_json::json!({
+        "command": "bib tidy",
+        "dry_run": dry_run,
+        "files": summaries,
+    }));
+    Ok(())
+}
+
+pub fn synctex_command(action: &SynctexAction) -> Result<()> {
+    match action {
+        SynctexAction::View { line, file, column, viewer, path } => synctex_view(*line, file.as_deref(), *column, viewer.as_deref(), path),
+    }
+}
+
+/// `synctex view`: find `line` of the source in the built PDF and print the
+/// location or hand it to a viewer's forward search
+fn synctex_view(line: u32, file: Option<&str>, column: u32, viewer: Option<&str>, path: &str) -> Result<()> {
+    let project_root = resolve_project_path(path)?.canonicalize()?;
+    let config = Config::load_project_in(&project_root)?.unwrap_or_else(Config::new);
+    let commands = config.project.compile.resolve_variables(&config.chain_variables(&project_root))?;
+    let source = match file {
+        Some(file) => PathBuf::from(file),
+        None => commands
+            .iter()
+            .flatten()
+            .find(|arg| arg.ends_with(".tex"))
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow::anyhow!("The compile chain names no .tex file; pass --file"))?,
+    };
+    let source = project_root.join(source);
+    let Some(pdf) = built_documents(&commands, &config, &project_root).into_iter().find(|pdf| pdf.is_file()) else {
+        anyhow::bail!("No PDF found; run `tpmgr compile` first");
+    };
+    if !pdf.with_extension("synctex.gz").is_file() && !pdf.with_extension("synctex").is_file() {
+        if !config.project.compile.synctex {
+            anyhow::bail!("{} has no SyncTeX data; set synctex = true under [project.compile] and compile again", pdf.display());
+        }
+        anyhow::bail!("{} has no SyncTeX data; run `tpmgr compile` again", pdf.display());
+    }
+    let name = source.strip_prefix(&project_root).unwrap_or(&source).display().to_string();
+    
+    if let Some(viewer) = viewer {
+        let command = crate::synctex::forward_search_command(viewer, &pdf, &source, line, column)?;
+        std::process::Command::new(&command[0])
+            .args(&command[1..])
+            .current_dir(&project_root)
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start {}: {}", command[0], e))?;
+        println!("👀 Showing {}:{} in {}", name, line, viewer);
+        return Ok(());
+    }
+    
+    let environment = crate::environment::project_environment(&config, &project_root)?;
+    let locations = crate::synctex::locate(&pdf, &source, line, column, &project_root, &environment)?;
+    if locations.is_empty() {
+        println!("❌ {}:{} is not in {}", name, line, pdf.display());
+    }
+    for location in &locations {
+        println!("📍 {}:{} → page {} (x {:.1}, y {:.1})", name, line, location.page, location.x, location.y);
+    }
+    crate::ui::summary(serde_json::json!({
+        "command": "synctex view",
+        "pdf": pdf,
+        "locations": locations,
+    }));
+    Ok(())
+}
+
+pub fn import_command(action: &ImportAction) -> Result<()> {
+    let Some(mut config) = Config::load_project_file()? else {
+        anyhow::bail!("No tpmgr.toml found; run 'tpmgr init' first");
+    };
+    let project_root = current_project_root()?;
+    let (source, imported, dry_run) = match action {
+        ImportAction::Latexmkrc { file, dry_run } => {
+            let source = match file {
+                Some(file) => PathBuf::from(file),
+                None => ["latexmkrc", ".latexmkrc"]
+                    .iter()
+                    .map(|name| project_root.join(name))
+                    .find(|path| path.is_file())
+                    .ok_or_else(|| anyhow::anyhow!("No latexmkrc or .latexmkrc in {}", project_root.display()))?,
+            };
+            let content = std::fs::read_to_string(&source).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", source.display(), e))?;
+            // Documents the rc does not name default to those of the current chain
+            let variables = config.chain_variables(&project_root);
+            let document = config.project.compile.document(&variables).unwrap_or(variables.main_file);
+            let dependencies = new_parser().and_then(|parser| parser.parse_project(&project_root)).unwrap_or_default();
+            (source, crate::import::from_latexmkrc(&content, &document, &dependencies), *dry_run)
+        }
+        ImportAction::Arara { file, dry_run } => {
+            let source = PathBuf::from(file);
+            let content = std::fs::read_to_string(&source).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", source.display(), e))?;
+            let document = source
+                .canonicalize()
+                .ok()
+                .and_then(|path| path.strip_prefix(&project_root).ok().map(|path| path.to_string_lossy().replace('\\', "/")))
+                .unwrap_or_else(|| file.clone());
+            (source, crate::import::from_arara(&content, &document), *dry_run)
+        }
+    };
+    if imported.steps.is_empty() {
+        anyhow::bail!("{} has no build steps tpmgr can convert", source.display());
+    }
+    
+    imported.apply(&mut config.project.compile);
+    println!("📥 Compile chain from {}:", source.display());
+    for (i, step) in imported.steps.iter().enumerate() {
+        println!("  {}. {} {}", i + 1, step.tool, step.args.join(" "));
+    }
+    if let Some(max_passes) = imported.max_passes {
+        println!("  max_passes = {}", max_passes);
+    }
+    for note in &imported.notes {
+        println!("⚠️  {}", note);
+    }
+    if dry_run {
+        println!("Dry run: tpmgr.toml was not changed");
+    } else {
+        config.save_project()?;
+        println!("✅ Saved the compile chain to tpmgr.toml");
+    }
+    crate::ui::summary(serde_json::json!({
+        "command": "import",
+        "source": source,
+        "chain": config.project.compile.to_string(),
+        "max_passes": imported.max_passes,
+        "notes": imported.notes,
+        "dry_run": dry_run,
+    }));
+    Ok(())
+}
+
+pub async fn stats_command(path: &str) -> Result<()> {
+    let project_root = resolve_project_path(path)?.canonicalize()?;
+    let parser = new_parser()?;
+    let mut sources = Vec::new();
+    TeXParser::collect_source_files(&project_root, &mut sources)?;
+    let mut dependencies = parser.parse_project(&project_root)?;
+    let sources_stats = crate::stats::SourceStats::count(&dependencies, sources.len());
+    let missing_figures = TeXParser::find_missing_figures(&dependencies, &project_root).len();
+    
+    let mut local_packages = TeXParser::find_local_packages(&project_root)?;
+    parser.add_local_package_dependencies(&mut dependencies, &mut local_packages);
+    let (local_provided, used): (Vec<String>, Vec<String>) = TeXParser::filter_core_packages(&TeXParser::get_unique_packages(&dependencies))
+        .into_iter()
+        .partition(|package| local_packages.contains_key(package));
+    
+    let manager = PackageManager::read_only(false)?;
+    let project_packages = manager.installed_packages().await?;
+    let footprint: u64 = project_packages.iter().map(|package| package.size).sum();
+    let mut texlive = TeXLiveManager::new();
+    let texlive_available = texlive.detect_texlive().is_ok() && texlive.scan_installed_packages().is_ok();
+    if texlive_available {
+        check_with_kpsewhich(&mut texlive, &used);
+    }
+    let (in_project, not_in_project): (Vec<&String>, Vec<&String>) = used
+        .iter()
+        .partition(|package| project_packages.iter().any(|installed| &installed.name == *package));
+    let (in_texlive, missing): (Vec<&String>, Vec<&String>) = not_in_project
+        .into_iter()
+        .partition(|package| texlive_available && texlive.is_package_installed(package));
+    let unused = project_packages.iter().filter(|package| !used.contains(&package.name)).count();
+    
+    println!("📊 {}", project_root.display());
+    println!("\n📄 Sources");
+    println!("   {} source files, {} documents", sources_stats.source_files, sources_stats.documents);
+    println!("   {} included files", sources_stats.included_files);
+    if missing_figures > 0 {
+        println!("   {} figures ({} missing)", sources_stats.figures, missing_figures);
+    } else {
+        println!("   {} figures", sources_stats.figures);
+    }
+    println!("   {} citations, {} bibliographies", sources_stats.citations, sources_stats.bibliographies);
+    
+    println!("\n📦 Packages");
+    println!("   {} used", used.len() + local_provided.len());
+    println!("   {} installed in the project, {} from TeX Live, {} provided by project files", in_project.len(), in_texlive.len(), local_provided.len());
+    if !missing.is_empty() {
+        println!("   ✗ {} missing: {}", missing.len(), missing.iter().map(|package| package.as_str()).collect::<Vec<_>>().join(", "));
+    }
+    println!("   {} packages installed in the project take {} on disk", project_packages.len(), crate::cache::format_size(footprint));
+    if unused > 0 {
+        println!("   {} installed packages are not used (remove them with 'tpmgr remove --unused')", unused);
+    }
+    
+    println!("\n🔨 Last build");
+    let last_build = crate::stats::LastBuild::load(&project_root, &sources);
+    match &last_build {
+        Some(build) => {
+            println!(
+                "   {} in {:.1}s{}, {} outputs ({})",
+                crate::release::date_from_timestamp(build.built_at),
+                build.duration_seconds,
+                if build.cached { " (from the build cache)" } else { "" },
+                build.outputs,
+                crate::cache::format_size(build.output_size)
+            );
+            if build.changed_sources > 0 {
+                println!("   ⚠️  {} sources changed since; run 'tpmgr compile' to rebuild", build.changed_sources);
+            } else {
+                println!("   ✓ Up to date with the sources");
+            }
+        }
+        None => println!("   Not built yet (or not with 'tpmgr compile')"),
+    }
+    
+    crate::ui::summary(serde_json::json!({
+        "command": "stats",
+        "sources": sources_stats,
+        "missing_figures": missing_figures,
+        "packages": {
+            "used": used.len() + local_provided.len(),
+            "project": in_project.len(),
+            "texlive": in_texlive.len(),
+            "local": local_provided.len(),
+            "missing": missing,
+            "installed": project_packages.len(),
+            "unused": unused,
+            "footprint": footprint,
+        },
+        "last_build": last_build,
+    }));
+    Ok(())
+}
+
+pub async fn status_command(path: &str) -> Result<()> {
+    let project_root = resolve_project_path(path)?.canonicalize()?;
+    let config = Config::load_project_in(&project_root)?;
+    if config.is_none() {
+        println!("No tpmgr.toml found in {}; treating every dependency as undeclared", project_root.display());
+    }
+    let declared = config.map(|config| config.dependencies).unwrap_or_default();
+    
+    let parser = new_parser()?;
+    let mut dependencies = parser.parse_project(&project_root)?;
+    let mut local_packages = TeXParser::find_local_packages(&project_root)?;
+    parser.add_local_package_dependencies(&mut dependencies, &mut local_packages);
+    let used: Vec<String> = TeXParser::filter_core_packages(&TeXParser::get_unique_packages(&dependencies))
+        .into_iter()
+        .filter(|package| !local_packages.contains_key(package))
+        .collect();
+    
+    let installed = PackageManager::read_only(false)?.installed_packages().await?;
+    let registry: std::collections::HashMap<String, String> = installed.iter().map(|package| (package.name.clone(), package.version.clone())).collect();
+    let mut texlive = TeXLiveManager::new();
+    let texlive_available = texlive.detect_texlive().is_ok() && texlive.scan_installed_packages().is_ok();
+    if texlive_available {
+        let not_in_project: Vec<String> = used.iter().filter(|package| !registry.contains_key(*package)).cloned().collect();
+        check_with_kpsewhich(&mut texlive, &not_in_project);
+    }
+    let report = crate::drift::DriftReport::detect(
+        &declared,
+        &registry,
+        &used,
+        |package| texlive_available && texlive.is_package_installed(package),
+        |package| installed.iter().find(|installed| installed.name == package).map(|installed| installed.requires.clone()).unwrap_or_default(),
+    );
+    
+    println!("📋 Dependency status of {}", project_root.display());
+    println!("   {} declared, {} installed in the project, {} used by the documents", declared.len(), registry.len(), used.len());
+    let sections: [(&str, &[String], &str); 5] = [
+        ("Declared in tpmgr.toml but not installed", &report.declared_not_installed, "run 'tpmgr install <package>'"),
+        ("Declared but not used by any document", &report.declared_unused, "remove them from [dependencies]"),
+        ("Used and installed, but not declared", &report.used_undeclared, "add them to [dependencies]"),
+        ("Used but not installed anywhere", &report.missing, "run 'tpmgr install'"),
+        ("Installed but neither declared nor used", &report.installed_unused, "run 'tpmgr remove --unused'"),
+    ];
+    for (title, packages, hint) in sections {
+        if !packages.is_empty() {
+            println!("\n⚠️  {} ({}):", title, hint);
+            for package in packages {
+                println!("  - {}", package);
+            }
+        }
+    }
+    if !report.version_mismatch.is_empty() {
+        println!("\n⚠️  Installed version differs from the declared one (run 'tpmgr update <package>' or change [dependencies]):");
+        for (package, declared, installed) in &report.version_mismatch {
+            println!("  - {}: declared {}, installed {}", package, declared, installed);
+        }
+    }
+    if report.is_empty() {
+        println!("\n✅ tpmgr.toml, the installed packages and the documents agree");
+    }
+    
+    crate::ui::summary(serde_json::json!({
+        "command": "status",
+        "drift": report,
+    }));
+    if crate::ui::is_non_interactive() && !report.is_empty() {
+        anyhow::bail!("The project's dependencies have drifted (see above)");
+    }
+    Ok(())
+}
+
+pub async fn clean_command(path: &str, dry_run: bool, deep: bool, patterns: &[String]) -> Result<()> {
+    let project_root = resolve_project_path(path)?.canonicalize()?;
+    let config = Config::load_project_in(&project_root)?.unwrap_or_else(Config::new);
+    
+    let patterns = if patterns.is_empty() {
+        config.clean_patterns()
+    } else {
+        patterns.to_vec()
+    };
+    let mut targets = find_clean_targets(&project_root, &patterns)?;
+    if deep {
+        for pdf in output_documents(&config, &project_root)? {
+            if pdf.is_file() && !targets.contains(&pdf) {
+                targets.push(pdf);
+            }
+        }
+        targets.sort();
+    }
+    let (targets, kept) = split_kept_files(&config, &project_root, targets);
+    let cache_dir = project_root.join(".tpmgr");
+    let clean_cache = deep && cache_dir.is_dir();
+    
+    if dry_run && !kept.is_empty() {
+        println!("🛡️  Kept by clean.keep:");
+        print_files_by_directory(&project_root, &kept);
+    }
+    if targets.is_empty() && !clean_cache {
+        println!("Nothing to clean in {}", project_root.display());
+        return Ok(());
+    }
+    
+    if dry_run {
+        println!("🧹 Would remove:");
+        print_files_by_directory(&project_root, &targets);
+        if clean_cache {
+            println!("   .tpmgr/ (package cache)");
+        }
+        return Ok(());
+    }
+    
+    println!("🧹 Cleaning {}...", project_root.display());
+    let removed = remove_clean_targets(&project_root, &targets);
+    if clean_cache {
+        std::fs::remove_dir_all(&cache_dir)?;
+        println!("   .tpmgr/ (package cache)");
+    }
+    println!("✅ Cleaned {} files", removed);
+    
+    Ok(())
+}
+
+pub async fn env_command() -> Result<()> {
+    let config = Config::load_project_or_default()?;
+    for (name, value) in crate::environment::project_environment(&config, &current_project_root()?)? {
+        println!("{}={}", name, value);
+    }
+    
+    Ok(())
+}
+
+pub async fn exec_command(command: &[String]) -> Result<()> {
+    let config = Config::load_project_or_default()?;
+    let environment = crate::environment::project_environment(&config, &current_project_root()?)?;
+    let (program, args) = command.split_first().ok_or_else(|| anyhow::anyhow!("No command given"))?;
+    
+    let status = std::process::Command::new(program)
+        .args(args)
+        .envs(environment)
+        .status()
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", program, e))?;
+    if !status.success() {
+        // Pass the tool's exit code on, as if it had been run directly
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    
+    Ok(())
+}
+
+/// Set inside `tpmgr shell` to the project root, e.g. for use in a prompt
+const SHELL_MARKER_VARIABLE: &str = "TPMGR_SHELL";
+
+pub async fn shell_command() -> Result<()> {
+    if let Ok(project) = std::env::var(SHELL_MARKER_VARIABLE) {
+        println!("⚠️  Already inside a tpmgr shell for {}", project);
+    }
+    
+    let config = Config::load_project_or_default()?;
+    let project_root = current_project_root()?;
+    let environment = crate::environment::project_environment(&config, &project_root)?;
+    let shell = if cfg!(windows) {
+        std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string())
+    } else {
+        std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+    };
+    
+    println!("🐚 Starting {} with the TeX environment of {}", shell, project_root.display());
+    println!("   Type 'exit' to leave");
+    let status = std::process::Command::new(&shell)
+        .envs(environment)
+        .env(SHELL_MARKER_VARIABLE, &project_root)
+        .status()
+        .map_err(|e| anyhow::anyhow!("Failed to start {}: {}", shell, e))?;
+    println!("Left the tpmgr shell");
+    if !status.success() {
+        std::process::exit(status.code().unwrap_or(1));
+    }
+    
+    Ok(())
+}
+
+/// The PDFs produced by the compile chain: one per .tex document it compiles,
+/// in the project root and in the configured output directory, plus the
+/// files its post-processing steps write
+fn output_documents(config: &Config, project_root: &Path) -> Result<Vec<PathBuf>> {
+    let commands = config.project.compile.resolve_variables(&config.chain_variables(project_root))?;
+    let mut documents = built_documents(&commands, config, project_root);
+    documents.extend(post_outputs(&documents, config));
+    Ok(documents)
+}
+
+/// Everything a build leaves next to `documents`: the PDFs, the files of the
+/// post-processing steps and, with `synctex`, the SyncTeX data
+fn produced_files(documents: &[PathBuf], config: &Config) -> Vec<PathBuf> {
+    let mut files = documents.to_vec();
+    files.extend(post_outputs(documents, config));
+    if config.project.compile.synctex {
+        files.extend(documents.iter().map(|pdf| pdf.with_extension("synctex.gz")));
+    }
+    files
+}
+
+/// Write `.tpmgr/build-manifest.json` for a successful build; returns its
+/// path, or None (with a warning) when it could not be written
+fn write_build_manifest(
+    config: &Config,
+    project_root: &Path,
+    commands: &[Vec<String>],
+    documents: &[PathBuf],
+    started: std::time::Instant,
+    cached: bool,
+    environment: &[(String, String)],
+) -> Option<PathBuf> {
+    let duration = started.elapsed().as_secs_f64();
+    let written = crate::manifest::BuildManifest::collect(config, project_root, commands, &produced_files(documents, config), duration, cached, environment)
+        .and_then(|manifest| manifest.save(project_root));
+    match written {
+        Ok(path) => Some(path),
+        Err(e) => {
+            println!("Warning: Failed to write the build manifest: {}", e);
+            None
+        }
+    }
+}
+
+/// The extra files `[[project.compile.post]]` steps write next to `documents`
+fn post_outputs(documents: &[PathBuf], config: &Config) -> Vec<PathBuf> {
+    let mut outputs = Vec::new();
+    for document in documents {
+        for output in crate::postprocess::post_outputs(&config.project.compile.post, document) {
+            if !documents.contains(&output) && !outputs.contains(&output) {
+                outputs.push(output);
+            }
+        }
+    }
+    outputs
+}
+
+/// The PDFs a resolved compile chain produces from the .tex files it is given
+fn built_documents(commands: &[Vec<String>], config: &Config, project_root: &Path) -> Vec<PathBuf> {
+    let mut documents = Vec::new();
+    for arg in commands.iter().flatten() {
+        if !arg.ends_with(".tex") {
+            continue;
+        }
+        let Some(file_name) = Path::new(arg).with_extension("pdf").file_name().map(PathBuf::from) else {
+            continue;
+        };
+        let mut candidates = vec![project_root.join(&file_name)];
+        if let Some(output_dir) = &config.project.output_dir {
+            candidates.push(project_root.join(output_dir).join(&file_name));
+        }
+        for candidate in candidates {
+            if !documents.contains(&candidate) {
+                documents.push(candidate);
+            }
+        }
+    }
+    documents
+}
+
+/// Print figures referenced by \includegraphics that do not exist on disk.
+/// Returns the number of missing figures.
+fn report_missing_figures(dependencies: &[crate::tex_parser::TeXDependency], base_dir: &Path) -> usize {
+    let missing_figures = TeXParser::find_missing_figures(dependencies, base_dir);
+    
+    if !missing_figures.is_empty() {
+        println!("\n⚠️  Missing figures:");
+        for figure in &missing_figures {
+            println!("  ✗ {} (line {}): {}", figure.package_name, figure.line_number, figure.context);
+        }
+    }
+    
+    missing_figures.len()
+}
+
+/// Report packages loaded twice with clashing options, with both loads
+fn report_option_clashes(dependencies: &[crate::tex_parser::TeXDependency], base_dir: &Path) -> usize {
+    let clashes = TeXParser::find_option_clashes(dependencies);
+    let location = |dep| dependency_location(dep, base_dir);
+    
+    if !clashes.is_empty() {
+        println!("\n⚠️  Option clashes (LaTeX stops with \"Option clash for package ...\"):");
+        for clash in &clashes {
+            println!("  ✗ {} loaded with [{}] at {}", clash.package, clash.first.options.join(","), location(&clash.first));
+            println!("      and again with [{}] at {}: {}", clash.clashing.options.join(","), location(&clash.clashing), clash.clashing.context);
+        }
+        println!("  Load each package once with all its options, or pass them with \\PassOptionsToPackage before the first load");
+    }
+    
+    clashes.len()
+}
+
+/// Warn about packages loaded in an order known to cause trouble, with the move that fixes it
+fn report_load_order(dependencies: &[crate::tex_parser::TeXDependency], base_dir: &Path, engine: Option<&str>) -> usize {
+    let mut warnings: Vec<crate::load_order::LoadOrderWarning> = Vec::new();
+    for loads in TeXParser::load_sequences(dependencies) {
+        for warning in crate::load_order::check(&loads, engine) {
+            let same = |known: &crate::load_order::LoadOrderWarning| {
+                dependency_location(&known.package, base_dir) == dependency_location(&warning.package, base_dir)
+                    && known.before.package_name == warning.before.package_name
+            };
+            if !warnings.iter().any(same) {
+                warnings.push(warning);
+            }
+        }
+    }
+    
+    if !warnings.is_empty() {
+        println!("\n⚠️  Package load order:");
+        for warning in &warnings {
+            println!(
+                "  ✗ Move {} ({}) above {} ({})",
+                warning.package.package_name,
+                dependency_location(&warning.package, base_dir),
+                warning.before.package_name,
+                dependency_location(&warning.before, base_dir)
+            );
+            println!("      {}", warning.reason);
+        }
+    }
+    
+    warnings.len()
+}
+
+/// "file:line" of a dependency, relative to `base_dir`
+fn dependency_location(dependency: &crate::tex_parser::TeXDependency, base_dir: &Path) -> String {
+    match &dependency.source_file {
+        Some(file) => format!("{}:{}", file.strip_prefix(base_dir).unwrap_or(file).display(), dependency.line_number),
+        None => format!("line {}", dependency.line_number),
+    }
+}
+
+/// Map missing .sty/.cls/font files to their TeX Live packages using the mirror's package index
+async fn resolve_owning_packages(names: &[String]) -> Vec<String> {
+    if names.is_empty() {
+        return Vec::new();
+    }
+    
+    let mirror_url = project_mirror_url();
+    let mut texlive = TeXLiveManager::new();
+    if let Err(e) = texlive.fetch_remote_file_index(&mirror_url).await {
+        println!("Warning: Failed to load package index, using file names as package names: {}", e);
+    }
+    
+    let mut packages = Vec::new();
+    for name in names {
+        let package = if let Some(font_name) = name.strip_prefix(crate::tex_parser::FONT_NAME_PREFIX) {
+            match texlive.package_for_font_name(font_name) {
+                Some(package) => package.to_string(),
+                None => {
+                    println!("  ⚠️  No TeX Live package provides font '{}'; install it on the system instead", font_name);
+                    continue;
+                }
+            }
+        } else {
+            texlive.resolve_package_name(name)
+        };
+        if package != *name {
+            println!("  '{}' is provided by package '{}'", name, package);
+        }
+        if !packages.contains(&package) {
+            packages.push(package);
+        }
+    }
+    packages
+}
+
+/// Check that the TeX engine will find freshly installed files, so a broken
+/// layout or TEXINPUTS shows up now instead of at the next compile
+fn report_unfindable_files(manager: &PackageManager, files: &[PathBuf]) {
+    // Without kpsewhich (no TeX installation) there is nothing to check against
+    let Ok(unfindable) = manager.unfindable_files(files) else {
+        return;
+    };
+    for file in unfindable {
+        let (variable, search_path) = manager.search_path_for(&file);
+        println!("  ⚠️  kpsewhich cannot find {} with {}={}", file.display(), variable, search_path);
+    }
+}
+
+/// Installs above either limit ask for confirmation first
+const CONFIRM_PACKAGE_COUNT: usize = 10;
+const CONFIRM_DOWNLOAD_MB: u64 = 100;
+
+/// The install plan of each package: None if it is already installed
+async fn plan_installs(manager: &PackageManager, packages: &[String]) -> Vec<(String, Result<Option<InstallStep>>)> {
+    let mut plan = Vec::new();
+    for package_name in packages {
+        plan.push((package_name.clone(), manager.plan_install(package_name).await));
+    }
+    plan
+}
+
+/// Print what installing the packages would do: the resolved version and
+/// repository, declared dependencies, download size and files written
+async fn print_install_plan(manager: &PackageManager, packages: &[String]) {
+    println!("📋 Install plan:");
+    let mut planned = 0;
+    let mut download_size = 0;
+    for (package_name, step) in plan_installs(manager, packages).await {
+        let step = match step {
+            Ok(Some(step)) => step,
+            Ok(None) => {
+                println!("  {}: already installed", package_name);
+                continue;
+            }
+            Err(e) => {
+                println!("  ✗ {}: {}", package_name, e);
+                continue;
+            }
+        };
+        
+        planned += 1;
+        if let Some(tlmgr) = &step.tlmgr {
+            println!("  {} [installed with {}]", step.info.name, tlmgr.display());
+            continue;
+        }
+        download_size += step.download_size();
+        let download = if step.cached { " (cached)".to_string() } else { format_download_size(step.info.size) };
+        println!("  {} {} [{}]{}", step.info.name, step.info.version, step.info.repository, download);
+        if !step.dependencies.is_empty() {
+            let dependencies: Vec<String> = step.dependencies
+                .iter()
+                .map(|(name, installed)| if *installed { format!("{} (installed)", name) } else { name.clone() })
+                .collect();
+            println!("      requires: {}", dependencies.join(", "));
+        }
+        match &step.files {
+            Some(files) => {
+                for file in files {
+                    println!("      writes: {}", file.display());
+                }
+            }
+            None => println!("      writes: the archive contents (listed after download)"),
+        }
+    }
+    println!("Total: {} packages, {} to download", planned, crate::cache::format_size(download_size));
+    println!("No changes made (--dry-run)");
+}
+
+/// Ask before installing more than CONFIRM_PACKAGE_COUNT packages or
+/// downloading more than CONFIRM_DOWNLOAD_MB. Skipped with --yes, in
+/// non-interactive mode or when stdin is not a terminal.
+async fn confirm_install(manager: &PackageManager, packages: &[String], yes: bool) -> Result<bool> {
+    use std::io::IsTerminal;
+    
+    if yes || crate::ui::is_non_interactive() || !std::io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    
+    let steps: Vec<InstallStep> = plan_installs(manager, packages)
+        .await
+        .into_iter()
+        .filter_map(|(_, step)| step.ok().flatten())
+        .collect();
+    let download_size: u64 = steps.iter().map(InstallStep::download_size).sum();
+    if steps.len() <= CONFIRM_PACKAGE_COUNT && download_size <= CONFIRM_DOWNLOAD_MB * 1024 * 1024 {
+        return Ok(true);
+    }
+    
+    let name_width = steps.iter().map(|step| step.info.name.len()).max().unwrap_or(0).max("Package".len());
+    let version_width = steps.iter().map(|step| step.info.version.len()).max().unwrap_or(0).max("Version".len());
+    let repository_width = steps.iter().map(|step| step.info.repository.len()).max().unwrap_or(0).max("Repository".len());
+    println!("  {:<name_width$}  {:<version_width$}  {:<repository_width$}  Download", "Package", "Version", "Repository");
+    for step in &steps {
+        let download = if step.cached {
+            "cached".to_string()
+        } else if step.tlmgr.is_some() {
+            "by tlmgr".to_string()
+        } else if step.info.size == 0 {
+            "unknown".to_string()
+        } else {
+            crate::cache::format_size(step.info.size)
+        };
+        println!("  {:<name_width$}  {:<version_width$}  {:<repository_width$}  {}",
+            step.info.name, step.info.version, step.info.repository, download);
+    }
+    println!("  Total: {} packages, {} to download", steps.len(), crate::cache::format_size(download_size));
+    
+    ask_yes_no(&format!("Install {} packages?", steps.len()), false)
+}
+
+/// ", 1.2 MB download" or nothing when the repository does not report a size
+fn format_download_size(size: u64) -> String {
+    if size == 0 {
+        String::new()
+    } else {
+        format!(", {} download", crate::cache::format_size(size))
+    }
+}
+
+/// The root of the current project, or the working directory outside a project
+fn current_project_root() -> Result<PathBuf> {
+    Ok(project_root_from(&std::env::current_dir()?))
+}
+
+/// The root of the project `start` belongs to, or `start` itself outside a project
+fn project_root_from(start: &Path) -> PathBuf {
+    crate::config::project_root_for(start).unwrap_or_else(|| start.to_path_buf())
+}
+
+/// The default path "." means the whole project, even when run from a subdirectory
+fn resolve_project_path(path: &str) -> Result<PathBuf> {
+    if path == "." {
+        current_project_root()
+    } else {
+        Ok(PathBuf::from(path))
+    }
+}
+
+/// The global config.toml (created if missing) or the project's tpmgr.toml
+fn config_file_path(global: bool) -> Result<PathBuf> {
+    if global {
+        let path = crate::config::GlobalConfig::get_config_path()?;
+        if !path.exists() {
+            crate::config::GlobalConfig::load()?.save()?;
+        }
+        Ok(path)
+    } else {
+        let path = crate::config::project_config_path();
+        if !path.is_file() {
+            anyhow::bail!("No tpmgr.toml found; run 'tpmgr init' to create a project or pass --global");
+        }
+        Ok(path)
+    }
+}
+
+fn check_config_file(content: &str, global: bool) -> Vec<crate::config_check::ConfigIssue> {
+    if global {
+        crate::config_check::check_global_config(content)
+    } else {
+        crate::config_check::check_project_config(content)
+    }
+}
+
+fn print_config_issues(issues: &[crate::config_check::ConfigIssue]) {
+    use crate::config_check::Severity;
+    
+    for issue in issues {
+        match issue.severity {
+            Severity::Error => println!("  ❌ {}", issue),
+            Severity::Warning => println!("  ⚠️  {}", issue),
+        }
+    }
+}
+
+/// Open a configuration file in the user's editor until it validates; if the
+/// user gives up, the previous contents are restored
+fn edit_config_file(path: &Path, global: bool) -> Result<()> {
+    let original = std::fs::read_to_string(path)?;
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    
+    loop {
+        // The editor may include arguments, e.g. "code --wait"
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        let status = std::process::Command::new(program)
+            .args(words)
+            .arg(path)
+            .status()
+            .map_err(|e| anyhow::anyhow!("Failed to start editor '{}': {}", editor, e))?;
+        if !status.success() {
+            anyhow::bail!("Editor '{}' exited with {}", editor, status);
+        }
+        
+        let content = std::fs::read_to_string(path)?;
+        let issues = check_config_file(&content, global);
+        if !crate::config_check::has_errors(&issues) {
+            print_config_issues(&issues);
+            if content == original {
+                println!("No changes made to {}", path.display());
+            } else {
+                println!("✓ Saved {}", path.display());
+            }
+            return Ok(());
+        }
+        
+        println!("❌ {} is invalid:", path.display());
+        print_config_issues(&issues);
+        if !ask_yes_no("Edit again?", true)? {
+            let mut rejected = path.as_os_str().to_owned();
+            rejected.push(".rejected");
+            std::fs::write(&rejected, &content)?;
+            std::fs::write(path, &original)?;
+            println!("Restored the previous configuration; your edits were saved to {}", Path::new(&rejected).display());
+            anyhow::bail!("Configuration not changed");
+        }
+    }
+}
+
+/// Ask a yes/no question on the terminal; an empty answer picks the default
+fn ask_yes_no(question: &str, default: bool) -> Result<bool> {
+    use std::io::Write;
+    
+    print!("{} [{}] ", question, if default { "Y/n" } else { "y/N" });
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        // No terminal input (e.g. piped from /dev/null)
+        println!();
+        return Ok(false);
+    }
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "" => default,
+        "y" | "yes" => true,
+        _ => false,
+    })
+}
+
+/// The TeX Live mirror configured for this project, falling back to the global setting
+fn project_mirror_url() -> String {
+    Config::load_project()
+        .ok()
+        .flatten()
+        .unwrap_or_else(Config::new)
+        .resolved_mirror_url()
+}
 
+/// List packages that provide commands/environments used without loading them
+fn report_package_suggestions(parser: &TeXParser, path: &Path, loaded_packages: &[String]) -> Result<()> {
+    let suggestions = parser.suggest_packages(path, loaded_packages)?;
+    
+    if suggestions.is_empty() {
+        println!("\n✓ No commands from unloaded packages found");
+        return Ok(());
+    }
+    
+    let mut by_package: std::collections::BTreeMap<&str, Vec<String>> = std::collections::BTreeMap::new();
+    for suggestion in &suggestions {
+        let location = match &suggestion.file {
+            Some(file) => format!("{} ({}:{})", suggestion.name, file.display(), suggestion.line_number),
+            None => format!("{} (line {})", suggestion.name, suggestion.line_number),
+        };
+        by_package.entry(suggestion.package.as_str()).or_default().push(location);
+    }
+    
+    println!("\n💡 Suggested packages for commands used without their package:");
+    for (package, usages) in by_package {
+        println!("  - {}: {}", package, usages.join(", "));
+    }
+    println!("\nAdd them with \\usepackage and run 'tpmgr install'");
+    
+    Ok(())
+}
 
+/// Report undefined citations and references from the main document's .log/.aux,
+/// which LaTeX writes to the project root (the directory the chain runs in)
+fn report_undefined_references(project_root: &Path, resolved_commands: &[Vec<String>]) -> usize {
+    let Some(main_document) = resolved_commands.iter().flatten().rev().find(|arg| arg.ends_with(".tex")) else {
+        return 0;
+    };
+    let Some(job_name) = Path::new(main_document).file_stem() else {
+        return 0;
+    };
+    let job = project_root.join(job_name);
+    let Ok(log) = std::fs::read(job.with_extension("log")) else {
+        return 0;
+    };
+    let aux = std::fs::read(job.with_extension("aux")).ok();
+    
+    let mut references = crate::references::find_undefined_references(
+        &String::from_utf8_lossy(&log),
+        aux.as_deref().map(String::from_utf8_lossy).as_deref(),
+    );
+    if references.is_empty() {
+        return 0;
+    }
+    
+    let mut source_files = Vec::new();
+    if TeXParser::collect_source_files(project_root, &mut source_files).is_ok() {
+        crate::references::locate_in_sources(&mut references, &source_files);
+    }
+    crate::references::print_report(&references, project_root)
+}
 
+/// Check that CJK documents have a suitable engine and their font/language packages
+fn report_cjk_support(packages: &[String], compile_cmd: &crate::config::CompileCommand, texlive: Option<&TeXLiveManager>) {
+    for support in crate::cjk::detect(packages) {
+        println!("\n🀄 CJK support ({}):", support.name);
+        
+        let engine = compile_cmd.tex_engine();
+        match &engine {
+            Some(engine) if support.engines.contains(&engine.as_str()) => {
+                println!("  ✓ Engine: {}", engine);
+            }
+            Some(engine) => {
+                println!("  ✗ Engine: {} does not work with {}", engine, support.name);
+                println!("    Fix: compile with {}, e.g.", support.engines[0]);
+                println!("         tpmgr config set compile \"{} -interaction=nonstopmode main.tex\"", support.engines[0]);
+            }
+            None => println!("  ⚠️  Engine: unknown, {} needs one of {}", support.name, support.engines.join(", ")),
+        }
+        
+        let Some(texlive) = texlive else {
+            continue;
+        };
+        
+        let required = support.required_packages(engine.as_deref());
+        let missing: Vec<&str> = required
+            .iter()
+            .copied()
+            .filter(|package| !texlive.is_package_installed(package))
+            .collect();
+        if missing.is_empty() {
+            println!("  ✓ Language packages: {}", required.join(", "));
+        } else {
+            println!("  ✗ Missing language packages: {}", missing.join(", "));
+        }
+        
+        match support.font_packages.iter().find(|package| texlive.is_package_installed(package)) {
+            Some(font_package) => println!("  ✓ Fonts: {}", font_package),
+            None => {
+                println!("  ✗ No CJK font package installed");
+                println!("    Fix: tpmgr install {}", support.font_packages[0]);
+            }
+        }
+    }
+}
+
+/// Warn about packages that need external programs or shell escape to compile
+fn report_external_tool_requirements(packages: &[String], compile_cmd: &crate::config::CompileCommand) {
+    for requirement in crate::tools::requirements_for(packages) {
+        let tool_found = crate::tools::find_executable(requirement.tool).is_some();
+        let shell_escape_missing = requirement.needs_shell_escape && !compile_cmd.has_shell_escape();
+        
+        if tool_found && !shell_escape_missing {
+            continue;
+        }
+        
+        println!("\n⚠️  Package '{}' needs extra setup:", requirement.package);
+        if !tool_found {
+            println!("  ✗ '{}' was not found on PATH", requirement.tool);
+            println!("    Fix: {}", requirement.install_hint);
+        }
+        if shell_escape_missing {
+            println!("  ✗ Shell escape is not enabled in the compile chain");
+            println!("    Fix: add -shell-escape to the LaTeX steps, e.g.");
+            println!("         tpmgr config set compile \"pdflatex -shell-escape -interaction=nonstopmode main.tex\"");
+        }
+    }
+}
 
-
-
-
-
-
+fn clean_intermediate_files(project_root: &Path, config: &Config) -> Result<()> {
+    let targets = find_clean_targets(project_root, &config.clean_patterns())?;
+    let (targets, _) = split_kept_files(config, project_root, targets);
+    let cleaned_count = remove_clean_targets(project_root, &targets);
+    
+    if cleaned_count > 0 {
+        println!("✅ Cleaned {} intermediate files", cleaned_count);
+    } else {
+        println!("   No intermediate files to clean");
+    }
+    
+    Ok(())
+}
+
+/// Files matching the clean patterns, skipping those excluded by .tpmgrignore.
+/// Patterns without a slash (e.g. `*.aux`) match in every subdirectory except
+/// the output directory; others are relative to the project root.
+fn find_clean_targets(project_root: &Path, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut targets = Vec::new();
+    let ignore_rules = crate::ignore::IgnoreRules::load(project_root)?;
+    
+    let (anchored, unanchored): (Vec<&String>, Vec<&String>) = patterns
+        .iter()
+        .partition(|pattern| pattern.contains('/') || pattern.contains('\\') || pattern.contains(':'));
+    
+    let name_patterns: Vec<glob::Pattern> = unanchored
+        .iter()
+        .filter_map(|pattern| match glob::Pattern::new(pattern) {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                println!("   Warning: Invalid glob pattern '{}': {}", pattern, e);
+                None
+            }
+        })
+        .collect();
+    if !name_patterns.is_empty() {
+        let mut files = Vec::new();
+        TeXParser::collect_project_files(project_root, &mut files)?;
+        for path in files {
+            let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            if name_patterns.iter().any(|pattern| pattern.matches(&file_name)) {
+                targets.push(path);
+            }
+        }
+    }
+    
+    for pattern in anchored {
+        // Convert pattern to absolute path relative to project root
+        let full_pattern = if pattern.starts_with('/') || pattern.contains(':') {
+            // Absolute pattern
+            pattern.clone()
+        } else {
+            // Relative pattern - make it relative to project root
+            project_root.join(pattern).to_string_lossy().to_string()
+        };
+        
+        // Use glob to find matching files
+        match glob::glob(&full_pattern) {
+            Ok(paths) => {
+                for path_result in paths {
+                    match path_result {
+                        Ok(path) => {
+                            let ignored = path.strip_prefix(project_root)
+                                .map(|relative_path| ignore_rules.is_ignored(relative_path, false))
+                                .unwrap_or(false);
+                            if path.is_file() && !ignored && !targets.contains(&path) {
+                                targets.push(path);
+                            }
+                        }
+                        Err(e) => {
+                            println!("   Warning: Pattern error for {}: {}", full_pattern, e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                println!("   Warning: Invalid glob pattern '{}': {}", full_pattern, e);
+            }
+        }
+    }
+    
+    targets.sort();
+    Ok(targets)
+}
+
+/// Split clean targets into files to delete and files protected by `clean.keep`
+fn split_kept_files(config: &Config, project_root: &Path, targets: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    targets
+        .into_iter()
+        .partition(|path| !config.clean.is_kept(path.strip_prefix(project_root).unwrap_or(path)))
+}
+
+/// Delete the given files, returning how many were removed
+fn remove_clean_targets(project_root: &Path, targets: &[PathBuf]) -> usize {
+    let mut removed = Vec::new();
+    for path in targets {
+        match std::fs::remove_file(path) {
+            Ok(_) => removed.push(path.clone()),
+            Err(e) => {
+                println!("   Warning: Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+    print_files_by_directory(project_root, &removed);
+    removed.len()
+}
+
+/// Print files grouped by their directory relative to the project root
+fn print_files_by_directory(project_root: &Path, files: &[PathBuf]) {
+    let mut directories: std::collections::BTreeMap<PathBuf, Vec<String>> = std::collections::BTreeMap::new();
+    for path in files {
+        let relative_path = path.strip_prefix(project_root).unwrap_or(path);
+        let directory = relative_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let file_name = relative_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        directories.entry(directory).or_default().push(file_name);
+    }
+    
+    for (directory, names) in directories {
+        let directory = if directory.as_os_str().is_empty() {
+            "./".to_string()
+        } else {
+            format!("{}/", directory.display())
+        };
+        println!("   {} ({}): {}", directory, names.len(), names.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_target_from_subdirectory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::new();
+        config.project.compile = crate::config::CompileCommand::from_string("pdflatex main.tex").unwrap();
+        config.save(dir.path().join(crate::config::PROJECT_CONFIG_FILE)).unwrap();
+        std::fs::write(dir.path().join("main.tex"), "\\documentclass{article}").unwrap();
+        let sections = dir.path().join("sections");
+        std::fs::create_dir(&sections).unwrap();
+
+        // From sections/, the compile target is still the project's main.tex
+        let project_root = project_root_from(&sections);
+        assert_eq!(project_root, dir.path().canonicalize().unwrap());
+        let commands = config.project.compile.resolve_variables(&config.chain_variables(&project_root)).unwrap();
+        let target = commands.last().and_then(|command| command.last()).unwrap();
+        assert!(project_root.join(target).is_file());
+        assert!(!sections.join(target).exists());
+    }
+
+    #[tokio::test]
+    async fn test_includeonly_needs_project_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.tex");
+        std::fs::write(&main, "\\includeonly{ch1}\n\\include{ch1}\n\\include{ch2}").unwrap();
+
+        let error = analyze_command(main.to_str().unwrap(), false, false, true, false).await.unwrap_err();
+        assert!(error.to_string().contains("--respect-includeonly needs a project directory"));
+    }
+}
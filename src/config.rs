@@ -1,13 +1,21 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fmt;
+use std::sync::OnceLock;
+use crate::command_db::CommandPackageMap;
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct CompileStep {
     pub tool: String,
     pub args: Vec<String>,
+    /// How other steps refer to this one in `needs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The named steps this one waits for; without it, the step before it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub needs: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -15,8 +23,209 @@ pub struct CompileCommand {
     pub steps: Vec<CompileStep>,
     #[serde(default)]
     pub auto_clean: bool,
-    #[serde(default)]
+    /// Added to the inherited clean patterns; `!pattern` drops an inherited one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub clean_patterns: Vec<String>,
+    /// Run on the main PDF after a successful compile, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post: Vec<PostStep>,
+    /// Extra post-processing and validation selected with `--profile <name>`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, CompileProfile>,
+    /// Engine or chain for documents matching a glob, e.g. "slides/*.tex"
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, CompileOverride>,
+    /// How the indexes of documents using \makeindex are sorted
+    #[serde(default, skip_serializing_if = "IndexConfig::is_default")]
+    pub index: IndexConfig,
+    /// Most runs of the last engine step while its auxiliary files keep changing
+    #[serde(default = "default_max_passes", skip_serializing_if = "is_default_max_passes")]
+    pub max_passes: u32,
+    /// Interaction mode passed to the engine and latexmk steps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interaction: Option<InteractionMode>,
+    /// Pass -halt-on-error to the engine and latexmk steps
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub halt_on_error: bool,
+    /// Pass -synctex=1 to the engine and latexmk steps and keep the .synctex.gz files
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub synctex: bool,
+}
+
+pub const DEFAULT_MAX_PASSES: u32 = 5;
+
+fn default_max_passes() -> u32 {
+    DEFAULT_MAX_PASSES
+}
+
+fn is_default_max_passes(max_passes: &u32) -> bool {
+    *max_passes == DEFAULT_MAX_PASSES
+}
+
+/// The `[project.compile.index]` block
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IndexConfig {
+    #[serde(default)]
+    pub tool: IndexTool,
+    /// makeindex style (.ist) or xindy module (.xdy)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+    /// Extra arguments, passed before the input file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+impl IndexConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The program that sorts an index
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexTool {
+    #[default]
+    Makeindex,
+    /// xindy through its LaTeX front end, texindy
+    Xindy,
+}
+
+/// How the engine reacts to errors: `-interaction=<mode>`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InteractionMode {
+    Batchmode,
+    Nonstopmode,
+    Scrollmode,
+    /// Stops at each error and waits for input on the terminal
+    Errorstopmode,
+}
+
+impl InteractionMode {
+    pub const NAMES: [&'static str; 4] = ["batchmode", "nonstopmode", "scrollmode", "errorstopmode"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "batchmode" => Some(Self::Batchmode),
+            "nonstopmode" => Some(Self::Nonstopmode),
+            "scrollmode" => Some(Self::Scrollmode),
+            "errorstopmode" => Some(Self::Errorstopmode),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        Self::NAMES[*self as usize]
+    }
+}
+
+/// TeX engines a compile step may run directly
+pub const TEX_ENGINES: &[&str] = &["pdflatex", "xelatex", "lualatex", "latex", "platex", "uplatex"];
+
+/// The compile profile that is available without configuration
+pub const ARCHIVE_PROFILE: &str = "archive";
+
+/// A `[project.compile.overrides."<glob>"]` block
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CompileOverride {
+    /// Run this engine in place of the chain's engine (and latexmk's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+    /// Replace the chain; steps without a file argument get the document
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<CompileStep>,
+}
+
+/// A `[project.compile.profiles.<name>]` block
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CompileProfile {
+    /// Run after the regular post-processing steps
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post: Vec<PostStep>,
+    /// Check the final PDF; the compile fails when it does not pass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate: Option<Validation>,
+}
+
+/// How a compiled PDF is validated
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Validation {
+    /// PDF/A flavour to check against, e.g. "2b"
+    #[serde(default = "default_pdfa_flavour")]
+    pub pdfa: String,
+    #[serde(default)]
+    pub validator: Validator,
+}
+
+fn default_pdfa_flavour() -> String {
+    "2b".to_string()
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self {
+            pdfa: default_pdfa_flavour(),
+            validator: Validator::default(),
+        }
+    }
+}
+
+/// The tool that checks a PDF
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Validator {
+    /// veraPDF when it is installed, else qpdf
+    #[default]
+    Auto,
+    /// Full PDF/A validation with veraPDF
+    Verapdf,
+    /// qpdf's structural checks and the PDF/A identification in the metadata
+    Qpdf,
+}
+
+/// What a post-processing step does to the PDF
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PostAction {
+    /// Ghostscript recompression (`quality`: screen, ebook, printer, prepress)
+    Compress,
+    /// Linearize for fast web view with qpdf
+    Linearize,
+    /// Ghostscript PDF/A conversion (`level`: 1, 2 or 3)
+    Pdfa,
+    /// Any tool, with `${INPUT}` and `${OUTPUT}` in its args
+    Command,
+}
+
+impl fmt::Display for PostAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            PostAction::Compress => "compress",
+            PostAction::Linearize => "linearize",
+            PostAction::Pdfa => "pdfa",
+            PostAction::Command => "command",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A post-processing step (`[[project.compile.post]]`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PostStep {
+    pub action: PostAction,
+    /// File written next to the PDF (`${NAME}` is the document's name);
+    /// without it the step replaces its input
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
 }
 
 impl CompileCommand {
@@ -25,14 +234,23 @@ impl CompileCommand {
             steps: vec![CompileStep {
                 tool: "pdflatex".to_string(),
                 args: vec!["-interaction=nonstopmode".to_string(), "${PROJECT_ROOT}/main.tex".to_string()],
+                ..Default::default()
             }],
             auto_clean: false,
-            clean_patterns: Self::default_clean_patterns(),
+            clean_patterns: Vec::new(),
+            post: Vec::new(),
+            profiles: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+            index: IndexConfig::default(),
+            max_passes: DEFAULT_MAX_PASSES,
+            interaction: None,
+            halt_on_error: false,
+            synctex: false,
         }
     }
 
     /// 获取默认的清理文件模式
-    fn default_clean_patterns() -> Vec<String> {
+    pub fn default_clean_patterns() -> Vec<String> {
         vec![
             "*.aux".to_string(),
             "*.log".to_string(),
@@ -50,7 +268,10 @@ impl CompileCommand {
             "*.ilg".to_string(),
             "*.glo".to_string(),
             "*.gls".to_string(),
+            "*.glg".to_string(),
             "*.ist".to_string(),
+            "*.nlo".to_string(),
+            "*.nls".to_string(),
             "*.fls".to_string(),
             "*.fdb_latexmk".to_string(),
             "*.synctex.gz".to_string(),
@@ -87,7 +308,7 @@ impl CompileCommand {
 
             let tool = cmd_parts[0].to_string();
             let args = cmd_parts.iter().skip(1).map(|s| s.to_string()).collect();
-            steps.push(CompileStep { tool, args });
+            steps.push(CompileStep { tool, args, ..Default::default() });
         }
 
         if steps.is_empty() {
@@ -97,7 +318,15 @@ impl CompileCommand {
         Ok(Self {
             steps,
             auto_clean: false,
-            clean_patterns: Self::default_clean_patterns(),
+            clean_patterns: Vec::new(),
+            post: Vec::new(),
+            profiles: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+            index: IndexConfig::default(),
+            max_passes: DEFAULT_MAX_PASSES,
+            interaction: None,
+            halt_on_error: false,
+            synctex: false,
         })
     }
 
@@ -109,18 +338,8 @@ impl CompileCommand {
         Self::from_string(chain)
     }
 
-    pub fn to_string(&self) -> String {
-        let steps_str: Vec<String> = self.steps.iter().map(|step| {
-            let mut cmd = vec![step.tool.clone()];
-            cmd.extend(step.args.clone());
-            cmd.join(" ")
-        }).collect();
-        
-        steps_str.join(" | ")
-    }
-
     /// 解析魔法变量并构建实际的编译命令列表
-    pub fn resolve_variables(&self, project_root: &std::path::Path) -> Result<Vec<Vec<String>>> {
+    pub fn resolve_variables(&self, variables: &ChainVariables) -> Result<Vec<Vec<String>>> {
         let mut resolved_commands = Vec::new();
         
         for step in &self.steps {
@@ -128,7 +347,7 @@ impl CompileCommand {
             
             // 解析参数中的魔法变量
             for arg in &step.args {
-                let resolved_arg = self.resolve_variables_in_string(arg, project_root)?;
+                let resolved_arg = self.resolve_variables_in_string(arg, variables)?;
                 resolved_args.push(resolved_arg);
             }
             
@@ -139,12 +358,12 @@ impl CompileCommand {
     }
 
     /// 解析字符串中的魔法变量
-    fn resolve_variables_in_string(&self, input: &str, project_root: &std::path::Path) -> Result<String> {
+    fn resolve_variables_in_string(&self, input: &str, variables: &ChainVariables) -> Result<String> {
         let mut resolved = input.to_string();
         
         // 替换 ${PROJECT_ROOT}
         if resolved.contains("${PROJECT_ROOT}") {
-            let project_root_str = project_root.to_string_lossy();
+            let project_root_str = variables.project_root.to_string_lossy();
             resolved = resolved.replace("${PROJECT_ROOT}", &project_root_str);
         }
         
@@ -163,83 +382,272 @@ impl CompileCommand {
             }
         }
         
+        // 替换项目变量: ${MAIN_FILE}、${MAIN_BASENAME}、${OUTDIR}、${PROJECT_NAME}
+        let main_basename = Path::new(&variables.main_file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        resolved = resolved
+            .replace("${MAIN_FILE}", &variables.main_file)
+            .replace("${MAIN_BASENAME}", &main_basename)
+            .replace("${OUTDIR}", &variables.output_dir)
+            .replace("${PROJECT_NAME}", &variables.project_name);
+        
+        // 替换 ${env:VAR}
+        while let Some(start) = resolved.find("${env:") {
+            let Some(length) = resolved[start..].find('}') else {
+                break;
+            };
+            let name = &resolved[start + "${env:".len()..start + length];
+            let value = std::env::var(name)
+                .map_err(|_| anyhow::anyhow!("The compile chain uses ${{env:{}}}, but {} is not set", name, name))?;
+            resolved.replace_range(start..=start + length, &value);
+        }
+        
         Ok(resolved)
     }
 
-    /// 获取支持的魔法变量列表
-    #[allow(dead_code)]
+    /// The compile profile `name`: a configured one, else the built-in
+    /// "archive" profile, which converts to PDF/A-2 (unless a post step already
+    /// does) and validates the result
+    pub fn profile(&self, name: &str) -> Option<CompileProfile> {
+        if let Some(profile) = self.profiles.get(name) {
+            return Some(profile.clone());
+        }
+        if name != ARCHIVE_PROFILE {
+            return None;
+        }
+        let mut post = Vec::new();
+        if !self.post.iter().any(|step| step.action == PostAction::Pdfa) {
+            post.push(PostStep {
+                action: PostAction::Pdfa,
+                output: None,
+                quality: None,
+                level: Some(2),
+                tool: None,
+                args: Vec::new(),
+            });
+        }
+        Some(CompileProfile {
+            post,
+            validate: Some(Validation::default()),
+        })
+    }
+
+    /// Retarget the chain at another document. Arguments naming a .tex file are
+    /// replaced by `target`, and bare job names (e.g. `bibtex main`) by its stem.
+    pub fn with_target(&self, target: &std::path::Path) -> Self {
+        let original_stem = self.steps.iter()
+            .flat_map(|step| step.args.iter())
+            .find(|arg| arg.ends_with(".tex"))
+            .and_then(|arg| std::path::Path::new(arg).file_stem())
+            .map(|stem| stem.to_string_lossy().to_string());
+        let target_str = target.to_string_lossy().to_string();
+        let target_stem = target.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| target_str.clone());
+        
+        let mut retargeted = self.clone();
+        for step in &mut retargeted.steps {
+            for arg in &mut step.args {
+                if arg.ends_with(".tex") {
+                    *arg = target_str.clone();
+                } else if original_stem.as_deref() == Some(arg.as_str()) {
+                    *arg = target_stem.clone();
+                }
+            }
+        }
+        retargeted
+    }
+
+    /// The override for `document` (relative to the project root): the one
+    /// with the longest matching pattern
+    pub fn override_for(&self, document: &str) -> Option<(&str, &CompileOverride)> {
+        let options = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+        self.overrides
+            .iter()
+            .filter(|(pattern, _)| glob::Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_with(document, options)))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(pattern, compile_override)| (pattern.as_str(), compile_override))
+    }
+
+    /// The chain with `compile_override` applied for `document`
+    pub fn with_override(&self, compile_override: &CompileOverride, document: &str) -> Self {
+        let mut overridden = self.clone();
+        if !compile_override.steps.is_empty() {
+            overridden.steps = compile_override.steps.clone();
+            crate::chain::fill_document(&mut overridden.steps, document);
+        }
+        if let Some(engine) = &compile_override.engine {
+            for step in &mut overridden.steps {
+                step.set_engine(engine);
+            }
+        }
+        overridden
+    }
+
+    /// Check whether any step enables shell escape (\write18)
+    pub fn has_shell_escape(&self) -> bool {
+        self.steps.iter().any(|step| {
+            step.args.iter().any(|arg| {
+                matches!(
+                    arg.as_str(),
+                    "-shell-escape" | "--shell-escape" | "-enable-write18" | "--enable-write18"
+                )
+            })
+        })
+    }
+
+    /// The TeX engine the chain runs, e.g. "xelatex" (latexmk flags are mapped
+    /// to the engine they select)
+    pub fn tex_engine(&self) -> Option<String> {
+        self.steps.iter().find_map(|step| {
+            let tool = std::path::Path::new(&step.tool)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())?;
+            if TEX_ENGINES.contains(&tool.as_str()) {
+                return Some(tool);
+            }
+            if tool != "latexmk" {
+                return None;
+            }
+            let engine = step.args.iter().find_map(|arg| match arg.as_str() {
+                "-xelatex" | "-pdfxe" | "-xe" => Some("xelatex"),
+                "-lualatex" | "-pdflua" | "-lua" => Some("lualatex"),
+                "-pdf" | "-pdflatex" => Some("pdflatex"),
+                _ => None,
+            });
+            Some(engine.unwrap_or("latex").to_string())
+        })
+    }
+
+    /// The first .tex file the chain names, relative to the project root
+    pub fn document(&self, variables: &ChainVariables) -> Option<String> {
+        let document = self
+            .steps
+            .iter()
+            .flat_map(|step| step.args.iter())
+            .filter_map(|arg| self.resolve_variables_in_string(arg, variables).ok())
+            .find(|arg| arg.ends_with(".tex"))?;
+        Some(match Path::new(&document).strip_prefix(&variables.project_root) {
+            Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+            Err(_) => document,
+        })
+    }
+
+    /// 获取支持的魔法变量列表 (`${env:VAR}` 另外支持)
     pub fn supported_variables() -> Vec<&'static str> {
-        vec!["${PROJECT_ROOT}", "${CURRENT_DIR}", "${HOME}"]
+        vec![
+            "${PROJECT_ROOT}", "${CURRENT_DIR}", "${HOME}", "${MAIN_FILE}", "${MAIN_BASENAME}", "${OUTDIR}", "${PROJECT_NAME}",
+        ]
     }
 }
 
+/// What the magic variables of compile args stand for in a project
+#[derive(Debug, Clone)]
+pub struct ChainVariables {
+    pub project_root: PathBuf,
+    pub project_name: String,
+    /// The document being built, relative to the project root
+    pub main_file: String,
+    /// The output directory relative to the project root, "." when unset
+    pub output_dir: String,
+}
+
 impl fmt::Display for CompileCommand {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        let steps_str: Vec<String> = self.steps.iter().map(|step| {
+            let mut cmd = vec![step.tool.clone()];
+            cmd.extend(step.args.clone());
+            cmd.join(" ")
+        }).collect();
+        
+        write!(f, "{}", steps_str.join(" | "))
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct GlobalConfig {
-    pub texlive_path: Option<String>,
-    pub mirror_url: Option<String>,
-    pub compile_command: CompileCommand,
-    pub install_global: bool,
+/// Download cache settings (`[cache]`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheConfig {
+    /// Keep downloaded archives after installing; false empties the cache after every install
+    #[serde(default = "default_true")]
+    pub keep: bool,
+    /// Remove the least recently used archives once the cache grows beyond this size
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<u64>,
+    /// Remove archives that have not been used for this many days
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u64>,
 }
 
-impl GlobalConfig {
-    pub fn new() -> Self {
+fn default_true() -> bool {
+    true
+}
+
+/// Output settings (`[ui]`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UiConfig {
+    /// Decorate output with emoji; false prints plain ASCII markers like `--plain`
+    #[serde(default = "default_true")]
+    pub emoji: bool,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self { emoji: true }
+    }
+}
+
+impl UiConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
         Self {
-            texlive_path: None,
-            mirror_url: None,
-            compile_command: CompileCommand::new(),
-            install_global: false,
+            keep: true,
+            max_size_mb: None,
+            max_age_days: None,
         }
     }
+}
 
-    pub fn get_config_path() -> Result<PathBuf> {
-        let mut path = dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
-        path.push("tpmgr");
-        std::fs::create_dir_all(&path)?;
-        path.push("config.toml");
-        Ok(path)
+impl CacheConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
     }
 
-    pub fn load() -> Result<Self> {
-        let path = Self::get_config_path()?;
-        if path.exists() {
-            let content = std::fs::read_to_string(path)?;
-            Ok(toml::from_str(&content)?)
-        } else {
-            Ok(Self::new())
-        }
+    /// Configuration keys handled by `set`/`get`
+    pub fn keys() -> Vec<&'static str> {
+        vec!["cache.keep", "cache.max_size_mb", "cache.max_age_days"]
     }
 
-    pub fn save(&self) -> Result<()> {
-        let path = Self::get_config_path()?;
-        let content = toml::to_string_pretty(self)?;
-        std::fs::write(path, content)?;
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let optional_number = |value: &str| -> Result<Option<u64>> {
+            if value.trim().is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(value.trim().parse()?))
+            }
+        };
+        match key {
+            "cache.keep" => self.keep = value.parse()?,
+            "cache.max_size_mb" => self.max_size_mb = optional_number(value)?,
+            "cache.max_age_days" => self.max_age_days = optional_number(value)?,
+            _ => return Err(anyhow::anyhow!("Unknown config key: {}", key)),
+        }
         Ok(())
     }
 
-    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+    /// Restore the default of a `cache.*` key
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let default = Self::default();
         match key {
-            "texlive_path" => {
-                if value.trim().is_empty() {
-                    self.texlive_path = None;
-                } else {
-                    self.texlive_path = Some(value.to_string());
-                }
-            },
-            "mirror_url" => {
-                if value.trim().is_empty() {
-                    self.mirror_url = None;
-                } else {
-                    self.mirror_url = Some(value.to_string());
-                }
-            },
-            "compile_command" => self.compile_command = CompileCommand::from_string(value)?,
-            "install_global" => self.install_global = value.parse()?,
+            "cache.keep" => self.keep = default.keep,
+            "cache.max_size_mb" => self.max_size_mb = default.max_size_mb,
+            "cache.max_age_days" => self.max_age_days = default.max_age_days,
             _ => return Err(anyhow::anyhow!("Unknown config key: {}", key)),
         }
         Ok(())
@@ -247,110 +655,1145 @@ impl GlobalConfig {
 
     pub fn get(&self, key: &str) -> Option<String> {
         match key {
-            "texlive_path" => self.texlive_path.clone(),
-            "mirror_url" => self.mirror_url.clone(),
-            "compile_command" => Some(self.compile_command.to_string()),
-            "install_global" => Some(self.install_global.to_string()),
+            "cache.keep" => Some(self.keep.to_string()),
+            "cache.max_size_mb" => self.max_size_mb.map(|size| size.to_string()),
+            "cache.max_age_days" => self.max_age_days.map(|days| days.to_string()),
             _ => None,
         }
     }
+}
 
-    pub fn list_keys() -> Vec<&'static str> {
-        vec!["texlive_path", "mirror_url", "compile_command", "install_global"]
-    }
+/// Reuse of earlier build outputs (`[build_cache]`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BuildCacheConfig {
+    /// Skip the compile chain when its inputs match an earlier build
+    #[serde(default)]
+    pub enabled: bool,
+    /// Where builds are kept, relative to the project root; checkouts that
+    /// point at the same directory share their builds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    /// Builds kept; the least recently used are removed first
+    #[serde(default = "default_build_cache_entries")]
+    pub max_entries: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Config {
-    pub project: ProjectConfig,
-    pub dependencies: HashMap<String, String>,
-    pub repositories: Vec<Repository>,
+fn default_build_cache_entries() -> usize {
+    20
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct ProjectConfig {
-    pub name: String,
-    pub version: String,
-    pub compile: CompileCommand,
-    pub package_dir: String,
-    pub texlive_path: Option<String>,
-    pub mirror_url: Option<String>,
-    pub install_global: Option<bool>,
+impl Default for BuildCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            max_entries: default_build_cache_entries(),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Repository {
+/// How `tpmgr release` names and stores release PDFs (`[release]`)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReleaseConfig {
+    /// File name without extension; `${NAME}`, `${PROJECT}`, `${VERSION}`,
+    /// `${DATE}` and `${GIT}` (git describe) are replaced
+    #[serde(default = "default_release_name")]
     pub name: String,
-    pub url: String,
-    pub priority: u8,
+    /// Directory the releases are stored in, relative to the project root
+    #[serde(default = "default_release_dir")]
+    pub dir: String,
+    /// Compile profile the release is built with, unless --profile is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
 }
 
-impl Config {
-    pub fn new() -> Self {
+fn default_release_name() -> String {
+    "${NAME}-${VERSION}-${DATE}".to_string()
+}
+
+fn default_release_dir() -> String {
+    "releases".to_string()
+}
+
+impl Default for ReleaseConfig {
+    fn default() -> Self {
         Self {
-            project: ProjectConfig {
-                name: "latex-project".to_string(),
-                version: "0.1.0".to_string(),
-                compile: CompileCommand::new(),
-                package_dir: "packages".to_string(),
-                texlive_path: None,
-                mirror_url: None,
-                install_global: None,
-            },
-            dependencies: HashMap::new(),
-            repositories: vec![
-                Repository {
-                    name: "ctan".to_string(),
-                    url: "https://ctan.org/".to_string(),
-                    priority: 1,
-                },
-                Repository {
-                    name: "texlive".to_string(),
-                    url: "https://mirror.ctan.org/systems/texlive/tlnet/".to_string(),
-                    priority: 2,
-                },
-            ],
+            name: default_release_name(),
+            dir: default_release_dir(),
+            profile: None,
         }
     }
-    
-    pub fn load(path: &str) -> Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&content)?;
-        Ok(config)
+}
+
+impl ReleaseConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
     }
-    
-    pub fn save(&self, path: &str) -> Result<()> {
-        let content = toml::to_string_pretty(self)?;
-        std::fs::write(path, content)?;
-        Ok(())
+}
+
+impl BuildCacheConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
     }
-    
-    #[allow(dead_code)]
-    pub fn add_dependency(&mut self, name: String, version: String) {
-        self.dependencies.insert(name, version);
+
+    /// Configuration keys handled by `set`/`get`
+    pub fn keys() -> Vec<&'static str> {
+        vec!["build_cache.enabled", "build_cache.dir", "build_cache.max_entries"]
     }
-    
-    #[allow(dead_code)]
-    pub fn remove_dependency(&mut self, name: &str) -> Option<String> {
-        self.dependencies.remove(name)
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "build_cache.enabled" => self.enabled = value.parse()?,
+            "build_cache.dir" => self.dir = Some(value.to_string()).filter(|dir| !dir.trim().is_empty()),
+            "build_cache.max_entries" => self.max_entries = value.trim().parse()?,
+            _ => return Err(anyhow::anyhow!("Unknown config key: {}", key)),
+        }
+        Ok(())
     }
-    
-    #[allow(dead_code)]
-    pub fn get_package_dir(&self) -> &str {
-        &self.project.package_dir
+
+    /// Restore the default of a `build_cache.*` key
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        let default = Self::default();
+        match key {
+            "build_cache.enabled" => self.enabled = default.enabled,
+            "build_cache.dir" => self.dir = default.dir,
+            "build_cache.max_entries" => self.max_entries = default.max_entries,
+            _ => return Err(anyhow::anyhow!("Unknown config key: {}", key)),
+        }
+        Ok(())
     }
 
-    /// 设置项目配置值
-    pub fn set_project_config(&mut self, key: &str, value: &str) -> Result<()> {
+    pub fn get(&self, key: &str) -> Option<String> {
         match key {
-            "name" => self.project.name = value.to_string(),
-            "version" => self.project.version = value.to_string(),
-            "compile" => self.project.compile = CompileCommand::from_string(value)?,
-            "package_dir" => self.project.package_dir = value.to_string(),
-            "texlive_path" => {
-                if value.trim().is_empty() {
-                    self.project.texlive_path = None;
-                } else {
+            "build_cache.enabled" => Some(self.enabled.to_string()),
+            "build_cache.dir" => self.dir.clone(),
+            "build_cache.max_entries" => Some(self.max_entries.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Figures generated and converted before compiling (`[assets]`)
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AssetsConfig {
+    /// Run first, so their outputs can be converted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generate: Vec<GenerateRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub convert: Vec<ConvertRule>,
+}
+
+impl AssetsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.generate.is_empty() && self.convert.is_empty()
+    }
+}
+
+/// An `[[assets.generate]]` rule: a command that writes `outputs` from
+/// `inputs`, run again only when an input or the command changed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GenerateRule {
+    pub tool: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Files the command reads (globs relative to the project root), e.g.
+    /// the script and its data
+    #[serde(default)]
+    pub inputs: Vec<String>,
+    /// Files the command writes, relative to the project root
+    pub outputs: Vec<String>,
+}
+
+/// An `[[assets.convert]]` rule: every file matching `from` is converted
+/// when it is newer than its converted file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ConvertRule {
+    /// Glob relative to the project root, e.g. "figures/*.svg"
+    pub from: String,
+    /// Converted file; `${DIR}` and `${NAME}` are the source's directory and
+    /// name without extension (default "${DIR}/${NAME}.pdf")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// Converter; inkscape for .svg and epstopdf for .eps when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    /// Arguments of `tool`, with `${INPUT}` and `${OUTPUT}`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+/// The mirror chosen with `tpmgr mirror use`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MirrorSelection {
+    pub name: String,
+    pub url: String,
+    /// Response time measured when the mirror was tested
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// Archive URL with `{package}`, when the archives do not lie in the
+    /// repository's archive/ directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_template: Option<String>,
+}
+
+/// A user-defined mirror added with `tpmgr mirror add`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CustomMirror {
+    pub name: String,
+    /// CTAN root URL of the mirror, or its TeX Live repository (ending in tlnet)
+    pub url: String,
+    /// URL template of the package index, with `{url}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_template: Option<String>,
+    /// URL template of the package archives, with `{url}` and `{package}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_template: Option<String>,
+}
+
+fn is_default_clean_patterns(patterns: &Vec<String>) -> bool {
+    *patterns == CompileCommand::default_clean_patterns()
+}
+
+/// Keys holding lists, edited with `config add` and `config remove`
+pub fn list_value_keys() -> Vec<&'static str> {
+    vec!["clean_patterns", "clean.keep", "repositories"]
+}
+
+/// Files that cleaning must never delete
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CleanConfig {
+    /// Protected paths; patterns without a slash match a file name at any depth
+    #[serde(default)]
+    pub keep: Vec<String>,
+}
+
+impl CleanConfig {
+    pub fn is_empty(&self) -> bool {
+        self.keep.is_empty()
+    }
+
+    /// Check whether a path relative to the project root is protected
+    pub fn is_kept(&self, relative_path: &Path) -> bool {
+        let path = relative_path.to_string_lossy().replace('\\', "/");
+        let file_name = relative_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        self.keep.iter().any(|pattern| {
+            let candidate = if pattern.contains('/') { path.as_str() } else { file_name.as_str() };
+            glob::Pattern::new(pattern.trim_start_matches('/'))
+                .map(|pattern| pattern.matches(candidate))
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Apply a list of clean pattern changes to inherited patterns: plain entries
+/// are added, `!pattern` entries remove an inherited pattern
+pub fn resolve_clean_patterns(inherited: &[String], changes: &[String]) -> Vec<String> {
+    let mut patterns = inherited.to_vec();
+    for change in changes {
+        match change.strip_prefix('!') {
+            Some(removed) => patterns.retain(|pattern| pattern != removed),
+            None if !patterns.contains(change) => patterns.push(change.clone()),
+            None => {}
+        }
+    }
+    patterns
+}
+
+/// Append a clean pattern; returns false if it was already present
+fn add_clean_pattern(patterns: &mut Vec<String>, pattern: &str) -> Result<bool> {
+    let glob = pattern.strip_prefix('!').unwrap_or(pattern);
+    glob::Pattern::new(glob).map_err(|e| anyhow::anyhow!("Invalid glob pattern '{}': {}", pattern, e.msg))?;
+    if patterns.iter().any(|existing| existing == pattern) {
+        return Ok(false);
+    }
+    patterns.push(pattern.to_string());
+    Ok(true)
+}
+
+fn remove_clean_pattern(patterns: &mut Vec<String>, pattern: &str) -> bool {
+    let count = patterns.len();
+    patterns.retain(|existing| existing != pattern);
+    patterns.len() != count
+}
+
+/// Add a repository given as `NAME=URL` after the existing ones, or change
+/// the URL of the repository with that name; returns false in the latter case
+fn add_repository(repositories: &mut Vec<Repository>, value: &str) -> Result<bool> {
+    let (name, url) = value
+        .split_once('=')
+        .map(|(name, url)| (name.trim(), url.trim()))
+        .filter(|(name, url)| !name.is_empty() && !url.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("Repositories are given as NAME=URL, e.g. ctan=https://ctan.org/"))?;
+
+    if let Some(existing) = repositories.iter_mut().find(|repository| repository.name == name) {
+        existing.url = url.to_string();
+        return Ok(false);
+    }
+    let priority = repositories
+        .iter()
+        .map(|repository| repository.priority.saturating_add(1))
+        .max()
+        .unwrap_or(1);
+    repositories.push(Repository {
+        name: name.to_string(),
+        url: url.to_string(),
+        priority,
+        kind: None,
+        archive_template: None,
+    });
+    Ok(true)
+}
+
+/// Remove a repository by name (`NAME` or `NAME=URL`)
+fn remove_repository(repositories: &mut Vec<Repository>, value: &str) -> bool {
+    let name = value.split('=').next().unwrap_or_default().trim();
+    let count = repositories.len();
+    repositories.retain(|repository| repository.name != name);
+    repositories.len() != count
+}
+
+/// A `[profiles.<name>]` block: settings that replace the top-level ones
+/// while the profile is active (`--profile <name>` or TPMGR_PROFILE)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texlive_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorSelection>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+static ACTIVE_PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Activate a global configuration profile for this run
+pub fn set_active_profile(name: &str) -> Result<()> {
+    ACTIVE_PROFILE
+        .set(name.to_string())
+        .map_err(|_| anyhow::anyhow!("Profile already set"))
+}
+
+/// The profile selected with `--profile` / TPMGR_PROFILE, if any
+pub fn active_profile() -> Option<&'static str> {
+    ACTIVE_PROFILE.get().map(String::as_str)
+}
+
+/// Whether `name` is a compile profile of the current project or the
+/// built-in one, which need no `[profiles.<name>]` block
+pub fn is_compile_profile(name: &str) -> bool {
+    name == ARCHIVE_PROFILE
+        || Config::load_project()
+            .ok()
+            .flatten()
+            .is_some_and(|config| config.project.compile.profiles.contains_key(name))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GlobalConfig {
+    pub texlive_path: Option<String>,
+    pub mirror_url: Option<String>,
+    /// Mirror that `mirror_url` was derived from, if picked with `mirror use`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorSelection>,
+    /// Proxy for all downloads, e.g. http://proxy.example.com:8080
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Mirrors added by the user, offered alongside the built-in list
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_mirrors: Vec<CustomMirror>,
+    /// Package repositories for all projects; project entries with the same name override them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<Repository>,
+    #[serde(default, skip_serializing_if = "CacheConfig::is_default")]
+    pub cache: CacheConfig,
+    #[serde(default, skip_serializing_if = "UiConfig::is_default")]
+    pub ui: UiConfig,
+    /// Intermediate files removed by cleaning; projects add to these
+    #[serde(default = "CompileCommand::default_clean_patterns", skip_serializing_if = "is_default_clean_patterns")]
+    pub clean_patterns: Vec<String>,
+    pub compile_command: CompileCommand,
+    pub install_global: bool,
+    /// Extra command/environment to package mappings for all projects
+    #[serde(default, skip_serializing_if = "CommandPackageMap::is_empty")]
+    pub command_packages: CommandPackageMap,
+    /// Named sets of settings for different networks or machines
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+    /// The applied profile and the configuration as stored before applying it
+    #[serde(skip)]
+    stored: Option<(String, Box<GlobalConfig>)>,
+}
+
+impl GlobalConfig {
+    pub fn new() -> Self {
+        Self {
+            texlive_path: None,
+            mirror_url: None,
+            mirror: None,
+            proxy: None,
+            custom_mirrors: Vec::new(),
+            repositories: Vec::new(),
+            cache: CacheConfig::default(),
+            ui: UiConfig::default(),
+            clean_patterns: CompileCommand::default_clean_patterns(),
+            compile_command: CompileCommand::new(),
+            install_global: false,
+            command_packages: CommandPackageMap::default(),
+            profiles: BTreeMap::new(),
+            stored: None,
+        }
+    }
+
+    pub fn get_config_path() -> Result<PathBuf> {
+        let mut path = dirs::config_dir().ok_or_else(|| anyhow::anyhow!("Could not find config directory"))?;
+        path.push("tpmgr");
+        std::fs::create_dir_all(&path)?;
+        path.push("config.toml");
+        Ok(path)
+    }
+
+    /// Load the global configuration with the active profile applied
+    pub fn load() -> Result<Self> {
+        let path = Self::get_config_path()?;
+        let config = if path.exists() {
+            let content = std::fs::read_to_string(path)?;
+            toml::from_str(&content)?
+        } else {
+            Self::new()
+        };
+        match active_profile() {
+            Some(name) if !config.profiles.contains_key(name) && is_compile_profile(name) => Ok(config),
+            Some(name) => config.with_profile(name),
+            None => Ok(config),
+        }
+    }
+
+    /// Save the configuration; while a profile is active, settings the
+    /// profile overrides are written back to the profile
+    pub fn save(&self) -> Result<()> {
+        let path = Self::get_config_path()?;
+        let content = toml::to_string_pretty(&self.to_stored())?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// The settings as TOML for `config export`, leaving out the
+    /// machine-specific texlive_path
+    pub fn export(&self) -> Result<String> {
+        let mut config = self.to_stored();
+        config.texlive_path = None;
+        Ok(toml::to_string_pretty(&config)?)
+    }
+
+    /// Apply settings exported on another machine: every top-level key in
+    /// `content` replaces the local one, other local settings are kept
+    pub fn import(content: &str) -> Result<Self> {
+        let issues = crate::config_check::check_global_config(content);
+        if crate::config_check::has_errors(&issues) {
+            let errors: Vec<String> = issues.iter().map(|issue| format!("  {}", issue)).collect();
+            anyhow::bail!("Invalid settings file:\n{}", errors.join("\n"));
+        }
+
+        let path = Self::get_config_path()?;
+        let mut table = if path.exists() {
+            std::fs::read_to_string(&path)?.parse::<toml::Table>()?
+        } else {
+            toml::Table::try_from(Self::new())?
+        };
+        table.extend(content.parse::<toml::Table>()?);
+
+        let config: Self = table.try_into()?;
+        config.save()?;
+        Ok(config)
+    }
+
+    /// Apply the settings of profile `name` on top of this configuration
+    pub fn with_profile(self, name: &str) -> Result<Self> {
+        let Some(profile) = self.profiles.get(name).cloned() else {
+            let available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            if available.is_empty() {
+                anyhow::bail!("Unknown profile '{}': no profiles are configured", name);
+            }
+            anyhow::bail!("Unknown profile '{}' (available: {})", name, available.join(", "));
+        };
+
+        let mut config = self.clone();
+        config.stored = Some((name.to_string(), Box::new(self)));
+        if profile.texlive_path.is_some() {
+            config.texlive_path = profile.texlive_path;
+        }
+        if profile.mirror_url.is_some() {
+            config.mirror_url = profile.mirror_url;
+            config.mirror = profile.mirror;
+        }
+        if profile.proxy.is_some() {
+            config.proxy = profile.proxy;
+        }
+        Ok(config)
+    }
+
+    /// Undo `with_profile`, moving the overridden settings into the profile
+    fn to_stored(&self) -> Self {
+        let Some((name, stored)) = &self.stored else {
+            return self.clone();
+        };
+
+        let mut config = self.clone();
+        config.stored = None;
+        let profile = config.profiles.entry(name.clone()).or_default();
+        if profile.texlive_path.is_some() {
+            profile.texlive_path = self.texlive_path.clone();
+            config.texlive_path = stored.texlive_path.clone();
+        }
+        if profile.mirror_url.is_some() {
+            profile.mirror_url = self.mirror_url.clone();
+            profile.mirror = self.mirror.clone();
+            config.mirror_url = stored.mirror_url.clone();
+            config.mirror = stored.mirror.clone();
+        }
+        if profile.proxy.is_some() {
+            profile.proxy = self.proxy.clone();
+            config.proxy = stored.proxy.clone();
+        }
+        config
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "texlive_path" => {
+                if value.trim().is_empty() {
+                    self.texlive_path = None;
+                } else {
+                    self.texlive_path = Some(value.to_string());
+                }
+            },
+            "mirror_url" => {
+                // A hand-set URL no longer corresponds to the selected mirror
+                self.mirror = None;
+                if value.trim().is_empty() {
+                    self.mirror_url = None;
+                } else {
+                    self.mirror_url = Some(value.to_string());
+                }
+            },
+            "proxy" => {
+                if value.trim().is_empty() {
+                    self.proxy = None;
+                } else {
+                    reqwest::Proxy::all(value).map_err(|e| anyhow::anyhow!("Invalid proxy URL '{}': {}", value, e))?;
+                    self.proxy = Some(value.to_string());
+                }
+            },
+            "compile_command" => self.compile_command = CompileCommand::from_string(value)?,
+            "install_global" => self.install_global = value.parse()?,
+            "ui.emoji" => self.ui.emoji = value.parse()?,
+            key if key.starts_with("cache.") => self.cache.set(key, value)?,
+            _ => return Err(anyhow::anyhow!("Unknown config key: {}", key)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "texlive_path" => self.texlive_path.clone(),
+            "mirror_url" => self.mirror_url.clone(),
+            "proxy" => self.proxy.clone(),
+            "compile_command" => Some(self.compile_command.to_string()),
+            "install_global" => Some(self.install_global.to_string()),
+            "ui.emoji" => Some(self.ui.emoji.to_string()),
+            key if key.starts_with("cache.") => self.cache.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn list_keys() -> Vec<&'static str> {
+        let mut keys = vec!["texlive_path", "mirror_url", "proxy", "compile_command", "install_global", "ui.emoji"];
+        keys.extend(CacheConfig::keys());
+        keys
+    }
+
+    /// Restore the default of a key
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        match key {
+            "texlive_path" => self.texlive_path = None,
+            "mirror_url" => {
+                self.mirror_url = None;
+                self.mirror = None;
+            },
+            "proxy" => self.proxy = None,
+            "compile_command" => self.compile_command = CompileCommand::new(),
+            "install_global" => self.install_global = false,
+            "ui.emoji" => self.ui = UiConfig::default(),
+            key if key.starts_with("cache.") => self.cache.unset(key)?,
+            _ => return Err(anyhow::anyhow!("Unknown config key: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Append a value to a list key; returns false if nothing was added
+    pub fn add_to_list(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "clean_patterns" => add_clean_pattern(&mut self.clean_patterns, value),
+            "repositories" => add_repository(&mut self.repositories, value),
+            _ => Err(anyhow::anyhow!("'{}' is not a list; use 'config set' instead", key)),
+        }
+    }
+
+    /// Remove a value from a list key; returns false if it was not present
+    pub fn remove_from_list(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "clean_patterns" => Ok(remove_clean_pattern(&mut self.clean_patterns, value)),
+            "repositories" => Ok(remove_repository(&mut self.repositories, value)),
+            _ => Err(anyhow::anyhow!("'{}' is not a list; use 'config unset' instead", key)),
+        }
+    }
+
+    /// Add or replace a custom mirror, with optional index and archive URL
+    /// templates; returns true if one with the same name existed
+    pub fn add_custom_mirror(&mut self, name: &str, url: &str, index_template: Option<&str>, archive_template: Option<&str>) -> Result<bool> {
+        if name.trim().is_empty() {
+            return Err(anyhow::anyhow!("Mirror name must not be empty"));
+        }
+        if !["http://", "https://", "rsync://"].iter().any(|scheme| url.starts_with(scheme)) {
+            return Err(anyhow::anyhow!("Mirror URL must start with http://, https:// or rsync://: {}", url));
+        }
+
+        // Accept the TeX Live repository URL as well as the CTAN root
+        let url = url.trim_end_matches('/');
+        let url = url.strip_suffix("/systems/texlive/tlnet").unwrap_or(url).to_string();
+
+        let defaults = crate::mirror::MirrorTemplates::for_url(&url);
+        crate::mirror::MirrorTemplates {
+            index: index_template.map(str::to_string).unwrap_or(defaults.index),
+            archive: archive_template.map(str::to_string).unwrap_or(defaults.archive),
+        }
+        .validate(&url)?;
+
+        let mirror = CustomMirror {
+            name: name.to_string(),
+            url,
+            index_template: index_template.map(str::to_string),
+            archive_template: archive_template.map(str::to_string),
+        };
+        match self.custom_mirrors.iter_mut().find(|m| m.name == name) {
+            Some(existing) => {
+                *existing = mirror;
+                Ok(true)
+            }
+            None => {
+                self.custom_mirrors.push(mirror);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Remove a custom mirror; returns false if there was none with that name
+    pub fn remove_custom_mirror(&mut self, name: &str) -> bool {
+        let count = self.custom_mirrors.len();
+        self.custom_mirrors.retain(|m| m.name != name);
+        self.custom_mirrors.len() != count
+    }
+}
+
+/// How long a failed first-run detection is remembered before it is tried again
+const AUTO_CONFIG_RETRY_SECS: u64 = 24 * 60 * 60;
+
+/// First-run detections that failed, kept next to the global config so that
+/// offline runs do not repeat them on every invocation
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AutoConfigState {
+    /// When TeX Live detection last failed (seconds since the Unix epoch)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texlive_failed_at: Option<u64>,
+    /// When no mirror could be reached (seconds since the Unix epoch)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror_failed_at: Option<u64>,
+}
+
+impl AutoConfigState {
+    pub fn load() -> Self {
+        Self::path()
+            .ok()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::write(Self::path()?, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn path() -> Result<PathBuf> {
+        Ok(GlobalConfig::get_config_path()?.with_file_name("auto-config.toml"))
+    }
+
+    /// Whether a detection that failed at `failed_at` is due to be tried again at `now`
+    pub fn should_retry(failed_at: Option<u64>, now: u64) -> bool {
+        failed_at
+            .map(|failed_at| now.saturating_sub(failed_at) >= AUTO_CONFIG_RETRY_SECS)
+            .unwrap_or(true)
+    }
+}
+
+/// Name of the project configuration file
+pub const PROJECT_CONFIG_FILE: &str = "tpmgr.toml";
+
+static PROJECT_CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `path` instead of ./tpmgr.toml for this run (`--config` / TPMGR_CONFIG).
+/// The path is made absolute so it survives changes of the working directory.
+pub fn set_project_config_path(path: &Path) -> Result<()> {
+    let path = std::env::current_dir()?.join(path);
+    PROJECT_CONFIG_PATH
+        .set(path)
+        .map_err(|_| anyhow::anyhow!("Project configuration path already set"))
+}
+
+/// The project configuration file in effect: the `--config` file, else the
+/// nearest tpmgr.toml from the working directory upwards, else ./tpmgr.toml
+pub fn project_config_path() -> PathBuf {
+    if let Some(path) = PROJECT_CONFIG_PATH.get() {
+        return path.clone();
+    }
+    std::env::current_dir()
+        .ok()
+        .and_then(|dir| find_project_root(&dir))
+        .map(|root| root.join(PROJECT_CONFIG_FILE))
+        .unwrap_or_else(|| PathBuf::from(PROJECT_CONFIG_FILE))
+}
+
+/// The project configuration file for `start`: the `--config` file, else the
+/// nearest tpmgr.toml from `start` upwards, else `start`/tpmgr.toml
+pub fn project_config_path_for(start: &Path) -> PathBuf {
+    if let Some(path) = PROJECT_CONFIG_PATH.get() {
+        return path.clone();
+    }
+    find_project_root(start)
+        .map(|root| root.join(PROJECT_CONFIG_FILE))
+        .unwrap_or_else(|| start.join(PROJECT_CONFIG_FILE))
+}
+
+/// The nearest directory containing tpmgr.toml, starting at `start` and
+/// walking up through its parents (like cargo does for Cargo.toml)
+pub fn find_project_root(start: &Path) -> Option<PathBuf> {
+    let start = start.canonicalize().ok()?;
+    start
+        .ancestors()
+        .find(|dir| dir.join(PROJECT_CONFIG_FILE).is_file())
+        .map(Path::to_path_buf)
+}
+
+/// The root of the project `start` belongs to: the directory of the `--config`
+/// file if one was given, else the nearest directory with a tpmgr.toml
+pub fn project_root_for(start: &Path) -> Option<PathBuf> {
+    match PROJECT_CONFIG_PATH.get() {
+        Some(path) => path.is_file().then(|| path.parent().map(Path::to_path_buf)).flatten(),
+        None => find_project_root(start),
+    }
+}
+
+/// The root of the current project, if the working directory is inside one
+pub fn project_root() -> Option<PathBuf> {
+    project_root_for(&std::env::current_dir().ok()?)
+}
+
+/// Resolve a project-relative path (e.g. "packages") against the project
+/// root; outside a project it stays relative to the working directory
+pub fn project_path(relative: impl AsRef<Path>) -> PathBuf {
+    match project_root() {
+        Some(root) => root.join(relative),
+        None => relative.as_ref().to_path_buf(),
+    }
+}
+
+/// Settings in the `[workspace]` table of a root tpmgr.toml, inherited by
+/// the projects below it unless they set their own
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct WorkspaceConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texlive_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_global: Option<bool>,
+    /// Added to the clean patterns of every project
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clean_patterns: Vec<String>,
+    /// Repositories for every project; project entries with the same name override them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub repositories: Vec<Repository>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheConfig>,
+    /// Per-profile overrides of texlive_path and mirror_url, selected with `--profile`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl WorkspaceConfig {
+    /// The nearest `[workspace]` in a tpmgr.toml at or above `dir`, and the file it is in
+    pub fn find(dir: &Path) -> Result<Option<(PathBuf, Self)>> {
+        let Ok(dir) = dir.canonicalize() else {
+            return Ok(None);
+        };
+        for ancestor in dir.ancestors() {
+            let path = ancestor.join(PROJECT_CONFIG_FILE);
+            if !path.is_file() {
+                continue;
+            }
+            let table = std::fs::read_to_string(&path)?
+                .parse::<toml::Table>()
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+            if let Some(workspace) = table.get("workspace") {
+                let workspace = workspace
+                    .clone()
+                    .try_into()
+                    .map_err(|e| anyhow::anyhow!("Invalid [workspace] in {}: {}", path.display(), e))?;
+                return Ok(Some((path, workspace)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The workspace settings with the active profile applied
+    fn resolved(&self) -> Self {
+        let mut workspace = self.clone();
+        if let Some(profile) = active_profile().and_then(|name| self.profiles.get(name)) {
+            if profile.texlive_path.is_some() {
+                workspace.texlive_path = profile.texlive_path.clone();
+            }
+            if profile.mirror_url.is_some() {
+                workspace.mirror_url = profile.mirror_url.clone();
+            }
+        }
+        workspace
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Config {
+    /// May be left out in a tpmgr.toml that only defines a `[workspace]`
+    #[serde(default)]
+    pub project: ProjectConfig,
+    #[serde(default)]
+    pub dependencies: HashMap<String, String>,
+    #[serde(default = "Config::default_repositories")]
+    pub repositories: Vec<Repository>,
+    /// Project-specific command/environment to package mappings
+    #[serde(default, skip_serializing_if = "CommandPackageMap::is_empty")]
+    pub command_packages: CommandPackageMap,
+    /// Cache settings for this project, replacing the global `[cache]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheConfig>,
+    #[serde(default, skip_serializing_if = "CleanConfig::is_empty")]
+    pub clean: CleanConfig,
+    #[serde(default, skip_serializing_if = "BuildCacheConfig::is_default")]
+    pub build_cache: BuildCacheConfig,
+    #[serde(default, skip_serializing_if = "AssetsConfig::is_empty")]
+    pub assets: AssetsConfig,
+    #[serde(default, skip_serializing_if = "ReleaseConfig::is_default")]
+    pub release: ReleaseConfig,
+    /// Settings shared with the projects in subdirectories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<WorkspaceConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectConfig {
+    pub name: String,
+    pub version: String,
+    pub compile: CompileCommand,
+    pub package_dir: String,
+    pub texlive_path: Option<String>,
+    pub mirror_url: Option<String>,
+    pub install_global: Option<bool>,
+    /// Directory that receives build output; never scanned for sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_dir: Option<String>,
+    /// The main document, `${MAIN_FILE}` in compile args (default main.tex)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Repository {
+    pub name: String,
+    pub url: String,
+    /// Repositories with a lower number are consulted first
+    pub priority: u8,
+    /// Layout of the repository; inferred from the URL when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<RepositoryKind>,
+    /// Archive URL of TeX Live repositories, with `{package}`; defaults to
+    /// `<url>/archive/{package}.tar.xz`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_template: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RepositoryKind {
+    /// A TeX Live package repository (tlnet layout with tlpkg/texlive.tlpdb)
+    TexLive,
+    /// The CTAN package catalogue
+    Ctan,
+    /// A self-hosted repository written by `tpmgr publish` (index.json and archives)
+    Index,
+}
+
+impl Repository {
+    pub fn kind(&self) -> RepositoryKind {
+        if let Some(kind) = self.kind {
+            return kind;
+        }
+        let is_remote = ["http://", "https://", "rsync://"].iter().any(|scheme| self.url.starts_with(scheme));
+        if !is_remote || self.url.trim_end_matches('/').ends_with(crate::repository::INDEX_FILE) {
+            return RepositoryKind::Index;
+        }
+        let host = self.url.split("://").nth(1).unwrap_or(&self.url).split('/').next().unwrap_or_default();
+        if !self.url.contains("tlnet") && (host == "ctan.org" || host == "www.ctan.org") {
+            RepositoryKind::Ctan
+        } else {
+            RepositoryKind::TexLive
+        }
+    }
+}
+
+impl Default for ProjectConfig {
+    fn default() -> Self {
+        Self {
+            name: "latex-project".to_string(),
+            version: "0.1.0".to_string(),
+            compile: CompileCommand::new(),
+            package_dir: "packages".to_string(),
+            texlive_path: None,
+            mirror_url: None,
+            install_global: None,
+            output_dir: None,
+            main: None,
+        }
+    }
+}
+
+impl Config {
+    /// The magic variables of the project's compile chain
+    pub fn chain_variables(&self, project_root: &Path) -> ChainVariables {
+        ChainVariables {
+            project_root: project_root.to_path_buf(),
+            project_name: self.project.name.clone(),
+            main_file: self.project.main.clone().unwrap_or_else(|| "main.tex".to_string()),
+            output_dir: self.project.output_dir.clone().unwrap_or_else(|| ".".to_string()),
+        }
+    }
+
+    pub fn new() -> Self {
+        Self {
+            project: ProjectConfig::default(),
+            dependencies: HashMap::new(),
+            repositories: Self::default_repositories(),
+            command_packages: CommandPackageMap::default(),
+            cache: None,
+            clean: CleanConfig::default(),
+            build_cache: BuildCacheConfig::default(),
+            assets: AssetsConfig::default(),
+            release: ReleaseConfig::default(),
+            workspace: None,
+        }
+    }
+    
+    /// Repositories used when neither the project nor the global config lists any
+    pub fn default_repositories() -> Vec<Repository> {
+        vec![
+            // TeX Live's .tar.xz archives install; CTAN's .zip downloads do not,
+            // so the catalogue only fills in packages TeX Live lacks
+            Repository {
+                name: "texlive".to_string(),
+                url: crate::mirror::DEFAULT_TLNET_URL.to_string(),
+                priority: 1,
+                kind: None,
+                archive_template: None,
+            },
+            Repository {
+                name: "ctan".to_string(),
+                url: "https://ctan.org/".to_string(),
+                priority: 2,
+                kind: None,
+                archive_template: None,
+            },
+        ]
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let issues = crate::config_check::check_project_config(&content);
+        if crate::config_check::has_errors(&issues) {
+            let errors: Vec<String> = issues
+                .iter()
+                .filter(|issue| issue.severity == crate::config_check::Severity::Error)
+                .map(|issue| format!("  {}", issue))
+                .collect();
+            anyhow::bail!("Invalid configuration in {}:\n{}", path.display(), errors.join("\n"));
+        }
+        let config: Config = toml::from_str(&content)?;
+        Ok(config)
+    }
+    
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let content = toml::to_string_pretty(self)?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Load the project configuration with the settings it inherits from an
+    /// enclosing workspace, or None when there is no project.
+    /// A file given with `--config` / TPMGR_CONFIG must exist.
+    pub fn load_project() -> Result<Option<Self>> {
+        Self::load_project_at(&project_config_path())
+    }
+
+    /// Like `load_project`, for the project containing `start` rather than
+    /// the working directory
+    pub fn load_project_in(start: &Path) -> Result<Option<Self>> {
+        Self::load_project_at(&project_config_path_for(start))
+    }
+
+    fn load_project_at(path: &Path) -> Result<Option<Self>> {
+        let Some(mut config) = Self::load_project_file_at(path)? else {
+            return Ok(None);
+        };
+        let project_dir = path
+            .parent()
+            .map(Path::to_path_buf)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| PathBuf::from("."));
+        if let Some((_, workspace)) = WorkspaceConfig::find(&project_dir)? {
+            config.inherit(&workspace.resolved());
+        }
+        Ok(Some(config))
+    }
+
+    /// Load the project configuration file as written, without workspace
+    /// settings; use this when the configuration is changed and saved
+    pub fn load_project_file() -> Result<Option<Self>> {
+        Self::load_project_file_at(&project_config_path())
+    }
+
+    fn load_project_file_at(path: &Path) -> Result<Option<Self>> {
+        if path.is_file() {
+            Ok(Some(Self::load(path)?))
+        } else if PROJECT_CONFIG_PATH.get().is_some() {
+            anyhow::bail!("Configuration file not found: {}", path.display())
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Fill in the settings this project leaves unset from its workspace
+    pub fn inherit(&mut self, workspace: &WorkspaceConfig) {
+        let project = &mut self.project;
+        if project.texlive_path.is_none() {
+            project.texlive_path = workspace.texlive_path.clone();
+        }
+        if project.mirror_url.is_none() {
+            project.mirror_url = workspace.mirror_url.clone();
+        }
+        if project.install_global.is_none() {
+            project.install_global = workspace.install_global;
+        }
+        if self.cache.is_none() {
+            self.cache = workspace.cache.clone();
+        }
+
+        // Workspace changes apply before the project's own
+        let patterns = &mut project.compile.clean_patterns;
+        patterns.splice(0..0, workspace.clean_patterns.iter().cloned());
+
+        let own: Vec<String> = self.repositories.iter().map(|repository| repository.name.clone()).collect();
+        self.repositories.extend(
+            workspace
+                .repositories
+                .iter()
+                .filter(|repository| !own.contains(&repository.name))
+                .cloned(),
+        );
+    }
+
+    /// Load the project configuration, falling back to the defaults
+    pub fn load_project_or_default() -> Result<Self> {
+        Ok(Self::load_project()?.unwrap_or_else(Self::new))
+    }
+
+    /// Save to the project configuration file in effect
+    pub fn save_project(&self) -> Result<()> {
+        self.save(project_config_path())
+    }
+    
+    #[allow(dead_code)]
+    pub fn add_dependency(&mut self, name: String, version: String) {
+        self.dependencies.insert(name, version);
+    }
+    
+    #[allow(dead_code)]
+    pub fn remove_dependency(&mut self, name: &str) -> Option<String> {
+        self.dependencies.remove(name)
+    }
+    
+    #[allow(dead_code)]
+    pub fn get_package_dir(&self) -> &str {
+        &self.project.package_dir
+    }
+
+    /// The cache settings in effect: the project's `[cache]`, else the global one
+    pub fn cache_config(&self) -> CacheConfig {
+        self.cache
+            .clone()
+            .or_else(|| GlobalConfig::load().ok().map(|config| config.cache))
+            .unwrap_or_default()
+    }
+
+    /// The TeX Live repository to download from: the project's mirror_url,
+    /// else the global one, else the CTAN redirector
+    pub fn resolved_mirror_url(&self) -> String {
+        self.resolved_mirror().0
+    }
+
+    /// The mirror to download from and where it is configured. In order of
+    /// precedence: the project's mirror_url (or the workspace's), the global
+    /// mirror_url (chosen with `mirror use` or auto-selected on first run),
+    /// and the CTAN redirector.
+    pub fn resolved_mirror(&self) -> (String, &'static str) {
+        if let Some(mirror_url) = &self.project.mirror_url {
+            return (mirror_url.clone(), "project");
+        }
+        match GlobalConfig::load().ok().and_then(|config| config.mirror_url) {
+            Some(mirror_url) => (mirror_url, "global"),
+            None => (crate::mirror::DEFAULT_TLNET_URL.to_string(), "default"),
+        }
+    }
+
+    /// The TeX Live installation to use: the project's texlive_path, else the global one
+    pub fn resolved_texlive_path(&self) -> Option<PathBuf> {
+        self.project
+            .texlive_path
+            .clone()
+            .or_else(|| GlobalConfig::load().ok().and_then(|config| config.texlive_path))
+            .map(PathBuf::from)
+    }
+
+    /// 设置项目配置值
+    pub fn set_project_config(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "name" => self.project.name = value.to_string(),
+            "version" => self.project.version = value.to_string(),
+            "compile" => self.project.compile = CompileCommand::from_string(value)?,
+            "package_dir" => self.project.package_dir = value.to_string(),
+            "texlive_path" => {
+                if value.trim().is_empty() {
+                    self.project.texlive_path = None;
+                } else {
                     self.project.texlive_path = Some(value.to_string());
                 }
             },
@@ -368,6 +1811,26 @@ impl Config {
                     self.project.install_global = Some(value.parse()?);
                 }
             },
+            "output_dir" => {
+                if value.trim().is_empty() {
+                    self.project.output_dir = None;
+                } else {
+                    self.project.output_dir = Some(value.to_string());
+                }
+            },
+            "main" => {
+                if value.trim().is_empty() {
+                    self.project.main = None;
+                } else {
+                    self.project.main = Some(value.to_string());
+                }
+            },
+            key if key.starts_with("cache.") => {
+                let mut cache = self.cache_config();
+                cache.set(key, value)?;
+                self.cache = Some(cache);
+            },
+            key if key.starts_with("build_cache.") => self.build_cache.set(key, value)?,
             _ => return Err(anyhow::anyhow!("Unknown project config key: {}", key)),
         }
         Ok(())
@@ -383,12 +1846,298 @@ impl Config {
             "texlive_path" => self.project.texlive_path.clone(),
             "mirror_url" => self.project.mirror_url.clone(),
             "install_global" => self.project.install_global.map(|b| b.to_string()),
+            "output_dir" => self.project.output_dir.clone(),
+            "main" => self.project.main.clone(),
+            key if key.starts_with("cache.") => self.cache.as_ref().and_then(|cache| cache.get(key)),
+            key if key.starts_with("build_cache.") => self.build_cache.get(key),
             _ => None,
         }
     }
 
     /// 列出所有项目配置键
     pub fn list_project_keys() -> Vec<&'static str> {
-        vec!["name", "version", "compile", "package_dir", "texlive_path", "mirror_url", "install_global"]
+        let mut keys = vec!["name", "version", "compile", "package_dir", "texlive_path", "mirror_url", "install_global", "output_dir", "main"];
+        keys.extend(CacheConfig::keys());
+        keys.extend(BuildCacheConfig::keys());
+        keys
+    }
+
+    /// Remove a project key; required keys go back to their defaults
+    pub fn unset_project_config(&mut self, key: &str) -> Result<()> {
+        let default = Config::new().project;
+        match key {
+            "name" => self.project.name = default.name,
+            "version" => self.project.version = default.version,
+            "compile" => self.project.compile = default.compile,
+            "package_dir" => self.project.package_dir = default.package_dir,
+            "texlive_path" => self.project.texlive_path = None,
+            "mirror_url" => self.project.mirror_url = None,
+            "install_global" => self.project.install_global = None,
+            "output_dir" => self.project.output_dir = None,
+            "main" => self.project.main = None,
+            key if key.starts_with("cache.") => {
+                if let Some(cache) = &mut self.cache {
+                    cache.unset(key)?;
+                    if cache.is_default() {
+                        self.cache = None;
+                    }
+                }
+            },
+            key if key.starts_with("build_cache.") => self.build_cache.unset(key)?,
+            _ => return Err(anyhow::anyhow!("Unknown project config key: {}", key)),
+        }
+        Ok(())
+    }
+
+    /// The clean patterns in effect: the global ones with the changes made by
+    /// the workspace and the project
+    pub fn clean_patterns(&self) -> Vec<String> {
+        let global = GlobalConfig::load()
+            .map(|config| config.clean_patterns)
+            .unwrap_or_else(|_| CompileCommand::default_clean_patterns());
+        let mut patterns = resolve_clean_patterns(&global, &self.project.compile.clean_patterns);
+        // Editors need the SyncTeX files for forward and inverse search
+        if self.project.compile.synctex {
+            patterns.retain(|pattern| pattern != "*.synctex.gz");
+        }
+        patterns
+    }
+
+    /// Append a value to a list key; returns false if nothing was added
+    pub fn add_to_list(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "clean_patterns" => add_clean_pattern(&mut self.project.compile.clean_patterns, value),
+            "clean.keep" => add_clean_pattern(&mut self.clean.keep, value),
+            "repositories" => add_repository(&mut self.repositories, value),
+            _ => Err(anyhow::anyhow!("'{}' is not a list; use 'config set' instead", key)),
+        }
+    }
+
+    /// Remove a value from a list key; returns false if it was not present.
+    /// Removing an inherited clean pattern records it as `!pattern`.
+    pub fn remove_from_list(&mut self, key: &str, value: &str) -> Result<bool> {
+        match key {
+            "clean_patterns" => {
+                if remove_clean_pattern(&mut self.project.compile.clean_patterns, value) {
+                    return Ok(true);
+                }
+                if !self.clean_patterns().iter().any(|pattern| pattern == value) {
+                    return Ok(false);
+                }
+                add_clean_pattern(&mut self.project.compile.clean_patterns, &format!("!{}", value))
+            },
+            "clean.keep" => Ok(remove_clean_pattern(&mut self.clean.keep, value)),
+            "repositories" => Ok(remove_repository(&mut self.repositories, value)),
+            _ => Err(anyhow::anyhow!("'{}' is not a list; use 'config unset' instead", key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_project_root() {
+        let root = tempfile::tempdir().unwrap();
+        let sections = root.path().join("sections").join("appendix");
+        std::fs::create_dir_all(&sections).unwrap();
+        assert_eq!(find_project_root(&sections), None);
+
+        Config::new().save(root.path().join(PROJECT_CONFIG_FILE)).unwrap();
+        let expected = root.path().canonicalize().unwrap();
+        assert_eq!(find_project_root(&sections), Some(expected.clone()));
+        assert_eq!(find_project_root(root.path()), Some(expected));
+    }
+
+    #[test]
+    fn test_list_values() {
+        let mut config = Config::new();
+        assert!(config.add_to_list("clean_patterns", "*.xdv").unwrap());
+        assert!(!config.add_to_list("clean_patterns", "*.xdv").unwrap());
+        assert!(config.add_to_list("clean_patterns", "[*.aux").is_err());
+        assert!(config.remove_from_list("clean_patterns", "*.xdv").unwrap());
+        assert!(config.project.compile.clean_patterns.is_empty());
+
+        assert!(config.add_to_list("repositories", "lab=https://tex.lab.example/repo").unwrap());
+        let lab = config.repositories.last().unwrap();
+        assert_eq!(lab.name, "lab");
+        assert_eq!(lab.priority, 3);
+        assert!(config.add_to_list("repositories", "lab").is_err());
+        assert!(config.remove_from_list("repositories", "lab").unwrap());
+        assert!(config.add_to_list("name", "paper").is_err());
+
+        config.set_project_config("output_dir", "build").unwrap();
+        config.unset_project_config("output_dir").unwrap();
+        assert_eq!(config.project.output_dir, None);
+    }
+
+    #[test]
+    fn test_resolve_clean_patterns() {
+        let inherited = vec!["*.aux".to_string(), "*.log".to_string()];
+        let changes = vec!["*.xdv".to_string(), "!*.log".to_string(), "*.aux".to_string()];
+        assert_eq!(resolve_clean_patterns(&inherited, &changes), vec!["*.aux", "*.xdv"]);
+        assert_eq!(resolve_clean_patterns(&inherited, &[]), inherited);
+    }
+
+    #[test]
+    fn test_clean_keep() {
+        let clean = CleanConfig { keep: vec!["main.pdf".to_string(), "figures/**".to_string()] };
+        assert!(clean.is_kept(Path::new("main.pdf")));
+        assert!(clean.is_kept(Path::new("sections/main.pdf")));
+        assert!(clean.is_kept(Path::new("figures/plots/result.pdf")));
+        assert!(!clean.is_kept(Path::new("main.aux")));
+        assert!(!clean.is_kept(Path::new("sections/figures/plot.pdf")));
+    }
+
+    #[test]
+    fn test_export_skips_texlive_path() {
+        let mut config = GlobalConfig::new();
+        config.texlive_path = Some("/usr/local/texlive/2024".to_string());
+        config.proxy = Some("http://proxy.example.com:3128".to_string());
+
+        let exported = config.export().unwrap();
+        assert!(!exported.contains("texlive_path"));
+        assert!(crate::config_check::check_global_config(&exported).is_empty());
+        let imported: GlobalConfig = toml::from_str(&exported).unwrap();
+        assert_eq!(imported.proxy, config.proxy);
+    }
+
+    #[test]
+    fn test_workspace_inheritance() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(
+            root.path().join(PROJECT_CONFIG_FILE),
+            "[workspace]\nmirror_url = \"https://mirror.example.org/tlnet\"\nclean_patterns = [\"*.xdv\"]\n\n\
+             [[workspace.repositories]]\nname = \"lab\"\nurl = \"https://tex.lab.example/repo\"\npriority = 0\n",
+        )
+        .unwrap();
+        let paper = root.path().join("papers").join("thesis");
+        std::fs::create_dir_all(&paper).unwrap();
+
+        let (path, workspace) = WorkspaceConfig::find(&paper).unwrap().unwrap();
+        assert_eq!(path, root.path().canonicalize().unwrap().join(PROJECT_CONFIG_FILE));
+        // A workspace-only file is still a valid project configuration
+        assert!(Config::load(&path).is_ok());
+
+        let mut config = Config::new();
+        config.project.compile.clean_patterns = vec!["*.aux".to_string()];
+        config.project.install_global = Some(true);
+        config.inherit(&workspace);
+        assert_eq!(config.project.mirror_url.as_deref(), Some("https://mirror.example.org/tlnet"));
+        assert_eq!(config.project.compile.clean_patterns, vec!["*.xdv", "*.aux"]);
+        assert_eq!(config.project.install_global, Some(true));
+        assert!(config.repositories.iter().any(|repository| repository.name == "lab"));
+    }
+
+    #[test]
+    fn test_profile_overrides() {
+        let mut config = GlobalConfig::new();
+        config.mirror_url = Some("https://home.example.org/tlnet".to_string());
+        config.profiles.insert(
+            "work".to_string(),
+            Profile {
+                mirror_url: Some("https://mirror.corp.example/tlnet".to_string()),
+                proxy: Some("http://proxy.corp.example:3128".to_string()),
+                ..Profile::default()
+            },
+        );
+
+        let work = config.clone().with_profile("work").unwrap();
+        assert_eq!(work.mirror_url.as_deref(), Some("https://mirror.corp.example/tlnet"));
+        assert_eq!(work.proxy.as_deref(), Some("http://proxy.corp.example:3128"));
+        assert_eq!(work.texlive_path, None);
+
+        // Changes made under the profile are saved to the profile
+        let mut changed = work.clone();
+        changed.set("mirror_url", "https://other.corp.example/tlnet").unwrap();
+        changed.set("install_global", "true").unwrap();
+        let stored = changed.to_stored();
+        assert_eq!(stored.mirror_url.as_deref(), Some("https://home.example.org/tlnet"));
+        assert_eq!(stored.profiles["work"].mirror_url.as_deref(), Some("https://other.corp.example/tlnet"));
+        assert!(stored.install_global);
+        assert_eq!(stored.proxy, None);
+
+        let error = config.with_profile("hotel").unwrap_err().to_string();
+        assert!(error.contains("available: work"));
+    }
+
+    #[test]
+    fn test_compile_overrides() {
+        let compile: CompileCommand = toml::from_str(
+            r#"
+steps = [
+    { tool = "pdflatex", args = ["-interaction=nonstopmode", "main.tex"] },
+    { tool = "bibtex", args = ["main"] },
+    { tool = "pdflatex", args = ["-interaction=nonstopmode", "main.tex"] },
+]
+
+[overrides."slides/*.tex"]
+engine = "xelatex"
+
+[overrides."slides/handout-*.tex"]
+steps = [{ tool = "latexmk", args = ["-pdf"] }]
+engine = "lualatex"
+"#,
+        )
+        .unwrap();
+        assert!(compile.override_for("main.tex").is_none());
+        assert!(compile.override_for("slides/old/intro.tex").is_none());
+
+        let (pattern, slides) = compile.override_for("slides/intro.tex").unwrap();
+        assert_eq!(pattern, "slides/*.tex");
+        let chain = compile.with_override(slides, "slides/intro.tex").with_target(Path::new("slides/intro.tex"));
+        assert_eq!(
+            chain.to_string(),
+            "xelatex -interaction=nonstopmode slides/intro.tex | bibtex intro | xelatex -interaction=nonstopmode slides/intro.tex"
+        );
+
+        let (pattern, handout) = compile.override_for("slides/handout-1.tex").unwrap();
+        assert_eq!(pattern, "slides/handout-*.tex");
+        assert_eq!(compile.with_override(handout, "slides/handout-1.tex").to_string(), "latexmk -lualatex slides/handout-1.tex");
+    }
+
+    #[test]
+    fn test_has_shell_escape() {
+        assert!(!CompileCommand::from_string("pdflatex main.tex | bibtex main").unwrap().has_shell_escape());
+        assert!(CompileCommand::from_string("pdflatex -shell-escape main.tex").unwrap().has_shell_escape());
+        assert!(CompileCommand::from_string("bibtex main | xelatex --shell-escape main.tex").unwrap().has_shell_escape());
+        assert!(CompileCommand::from_string("pdflatex -enable-write18 main.tex").unwrap().has_shell_escape());
+        assert!(!CompileCommand::from_string("pdflatex -no-shell-escape main.tex").unwrap().has_shell_escape());
+    }
+
+    #[test]
+    fn test_chain_variables() {
+        let mut config = Config::new();
+        config.project.name = "thesis".to_string();
+        config.project.main = Some("src/thesis.tex".to_string());
+        config.project.output_dir = Some("build".to_string());
+        let variables = config.chain_variables(Path::new("/p"));
+
+        let compile = CompileCommand::from_string("xelatex -output-directory=${OUTDIR} ${MAIN_FILE} | biber ${OUTDIR}/${MAIN_BASENAME}").unwrap();
+        assert_eq!(
+            compile.resolve_variables(&variables).unwrap(),
+            vec![
+                vec!["xelatex", "-output-directory=build", "src/thesis.tex"],
+                vec!["biber", "build/thesis"],
+            ]
+        );
+        assert_eq!(compile.document(&variables).as_deref(), Some("src/thesis.tex"));
+        let compile = CompileCommand::from_string("pdflatex ${PROJECT_ROOT}/main.tex").unwrap();
+        assert_eq!(compile.document(&variables).as_deref(), Some("main.tex"));
+
+        std::env::set_var("TPMGR_TEST_JOBNAME", "draft");
+        let compile = CompileCommand::from_string("pdflatex -jobname=${PROJECT_NAME}-${env:TPMGR_TEST_JOBNAME} main.tex").unwrap();
+        assert_eq!(compile.resolve_variables(&variables).unwrap()[0][1], "-jobname=thesis-draft");
+        let compile = CompileCommand::from_string("pdflatex -jobname=${env:TPMGR_TEST_UNSET} main.tex").unwrap();
+        assert!(compile.resolve_variables(&variables).is_err());
+    }
+
+    #[test]
+    fn test_auto_config_retry() {
+        let now = 1_700_000_000;
+        assert!(AutoConfigState::should_retry(None, now));
+        assert!(!AutoConfigState::should_retry(Some(now - 60), now));
+        assert!(AutoConfigState::should_retry(Some(now - AUTO_CONFIG_RETRY_SECS), now));
     }
 }
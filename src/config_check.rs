@@ -0,0 +1,544 @@
+use crate::config::{CompileCommand, Config, GlobalConfig};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a configuration file
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    /// Dotted key the issue refers to, e.g. `project.compile.clean_patterns`
+    pub key: Option<String>,
+    pub line: Option<usize>,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, "`{}`: ", key)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n    help: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keys allowed in each table, by dotted table path (`*` matches any name);
+/// tables not listed here accept any key
+type Schema = &'static [(&'static str, &'static [&'static str])];
+
+/// Tables of tpmgr.toml; dependencies and command mappings accept any key
+const PROJECT_SCHEMA: Schema = &[
+    ("", &["project", "dependencies", "repositories", "command_packages", "cache", "clean", "build_cache", "assets", "release", "workspace"]),
+    ("project", &["name", "version", "compile", "package_dir", "texlive_path", "mirror_url", "install_global", "output_dir", "main"]),
+    ("project.compile", &["steps", "auto_clean", "clean_patterns", "post", "profiles", "overrides", "index", "max_passes", "interaction", "halt_on_error", "synctex"]),
+    ("project.compile.index", &["tool", "style", "args"]),
+    ("project.compile.steps", &["tool", "args", "name", "needs"]),
+    ("project.compile.post", &["action", "output", "quality", "level", "tool", "args"]),
+    ("project.compile.overrides.*", &["engine", "steps"]),
+    ("project.compile.overrides.*.steps", &["tool", "args", "name", "needs"]),
+    ("project.compile.profiles.*", &["post", "validate"]),
+    ("project.compile.profiles.*.post", &["action", "output", "quality", "level", "tool", "args"]),
+    ("project.compile.profiles.*.validate", &["pdfa", "validator"]),
+    ("repositories", &["name", "url", "priority", "kind", "archive_template"]),
+    ("cache", &["keep", "max_size_mb", "max_age_days"]),
+    ("clean", &["keep"]),
+    ("build_cache", &["enabled", "dir", "max_entries"]),
+    ("release", &["name", "dir", "profile"]),
+    ("assets", &["generate", "convert"]),
+    ("assets.generate", &["tool", "args", "inputs", "outputs"]),
+    ("assets.convert", &["from", "to", "tool", "args"]),
+    ("command_packages", &["commands", "environments"]),
+    ("workspace", &["texlive_path", "mirror_url", "install_global", "clean_patterns", "repositories", "cache", "profiles"]),
+    ("workspace.repositories", &["name", "url", "priority", "kind", "archive_template"]),
+    ("workspace.cache", &["keep", "max_size_mb", "max_age_days"]),
+    ("workspace.profiles.*", &["texlive_path", "mirror_url"]),
+];
+
+/// Tables of the global config.toml
+const GLOBAL_SCHEMA: Schema = &[
+    ("", &[
+        "texlive_path", "mirror_url", "mirror", "proxy", "custom_mirrors", "repositories", "cache", "ui",
+        "clean_patterns", "compile_command", "install_global", "command_packages", "profiles",
+    ]),
+    ("ui", &["emoji"]),
+    ("mirror", &["name", "url", "latency_ms", "archive_template"]),
+    ("custom_mirrors", &["name", "url", "index_template", "archive_template"]),
+    ("repositories", &["name", "url", "priority", "kind", "archive_template"]),
+    ("cache", &["keep", "max_size_mb", "max_age_days"]),
+    ("compile_command", &["steps", "auto_clean", "clean_patterns", "post", "profiles", "overrides", "index", "max_passes", "interaction", "halt_on_error", "synctex"]),
+    ("compile_command.index", &["tool", "style", "args"]),
+    ("compile_command.steps", &["tool", "args", "name", "needs"]),
+    ("compile_command.post", &["action", "output", "quality", "level", "tool", "args"]),
+    ("compile_command.overrides.*", &["engine", "steps"]),
+    ("compile_command.overrides.*.steps", &["tool", "args", "name", "needs"]),
+    ("compile_command.profiles.*", &["post", "validate"]),
+    ("compile_command.profiles.*.post", &["action", "output", "quality", "level", "tool", "args"]),
+    ("compile_command.profiles.*.validate", &["pdfa", "validator"]),
+    ("command_packages", &["commands", "environments"]),
+    ("profiles.*", &["texlive_path", "mirror_url", "mirror", "proxy"]),
+    ("profiles.*.mirror", &["name", "url", "latency_ms", "archive_template"]),
+];
+
+/// Check the contents of a tpmgr.toml: syntax, unknown keys, value types,
+/// glob patterns, empty compile chains and conflicting options
+pub fn check_project_config(content: &str) -> Vec<ConfigIssue> {
+    let table = match content.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(e) => return vec![parse_error(content, &e)],
+    };
+
+    let mut issues = Vec::new();
+    check_keys(PROJECT_SCHEMA, &table, "", content, &mut issues);
+    if !issues.is_empty() {
+        return issues;
+    }
+
+    match toml::from_str::<Config>(content) {
+        Ok(config) => check_values(&config, content, &mut issues),
+        Err(e) => issues.push(parse_error(content, &e)),
+    }
+    issues
+}
+
+/// Check the contents of the global config.toml: syntax, unknown keys,
+/// value types and glob patterns
+pub fn check_global_config(content: &str) -> Vec<ConfigIssue> {
+    let table = match content.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(e) => return vec![parse_error(content, &e)],
+    };
+
+    let mut issues = Vec::new();
+    check_keys(GLOBAL_SCHEMA, &table, "", content, &mut issues);
+    if !issues.is_empty() {
+        return issues;
+    }
+
+    match toml::from_str::<GlobalConfig>(content) {
+        Ok(config) => {
+            for pattern in &config.clean_patterns {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    issues.push(ConfigIssue {
+                        severity: Severity::Error,
+                        key: Some("clean_patterns".to_string()),
+                        line: find_line(content, "clean_patterns"),
+                        message: format!("invalid glob pattern \"{}\": {}", pattern, e.msg),
+                        suggestion: None,
+                    });
+                }
+            }
+        }
+        Err(e) => issues.push(parse_error(content, &e)),
+    }
+    issues
+}
+
+/// Whether any of the issues is an error
+pub fn has_errors(issues: &[ConfigIssue]) -> bool {
+    issues.iter().any(|issue| issue.severity == Severity::Error)
+}
+
+fn check_keys(schema: Schema, table: &toml::Table, path: &str, content: &str, issues: &mut Vec<ConfigIssue>) {
+    let Some(allowed) = schema_keys(schema, path) else {
+        return;
+    };
+
+    for (key, value) in table {
+        let full_key = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+        if !allowed.contains(&key.as_str()) {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                line: find_line(content, &full_key),
+                message: "unknown key".to_string(),
+                suggestion: suggest_key(key, allowed).map(|similar| format!("did you mean `{}`?", similar)),
+                key: Some(full_key),
+            });
+            continue;
+        }
+
+        match value {
+            toml::Value::Table(child) => check_keys(schema, child, &full_key, content, issues),
+            toml::Value::Array(items) => {
+                for item in items {
+                    if let toml::Value::Table(child) = item {
+                        check_keys(schema, child, &full_key, content, issues);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn check_values(config: &Config, content: &str, issues: &mut Vec<ConfigIssue>) {
+    let mut push = |severity, key: &str, message: String, suggestion: Option<&str>| {
+        issues.push(ConfigIssue {
+            severity,
+            key: Some(key.to_string()),
+            line: find_line(content, key),
+            message,
+            suggestion: suggestion.map(str::to_string),
+        });
+    };
+
+    let compile = &config.project.compile;
+    if compile.steps.is_empty() {
+        push(
+            Severity::Warning,
+            "project.compile",
+            "the compile chain has no steps, so `tpmgr compile` cannot build anything".to_string(),
+            Some("add a [[project.compile.steps]] entry, e.g. tool = \"pdflatex\", args = [\"main.tex\"]"),
+        );
+    }
+    for (index, step) in compile.steps.iter().enumerate() {
+        if step.tool.trim().is_empty() {
+            push(
+                Severity::Error,
+                "project.compile.steps.tool",
+                format!("compile step {} has an empty tool", index + 1),
+                None,
+            );
+        }
+        for arg in &step.args {
+            for variable in arg.split("${").skip(1).filter_map(|rest| rest.split_once('}')).map(|(name, _)| name) {
+                let known = CompileCommand::supported_variables().contains(&format!("${{{}}}", variable).as_str());
+                if !known && !variable.starts_with("env:") {
+                    push(
+                        Severity::Warning,
+                        "project.compile.steps.args",
+                        format!("compile step {} uses the unknown variable ${{{}}}, which is passed on as is", index + 1, variable),
+                        Some("use ${MAIN_FILE}, ${MAIN_BASENAME}, ${OUTDIR}, ${PROJECT_NAME}, ${PROJECT_ROOT}, ${CURRENT_DIR}, ${HOME} or ${env:VAR}"),
+                    );
+                }
+            }
+        }
+    }
+    let mut names: Vec<&str> = Vec::new();
+    for name in compile.steps.iter().filter_map(|step| step.name.as_deref()) {
+        if names.contains(&name) {
+            push(
+                Severity::Error,
+                "project.compile.steps.name",
+                format!("two compile steps are named \"{}\"", name),
+                Some("`needs` refers to steps by name, so names must be unique"),
+            );
+        }
+        names.push(name);
+    }
+    if let Err(e) = crate::chain::step_dependencies(&compile.steps).and_then(|dependencies| crate::chain::step_stages(&dependencies)) {
+        push(
+            Severity::Error,
+            "project.compile.steps.needs",
+            e.to_string(),
+            Some("`needs` lists the names of the steps a step waits for; without it, a step waits for the one before it"),
+        );
+    }
+    let release_profile = config.release.profile.as_deref();
+    if let Some(profile) = release_profile.filter(|name| *name != crate::config::ARCHIVE_PROFILE && !compile.profiles.contains_key(*name)) {
+        push(
+            Severity::Error,
+            "release.profile",
+            format!("release builds with compile profile \"{}\", which is not defined", profile),
+            Some("define it under [project.compile.profiles] or use the built-in \"archive\" profile"),
+        );
+    }
+    if compile.max_passes == 0 {
+        push(
+            Severity::Error,
+            "project.compile.max_passes",
+            "max_passes must be at least 1".to_string(),
+            Some("use 1 to never rerun the last engine step, or leave it out for 5"),
+        );
+    }
+    for (pattern, compile_override) in &compile.overrides {
+        if let Err(e) = glob::Pattern::new(pattern) {
+            push(
+                Severity::Error,
+                "project.compile.overrides",
+                format!("invalid glob pattern \"{}\": {}", pattern, e.msg),
+                Some("patterns are matched against document paths relative to the project root, e.g. \"slides/*.tex\""),
+            );
+        }
+        if let Some(engine) = compile_override.engine.as_deref().filter(|engine| !crate::config::TEX_ENGINES.contains(engine)) {
+            push(
+                Severity::Error,
+                "project.compile.overrides",
+                format!("override \"{}\" selects unknown engine \"{}\"", pattern, engine),
+                Some(&format!("use one of {}", crate::config::TEX_ENGINES.join(", "))),
+            );
+        }
+        if compile_override.engine.is_none() && compile_override.steps.is_empty() {
+            push(
+                Severity::Warning,
+                "project.compile.overrides",
+                format!("override \"{}\" sets neither engine nor steps and changes nothing", pattern),
+                None,
+            );
+        }
+    }
+    for (index, step) in compile.post.iter().enumerate() {
+        if let Some(problem) = step.problem() {
+            push(
+                Severity::Error,
+                "project.compile.post",
+                format!("post-processing step {}: {}", index + 1, problem),
+                None,
+            );
+        }
+    }
+    for (name, profile) in &compile.profiles {
+        for (index, step) in profile.post.iter().enumerate() {
+            if let Some(problem) = step.problem() {
+                push(
+                    Severity::Error,
+                    "project.compile.profiles",
+                    format!("profile \"{}\", post-processing step {}: {}", name, index + 1, problem),
+                    None,
+                );
+            }
+        }
+        let flavour = profile.validate.as_ref().map(|validation| validation.pdfa.as_str());
+        if let Some(flavour) = flavour.filter(|flavour| !crate::validate::PDFA_FLAVOURS.contains(flavour)) {
+            push(
+                Severity::Error,
+                "project.compile.profiles",
+                format!("profile \"{}\" validates against unknown PDF/A flavour \"{}\"", name, flavour),
+                Some(&format!("use one of {}", crate::validate::PDFA_FLAVOURS.join(", "))),
+            );
+        }
+    }
+
+    let workspace_patterns = config.workspace.iter().flat_map(|workspace| &workspace.clean_patterns);
+    let patterns = compile.clean_patterns.iter().map(|pattern| ("project.compile.clean_patterns", pattern))
+        .chain(config.clean.keep.iter().map(|pattern| ("clean.keep", pattern)));
+    for (key, pattern) in patterns.chain(workspace_patterns.map(|pattern| ("workspace.clean_patterns", pattern))) {
+        if let Err(e) = glob::Pattern::new(pattern.strip_prefix('!').unwrap_or(pattern)) {
+            push(
+                Severity::Error,
+                key,
+                format!("invalid glob pattern \"{}\": {}", pattern, e.msg),
+                Some("patterns support *, ?, [...] and ** wildcards"),
+            );
+        }
+    }
+
+    for rule in &config.assets.generate {
+        if let Some(problem) = rule.problem() {
+            push(Severity::Error, "assets.generate", problem, None);
+        }
+    }
+    for rule in &config.assets.convert {
+        if let Some(problem) = rule.problem() {
+            push(Severity::Error, "assets.convert", problem, None);
+        }
+    }
+
+    if config.project.output_dir.as_deref().map(|dir| dir.trim_end_matches('/'))
+        == Some(config.project.package_dir.trim_end_matches('/'))
+    {
+        push(
+            Severity::Error,
+            "project.output_dir",
+            format!("output_dir and package_dir are both \"{}\"", config.project.package_dir),
+            Some("build output is excluded from scanning; use a separate directory such as \"build\""),
+        );
+    }
+
+    let mut names: Vec<&str> = Vec::new();
+    for repository in &config.repositories {
+        if names.contains(&repository.name.as_str()) {
+            push(
+                Severity::Error,
+                "repositories.name",
+                format!("repository \"{}\" is defined more than once", repository.name),
+                Some("give each [[repositories]] entry a unique name"),
+            );
+        }
+        names.push(&repository.name);
+    }
+
+    if let Some(cache) = &config.cache {
+        if !cache.keep && (cache.max_size_mb.is_some() || cache.max_age_days.is_some()) {
+            push(
+                Severity::Warning,
+                "cache.keep",
+                "cache limits have no effect when keep = false".to_string(),
+                Some("remove max_size_mb/max_age_days or set keep = true"),
+            );
+        }
+    }
+}
+
+fn schema_keys(schema: Schema, path: &str) -> Option<&'static [&'static str]> {
+    let matches = |table: &str| {
+        let table_parts: Vec<&str> = table.split('.').collect();
+        let path_parts: Vec<&str> = path.split('.').collect();
+        table_parts.len() == path_parts.len()
+            && table_parts.iter().zip(&path_parts).all(|(pattern, part)| *pattern == "*" || pattern == part)
+    };
+    schema
+        .iter()
+        .find(|(table, _)| matches(table))
+        .map(|(_, keys)| *keys)
+}
+
+fn parse_error(content: &str, error: &toml::de::Error) -> ConfigIssue {
+    let line = error.span().map(|span| line_of_offset(content, span.start));
+    ConfigIssue {
+        severity: Severity::Error,
+        key: line.and_then(|line| key_on_line(content, line)),
+        line,
+        message: error.message().trim().to_string(),
+        suggestion: None,
+    }
+}
+
+fn line_of_offset(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// The key assigned on a line, e.g. `version` for `version = 2`
+fn key_on_line(content: &str, line: usize) -> Option<String> {
+    let text = content.lines().nth(line - 1)?;
+    let (key, _) = text.split_once('=')?;
+    Some(key.trim().trim_matches('"').to_string())
+}
+
+/// The line where a dotted key (or the closest enclosing table) is defined
+fn find_line(content: &str, key: &str) -> Option<usize> {
+    let mut key = key;
+    loop {
+        if let Some(line) = find_exact_line(content, key) {
+            return Some(line);
+        }
+        key = &key[..key.rfind('.')?];
+    }
+}
+
+fn find_exact_line(content: &str, key: &str) -> Option<usize> {
+    let mut table = String::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            let header = header.trim_start_matches('[');
+            let header = header.split(']').next().unwrap_or_default();
+            table = header.split('.').map(|part| part.trim().trim_matches('"')).collect::<Vec<_>>().join(".");
+            if table == key {
+                return Some(index + 1);
+            }
+        } else if let Some((name, _)) = line.split_once('=') {
+            let name = name.split('.').map(|part| part.trim().trim_matches('"')).collect::<Vec<_>>().join(".");
+            let full = if table.is_empty() { name } else { format!("{}.{}", table, name) };
+            if full == key {
+                return Some(index + 1);
+            }
+        }
+    }
+    None
+}
+
+/// The allowed key closest to a misspelt one
+fn suggest_key<'a>(key: &str, allowed: &[&'a str]) -> Option<&'a str> {
+    allowed
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(2))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = r#"[project]
+name = "paper"
+version = "0.1.0"
+package_dir = "packages"
+
+[project.compile]
+auto_clean = true
+clean_patterns = ["*.aux"]
+
+[[project.compile.steps]]
+tool = "pdflatex"
+args = ["main.tex"]
+
+[dependencies]
+
+[[repositories]]
+name = "ctan"
+url = "https://ctan.org/"
+priority = 1
+"#;
+
+    #[test]
+    fn test_valid_config() {
+        assert!(check_project_config(VALID).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_key() {
+        let content = VALID.replace("auto_clean = true", "auto_clen = true");
+        let issues = check_project_config(&content);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].key.as_deref(), Some("project.compile.auto_clen"));
+        assert_eq!(issues[0].line, Some(7));
+        assert_eq!(issues[0].suggestion.as_deref(), Some("did you mean `auto_clean`?"));
+    }
+
+    #[test]
+    fn test_bad_values() {
+        let content = VALID
+            .replace("[\"*.aux\"]", "[\"[*.aux\"]")
+            .replace("version = \"0.1.0\"", "version = 2");
+        let issues = check_project_config(&content);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, Some(3));
+        assert_eq!(issues[0].key.as_deref(), Some("version"));
+
+        let content = VALID.replace("[\"*.aux\"]", "[\"[*.aux\"]");
+        let issues = check_project_config(&content);
+        assert!(has_errors(&issues));
+        assert_eq!(issues[0].key.as_deref(), Some("project.compile.clean_patterns"));
+        assert_eq!(issues[0].line, Some(8));
+
+        let content = format!("{}\n[[project.compile.steps]]\ntool = \"makeindex\"\nargs = [\"main\"]\nneeds = [\"figures\"]\n", VALID);
+        let issues = check_project_config(&content);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].key.as_deref(), Some("project.compile.steps.needs"));
+        assert!(issues[0].message.contains("\"figures\""));
+
+        let content = format!("{}\n[[project.compile.post]]\naction = \"compress\"\nquality = \"tiny\"\n", VALID);
+        let issues = check_project_config(&content);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].key.as_deref(), Some("project.compile.post"));
+        assert!(issues[0].message.contains("tiny"));
+    }
+}
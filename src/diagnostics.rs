@@ -0,0 +1,89 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::package::PackageManager;
+use crate::tex_parser::{MissingPackageDiagnostic, TeXParser};
+use crate::texlive::TeXLiveManager;
+
+/// Missing packages of one file, as reported to editors
+#[derive(Debug, Serialize)]
+pub struct BufferReport {
+    pub file: String,
+    pub missing: Vec<String>,
+    pub diagnostics: Vec<MissingPackageDiagnostic>,
+}
+
+/// Analyzes editor buffers; the TeX Live package list is loaded once, so a
+/// long-running server answers repeated requests quickly
+pub struct BufferAnalyzer {
+    parser: TeXParser,
+    texlive: Option<TeXLiveManager>,
+}
+
+impl BufferAnalyzer {
+    /// Detect TeX Live and load its package list
+    pub fn new(parser: TeXParser) -> Self {
+        let mut texlive = TeXLiveManager::new();
+        let texlive_available = texlive.detect_texlive().is_ok() && texlive.scan_installed_packages().is_ok();
+        Self::with_texlive(parser, texlive_available.then_some(texlive))
+    }
+
+    pub fn with_texlive(parser: TeXParser, texlive: Option<TeXLiveManager>) -> Self {
+        Self { parser, texlive }
+    }
+
+    pub fn texlive(&self) -> Option<&TeXLiveManager> {
+        self.texlive.as_ref()
+    }
+
+    /// Report the packages `content` (the text of `file`, saved or not) loads
+    /// that are neither in TeX Live nor installed in the project. Local
+    /// .sty/.cls files next to `file` are read from disk.
+    pub async fn analyze(&self, file: &Path, content: &str) -> Result<BufferReport> {
+        let mut dependencies = self.parser.parse_content(content)?;
+        for dependency in &mut dependencies {
+            dependency.source_file = Some(file.to_path_buf());
+        }
+        let project_dir = file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let mut local_packages = TeXParser::find_local_packages(project_dir)?;
+        self.parser.add_local_package_dependencies(&mut dependencies, &mut local_packages);
+        let packages: Vec<String> = TeXParser::filter_core_packages(&TeXParser::get_unique_packages(&dependencies))
+            .into_iter()
+            .filter(|package| !local_packages.contains_key(package))
+            .collect();
+
+        let missing = self.unavailable_packages(&packages).await?;
+        // Requirements of local .sty files are listed as missing but have no place in the buffer
+        let buffer_dependencies: Vec<_> = dependencies
+            .into_iter()
+            .filter(|dependency| dependency.source_file.as_deref() == Some(file))
+            .collect();
+        let diagnostics = TeXParser::missing_package_diagnostics(content, &buffer_dependencies, &missing);
+        Ok(BufferReport {
+            file: file.display().to_string(),
+            missing,
+            diagnostics,
+        })
+    }
+
+    /// The packages that are neither in TeX Live nor installed in the project
+    async fn unavailable_packages(&self, packages: &[String]) -> Result<Vec<String>> {
+        let manager = PackageManager::read_only(false)?;
+        let mut missing = Vec::new();
+        for package in packages {
+            let in_texlive = self
+                .texlive
+                .as_ref()
+                .is_some_and(|texlive| texlive.is_package_installed(&texlive.resolve_package_name(package)));
+            if !in_texlive && !manager.is_package_installed(package).await? {
+                missing.push(package.clone());
+            }
+        }
+        // Files the database misses, e.g. copied into TEXMFHOME by hand
+        if let Some(found) = TeXLiveManager::kpsewhich_lookup(&missing) {
+            missing.retain(|package| !found.contains(package));
+        }
+        Ok(missing)
+    }
+}
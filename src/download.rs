@@ -0,0 +1,84 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Directory holding downloaded package indexes and mirror lists
+pub fn index_dir() -> Result<PathBuf> {
+    Ok(dirs::data_dir()
+        .ok_or_else(|| anyhow::anyhow!("Could not find data directory"))?
+        .join("tpmgr")
+        .join("index"))
+}
+
+/// Check whether a URL must be fetched with rsync
+pub fn is_rsync_url(url: &str) -> bool {
+    url.starts_with("rsync://")
+}
+
+/// Check whether the rsync binary is on PATH
+pub fn rsync_available() -> bool {
+    crate::tools::find_executable("rsync").is_some()
+}
+
+/// HTTP client honouring the configured `proxy` (HTTP(S)_PROXY is used otherwise)
+pub fn http_client() -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = crate::config::GlobalConfig::load().ok().and_then(|config| config.proxy) {
+        builder = builder.proxy(reqwest::Proxy::all(&proxy)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Download `url` to `dest`; http(s) URLs are fetched directly, rsync:// URLs
+/// with the rsync binary and file:// URLs are copied
+pub async fn fetch(url: &str, dest: &Path) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    if is_rsync_url(url) {
+        return rsync(url, dest).await;
+    }
+
+    // Self-hosted repositories may be plain directories
+    if let Some(path) = url.strip_prefix("file://") {
+        std::fs::copy(path, dest)?;
+        return Ok(());
+    }
+
+    let content = http_client()?.get(url).send().await?.error_for_status()?.bytes().await?;
+    std::fs::write(dest, &content)?;
+    Ok(())
+}
+
+/// Download `url` to `cache_path` unless the cached copy is younger than `max_age`
+pub async fn fetch_cached(url: &str, cache_path: &Path, max_age: Duration) -> Result<()> {
+    let is_fresh = std::fs::metadata(cache_path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|age| age < max_age)
+        .unwrap_or(false);
+
+    if !is_fresh {
+        println!("Downloading {}...", url);
+        fetch(url, cache_path).await?;
+    }
+    Ok(())
+}
+
+async fn rsync(url: &str, dest: &Path) -> Result<()> {
+    let rsync = crate::tools::find_executable("rsync")
+        .ok_or_else(|| anyhow::anyhow!("rsync is required to download {} but was not found on PATH", url))?;
+
+    let status = tokio::process::Command::new(rsync)
+        .args(["--quiet", "--timeout=60"])
+        .arg(url)
+        .arg(dest)
+        .status()
+        .await?;
+    if !status.success() {
+        anyhow::bail!("rsync failed to download {} ({})", url, status);
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// Where tpmgr.toml's [dependencies], the package registry (which records
+/// the installed versions) and the packages the documents load disagree
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct DriftReport {
+    /// Declared in tpmgr.toml but not installed in the project
+    pub declared_not_installed: Vec<String>,
+    /// Installed with another version than declared: (name, declared, installed)
+    pub version_mismatch: Vec<(String, String, String)>,
+    /// Declared but not loaded by any document or needed package
+    pub declared_unused: Vec<String>,
+    /// Loaded by a document and installed in the project, but not declared
+    pub used_undeclared: Vec<String>,
+    /// Loaded by a document but installed nowhere
+    pub missing: Vec<String>,
+    /// Installed in the project but neither declared nor needed
+    pub installed_unused: Vec<String>,
+}
+
+impl DriftReport {
+    /// Compare the declared dependencies and the registry with the packages
+    /// the documents load (`used`). `available` tells whether TeX Live or a
+    /// project file provides a package; `requires` lists the packages an
+    /// installed package loads, which count as used too.
+    pub fn detect(
+        declared: &HashMap<String, String>,
+        registry: &HashMap<String, String>,
+        used: &[String],
+        available: impl Fn(&str) -> bool,
+        requires: impl Fn(&str) -> Vec<String>,
+    ) -> Self {
+        let mut needed = BTreeSet::new();
+        let mut pending = used.to_vec();
+        while let Some(name) = pending.pop() {
+            if registry.contains_key(&name) && !needed.contains(&name) {
+                pending.extend(requires(&name));
+            }
+            needed.insert(name);
+        }
+
+        let mut report = Self::default();
+        for (name, version) in sorted(declared) {
+            match registry.get(name) {
+                None => report.declared_not_installed.push(name.clone()),
+                Some(installed) if !version_matches(version, installed) => {
+                    report.version_mismatch.push((name.clone(), version.clone(), installed.clone()))
+                }
+                Some(_) => {}
+            }
+            if !needed.contains(name) {
+                report.declared_unused.push(name.clone());
+            }
+        }
+        for name in used.iter().collect::<BTreeSet<_>>() {
+            if declared.contains_key(name) {
+                continue;
+            }
+            if registry.contains_key(name) {
+                report.used_undeclared.push(name.clone());
+            } else if !available(name) {
+                report.missing.push(name.clone());
+            }
+        }
+        for (name, _) in sorted(registry) {
+            if !declared.contains_key(name) && !needed.contains(name) {
+                report.installed_unused.push(name.clone());
+            }
+        }
+        report
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Whether an installed version satisfies a declared one ("*", "latest" or
+/// an empty version accept any)
+fn version_matches(declared: &str, installed: &str) -> bool {
+    matches!(declared.trim(), "" | "*" | "latest") || declared.trim() == installed
+}
+
+fn sorted(map: &HashMap<String, String>) -> Vec<(&String, &String)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort();
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drift_report() {
+        let map = |entries: &[(&str, &str)]| entries.iter().map(|(name, version)| (name.to_string(), version.to_string())).collect::<HashMap<_, _>>();
+        let declared = map(&[("geometry", "5.9"), ("siunitx", "*"), ("minted", "latest"), ("tikz-cd", "1.0")]);
+        let registry = map(&[("geometry", "5.8"), ("siunitx", "3.3"), ("pgfopts", "2.1"), ("mhchem", "4.1"), ("oldpkg", "1.0"), ("tikz-cd", "1.0")]);
+        let used: Vec<String> = ["geometry", "siunitx", "mhchem", "amsmath", "fancybox", "tikz-cd"].iter().map(|name| name.to_string()).collect();
+        let requires = |name: &str| if name == "siunitx" { vec!["pgfopts".to_string()] } else { Vec::new() };
+
+        let report = DriftReport::detect(&declared, &registry, &used, |name| name == "amsmath", requires);
+        assert_eq!(report.declared_not_installed, vec!["minted"]);
+        assert_eq!(report.version_mismatch, vec![("geometry".to_string(), "5.9".to_string(), "5.8".to_string())]);
+        assert_eq!(report.declared_unused, vec!["minted"]);
+        assert_eq!(report.used_undeclared, vec!["mhchem"]);
+        assert_eq!(report.missing, vec!["fancybox"]);
+        assert_eq!(report.installed_unused, vec!["oldpkg"]);
+        assert!(!report.is_empty());
+
+        let in_sync = DriftReport::detect(&map(&[("tikz-cd", "1.0")]), &map(&[("tikz-cd", "1.0")]), &["tikz-cd".to_string()], |_| false, |_| Vec::new());
+        assert!(in_sync.is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::config::Config;
+use crate::texlive::TeXLiveManager;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Search path variables that get the project's packages directory
+pub const SEARCH_PATH_VARIABLES: &[&str] = &["TEXINPUTS", "BSTINPUTS"];
+
+/// Environment variables for running TeX tools in the project: the local
+/// packages on the kpathsea search paths, the user's TEXMFHOME and the
+/// configured TeX Live first on PATH
+pub fn project_environment(config: &Config, project_root: &Path) -> Result<Vec<(String, String)>> {
+    let packages_dir = project_root.join("packages");
+    let mut variables: Vec<(String, String)> = SEARCH_PATH_VARIABLES
+        .iter()
+        .map(|variable| (variable.to_string(), search_path(variable, &packages_dir)))
+        .collect();
+
+    variables.push(("TEXMFHOME".to_string(), texmf_home()?.to_string_lossy().to_string()));
+
+    if let Some(bin_dir) = config.resolved_texlive_path().as_deref().and_then(TeXLiveManager::bin_dir) {
+        let inherited = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths: Vec<PathBuf> = std::env::split_paths(&inherited).filter(|path| *path != bin_dir).collect();
+        paths.insert(0, bin_dir);
+        let path = std::env::join_paths(paths)?;
+        variables.push(("PATH".to_string(), path.to_string_lossy().to_string()));
+    }
+
+    Ok(variables)
+}
+
+/// The user's TEXMF tree: $TEXMFHOME, else what kpsewhich reports (~/texmf by default)
+pub fn texmf_home() -> Result<PathBuf> {
+    match std::env::var("TEXMFHOME") {
+        Ok(texmf_home) => Ok(PathBuf::from(texmf_home)),
+        Err(_) => TeXLiveManager::new().get_texmf_home(),
+    }
+}
+
+/// A kpathsea search path variable (TEXINPUTS, BSTINPUTS, ...) with the
+/// working directory and `packages_dir` in front
+pub fn search_path(variable: &str, packages_dir: &Path) -> String {
+    #[cfg(windows)]
+    let separator = ";";
+    #[cfg(not(windows))]
+    let separator = ":";
+
+    // A trailing separator keeps the engine's default search path
+    let existing = std::env::var(variable).unwrap_or_default();
+    format!(".{}{}{}{}", separator, packages_dir.to_string_lossy(), separator, existing)
+}
@@ -0,0 +1,130 @@
+use anyhow::Result;
+use glob::{MatchOptions, Pattern};
+use std::path::{Component, Path};
+
+/// Name of the ignore file read from the project root
+pub const IGNORE_FILE: &str = ".tpmgrignore";
+
+/// A single line of an ignore file
+struct IgnoreRule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns containing a slash match against the path from the root,
+    /// others match a file or directory name at any depth
+    anchored: bool,
+}
+
+/// Paths excluded by a .tpmgrignore file (gitignore syntax)
+#[derive(Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+impl IgnoreRules {
+    /// Load the ignore file of a project; a missing file ignores nothing
+    pub fn load(project_root: &Path) -> Result<Self> {
+        let path = project_root.join(IGNORE_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Ok(Self::parse(&content))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut rules = Vec::new();
+
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.trim_start_matches('/');
+
+            match Pattern::new(line) {
+                Ok(pattern) => rules.push(IgnoreRule { pattern, negated, dir_only, anchored }),
+                Err(e) => println!("Warning: Invalid pattern '{}' in {}: {}", line, IGNORE_FILE, e),
+            }
+        }
+
+        Self { rules }
+    }
+
+    /// Check whether a path relative to the project root is ignored, either
+    /// itself or through one of its parent directories
+    pub fn is_ignored(&self, relative_path: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+
+        let components: Vec<&str> = relative_path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        (1..=components.len()).any(|depth| {
+            let candidate = components[..depth].join("/");
+            let candidate_is_dir = depth < components.len() || is_dir;
+            self.matches(&candidate, components[depth - 1], candidate_is_dir)
+        })
+    }
+
+    /// Evaluate the rules in order; the last matching rule decides
+    fn matches(&self, path: &str, name: &str, is_dir: bool) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let matched = if rule.anchored {
+                rule.pattern.matches_with(path, options)
+            } else {
+                rule.pattern.matches_with(name, options)
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gitignore_semantics() {
+        let rules = IgnoreRules::parse("# generated\nbuild/\n*.bak\n/examples/*\n!examples/minimal.tex\nthird_party/**/*.tex\n!keep.bak\n");
+
+        assert!(rules.is_ignored(Path::new("build/main.tex"), false));
+        assert!(rules.is_ignored(Path::new("chapters/build/x.tex"), false));
+        assert!(!rules.is_ignored(Path::new("build"), false));
+        assert!(rules.is_ignored(Path::new("notes.bak"), false));
+        assert!(!rules.is_ignored(Path::new("keep.bak"), false));
+        assert!(rules.is_ignored(Path::new("examples/demo.tex"), false));
+        assert!(!rules.is_ignored(Path::new("examples/minimal.tex"), false));
+        assert!(!rules.is_ignored(Path::new("src/examples/demo.tex"), false));
+        assert!(rules.is_ignored(Path::new("third_party/pkg/doc/a.tex"), false));
+        assert!(!rules.is_ignored(Path::new("main.tex"), false));
+    }
+}
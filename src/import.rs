@@ -0,0 +1,395 @@
+use regex::Regex;
+use std::path::Path;
+
+use crate::config::{CompileCommand, CompileStep};
+use crate::tex_parser::{DependencyType, TeXDependency};
+
+/// A compile chain converted from a latexmkrc or arara directives
+#[derive(Debug, Default)]
+pub struct ImportedChain {
+    pub steps: Vec<CompileStep>,
+    /// latexmk's `$max_repeat`
+    pub max_passes: Option<u32>,
+    /// Settings that were not converted, and why
+    pub notes: Vec<String>,
+}
+
+impl ImportedChain {
+    /// Replace the chain of `compile` with the imported one
+    pub fn apply(&self, compile: &mut CompileCommand) {
+        compile.steps = self.steps.clone();
+        if let Some(max_passes) = self.max_passes {
+            compile.max_passes = max_passes;
+        }
+    }
+}
+
+fn step(tool: &str, args: Vec<String>) -> CompileStep {
+    CompileStep { tool: tool.to_string(), args, ..Default::default() }
+}
+
+/// Split a command line into words, honouring single and double quotes
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote = None;
+    let mut in_word = false;
+    for c in command.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => word.push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            None if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            None => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// The bibliography tool the documents need: biber for biblatex, else
+/// bibtex when they name a bibliography
+fn bibliography_tool(dependencies: &[TeXDependency]) -> Option<&'static str> {
+    if crate::chain::uses_package(dependencies, "biblatex") {
+        Some("biber")
+    } else if dependencies.iter().any(|dependency| dependency.dependency_type == DependencyType::Bibliography) {
+        Some("bibtex")
+    } else {
+        None
+    }
+}
+
+/// A latexmk command template (`pdflatex -shell-escape %O %S`) as a step
+/// compiling `file`; other `%` placeholders are dropped
+fn latexmk_command(template: &str, file: &str, base: &str, notes: &mut Vec<String>) -> Option<CompileStep> {
+    let mut words = split_command(template).into_iter();
+    let tool = words.next()?;
+    let mut args = Vec::new();
+    for word in words {
+        match word.as_str() {
+            "%S" => args.push(file.to_string()),
+            "%B" => args.push(base.to_string()),
+            "%O" => {}
+            _ if word.contains('%') => notes.push(format!("Dropped `{}` from the {} command", word, tool)),
+            _ => args.push(word),
+        }
+    }
+    if !args.iter().any(|arg| arg == file || arg == base) {
+        args.push(file.to_string());
+    }
+    Some(step(&tool, args))
+}
+
+/// Convert a latexmkrc: `$pdf_mode` and the engine commands give the engine,
+/// `@default_files` the documents, `$out_dir` the output directory and
+/// `$max_repeat` the most engine passes. Each document gets an engine run,
+/// the bibliography run its sources need, and another engine run.
+pub fn from_latexmkrc(content: &str, default_file: &str, dependencies: &[TeXDependency]) -> ImportedChain {
+    let assignment = Regex::new(r#"^\s*\$(\w+)\s*=\s*(?:'([^']*)'|"([^"]*)"|([^;\s]+))\s*;"#).expect("valid regex");
+    let list = Regex::new(r"^\s*@default_files\s*=\s*\(([^)]*)\)").expect("valid regex");
+    let tex_cmds = Regex::new(r#"^\s*&?set_tex_cmds\(\s*(?:'([^']*)'|"([^"]*)")\s*\)"#).expect("valid regex");
+
+    let mut imported = ImportedChain::default();
+    let mut variables = std::collections::HashMap::new();
+    let mut files = Vec::new();
+    let mut shared_options = None;
+    for line in content.lines() {
+        let line = line.split(" #").next().unwrap_or(line);
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        if let Some(caps) = assignment.captures(line) {
+            let value = caps.get(2).or(caps.get(3)).or(caps.get(4)).map(|value| value.as_str().to_string()).unwrap_or_default();
+            variables.insert(caps[1].to_string(), value);
+        } else if let Some(caps) = list.captures(line) {
+            files = split_command(&caps[1].replace(',', " "));
+        } else if let Some(caps) = tex_cmds.captures(line) {
+            shared_options = caps.get(1).or(caps.get(2)).map(|options| options.as_str().to_string());
+        }
+    }
+    if files.is_empty() {
+        files.push(default_file.to_string());
+    }
+
+    let engine = match variables.get("pdf_mode").map(String::as_str) {
+        Some("4") => "lualatex",
+        Some("5") => "xelatex",
+        Some("0") | Some("2") | Some("3") => "latex",
+        _ if !variables.contains_key("pdf_mode") && variables.get("dvi_mode").is_some_and(|mode| mode != "0") => "latex",
+        _ => "pdflatex",
+    };
+    let output_dir = variables.get("out_dir").or(variables.get("aux_dir")).filter(|dir| !dir.is_empty() && *dir != ".");
+    let bibliography = match variables.get("bibtex_use").map(String::as_str) {
+        Some("0") => None,
+        _ => bibliography_tool(dependencies),
+    };
+
+    for file in &files {
+        let base = Path::new(file).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let template = match (variables.get(engine), &shared_options) {
+            (Some(template), _) => template.clone(),
+            (None, Some(options)) => format!("{} {}", engine, options),
+            (None, None) => format!("{} -interaction=nonstopmode %O %S", engine),
+        };
+        let Some(mut engine_step) = latexmk_command(&template, file, &base, &mut imported.notes) else {
+            continue;
+        };
+        if let Some(dir) = output_dir {
+            engine_step.args.insert(0, format!("-output-directory={}", dir));
+        }
+        if let Some(job) = variables.get("jobname") {
+            engine_step.args.insert(0, format!("-jobname={}", job));
+        }
+        let job = engine_step.job_path().unwrap_or(base.clone());
+
+        imported.steps.push(engine_step.clone());
+        if let Some(tool) = bibliography {
+            let bib_step = match variables.get(tool) {
+                Some(template) => latexmk_command(template, &job, &job, &mut imported.notes),
+                None => Some(step(tool, vec![job.clone()])),
+            };
+            imported.steps.extend(bib_step);
+            imported.steps.push(engine_step.clone());
+        }
+        match variables.get("pdf_mode").map(String::as_str) {
+            Some("2") => {
+                imported.steps.push(step("dvips", vec![format!("{}.dvi", job), "-o".to_string(), format!("{}.ps", job)]));
+                imported.steps.push(step("ps2pdf", vec![format!("{}.ps", job), format!("{}.pdf", job)]));
+            }
+            Some("3") => imported.steps.push(step("dvipdfmx", vec![format!("{}.dvi", job)])),
+            _ => {}
+        }
+    }
+
+    imported.max_passes = variables.get("max_repeat").and_then(|repeat| repeat.parse().ok());
+    for name in variables.keys() {
+        if matches!(name.as_str(), "makeindex" | "makeglossaries") {
+            imported.notes.push(format!("${} is not converted; tpmgr adds index runs for \\makeindex itself", name));
+        } else if !matches!(
+            name.as_str(),
+            "pdf_mode" | "dvi_mode" | "pdflatex" | "lualatex" | "xelatex" | "latex" | "out_dir" | "aux_dir"
+                | "bibtex_use" | "bibtex" | "biber" | "max_repeat" | "jobname"
+        ) {
+            imported.notes.push(format!("${} is not converted", name));
+        }
+    }
+    imported.notes.sort();
+    imported
+}
+
+/// One `% arara: rule: { key: value }` directive
+struct Directive {
+    rule: String,
+    parameters: Vec<(String, String)>,
+    condition: Option<String>,
+}
+
+impl Directive {
+    fn parameter(&self, key: &str) -> Option<&str> {
+        self.parameters.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.parameter(key).is_some_and(|value| matches!(value, "yes" | "true" | "on"))
+    }
+
+    /// A list parameter (`options: [ '-a', '-b' ]`) or a single value
+    fn list(&self, key: &str) -> Vec<String> {
+        let Some(value) = self.parameter(key) else {
+            return Vec::new();
+        };
+        let value = value.trim().trim_start_matches('[').trim_end_matches(']');
+        split_top_level(value).into_iter().map(|item| unquote(&item)).filter(|item| !item.is_empty()).collect()
+    }
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches(|c| c == '\'' || c == '"').to_string()
+}
+
+/// Split on commas outside brackets and quotes
+fn split_top_level(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(std::mem::take(&mut part));
+                continue;
+            }
+            _ => {}
+        }
+        part.push(c);
+    }
+    if !part.trim().is_empty() {
+        parts.push(part);
+    }
+    parts
+}
+
+fn parse_directive(text: &str) -> Option<Directive> {
+    let conditional = Regex::new(r"\s+(if|unless|while|until)\s+.*$").expect("valid regex");
+    let condition = conditional.find(text).map(|found| found.as_str().trim().to_string());
+    let text = conditional.replace(text, "");
+    let (rule, parameters) = match text.split_once(':') {
+        Some((rule, parameters)) => (rule.trim(), parameters.trim()),
+        None => (text.trim(), ""),
+    };
+    if rule.is_empty() || !rule.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let parameters = parameters.trim_start_matches('{').trim_end_matches('}');
+    let parameters = split_top_level(parameters)
+        .into_iter()
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once(':')?;
+            Some((key.trim().to_string(), unquote(value)))
+        })
+        .collect();
+    Some(Directive { rule: rule.to_string(), parameters, condition })
+}
+
+/// Convert the `% arara:` directives of `file`; `% arara: -->` lines continue
+/// the directive before them
+pub fn from_arara(content: &str, file: &str) -> ImportedChain {
+    let mut texts: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let Some(directive) = line.trim_start().strip_prefix('%').map(str::trim_start).and_then(|line| line.strip_prefix("arara:")) else {
+            continue;
+        };
+        let directive = directive.trim();
+        match (directive.strip_prefix("-->"), texts.last_mut()) {
+            (Some(continued), Some(last)) => {
+                last.push(' ');
+                last.push_str(continued.trim());
+            }
+            _ => texts.push(directive.to_string()),
+        }
+    }
+
+    let mut imported = ImportedChain::default();
+    for text in texts {
+        let Some(directive) = parse_directive(&text) else {
+            imported.notes.push(format!("Could not read the directive `{}`", text));
+            continue;
+        };
+        if let Some(condition) = &directive.condition {
+            imported.notes.push(format!(
+                "Dropped the condition `{}` of `{}`; tpmgr reruns the engine while the auxiliary files change",
+                condition, directive.rule
+            ));
+        }
+        let files = match directive.list("files") {
+            files if files.is_empty() => vec![file.to_string()],
+            files => files,
+        };
+        for file in files {
+            let job = Path::new(&file).with_extension("").to_string_lossy().to_string();
+            if let Some(converted) = arara_step(&directive, &file, &job) {
+                imported.steps.push(converted);
+            } else if directive.rule == "halt" {
+                return imported;
+            } else {
+                imported.notes.push(format!("The arara rule `{}` has no tpmgr equivalent and was skipped", directive.rule));
+            }
+        }
+    }
+    imported
+}
+
+fn arara_step(directive: &Directive, file: &str, job: &str) -> Option<CompileStep> {
+    let mut args = Vec::new();
+    let tool = directive.rule.as_str();
+    match tool {
+        "pdflatex" | "xelatex" | "lualatex" | "latex" | "platex" | "uplatex" => {
+            if directive.flag("shell") {
+                args.push("-shell-escape".to_string());
+            }
+            if directive.flag("synctex") {
+                args.push("-synctex=1".to_string());
+            }
+            if directive.flag("draft") {
+                args.push("-draftmode".to_string());
+            }
+            if let Some(mode) = directive.parameter("interaction") {
+                args.push(format!("-interaction={}", mode));
+            }
+            args.extend(directive.list("options"));
+            args.push(file.to_string());
+        }
+        "bibtex" | "biber" | "makeglossaries" => {
+            args.extend(directive.list("options"));
+            args.push(job.to_string());
+        }
+        "makeindex" => {
+            if let Some(style) = directive.parameter("style") {
+                args.extend(["-s".to_string(), style.to_string()]);
+            }
+            args.extend(directive.list("options"));
+            args.push(format!("{}.idx", job));
+        }
+        "nomencl" => return Some(crate::chain::nomencl_step(job)),
+        "dvips" => args.extend([format!("{}.dvi", job), "-o".to_string(), format!("{}.ps", job)]),
+        "ps2pdf" => args.extend([format!("{}.ps", job), format!("{}.pdf", job)]),
+        "dvipdfmx" | "dvipdfm" => args.push(format!("{}.dvi", job)),
+        _ => return None,
+    }
+    Some(step(tool, args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tex_parser::TeXParser;
+
+    #[test]
+    fn test_from_latexmkrc() {
+        let dependencies = TeXParser::new().unwrap().parse_content("\\usepackage{biblatex}\n\\addbibresource{refs.bib}").unwrap();
+        let rc = "# build settings\n$pdf_mode = 5;\n$xelatex = 'xelatex -shell-escape %O %S';\n$out_dir = \"build\";\n@default_files = ('thesis.tex');\n$max_repeat = 3;\n$clean_ext = 'nav snm';\n";
+        let imported = from_latexmkrc(rc, "main.tex", &dependencies);
+        let chain = CompileCommand { steps: imported.steps.clone(), ..CompileCommand::new() };
+        assert_eq!(
+            chain.to_string(),
+            "xelatex -output-directory=build -shell-escape thesis.tex | biber build/thesis | xelatex -output-directory=build -shell-escape thesis.tex"
+        );
+        assert_eq!(imported.max_passes, Some(3));
+        assert_eq!(imported.notes, vec!["$clean_ext is not converted"]);
+
+        // Defaults: pdflatex on the given file, no bibliography
+        let imported = from_latexmkrc("set_tex_cmds('-synctex=1 %O %S');\n", "main.tex", &[]);
+        assert_eq!(imported.steps, vec![step("pdflatex", vec!["-synctex=1".to_string(), "main.tex".to_string()])]);
+    }
+
+    #[test]
+    fn test_from_arara() {
+        let content = "% arara: pdflatex: { shell: yes, options: ['-file-line-error'] }\n% arara: bibtex\n% arara: makeindex: { style: mystyle.ist }\n% arara: pdflatex if changed('toc')\n% arara: --> && found('log', 'Rerun')\n% arara: clean: { extensions: [aux] }\n% arara: halt\n% arara: pdflatex\n\\documentclass{article}";
+        let imported = from_arara(content, "chapters/paper.tex");
+        let chain = CompileCommand { steps: imported.steps, ..CompileCommand::new() };
+        assert_eq!(
+            chain.to_string(),
+            "pdflatex -shell-escape -file-line-error chapters/paper.tex | bibtex chapters/paper | makeindex -s mystyle.ist chapters/paper.idx | pdflatex chapters/paper.tex"
+        );
+        assert_eq!(imported.notes.len(), 2);
+        assert!(imported.notes[0].contains("if changed('toc') && found('log', 'Rerun')"));
+        assert!(imported.notes[1].contains("`clean`"));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::tex_parser::TeXDependency;
+
+/// A package that has to be loaded before another one
+#[derive(Debug)]
+pub struct LoadOrderRule {
+    pub first: &'static str,
+    pub then: &'static str,
+    /// Engines the rule applies to (all when empty); rules with engines also
+    /// apply when the engine is unknown
+    pub engines: &'static [&'static str],
+    pub reason: &'static str,
+}
+
+const LOAD_ORDER_RULES: &[LoadOrderRule] = &[
+    LoadOrderRule { first: "hyperref", then: "cleveref", engines: &[], reason: "cleveref redefines the references hyperref sets up" },
+    LoadOrderRule { first: "hyperref", then: "bookmark", engines: &[], reason: "bookmark replaces hyperref's bookmark code" },
+    LoadOrderRule { first: "hyperref", then: "hypcap", engines: &[], reason: "hypcap moves the anchors hyperref places" },
+    LoadOrderRule { first: "hyperref", then: "glossaries", engines: &[], reason: "glossaries only adds links when hyperref is loaded" },
+    LoadOrderRule { first: "hyperref", then: "glossaries-extra", engines: &[], reason: "glossaries only adds links when hyperref is loaded" },
+    LoadOrderRule { first: "amsmath", then: "cleveref", engines: &[], reason: "cleveref patches amsmath's equation environments" },
+    LoadOrderRule { first: "amsmath", then: "amsthm", engines: &[], reason: "amsthm needs amsmath's definitions" },
+    LoadOrderRule { first: "amsmath", then: "unicode-math", engines: &[], reason: "unicode-math redefines amsmath's symbols" },
+    LoadOrderRule { first: "float", then: "hyperref", engines: &[], reason: "hyperref patches the floats float defines" },
+    LoadOrderRule { first: "algorithm", then: "hyperref", engines: &[], reason: "hyperref patches the algorithm float" },
+    LoadOrderRule { first: "footmisc", then: "hyperref", engines: &[], reason: "hyperref patches footmisc's footnotes" },
+    LoadOrderRule {
+        first: "inputenc",
+        then: "fontenc",
+        engines: &["pdflatex", "latex"],
+        reason: "the input encoding should be set before the font encoding",
+    },
+];
+
+/// hyperref redefines commands of many packages and should come after them
+const LOAD_LAST: &str = "hyperref";
+
+/// A package loaded too late, with the load it should move above
+#[derive(Debug, Clone)]
+pub struct LoadOrderWarning {
+    /// The load to move up
+    pub package: TeXDependency,
+    /// The load it should come before
+    pub before: TeXDependency,
+    pub reason: String,
+}
+
+/// Check one document's package loads, in load order, against the rules
+pub fn check(loads: &[&TeXDependency], engine: Option<&str>) -> Vec<LoadOrderWarning> {
+    let position = |name: &str| loads.iter().position(|load| load.package_name == name);
+    let mut warnings = Vec::new();
+
+    for rule in LOAD_ORDER_RULES {
+        if !rule.engines.is_empty() && engine.is_some_and(|engine| !rule.engines.contains(&engine)) {
+            continue;
+        }
+        if let (Some(first), Some(then)) = (position(rule.first), position(rule.then)) {
+            if first > then {
+                warnings.push(LoadOrderWarning {
+                    package: loads[first].clone(),
+                    before: loads[then].clone(),
+                    reason: rule.reason.to_string(),
+                });
+            }
+        }
+    }
+
+    // Apart from the packages that must follow it, hyperref goes last
+    if let Some(hyperref) = position(LOAD_LAST) {
+        let later: Vec<&TeXDependency> = loads[hyperref + 1..]
+            .iter()
+            .copied()
+            .filter(|load| load.package_name != LOAD_LAST)
+            .filter(|load| !LOAD_ORDER_RULES.iter().any(|rule| rule.first == LOAD_LAST && rule.then == load.package_name))
+            .collect();
+        if let Some(last) = later.last() {
+            let mut names: Vec<&str> = later.iter().map(|load| load.package_name.as_str()).collect();
+            names.dedup();
+            warnings.push(LoadOrderWarning {
+                package: (*last).clone(),
+                before: loads[hyperref].clone(),
+                reason: format!("hyperref should be loaded after {}; only packages such as cleveref and bookmark belong after it", names.join(", ")),
+            });
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tex_parser::TeXParser;
+
+    #[test]
+    fn test_load_order() {
+        let parser = TeXParser::new().unwrap();
+        let deps = parser
+            .parse_content("\\documentclass{article}\n\\usepackage[T1]{fontenc}\n\\usepackage[utf8]{inputenc}\n\\usepackage{cleveref}\n\\usepackage{hyperref}\n\\usepackage{siunitx}\n\\usepackage{bookmark}\n")
+            .unwrap();
+        let loads: Vec<&TeXDependency> = TeXParser::load_sequences(&deps).remove(0);
+
+        let warnings = check(&loads, Some("pdflatex"));
+        let moves: Vec<(&str, &str)> = warnings.iter().map(|w| (w.package.package_name.as_str(), w.before.package_name.as_str())).collect();
+        assert_eq!(moves, vec![("hyperref", "cleveref"), ("inputenc", "fontenc"), ("siunitx", "hyperref")]);
+        assert!(warnings[2].reason.starts_with("hyperref should be loaded after siunitx"));
+
+        let warnings = check(&loads, Some("xelatex"));
+        assert!(warnings.iter().all(|w| w.package.package_name != "inputenc"));
+    }
+}
@@ -0,0 +1,118 @@
+use anyhow::Result;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long to wait for another tpmgr process before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_secs(600);
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An advisory lock on a lock file, released when dropped
+#[derive(Debug)]
+pub struct FileLock {
+    _file: File,
+}
+
+/// Take an exclusive lock on `path` (created if missing), waiting for other
+/// tpmgr processes that hold it
+pub fn exclusive(path: &Path) -> Result<FileLock> {
+    acquire(path, false)
+}
+
+/// Like `exclusive`, for async code: waits without blocking the runtime's
+/// worker thread
+pub async fn exclusive_async(path: &Path) -> Result<FileLock> {
+    let started = Instant::now();
+    let mut announced = false;
+    loop {
+        if let Some(lock) = try_acquire(path, false)? {
+            return Ok(lock);
+        }
+        keep_waiting(path, started, &mut announced)?;
+        tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+    }
+}
+
+/// Take a shared lock on `path`, for reading while no process writes
+pub fn shared(path: &Path) -> Result<FileLock> {
+    acquire(path, true)
+}
+
+fn acquire(path: &Path, shared: bool) -> Result<FileLock> {
+    let started = Instant::now();
+    let mut announced = false;
+    loop {
+        if let Some(lock) = try_acquire(path, shared)? {
+            return Ok(lock);
+        }
+        keep_waiting(path, started, &mut announced)?;
+        std::thread::sleep(LOCK_POLL_INTERVAL);
+    }
+}
+
+/// Tell the user once that another process holds the lock; fails after LOCK_TIMEOUT
+fn keep_waiting(path: &Path, started: Instant, announced: &mut bool) -> Result<()> {
+    if !*announced {
+        println!("⏳ Another tpmgr process is running; waiting for it to finish...");
+        *announced = true;
+    }
+    if started.elapsed() >= LOCK_TIMEOUT {
+        anyhow::bail!(
+            "Another tpmgr process is still running (it holds {}); try again once it has finished",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// The lock if it is free right now, None if another process holds it
+fn try_acquire(path: &Path, shared: bool) -> Result<Option<FileLock>> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+    let locked = if shared { file.try_lock_shared() } else { file.try_lock() };
+    match locked {
+        Ok(()) => Ok(Some(FileLock { _file: file })),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(anyhow::anyhow!("Failed to lock {}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("registry.lock");
+
+        let first = shared(&path).unwrap();
+        assert!(try_acquire(&path, true).unwrap().is_some());
+        assert!(try_acquire(&path, false).unwrap().is_none());
+        drop(first);
+
+        let writer = exclusive(&path).unwrap();
+        assert!(try_acquire(&path, true).unwrap().is_none());
+        drop(writer);
+        assert!(try_acquire(&path, false).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_exclusive_async() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.lock");
+
+        // The holder releases the lock while the waiter polls on the same thread
+        let holder = exclusive(&path).unwrap();
+        let waiter = exclusive_async(&path);
+        let release = async move {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            drop(holder);
+        };
+        let (lock, ()) = tokio::join!(waiter, release);
+        assert!(lock.is_ok());
+        assert!(try_acquire(&path, true).unwrap().is_none());
+    }
+}
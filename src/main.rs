@@ -1,14 +1,50 @@
 use clap::{Parser, Subcommand};
 use anyhow::Result;
 
+// First, so that its println! is in scope in the other modules
+#[macro_use]
+mod ui;
 mod commands;
 mod config;
+mod config_check;
 mod package;
 mod resolver;
 mod error;
 mod mirror;
 mod texlive;
 mod tex_parser;
+mod tools;
+mod cjk;
+mod command_db;
+mod ignore;
+mod references;
+mod download;
+mod repository;
+mod publish;
+mod cache;
+mod environment;
+mod shadowing;
+mod query;
+mod lock;
+mod watch;
+mod diagnostics;
+mod server;
+mod bench;
+mod build_cache;
+mod remote;
+mod postprocess;
+mod validate;
+mod assets;
+mod bib;
+mod chain;
+mod synctex;
+mod release;
+mod manifest;
+mod import;
+mod stats;
+mod drift;
+mod load_order;
+mod mirror_health;
 
 use commands::*;
 
@@ -17,6 +53,25 @@ use commands::*;
 #[command(about = "A lightweight LaTeX package manager", long_about = None)]
 #[command(version)]
 struct Cli {
+    /// Project configuration file to use instead of ./tpmgr.toml
+    #[arg(long, global = true, value_name = "PATH", env = "TPMGR_CONFIG")]
+    config: Option<std::path::PathBuf>,
+    /// Global configuration profile to use (a [profiles.<name>] block)
+    #[arg(long, global = true, value_name = "NAME", env = "TPMGR_PROFILE")]
+    profile: Option<String>,
+    /// Never prompt, print plain output with JSON summaries and fail on errors
+    /// (the default when a CI environment is detected)
+    #[arg(long, global = true)]
+    non_interactive: bool,
+    /// Print ASCII markers instead of emoji (also `ui.emoji = false` in the global config)
+    #[arg(long, global = true)]
+    plain: bool,
+    /// Do not show progress spinners
+    #[arg(long, global = true)]
+    quiet: bool,
+    /// Do not detect TeX Live or pick a mirror when the global config lacks them
+    #[arg(long, global = true)]
+    no_auto_config: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -41,6 +96,12 @@ enum Commands {
         /// Use compilation errors to detect missing packages
         #[arg(short, long)]
         compile: bool,
+        /// Show what would be installed without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Install without asking, even when many packages would be installed
+        #[arg(short, long)]
+        yes: bool,
     },
     /// Remove packages
     Remove {
@@ -49,33 +110,78 @@ enum Commands {
         /// Remove packages globally
         #[arg(short, long)]
         global: bool,
+        /// Remove installed packages that no document in the project uses
+        #[arg(long, conflicts_with_all = ["packages", "global"])]
+        unused: bool,
+        /// Show what would be removed without changing anything
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Update packages
     Update {
         /// Package names to update (all if not specified)
         packages: Vec<String>,
+        /// Show what would be updated without changing anything
+        #[arg(long)]
+        dry_run: bool,
     },
     /// List installed packages
     List {
         /// Show global packages
         #[arg(short, long)]
         global: bool,
+        /// Show packages installed in more than one of project, global and TeX Live
+        #[arg(long, conflicts_with = "global")]
+        shadowed: bool,
+        /// Only show packages whose name matches this glob (e.g. "pgf*")
+        pattern: Option<String>,
+        /// Show which installed packages each package loads
+        #[arg(long, conflicts_with_all = ["shadowed", "outdated"])]
+        tree: bool,
+        /// Only show packages with a newer version in the repositories
+        #[arg(long, conflicts_with = "shadowed")]
+        outdated: bool,
+        /// Sort by name or by installed size (largest first)
+        #[arg(long, default_value = "name", value_parser = ["name", "size"])]
+        sort: String,
     },
     /// Search for packages
     Search {
-        /// Search query
+        /// Search query; prefix terms with name: or desc: to search one field
         query: String,
+        /// Treat the query terms as regular expressions
+        #[arg(long)]
+        regex: bool,
+        /// Show at most this many results
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Show all results
+        #[arg(long, conflicts_with = "limit")]
+        all: bool,
+        /// Only show packages that are already installed
+        #[arg(long)]
+        installed: bool,
     },
     /// Show package information
     Info {
         /// Package name
         package: String,
     },
+    /// Package cache management
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
     /// Mirror management
     Mirror {
         #[command(subcommand)]
         action: MirrorAction,
     },
+    /// TeX Live installation management
+    Texlive {
+        #[command(subcommand)]
+        action: TexliveAction,
+    },
     /// Analyze TeX file dependencies
     Analyze {
         /// Path to TeX file or project directory
@@ -87,6 +193,36 @@ enum Commands {
         /// Use compilation errors to detect missing packages
         #[arg(short, long)]
         compile: bool,
+        /// Only consider chapters selected by \includeonly (project directories only)
+        #[arg(long)]
+        respect_includeonly: bool,
+        /// Suggest packages for commands and environments used without loading them
+        #[arg(long)]
+        suggest: bool,
+        /// Read the file contents from stdin and print JSON diagnostics (for editors)
+        #[arg(long, requires = "filename", conflicts_with_all = ["compile", "suggest", "verbose"])]
+        stdin: bool,
+        /// Path of the file whose contents are read from stdin
+        #[arg(long, requires = "stdin")]
+        filename: Option<String>,
+    },
+    /// Watch the project and install newly used packages as files are saved
+    Daemon {
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+        /// How often to check for changes, in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        interval: u64,
+        /// Also post a desktop notification for each installed package
+        #[arg(long)]
+        notify: bool,
+    },
+    /// Answer JSON-RPC requests from editor plugins (analyze, install, status)
+    Serve {
+        /// Listen on a Unix socket instead of stdin/stdout
+        #[arg(long)]
+        socket: Option<String>,
     },
     /// Configuration management
     Config {
@@ -104,9 +240,125 @@ enum Commands {
         /// Show verbose compilation output
         #[arg(short, long)]
         verbose: bool,
+        /// Fail the build when citations or references are undefined
+        #[arg(long)]
+        strict_refs: bool,
+        /// Install packages a failed step reports missing and run the step again
+        #[arg(long)]
+        auto_install: bool,
+        /// Run the compile chain even when the build cache has a build of the same inputs
+        #[arg(long)]
+        force: bool,
+        /// Compile on a build server (http(s)://...) or SSH host (ssh://[user@]host[:port][/dir])
+        #[arg(long, value_name = "URL", conflicts_with_all = ["auto_install", "force"])]
+        remote: Option<String>,
+        /// Engine interaction mode, overriding the config and the steps' args
+        #[arg(long, value_name = "MODE", value_parser = crate::config::InteractionMode::NAMES, conflicts_with = "remote")]
+        interaction: Option<String>,
+        /// Stop the engine at the first error
+        #[arg(long, conflicts_with = "remote")]
+        halt_on_error: bool,
+        /// Quick build: draft class option, no bibliography or index runs
+        #[arg(long, conflicts_with = "remote")]
+        draft: bool,
+        /// Show the stages of the compile chain and what each step waits for, without compiling
+        #[arg(long)]
+        graph: bool,
+    },
+    /// Benchmark builds
+    Bench {
+        #[command(subcommand)]
+        action: BenchAction,
+    },
+    /// Build the project and store its PDFs as a named release with a checksum
+    Release {
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+        /// Replace a release of the same name
+        #[arg(long)]
+        force: bool,
+    },
+    /// Manage the project's bibliography
+    Bib {
+        #[command(subcommand)]
+        action: BibAction,
+    },
+    /// Jump between the source and the PDF with SyncTeX
+    Synctex {
+        #[command(subcommand)]
+        action: SynctexAction,
+    },
+    /// Convert a latexmk or arara setup into the project's compile chain
+    Import {
+        #[command(subcommand)]
+        action: ImportAction,
+    },
+    /// Show counts of documents, includes, figures, citations and packages, and the last build
+    Stats {
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+    },
+    /// Compare tpmgr.toml's [dependencies], the installed packages and the packages the documents load
+    Status {
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+    },
+    /// Remove intermediate build files
+    Clean {
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+        /// Show the files that would be removed without deleting them
+        #[arg(long)]
+        dry_run: bool,
+        /// Also remove the output PDF and the .tpmgr package cache
+        #[arg(long)]
+        deep: bool,
+        /// Clean files matching this pattern instead of the configured ones (repeatable)
+        #[arg(long = "pattern", value_name = "GLOB")]
+        patterns: Vec<String>,
+    },
+    /// Print the environment variables tpmgr sets for TeX tools
+    Env,
+    /// Run a command with the project's TeX environment (e.g. tpmgr exec -- texcount main.tex)
+    Exec {
+        /// Command and its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Start an interactive shell with the project's TeX environment
+    Shell,
+    /// Package a local .sty/.cls project into a self-hosted repository
+    Publish {
+        /// Repository directory (created if missing)
+        repository: String,
+        /// Directory containing the package files
+        #[arg(short, long, default_value = ".")]
+        path: String,
+        /// Package name (defaults to the .sty/.cls file name)
+        #[arg(long)]
+        name: Option<String>,
+        /// Package version (defaults to the version in \ProvidesPackage)
+        #[arg(long)]
+        version: Option<String>,
     },
 }
 
+impl Commands {
+    /// Commands that download packages or copy the global TeX Live and mirror
+    /// settings, and so run the first-run auto-configuration
+    fn needs_auto_config(&self) -> bool {
+        matches!(
+            self,
+            Commands::Init { .. } | Commands::Install { .. } | Commands::Update { .. } | Commands::Search { .. } | Commands::Info { .. }
+                | Commands::Daemon { .. }
+        )
+    }
+}
+
 #[derive(Subcommand)]
 enum ConfigAction {
     /// Show current configuration
@@ -133,12 +385,59 @@ enum ConfigAction {
         #[arg(long, short)]
         global: bool,
     },
+    /// Remove a configuration value, restoring its default
+    Unset {
+        /// Configuration key
+        key: String,
+        /// Unset in the global configuration
+        #[arg(long, short)]
+        global: bool,
+    },
+    /// Append a value to a list (clean_patterns, clean.keep, or repositories as NAME=URL)
+    Add {
+        /// List key
+        key: String,
+        /// Value to append
+        value: String,
+        /// Change the global configuration
+        #[arg(long, short)]
+        global: bool,
+    },
+    /// Remove a value from a list (repositories are removed by name)
+    Remove {
+        /// List key
+        key: String,
+        /// Value to remove
+        value: String,
+        /// Change the global configuration
+        #[arg(long, short)]
+        global: bool,
+    },
     /// List all configuration keys
     List {
         /// Show global configuration keys only
         #[arg(long, short)]
         global: bool,
     },
+    /// Check the configuration for mistakes
+    Check {
+        /// Check the global configuration
+        #[arg(long, short)]
+        global: bool,
+    },
+    /// Open the configuration in $VISUAL/$EDITOR and validate it after saving
+    Edit {
+        /// Edit the global configuration
+        #[arg(long, short)]
+        global: bool,
+    },
+    /// Print the global settings as TOML (without texlive_path)
+    Export,
+    /// Import global settings exported on another machine ("-" reads stdin)
+    Import {
+        /// Settings file written by 'tpmgr config export'
+        file: String,
+    },
     /// Reset configuration to defaults
     Reset {
         /// Reset global configuration only
@@ -147,10 +446,156 @@ enum ConfigAction {
     },
 }
 
+#[derive(Subcommand)]
+enum BenchAction {
+    /// Run the compile chain several times and report per-step timings
+    Compile {
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+        /// Number of measured runs
+        #[arg(short = 'n', long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+        runs: u32,
+        /// Remove intermediate files before every run instead of warming up once
+        #[arg(long)]
+        cold: bool,
+        /// Save the results as the baseline later runs are compared with
+        #[arg(long)]
+        save_baseline: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum BibAction {
+    /// Fetch BibTeX entries for DOIs or arXiv IDs and append them to the .bib file
+    Add {
+        /// DOIs (10.xxxx/..., doi.org links) or arXiv IDs (2101.00001, arXiv:...)
+        #[arg(required = true)]
+        ids: Vec<String>,
+        /// .bib file to add to (default: the one the documents use)
+        #[arg(long, value_name = "FILE")]
+        file: Option<String>,
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+    },
+    /// Normalize, deduplicate and sort .bib files and report uncited entries
+    Tidy {
+        /// .bib file to tidy (default: every one the documents use)
+        #[arg(long, value_name = "FILE")]
+        file: Option<String>,
+        /// Show what would change without rewriting the files
+        #[arg(long)]
+        dry_run: bool,
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SynctexAction {
+    /// Show where a source line is in the PDF, or open it there in a viewer
+    View {
+        /// Line in the source file
+        line: u32,
+        /// Source file (default: the main document)
+        #[arg(long, value_name = "FILE")]
+        file: Option<String>,
+        /// Column in the line (0 matches the whole line)
+        #[arg(long, default_value_t = 0)]
+        column: u32,
+        /// Viewer to send the location to
+        #[arg(long, value_parser = crate::synctex::VIEWERS.to_vec())]
+        viewer: Option<String>,
+        /// Path to project directory
+        #[arg(short, long, default_value = ".")]
+        path: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImportAction {
+    /// Convert a latexmkrc (default: latexmkrc or .latexmkrc in the project root)
+    Latexmkrc {
+        /// The latexmkrc to convert
+        file: Option<String>,
+        /// Show the converted chain without saving it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Convert the `% arara:` directives of a document
+    Arara {
+        /// The document with the directives
+        file: String,
+        /// Show the converted chain without saving it
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Show the location and size of the package cache
+    Info {
+        /// Use the global package cache
+        #[arg(short, long)]
+        global: bool,
+    },
+    /// Delete all downloaded archives
+    Clean {
+        /// Use the global package cache
+        #[arg(short, long)]
+        global: bool,
+    },
+    /// Remove old archives according to the [cache] settings
+    Gc {
+        /// Use the global package cache
+        #[arg(short, long)]
+        global: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TexliveAction {
+    /// Switch to a newer TeX Live release installed next to the configured one
+    Migrate {
+        /// TeX Live root to switch to (defaults to the newest release found)
+        #[arg(long, value_name = "PATH")]
+        to: Option<std::path::PathBuf>,
+        /// Show what would change without saving anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
 #[derive(Subcommand)]
 enum MirrorAction {
-    /// List available mirrors
-    List,
+    /// List available mirrors with their last measured latency
+    List {
+        /// Only list mirrors on this continent
+        #[arg(long)]
+        continent: Option<String>,
+        /// Only list mirrors in this country
+        #[arg(long)]
+        country: Option<String>,
+        /// Only list mirrors reachable over this protocol
+        #[arg(long, value_parser = ["https", "http", "ftp", "rsync"])]
+        protocol: Option<String>,
+        /// Print the mirrors as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the mirror downloads go through and where it is configured
+    Current,
+    /// Check that the mirror serves an up-to-date package index
+    Check {
+        /// Switch to a fresh mirror without asking when the check fails
+        #[arg(long)]
+        failover: bool,
+    },
+    /// Download the CTAN mirror list again
+    Update,
     /// Use a specific mirror or auto-select the best one
     Use {
         /// Mirror name (optional if using --auto)
@@ -159,34 +604,111 @@ enum MirrorAction {
         #[arg(short, long)]
         auto: bool,
     },
+    /// Benchmark mirrors and save the results for auto-selection
+    Test {
+        /// Only test mirrors whose name, country or continent contains this text
+        #[arg(long)]
+        filter: Option<String>,
+        /// Sort the results by latency, throughput or name
+        #[arg(long, default_value = "latency", value_parser = ["latency", "throughput", "name"])]
+        sort: String,
+    },
+    /// Add a custom mirror (e.g. an internal campus mirror)
+    Add {
+        /// Mirror name
+        name: String,
+        /// CTAN root URL of the mirror, or its TeX Live repository (ending in tlnet)
+        url: String,
+        /// URL template of the package index, e.g. "{url}/texlive/tlnet/tlpkg/texlive.tlpdb"
+        #[arg(long, value_name = "TEMPLATE")]
+        index_template: Option<String>,
+        /// URL template of the package archives, with {url} and {package}
+        #[arg(long, value_name = "TEMPLATE")]
+        archive_template: Option<String>,
+    },
+    /// Remove a custom mirror
+    Remove {
+        /// Mirror name
+        name: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
+    ui::set_non_interactive(cli.non_interactive || ui::detect_ci());
+    ui::set_quiet(cli.quiet);
+    if let Some(path) = &cli.config {
+        config::set_project_config_path(path)?;
+    }
+    if let Some(profile) = &cli.profile {
+        config::set_active_profile(profile)?;
+        // Fail early on a misspelt profile name
+        config::GlobalConfig::load()?;
+    }
+    ui::set_plain(cli.plain || config::GlobalConfig::load().is_ok_and(|config| !config.ui.emoji));
 
-    // Initialize global configuration on first run
-    if let Err(e) = commands::ensure_global_config_initialized().await {
-        eprintln!("Warning: Failed to initialize global configuration: {}", e);
+    // Initialize global configuration on first run, when the command needs it
+    let needs_auto_config = cli.command.as_ref().map(Commands::needs_auto_config).unwrap_or(false);
+    if needs_auto_config && !cli.no_auto_config {
+        if let Err(e) = commands::ensure_global_config_initialized().await {
+            eprintln!("Warning: Failed to initialize global configuration: {}", e);
+        }
+    }
+    if needs_auto_config {
+        commands::report_newer_texlive();
     }
 
     match &cli.command {
         Some(Commands::Init { name }) => init_command(name.clone()).await,
-        Some(Commands::Install { packages, global, path, compile }) => {
-            install_command(packages, *global, path, *compile).await
+        Some(Commands::Install { packages, global, path, compile, dry_run, yes }) => {
+            install_command(packages, *global, path, *compile, *dry_run, *yes).await
+        },
+        Some(Commands::Remove { packages, global, unused, dry_run }) => {
+            remove_command(packages, *global, *unused, *dry_run).await
+        },
+        Some(Commands::Update { packages, dry_run }) => update_command(packages, *dry_run).await,
+        Some(Commands::List { global, shadowed, pattern, tree, outdated, sort }) => {
+            list_command(*global, *shadowed, pattern.as_deref(), *tree, *outdated, sort).await
+        },
+        Some(Commands::Search { query, regex, limit, all, installed }) => {
+            search_command(query, *regex, (!*all).then_some(*limit), *installed).await
         },
-        Some(Commands::Remove { packages, global }) => remove_command(packages, *global).await,
-        Some(Commands::Update { packages }) => update_command(packages).await,
-        Some(Commands::List { global }) => list_command(*global).await,
-        Some(Commands::Search { query }) => search_command(query).await,
         Some(Commands::Info { package }) => info_command(package).await,
+        Some(Commands::Cache { action }) => cache_command(action).await,
         Some(Commands::Mirror { action }) => mirror_command(action).await,
-        Some(Commands::Analyze { path, verbose, compile }) => {
-            analyze_command(path, *verbose, *compile).await
+        Some(Commands::Texlive { action }) => texlive_command(action).await,
+        Some(Commands::Analyze { filename: Some(filename), stdin: true, .. }) => analyze_stdin_command(filename).await,
+        Some(Commands::Analyze { path, verbose, compile, respect_includeonly, suggest, .. }) => {
+            analyze_command(path, *verbose, *compile, *respect_includeonly, *suggest).await
         },
+        Some(Commands::Daemon { path, interval, notify }) => daemon_command(path, *interval, *notify).await,
+        Some(Commands::Serve { socket }) => serve_command(socket.as_deref()).await,
         Some(Commands::Config { action }) => config_command(action).await,
-        Some(Commands::Compile { path, clean, verbose }) => {
-            compile_command(path, *clean, *verbose).await
+        Some(Commands::Compile { path, clean, verbose, strict_refs, auto_install, force, remote, interaction, halt_on_error, draft, graph }) => {
+            let options = chain::ChainOptions {
+                interaction: interaction.as_deref().and_then(config::InteractionMode::from_name),
+                halt_on_error: *halt_on_error,
+                draft: *draft,
+                graph: *graph,
+            };
+            compile_command(path, *clean, *verbose, *strict_refs, *auto_install, *force, remote.as_deref(), options).await.map(|_| ())
+        },
+        Some(Commands::Bench { action }) => bench_command(action).await,
+        Some(Commands::Release { path, force }) => release_command(path, *force).await,
+        Some(Commands::Bib { action }) => bib_command(action).await,
+        Some(Commands::Synctex { action }) => synctex_command(action),
+        Some(Commands::Import { action }) => import_command(action),
+        Some(Commands::Stats { path }) => stats_command(path).await,
+        Some(Commands::Status { path }) => status_command(path).await,
+        Some(Commands::Clean { path, dry_run, deep, patterns }) => {
+            clean_command(path, *dry_run, *deep, patterns).await
+        },
+        Some(Commands::Env) => env_command().await,
+        Some(Commands::Exec { command }) => exec_command(command).await,
+        Some(Commands::Shell) => shell_command().await,
+        Some(Commands::Publish { repository, path, name, version }) => {
+            publish_command(repository, path, name.as_deref(), version.as_deref()).await
         },
         None => {
             println!("tpmgr - LaTeX Package Manager");
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+
+/// What a successful compile produced and with which tools, for CI to keep
+/// with the build artifacts
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BuildManifest {
+    pub project: String,
+    pub version: String,
+    /// Unix time the build finished
+    pub built_at: u64,
+    pub duration_seconds: f64,
+    /// Whether the outputs were restored from the build cache
+    pub cached: bool,
+    pub chain: Vec<String>,
+    pub outputs: Vec<OutputFile>,
+    /// First line of `<tool> --version` for each tool of the chain
+    pub tools: BTreeMap<String, String>,
+    /// SHA-256 of the package registry, which records the installed versions
+    pub registry_sha256: Option<String>,
+    /// `git describe --tags --always --dirty`, when the project is in git
+    pub git: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct OutputFile {
+    /// Relative to the project root
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl BuildManifest {
+    /// Describe the build of `outputs` (files that do not exist are left out)
+    pub fn collect(
+        config: &Config,
+        project_root: &Path,
+        commands: &[Vec<String>],
+        outputs: &[PathBuf],
+        duration_seconds: f64,
+        cached: bool,
+        environment: &[(String, String)],
+    ) -> Result<Self> {
+        let mut files = Vec::new();
+        for output in outputs.iter().filter(|output| output.is_file()) {
+            let content = std::fs::read(output)?;
+            files.push(OutputFile {
+                path: output.strip_prefix(project_root).unwrap_or(output).to_string_lossy().replace('\\', "/"),
+                sha256: format!("{:x}", Sha256::digest(&content)),
+                size: content.len() as u64,
+            });
+        }
+
+        let mut tools = BTreeMap::new();
+        for tool in commands.iter().filter_map(|command| command.first()) {
+            if !tools.contains_key(tool) {
+                if let Some(version) = tool_version(tool, project_root, environment) {
+                    tools.insert(tool.clone(), version);
+                }
+            }
+        }
+
+        let registry = project_root.join(&config.project.package_dir).join("registry.json");
+        Ok(Self {
+            project: config.project.name.clone(),
+            version: config.project.version.clone(),
+            built_at: crate::mirror::unix_timestamp(),
+            duration_seconds,
+            cached,
+            chain: commands.iter().map(|command| command.join(" ")).collect(),
+            outputs: files,
+            tools,
+            registry_sha256: std::fs::read(registry).ok().map(|content| format!("{:x}", Sha256::digest(content))),
+            git: crate::release::git_describe(project_root),
+        })
+    }
+
+    pub fn path(project_root: &Path) -> PathBuf {
+        project_root.join(".tpmgr").join("build-manifest.json")
+    }
+
+    /// The manifest of the project's last build, if there is a readable one
+    pub fn load(project_root: &Path) -> Option<Self> {
+        serde_json::from_str(&std::fs::read_to_string(Self::path(project_root)).ok()?).ok()
+    }
+
+    pub fn save(&self, project_root: &Path) -> Result<PathBuf> {
+        let path = Self::path(project_root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+/// The first line `tool --version` prints, if it runs
+fn tool_version(tool: &str, project_root: &Path, environment: &[(String, String)]) -> Option<String> {
+    let output = Command::new(tool)
+        .arg("--version")
+        .current_dir(project_root)
+        .envs(environment.iter().cloned())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().map(str::trim).find(|line| !line.is_empty())?;
+    output.status.success().then(|| line.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("packages")).unwrap();
+        std::fs::write(dir.path().join("main.pdf"), "%PDF-1.5").unwrap();
+        std::fs::write(dir.path().join("packages/registry.json"), "{}").unwrap();
+        let mut config = Config::new();
+        config.project.name = "paper".to_string();
+        config.project.package_dir = "packages".to_string();
+
+        let commands = vec![vec!["true".to_string(), "main.tex".to_string()]];
+        let outputs = vec![dir.path().join("main.pdf"), dir.path().join("build/main.pdf")];
+        let manifest = BuildManifest::collect(&config, dir.path(), &commands, &outputs, 1.5, false, &[]).unwrap();
+        assert_eq!(
+            manifest.outputs,
+            vec![OutputFile {
+                path: "main.pdf".to_string(),
+                sha256: format!("{:x}", Sha256::digest(b"%PDF-1.5")),
+                size: 8,
+            }]
+        );
+        assert_eq!(manifest.chain, vec!["true main.tex"]);
+        assert_eq!(manifest.registry_sha256, Some(format!("{:x}", Sha256::digest(b"{}"))));
+
+        let path = manifest.save(dir.path()).unwrap();
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(saved["project"], "paper");
+        assert_eq!(saved["outputs"][0]["path"], "main.pdf");
+    }
+}
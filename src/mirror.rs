@@ -1,6 +1,92 @@
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
-use reqwest;
+use crate::config::{CustomMirror, MirrorSelection};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// TeX Live repository used when no mirror has been selected
+pub const DEFAULT_TLNET_URL: &str = "https://mirror.ctan.org/systems/texlive/tlnet/";
+
+/// Official CTAN mirror list, generated from mirmon and shipped with the TeX Live installer
+const CTAN_MIRRORS_URL: &str = "https://mirror.ctan.org/systems/texlive/tlnet/tlpkg/installer/ctan-mirrors.pl";
+
+/// How long a downloaded mirror list is used before fetching it again
+const MIRROR_LIST_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// How long `mirror test` results are trusted by auto-selection
+const BENCHMARK_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Number of mirrors benchmarked at the same time
+const BENCHMARK_CONCURRENCY: usize = 16;
+
+/// Bytes of the package index downloaded to measure throughput
+const THROUGHPUT_SAMPLE_BYTES: usize = 256 * 1024;
+
+/// Where the package index and archives of a TeX Live repository lie below it
+const INDEX_PATH: &str = "tlpkg/texlive.tlpdb";
+const ARCHIVE_PATH: &str = "archive/{package}.tar.xz";
+
+/// Where a mirror keeps the TeX Live package index and archives, as URL
+/// templates with `{url}` (the mirror's URL) and, for archives, `{package}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MirrorTemplates {
+    pub index: String,
+    pub archive: String,
+}
+
+impl MirrorTemplates {
+    /// Templates of a CTAN mirror, with TeX Live under systems/texlive/tlnet
+    pub fn ctan() -> Self {
+        Self {
+            index: format!("{{url}}/systems/texlive/tlnet/{}", INDEX_PATH),
+            archive: format!("{{url}}/systems/texlive/tlnet/{}", ARCHIVE_PATH),
+        }
+    }
+
+    /// Templates of a mirror whose URL is the TeX Live repository itself
+    pub fn tlnet() -> Self {
+        Self {
+            index: format!("{{url}}/{}", INDEX_PATH),
+            archive: format!("{{url}}/{}", ARCHIVE_PATH),
+        }
+    }
+
+    /// The layout a mirror URL suggests: a URL ending in tlnet is the
+    /// repository itself, anything else a CTAN root
+    pub fn for_url(url: &str) -> Self {
+        if url.trim_end_matches('/').ends_with("tlnet") {
+            Self::tlnet()
+        } else {
+            Self::ctan()
+        }
+    }
+
+    /// Check the templates before a mirror is selected with them. The
+    /// index has to end in tlpkg/texlive.tlpdb, since TeX Live repositories
+    /// are addressed by the directory above it.
+    pub fn validate(&self, url: &str) -> Result<()> {
+        let placeholder = regex::Regex::new(r"\{[^{}]*\}").expect("valid regex");
+        for (kind, template, allowed) in [("index", &self.index, &["{url}"][..]), ("archive", &self.archive, &["{url}", "{package}"][..])] {
+            if let Some(unknown) = placeholder.find_iter(template).find(|found| !allowed.contains(&found.as_str())) {
+                anyhow::bail!("Unknown placeholder {} in the {} template \"{}\"; use {}", unknown.as_str(), kind, template, allowed.join(" or "));
+            }
+            let resolved = template.replace("{url}", url.trim_end_matches('/'));
+            if !["http://", "https://", "rsync://"].iter().any(|scheme| resolved.starts_with(scheme)) {
+                anyhow::bail!("The {} template \"{}\" does not give an http://, https:// or rsync:// URL", kind, template);
+            }
+        }
+        if !self.index.ends_with(&format!("/{}", INDEX_PATH)) {
+            anyhow::bail!("The index template \"{}\" must end in /{}", self.index, INDEX_PATH);
+        }
+        if !self.archive.contains("{package}") || !self.archive.ends_with(".tar.xz") {
+            anyhow::bail!("The archive template \"{}\" must contain {{package}} and end in .tar.xz", self.archive);
+        }
+        Ok(())
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Mirror {
@@ -14,17 +100,172 @@ pub struct Mirror {
     pub https: bool,
     pub rsync: bool,
     pub ftp: bool,
+    /// Index and archive locations; derived from the URL when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub templates: Option<MirrorTemplates>,
+}
+
+impl Mirror {
+    /// A mirror known only by name and CTAN root URL
+    pub fn from_url(name: &str, url: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            url: url.trim_end_matches('/').to_string(),
+            country: "Unknown".to_string(),
+            location: "Unknown".to_string(),
+            continent: "Unknown".to_string(),
+            sponsor: String::new(),
+            http: url.starts_with("http://"),
+            https: url.starts_with("https://"),
+            rsync: crate::download::is_rsync_url(url),
+            ftp: false,
+            templates: None,
+        }
+    }
+
+    /// A mirror added with `tpmgr mirror add`
+    pub fn from_custom(custom: &CustomMirror) -> Self {
+        let mut mirror = Mirror::from_url(&custom.name, &custom.url);
+        mirror.country = "Custom".to_string();
+        if custom.index_template.is_some() || custom.archive_template.is_some() {
+            let defaults = MirrorTemplates::for_url(&custom.url);
+            mirror.templates = Some(MirrorTemplates {
+                index: custom.index_template.clone().unwrap_or(defaults.index),
+                archive: custom.archive_template.clone().unwrap_or(defaults.archive),
+            });
+        }
+        mirror
+    }
+
+    /// Protocols the mirror is reachable over
+    pub fn protocols(&self) -> Vec<&'static str> {
+        [("https", self.https), ("http", self.http), ("ftp", self.ftp), ("rsync", self.rsync)]
+            .into_iter()
+            .filter(|(_, supported)| *supported)
+            .map(|(protocol, _)| protocol)
+            .collect()
+    }
+
+    /// Case-insensitive match of name, country or continent
+    pub fn matches(&self, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+        [&self.name, &self.country, &self.continent]
+            .iter()
+            .any(|field| field.to_lowercase().contains(&filter))
+    }
+
+    /// The mirror's URL templates, explicit or derived from its URL
+    pub fn templates(&self) -> MirrorTemplates {
+        self.templates.clone().unwrap_or_else(|| MirrorTemplates::for_url(&self.url))
+    }
+
+    /// URL of the package index (texlive.tlpdb) on this mirror
+    pub fn index_url(&self) -> String {
+        self.templates().index.replace("{url}", self.url.trim_end_matches('/'))
+    }
+
+    /// URL of the archive of `package` on this mirror
+    pub fn archive_url(&self, package: &str) -> String {
+        self.archive_template().replace("{package}", package)
+    }
+
+    /// The archive template with the mirror's URL filled in
+    pub fn archive_template(&self) -> String {
+        self.templates().archive.replace("{url}", self.url.trim_end_matches('/'))
+    }
+
+    /// URL of the TeX Live package repository on this mirror, the directory
+    /// the package index lies in
+    pub fn tlnet_url(&self) -> String {
+        let index_url = self.index_url();
+        format!("{}/", index_url.strip_suffix(INDEX_PATH).unwrap_or(&index_url).trim_end_matches('/'))
+    }
+
+    /// Check the mirror's templates before it is selected
+    pub fn validate(&self) -> Result<()> {
+        self.templates().validate(&self.url)
+    }
+
+    /// Whether the archives lie in the repository's archive/ directory, where
+    /// every TeX Live client looks for them
+    pub fn has_standard_archives(&self) -> bool {
+        self.archive_template() == format!("{}{}", self.tlnet_url(), ARCHIVE_PATH)
+    }
+}
+
+/// Which mirrors `tpmgr mirror list` shows
+#[derive(Debug, Default)]
+pub struct MirrorFilter {
+    /// Case-insensitive substring of the continent
+    pub continent: Option<String>,
+    /// Case-insensitive substring of the country
+    pub country: Option<String>,
+    /// A protocol the mirror must support (https, http, ftp or rsync)
+    pub protocol: Option<String>,
+}
+
+impl MirrorFilter {
+    pub fn matches(&self, mirror: &Mirror) -> bool {
+        let contains = |field: &str, filter: &Option<String>| {
+            filter.as_ref().is_none_or(|filter| field.to_lowercase().contains(&filter.to_lowercase()))
+        };
+        contains(&mirror.continent, &self.continent)
+            && contains(&mirror.country, &self.country)
+            && self.protocol.as_ref().is_none_or(|protocol| mirror.protocols().contains(&protocol.to_lowercase().as_str()))
+    }
+}
+
+/// A row of `tpmgr mirror list`: the mirror with its last benchmark result
+#[derive(Serialize, Debug, PartialEq)]
+pub struct MirrorListEntry {
+    pub name: String,
+    pub url: String,
+    pub country: String,
+    pub continent: String,
+    pub protocols: Vec<&'static str>,
+    pub selected: bool,
+    /// Last measured latency; None if never measured or unreachable
+    pub latency_ms: Option<u64>,
+    pub throughput_kbps: Option<u64>,
+    /// When the latency was measured, in seconds since the Unix epoch
+    pub tested_at: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MirrorList {
     pub mirrors: Vec<Mirror>,
+    /// Seconds since the Unix epoch
     pub last_updated: String,
 }
 
+/// Result of benchmarking one mirror with `tpmgr mirror test`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BenchmarkResult {
+    pub name: String,
+    pub country: String,
+    pub url: String,
+    /// Time to answer a HEAD request for the TeX Live repository; None if unreachable
+    pub latency_ms: Option<u64>,
+    /// Download speed measured on the start of the package index
+    pub throughput_kbps: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub tested_at: u64,
+}
+
+impl MirrorList {
+    fn is_fresh(&self) -> bool {
+        self.last_updated
+            .parse::<u64>()
+            .map(|updated| unix_timestamp().saturating_sub(updated) < MIRROR_LIST_MAX_AGE.as_secs())
+            .unwrap_or(false)
+    }
+}
+
 pub struct MirrorManager {
     mirrors: Vec<Mirror>,
     selected_mirror: Option<Mirror>,
+    /// Response time of the selected mirror, when it was measured
+    selected_latency: Option<std::time::Duration>,
     client: reqwest::Client,
 }
 
@@ -33,84 +274,124 @@ impl MirrorManager {
         Self {
             mirrors: Vec::new(),
             selected_mirror: None,
-            client: reqwest::Client::new(),
+            selected_latency: None,
+            client: crate::download::http_client().unwrap_or_default(),
         }
     }
 
     /// 从CTAN获取镜像列表
+    ///
+    /// The list is cached for MIRROR_LIST_MAX_AGE; if CTAN cannot be reached
+    /// a stale cache or the built-in list is used instead.
     pub async fn fetch_mirrors(&mut self) -> Result<()> {
+        self.load_mirrors(false).await
+    }
+
+    /// Download the mirror list again, ignoring the cache
+    pub async fn update_mirrors(&mut self) -> Result<()> {
+        self.load_mirrors(true).await
+    }
+
+    async fn load_mirrors(&mut self, force: bool) -> Result<()> {
+        let cache_path = Self::cache_path()?;
+        let cached = std::fs::read_to_string(&cache_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<MirrorList>(&content).ok());
+        let is_fresh = cached.as_ref().map(MirrorList::is_fresh).unwrap_or(false);
+
+        if let Some(list) = cached.as_ref().filter(|_| is_fresh && !force) {
+            self.mirrors = list.mirrors.clone();
+            println!("Loaded {} mirrors (cached)", self.mirrors.len());
+            return Ok(());
+        }
+
         println!("Fetching mirror list from CTAN...");
-        
-        // 如果API不可用，使用内置的镜像列表
-        let builtin_mirrors = vec![
-            Mirror {
-                name: "CTAN Main".to_string(),
-                url: "https://mirrors.ctan.org".to_string(),
-                country: "Global".to_string(),
-                location: "Global".to_string(),
-                continent: "Global".to_string(),
-                sponsor: "CTAN".to_string(),
-                http: true,
-                https: true,
-                rsync: false,
-                ftp: false,
-            },
-            Mirror {
-                name: "USTC Mirror".to_string(),
-                url: "https://mirrors.ustc.edu.cn/CTAN".to_string(),
-                country: "China".to_string(),
-                location: "Hefei".to_string(),
-                continent: "Asia".to_string(),
-                sponsor: "USTC".to_string(),
-                http: true,
-                https: true,
-                rsync: false,
-                ftp: false,
-            },
-            Mirror {
-                name: "Tsinghua Mirror".to_string(),
-                url: "https://mirrors.tuna.tsinghua.edu.cn/CTAN".to_string(),
-                country: "China".to_string(),
-                location: "Beijing".to_string(),
-                continent: "Asia".to_string(),
-                sponsor: "Tsinghua University".to_string(),
-                http: true,
-                https: true,
-                rsync: false,
-                ftp: false,
-            },
-            Mirror {
-                name: "MIT Mirror".to_string(),
-                url: "http://mirrors.mit.edu/CTAN".to_string(),
-                country: "USA".to_string(),
-                location: "Cambridge".to_string(),
-                continent: "North America".to_string(),
-                sponsor: "MIT".to_string(),
-                http: true,
-                https: false,
-                rsync: false,
-                ftp: false,
+        match self.download_mirror_list().await {
+            Ok(mirrors) => {
+                let list = MirrorList {
+                    mirrors,
+                    last_updated: unix_timestamp().to_string(),
+                };
+                if let Some(parent) = cache_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&cache_path, serde_json::to_string_pretty(&list)?)?;
+                self.mirrors = list.mirrors;
+            }
+            Err(e) => match cached {
+                Some(list) => {
+                    println!("Warning: Failed to fetch mirror list ({}), using cached list", e);
+                    self.mirrors = list.mirrors;
+                }
+                None => {
+                    // 如果API不可用，使用内置的镜像列表
+                    println!("Warning: Failed to fetch mirror list ({}), using built-in mirrors", e);
+                    self.mirrors = builtin_mirrors();
+                }
             },
-        ];
-        
-        self.mirrors = builtin_mirrors;
+        }
+
         println!("Loaded {} mirrors", self.mirrors.len());
         Ok(())
     }
 
+    async fn download_mirror_list(&self) -> Result<Vec<Mirror>> {
+        let content = self
+            .client
+            .get(CTAN_MIRRORS_URL)
+            .timeout(std::time::Duration::from_secs(30))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        let mirrors = parse_ctan_mirrors(&content);
+        if mirrors.is_empty() {
+            anyhow::bail!("no mirrors found in {}", CTAN_MIRRORS_URL);
+        }
+        Ok(mirrors)
+    }
+
+    fn cache_path() -> Result<PathBuf> {
+        Ok(crate::download::index_dir()?.join("mirrors.json"))
+    }
+
+    /// Add user-defined mirrors in front of the loaded list, so they are
+    /// always among the mirrors tested by auto-selection
+    pub fn add_custom_mirrors(&mut self, custom_mirrors: &[CustomMirror]) {
+        let mut mirrors: Vec<Mirror> = custom_mirrors.iter().map(Mirror::from_custom).collect();
+        self.mirrors.retain(|m| !custom_mirrors.iter().any(|custom| custom.name == m.name));
+        mirrors.append(&mut self.mirrors);
+        self.mirrors = mirrors;
+    }
+
+    /// Whether a mirror with this name is in the loaded list
+    pub fn has_mirror(&self, name: &str) -> bool {
+        self.mirrors.iter().any(|m| m.name == name)
+    }
+
     /// 自动选择最佳镜像（基于地理位置和响应速度）
     pub async fn select_best_mirror(&mut self) -> Result<()> {
         if self.mirrors.is_empty() {
             self.fetch_mirrors().await?;
         }
 
+        if let Some((mirror, result)) = self.best_benchmarked_mirror() {
+            println!("Using results of 'tpmgr mirror test'");
+            println!("Selected mirror: {} ({})", mirror.name, mirror.country);
+            self.selected_latency = result.latency_ms.map(Duration::from_millis);
+            self.selected_mirror = Some(mirror);
+            return Ok(());
+        }
+
         println!("Testing mirror response times...");
         let mut best_mirror: Option<Mirror> = None;
         let mut best_time = std::time::Duration::from_secs(10);
+        self.selected_latency = None;
 
         // 测试前10个镜像的响应时间
-        for mirror in self.mirrors.iter().take(10) {
-            let test_url = format!("{}/systems/texlive/tlnet/", mirror.url);
+        for mirror in self.mirrors.iter().filter(|m| m.validate().is_ok()).take(10) {
+            let test_url = mirror.tlnet_url();
             let start = std::time::Instant::now();
             
             match self.client.head(&test_url).timeout(std::time::Duration::from_secs(5)).send().await {
@@ -131,29 +412,113 @@ impl MirrorManager {
         if let Some(mirror) = best_mirror {
             println!("Selected mirror: {} ({})", mirror.name, mirror.country);
             self.selected_mirror = Some(mirror);
+            self.selected_latency = Some(best_time);
         } else {
             // 如果没有找到可用镜像，使用默认的CTAN镜像
-            self.selected_mirror = Some(Mirror {
-                name: "CTAN".to_string(),
-                url: "https://mirror.ctan.org".to_string(),
-                country: "Global".to_string(),
-                location: "Global".to_string(),
-                continent: "Global".to_string(),
-                sponsor: "CTAN".to_string(),
-                http: true,
-                https: true,
-                rsync: false,
-                ftp: false,
+            let mut mirror = Mirror::from_url("CTAN", "https://mirror.ctan.org");
+            mirror.country = "Global".to_string();
+            mirror.location = "Global".to_string();
+            mirror.continent = "Global".to_string();
+            mirror.sponsor = "CTAN".to_string();
+            self.selected_mirror = Some(mirror);
+        }
+
+        Ok(())
+    }
+
+    /// The best loaded mirror according to recent benchmark results
+    fn best_benchmarked_mirror(&self) -> Option<(Mirror, BenchmarkResult)> {
+        let now = unix_timestamp();
+        let results: Vec<BenchmarkResult> = Self::load_benchmark()
+            .into_iter()
+            .filter(|result| now.saturating_sub(result.tested_at) < BENCHMARK_MAX_AGE.as_secs())
+            .filter(|result| self.mirrors.iter().any(|m| m.name == result.name && m.validate().is_ok()))
+            .collect();
+        let best = best_result(&results)?.clone();
+        let mirror = self.mirrors.iter().find(|m| m.name == best.name)?.clone();
+        Some((mirror, best))
+    }
+
+    /// Time every loaded mirror matching `filter`, several at a time
+    pub async fn benchmark(&self, filter: Option<&str>) -> Vec<BenchmarkResult> {
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(BENCHMARK_CONCURRENCY));
+        let mut tasks = tokio::task::JoinSet::new();
+
+        for mirror in self.mirrors.iter().filter(|m| filter.is_none_or(|filter| m.matches(filter))) {
+            let client = self.client.clone();
+            let mirror = mirror.clone();
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                benchmark_mirror(&client, &mirror).await
             });
         }
 
+        let mut results = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(result) = result {
+                results.push(result);
+            }
+        }
+        results
+    }
+
+    /// Mirrors to fail over to from the repository `current_url`: recently
+    /// benchmarked ones fastest first, then the rest of the list
+    pub fn failover_candidates(&self, current_url: &str) -> Vec<Mirror> {
+        let now = unix_timestamp();
+        let mut results: Vec<BenchmarkResult> = Self::load_benchmark()
+            .into_iter()
+            .filter(|result| result.latency_ms.is_some() && now.saturating_sub(result.tested_at) < BENCHMARK_MAX_AGE.as_secs())
+            .collect();
+        sort_results(&mut results, "throughput");
+        let rank = |mirror: &Mirror| results.iter().position(|result| result.name == mirror.name).unwrap_or(results.len());
+
+        let mut candidates: Vec<Mirror> = self
+            .mirrors
+            .iter()
+            .filter(|mirror| mirror.tlnet_url().trim_end_matches('/') != current_url.trim_end_matches('/'))
+            .filter(|mirror| mirror.validate().is_ok())
+            .filter(|mirror| !crate::download::is_rsync_url(&mirror.url) || crate::download::rsync_available())
+            .cloned()
+            .collect();
+        candidates.sort_by_key(|mirror| rank(mirror));
+        candidates
+    }
+
+    /// Benchmark results saved by earlier runs
+    pub fn load_benchmark() -> Vec<BenchmarkResult> {
+        Self::benchmark_path()
+            .ok()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Save results, replacing earlier results for the same mirrors
+    pub fn save_benchmark(results: &[BenchmarkResult]) -> Result<()> {
+        let mut saved = Self::load_benchmark();
+        saved.retain(|old| !results.iter().any(|new| new.name == old.name));
+        saved.extend(results.iter().cloned());
+
+        let path = Self::benchmark_path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&saved)?)?;
         Ok(())
     }
 
+    fn benchmark_path() -> Result<PathBuf> {
+        Ok(Self::cache_path()?.with_file_name("mirror-benchmark.json"))
+    }
+
     /// 手动选择镜像
     pub fn select_mirror_by_name(&mut self, name: &str) -> Result<()> {
         if let Some(mirror) = self.mirrors.iter().find(|m| m.name == name) {
+            mirror.validate().map_err(|e| anyhow::anyhow!("Mirror '{}' cannot be used: {}", name, e))?;
             self.selected_mirror = Some(mirror.clone());
+            self.selected_latency = None;
             println!("Selected mirror: {} ({})", mirror.name, mirror.country);
             Ok(())
         } else {
@@ -161,52 +526,445 @@ impl MirrorManager {
         }
     }
 
-    /// 列出所有可用镜像
-    pub fn list_mirrors(&self) {
-        if self.mirrors.is_empty() {
-            println!("No mirrors loaded. Run 'tpmgr mirror update' first.");
-            return;
-        }
-
-        println!("Available mirrors:");
-        for (i, mirror) in self.mirrors.iter().enumerate() {
-            let selected = if let Some(ref selected) = self.selected_mirror {
-                if selected.name == mirror.name { " (selected)" } else { "" }
-            } else { "" };
-            
-            println!("  {}. {} ({}){}",
-                i + 1,
-                mirror.name,
-                mirror.country,
-                selected
-            );
-        }
+    /// Loaded mirrors matching `filter`, joined with the benchmark results
+    pub fn list_mirrors(&self, filter: &MirrorFilter, benchmark: &[BenchmarkResult]) -> Vec<MirrorListEntry> {
+        self.mirrors
+            .iter()
+            .filter(|mirror| filter.matches(mirror))
+            .map(|mirror| {
+                let selected = self.selected_mirror.as_ref().is_some_and(|selected| selected.name == mirror.name);
+                let result = benchmark.iter().find(|result| result.name == mirror.name);
+                // The latency measured by `mirror use --auto` stands in for a missing benchmark
+                let latency_ms = match result {
+                    Some(result) => result.latency_ms,
+                    None if selected => self.selected_latency.map(|latency| latency.as_millis() as u64),
+                    None => None,
+                };
+                MirrorListEntry {
+                    name: mirror.name.clone(),
+                    url: mirror.url.clone(),
+                    country: mirror.country.clone(),
+                    continent: mirror.continent.clone(),
+                    protocols: mirror.protocols(),
+                    selected,
+                    latency_ms,
+                    throughput_kbps: result.and_then(|result| result.throughput_kbps),
+                    tested_at: result.map(|result| result.tested_at),
+                }
+            })
+            .collect()
     }
 
     /// 获取当前选择的镜像
-    #[allow(dead_code)]
     pub fn get_selected_mirror(&self) -> Option<&Mirror> {
         self.selected_mirror.as_ref()
     }
 
+    /// The current selection in the form stored in the global config
+    pub fn selection(&self) -> Option<MirrorSelection> {
+        self.selected_mirror.as_ref().map(|mirror| MirrorSelection {
+            name: mirror.name.clone(),
+            url: mirror.url.clone(),
+            latency_ms: self.selected_latency.map(|latency| latency.as_millis() as u64),
+            archive_template: (!mirror.has_standard_archives()).then(|| mirror.archive_template()),
+        })
+    }
+
+    /// Restore a selection saved by an earlier run
+    pub fn restore_selection(&mut self, selection: &MirrorSelection) {
+        let mirror = self
+            .mirrors
+            .iter()
+            .find(|m| m.name == selection.name)
+            .cloned()
+            .unwrap_or_else(|| Mirror::from_url(&selection.name, &selection.url));
+        self.selected_mirror = Some(mirror);
+        self.selected_latency = selection.latency_ms.map(std::time::Duration::from_millis);
+    }
+
     /// 获取包的下载URL
     #[allow(dead_code)]
     pub fn get_package_url(&self, package_name: &str) -> Option<String> {
-        if let Some(mirror) = &self.selected_mirror {
-            Some(format!("{}/systems/texlive/tlnet/archive/{}.tar.xz", 
-                mirror.url, package_name))
-        } else {
-            None
-        }
+        self.selected_mirror.as_ref().map(|mirror| mirror.archive_url(package_name))
     }
 
     /// 获取包索引URL
     #[allow(dead_code)]
     pub fn get_package_index_url(&self) -> Option<String> {
-        if let Some(mirror) = &self.selected_mirror {
-            Some(format!("{}/systems/texlive/tlnet/tlpkg/texlive.tlpdb", mirror.url))
-        } else {
-            None
+        self.selected_mirror.as_ref().map(|mirror| mirror.index_url())
+    }
+}
+
+async fn benchmark_mirror(client: &reqwest::Client, mirror: &Mirror) -> BenchmarkResult {
+    let tlnet_url = mirror.tlnet_url();
+    let start = Instant::now();
+    let latency = match client.head(&tlnet_url).timeout(Duration::from_secs(5)).send().await {
+        Ok(response) if response.status().is_success() => Some(start.elapsed()),
+        _ => None,
+    };
+    let throughput = match latency {
+        Some(_) => measure_throughput(client, &mirror.index_url()).await,
+        None => None,
+    };
+
+    BenchmarkResult {
+        name: mirror.name.clone(),
+        country: mirror.country.clone(),
+        url: mirror.url.clone(),
+        latency_ms: latency.map(|latency| latency.as_millis() as u64),
+        throughput_kbps: throughput,
+        tested_at: unix_timestamp(),
+    }
+}
+
+/// Download the start of the package index and return the speed in KiB/s
+async fn measure_throughput(client: &reqwest::Client, url: &str) -> Option<u64> {
+    let start = Instant::now();
+    let mut response = client
+        .get(url)
+        .header(reqwest::header::RANGE, format!("bytes=0-{}", THROUGHPUT_SAMPLE_BYTES - 1))
+        .timeout(Duration::from_secs(15))
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?;
+
+    // Servers ignoring the range send the whole file; stop after the sample
+    let mut received = 0;
+    while let Some(chunk) = response.chunk().await.ok()? {
+        received += chunk.len();
+        if received >= THROUGHPUT_SAMPLE_BYTES {
+            break;
+        }
+    }
+
+    let seconds = start.elapsed().as_secs_f64();
+    (received > 0 && seconds > 0.0).then(|| (received as f64 / 1024.0 / seconds) as u64)
+}
+
+/// The reachable mirror with the highest throughput, then the lowest latency
+pub fn best_result(results: &[BenchmarkResult]) -> Option<&BenchmarkResult> {
+    results
+        .iter()
+        .filter(|result| result.latency_ms.is_some())
+        .min_by_key(|result| (Reverse(result.throughput_kbps.unwrap_or(0)), result.latency_ms))
+}
+
+/// Sort results by "latency", "throughput" or "name"; unreachable mirrors go last
+pub fn sort_results(results: &mut [BenchmarkResult], key: &str) {
+    match key {
+        "throughput" => results.sort_by_key(|result| {
+            (result.latency_ms.is_none(), Reverse(result.throughput_kbps.unwrap_or(0)), result.latency_ms)
+        }),
+        "name" => results.sort_by(|a, b| a.name.cmp(&b.name)),
+        _ => results.sort_by_key(|result| (result.latency_ms.is_none(), result.latency_ms)),
+    }
+}
+
+/// Print benchmark results as a table
+pub fn print_benchmark(results: &[BenchmarkResult]) {
+    println!("  {:<32} {:<16} {:>9} {:>12}", "Mirror", "Country", "Latency", "Throughput");
+    for result in results {
+        let latency = result
+            .latency_ms
+            .map(|latency| format!("{}ms", latency))
+            .unwrap_or_else(|| "timeout".to_string());
+        let throughput = match result.throughput_kbps {
+            Some(kbps) if kbps >= 1024 => format!("{:.1} MB/s", kbps as f64 / 1024.0),
+            Some(kbps) => format!("{} KB/s", kbps),
+            None => "-".to_string(),
+        };
+        println!("  {:<32} {:<16} {:>9} {:>12}", result.name, result.country, latency, throughput);
+    }
+}
+
+/// Print `tpmgr mirror list` rows as a table, marking the selected mirror
+pub fn print_mirror_list(entries: &[MirrorListEntry]) {
+    println!("  {:<32} {:<16} {:<14} {:<22} {:>9}", "Mirror", "Country", "Continent", "Protocols", "Latency");
+    for entry in entries {
+        let marker = if entry.selected { "*" } else { " " };
+        let latency = match (entry.latency_ms, entry.tested_at) {
+            (Some(latency), _) => format!("{}ms", latency),
+            (None, Some(_)) => "timeout".to_string(),
+            (None, None) => "-".to_string(),
+        };
+        println!("{} {:<32} {:<16} {:<14} {:<22} {:>9}", marker, entry.name, entry.country, entry.continent, entry.protocols.join(", "), latency);
+    }
+}
+
+/// Mirrors to fall back on when the CTAN list cannot be fetched
+fn builtin_mirrors() -> Vec<Mirror> {
+    vec![
+        Mirror {
+            name: "CTAN Main".to_string(),
+            url: "https://mirrors.ctan.org".to_string(),
+            country: "Global".to_string(),
+            location: "Global".to_string(),
+            continent: "Global".to_string(),
+            sponsor: "CTAN".to_string(),
+            http: true,
+            https: true,
+            rsync: false,
+            ftp: false,
+            templates: None,
+        },
+        Mirror {
+            name: "USTC Mirror".to_string(),
+            url: "https://mirrors.ustc.edu.cn/CTAN".to_string(),
+            country: "China".to_string(),
+            location: "Hefei".to_string(),
+            continent: "Asia".to_string(),
+            sponsor: "USTC".to_string(),
+            http: true,
+            https: true,
+            rsync: false,
+            ftp: false,
+            templates: None,
+        },
+        Mirror {
+            name: "Tsinghua Mirror".to_string(),
+            url: "https://mirrors.tuna.tsinghua.edu.cn/CTAN".to_string(),
+            country: "China".to_string(),
+            location: "Beijing".to_string(),
+            continent: "Asia".to_string(),
+            sponsor: "Tsinghua University".to_string(),
+            http: true,
+            https: true,
+            rsync: false,
+            ftp: false,
+            templates: None,
+        },
+        Mirror {
+            name: "MIT Mirror".to_string(),
+            url: "http://mirrors.mit.edu/CTAN".to_string(),
+            country: "USA".to_string(),
+            location: "Cambridge".to_string(),
+            continent: "North America".to_string(),
+            sponsor: "MIT".to_string(),
+            http: true,
+            https: false,
+            rsync: false,
+            ftp: false,
+            templates: None,
+        },
+    ]
+}
+
+/// Parse ctan-mirrors.pl, a Perl hash of continent => country => URL.
+/// URLs of the same host are merged into one mirror with all its protocols;
+/// the mirror URL is the HTTPS one, else HTTP, else rsync.
+pub fn parse_ctan_mirrors(content: &str) -> Vec<Mirror> {
+    let entry_regex = regex::Regex::new(r"'([^']+)'\s*=>\s*(\{|1)").expect("valid regex");
+    let mut path: Vec<String> = Vec::new();
+    let mut mirrors: BTreeMap<(String, String, String), Mirror> = BTreeMap::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(caps) = entry_regex.captures(line) {
+            if &caps[2] == "{" {
+                path.push(caps[1].to_string());
+                continue;
+            }
+            let [continent, country] = path.as_slice() else {
+                continue;
+            };
+            let Some((scheme, rest)) = caps[1].split_once("://") else {
+                continue;
+            };
+            let host = rest.split('/').next().unwrap_or(rest).to_string();
+            let key = (continent.clone(), country.clone(), host.clone());
+            let mirror = mirrors.entry(key).or_insert_with(|| Mirror {
+                name: host.clone(),
+                url: String::new(),
+                country: country.clone(),
+                location: country.clone(),
+                continent: continent.clone(),
+                sponsor: String::new(),
+                http: false,
+                https: false,
+                rsync: false,
+                ftp: false,
+                templates: None,
+            });
+
+            let url = caps[1].trim_end_matches('/').to_string();
+            match scheme {
+                "https" => {
+                    mirror.https = true;
+                    mirror.url = url;
+                }
+                "http" => {
+                    mirror.http = true;
+                    if !mirror.https {
+                        mirror.url = url;
+                    }
+                }
+                "ftp" => mirror.ftp = true,
+                "rsync" => {
+                    mirror.rsync = true;
+                    if mirror.url.is_empty() {
+                        mirror.url = url;
+                    }
+                }
+                _ => {}
+            }
+        } else if line.starts_with('}') {
+            path.pop();
         }
     }
+
+    // FTP-only mirrors cannot serve downloads
+    mirrors.into_values().filter(|mirror| !mirror.url.is_empty()).collect()
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ctan_mirrors() {
+        let content = r#"$mirrors = {
+  'Asia' => {
+    'China' => {
+      'http://mirrors.ustc.edu.cn/CTAN/' => 1,
+      'https://mirrors.ustc.edu.cn/CTAN/' => 1,
+      'rsync://mirrors.ustc.edu.cn/CTAN/' => 1,
+    },
+  },
+  'Europe' => {
+    'Germany' => {
+      'ftp://ftp.example.de/pub/ctan/' => 1,
+      'http://ftp.fau.de/ctan/' => 1,
+    },
+    'Sweden' => {
+      'rsync://ftp.example.se/CTAN/' => 1,
+    },
+  },
+};
+"#;
+        let mirrors = parse_ctan_mirrors(content);
+        assert_eq!(mirrors.len(), 3);
+
+        assert_eq!(mirrors[0].name, "mirrors.ustc.edu.cn");
+        assert_eq!(mirrors[0].url, "https://mirrors.ustc.edu.cn/CTAN");
+        assert_eq!((mirrors[0].continent.as_str(), mirrors[0].country.as_str()), ("Asia", "China"));
+        assert!(mirrors[0].http && mirrors[0].https && mirrors[0].rsync && !mirrors[0].ftp);
+
+        assert_eq!(mirrors[1].url, "http://ftp.fau.de/ctan");
+        assert_eq!(mirrors[1].country, "Germany");
+
+        assert_eq!(mirrors[2].tlnet_url(), "rsync://ftp.example.se/CTAN/systems/texlive/tlnet/");
+        assert!(mirrors[2].rsync && !mirrors[2].http && !mirrors[2].https);
+    }
+
+    #[test]
+    fn test_benchmark_ranking() {
+        let result = |name: &str, latency_ms: Option<u64>, throughput_kbps: Option<u64>| BenchmarkResult {
+            name: name.to_string(),
+            country: String::new(),
+            url: String::new(),
+            latency_ms,
+            throughput_kbps,
+            tested_at: 0,
+        };
+        let mut results = vec![
+            result("slow", Some(300), Some(200)),
+            result("down", None, None),
+            result("fast", Some(80), Some(4096)),
+            result("near", Some(20), Some(900)),
+        ];
+
+        assert_eq!(best_result(&results).unwrap().name, "fast");
+
+        sort_results(&mut results, "latency");
+        let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["near", "fast", "slow", "down"]);
+
+        sort_results(&mut results, "throughput");
+        let names: Vec<&str> = results.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["fast", "near", "slow", "down"]);
+    }
+
+    #[test]
+    fn test_custom_mirrors() {
+        let mut manager = MirrorManager::new();
+        manager.mirrors = builtin_mirrors();
+        manager.add_custom_mirrors(&[CustomMirror {
+            name: "Campus".to_string(),
+            url: "https://mirror.example.edu/CTAN".to_string(),
+            index_template: None,
+            archive_template: None,
+        }]);
+
+        assert_eq!(manager.mirrors[0].name, "Campus");
+        assert!(manager.has_mirror("USTC Mirror"));
+
+        manager.select_mirror_by_name("Campus").unwrap();
+        let filter = MirrorFilter { country: Some("china".to_string()), protocol: Some("https".to_string()), ..Default::default() };
+        let benchmark = vec![BenchmarkResult {
+            name: "USTC Mirror".to_string(),
+            country: "China".to_string(),
+            url: String::new(),
+            latency_ms: Some(42),
+            throughput_kbps: None,
+            tested_at: 1,
+        }];
+        let entries = manager.list_mirrors(&filter, &benchmark);
+        assert!(entries.iter().all(|entry| entry.country == "China" && entry.protocols.contains(&"https")));
+        let ustc = entries.iter().find(|entry| entry.name == "USTC Mirror").unwrap();
+        assert_eq!((ustc.latency_ms, ustc.selected), (Some(42), false));
+        let campus = manager.list_mirrors(&MirrorFilter::default(), &benchmark).remove(0);
+        assert!(campus.selected && campus.latency_ms.is_none());
+        assert!(manager.list_mirrors(&MirrorFilter { protocol: Some("rsync".to_string()), ..Default::default() }, &benchmark).is_empty());
+
+        assert_eq!(
+            manager.get_selected_mirror().unwrap().tlnet_url(),
+            "https://mirror.example.edu/CTAN/systems/texlive/tlnet/"
+        );
+    }
+
+    #[test]
+    fn test_mirror_templates() {
+        let ctan = Mirror::from_url("tuna", "https://mirrors.tuna.tsinghua.edu.cn/CTAN/");
+        assert_eq!(ctan.index_url(), "https://mirrors.tuna.tsinghua.edu.cn/CTAN/systems/texlive/tlnet/tlpkg/texlive.tlpdb");
+        assert!(ctan.has_standard_archives());
+
+        let tlnet = Mirror::from_url("campus", "https://tex.example.edu/texlive/tlnet");
+        assert_eq!(tlnet.tlnet_url(), "https://tex.example.edu/texlive/tlnet/");
+        assert_eq!(tlnet.archive_url("siunitx"), "https://tex.example.edu/texlive/tlnet/archive/siunitx.tar.xz");
+
+        let mut manager = MirrorManager::new();
+        manager.add_custom_mirrors(&[
+            CustomMirror {
+                name: "split".to_string(),
+                url: "https://tex.example.org".to_string(),
+                index_template: Some("{url}/tl/tlpkg/texlive.tlpdb".to_string()),
+                archive_template: Some("https://files.example.org/tl/{package}.tar.xz".to_string()),
+            },
+            CustomMirror {
+                name: "broken".to_string(),
+                url: "https://tex.example.org".to_string(),
+                index_template: Some("{root}/tlpkg/texlive.tlpdb".to_string()),
+                archive_template: None,
+            },
+        ]);
+        manager.select_mirror_by_name("split").unwrap();
+        let selection = manager.selection().unwrap();
+        assert_eq!(manager.get_selected_mirror().unwrap().tlnet_url(), "https://tex.example.org/tl/");
+        assert_eq!(selection.archive_template.as_deref(), Some("https://files.example.org/tl/{package}.tar.xz"));
+        assert!(manager.select_mirror_by_name("broken").unwrap_err().to_string().contains("{root}"));
+
+        let templates = |index: &str, archive: &str| MirrorTemplates { index: index.to_string(), archive: archive.to_string() };
+        assert!(templates("{url}/tlnet/texlive.tlpdb", "{url}/archive/{package}.tar.xz").validate("https://a.org").is_err());
+        assert!(templates("{url}/tlpkg/texlive.tlpdb", "{url}/archive/siunitx.tar.xz").validate("https://a.org").is_err());
+        assert!(templates("{url}/tlpkg/texlive.tlpdb", "{url}/archive/{package}.tar.xz").validate("file:///srv/tlnet").is_err());
+    }
 }
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+
+/// TeX Live repository on the CTAN main node, which the mirrors sync from
+pub const CTAN_MAIN_TLNET_URL: &str = "https://ftp.dante.de/tex-archive/systems/texlive/tlnet/";
+
+/// How far a mirror's package index may trail the main node's before it is stale
+const MAX_MIRROR_LAG: Duration = Duration::from_secs(2 * 24 * 60 * 60);
+
+/// How many revisions an undated mirror may trail the main node's before it is
+/// stale; TeX Live commits roughly this many in the two days of `MAX_MIRROR_LAG`
+const MAX_UNDATED_REVISION_GAP: u64 = 100;
+
+/// Bytes of the package index read for its header; 00texlive.config comes first
+const HEADER_SAMPLE_BYTES: usize = 16 * 1024;
+
+/// A repository's package index as seen by `tpmgr mirror check`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct IndexSnapshot {
+    /// TeX Live release the repository serves, e.g. 2025
+    pub release: u32,
+    /// Subversion revision the index was built from
+    pub revision: u64,
+    /// Last-Modified of texlive.tlpdb (seconds since the Unix epoch), if the server sent it
+    pub updated_at: Option<u64>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorHealth {
+    Fresh,
+    /// Serves an older release, or an index older than allowed
+    Stale,
+    /// Unreachable or not serving a package index
+    Broken,
+    /// Reachable, but the main node could not be reached to compare
+    Unverified,
+}
+
+impl MirrorHealth {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Fresh => "fresh",
+            Self::Stale => "stale",
+            Self::Broken => "broken",
+            Self::Unverified => "unverified",
+        }
+    }
+}
+
+/// Read release and revision from the 00texlive.config record of a texlive.tlpdb
+pub fn parse_index_header(content: &str) -> Option<(u32, u64)> {
+    let record = content.split("\n\n").find(|record| record.starts_with("name 00texlive.config"))?;
+    let depend = |key: &str| {
+        record
+            .lines()
+            .find_map(|line| line.strip_prefix("depend ")?.strip_prefix(key)?.strip_prefix('/').map(str::to_string))
+    };
+    Some((depend("release")?.trim().parse().ok()?, depend("revision")?.trim().parse().ok()?))
+}
+
+/// Fetch the head of the package index of the TeX Live repository `tlnet_url`
+pub async fn fetch_snapshot(client: &reqwest::Client, tlnet_url: &str) -> Result<IndexSnapshot> {
+    let url = format!("{}/tlpkg/texlive.tlpdb", tlnet_url.trim_end_matches('/'));
+    let (content, updated_at) = if url.starts_with("http://") || url.starts_with("https://") {
+        let mut response = client
+            .get(&url)
+            .header(reqwest::header::RANGE, format!("bytes=0-{}", HEADER_SAMPLE_BYTES - 1))
+            .timeout(Duration::from_secs(15))
+            .send()
+            .await?
+            .error_for_status()?;
+        let updated_at = response
+            .headers()
+            .get(reqwest::header::LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_http_date);
+        // Servers ignoring the range send the whole file; stop after the sample
+        let mut content = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            content.extend_from_slice(&chunk);
+            if content.len() >= HEADER_SAMPLE_BYTES {
+                break;
+            }
+        }
+        (String::from_utf8_lossy(&content).into_owned(), updated_at)
+    } else {
+        // rsync:// and file:// repositories are read whole, without a date
+        let file = tempfile::NamedTempFile::new()?;
+        crate::download::fetch(&url, file.path()).await?;
+        (String::from_utf8_lossy(&std::fs::read(file.path())?).into_owned(), None)
+    };
+
+    let (release, revision) = parse_index_header(&content)
+        .ok_or_else(|| anyhow::anyhow!("{} is not a TeX Live package index", url))?;
+    Ok(IndexSnapshot { release, revision, updated_at })
+}
+
+/// Compare a mirror's index with the main node's; returns the verdict with
+/// a short explanation
+pub fn assess(mirror: &IndexSnapshot, main: Option<&IndexSnapshot>) -> (MirrorHealth, String) {
+    let Some(main) = main else {
+        return (MirrorHealth::Unverified, "the CTAN main node could not be reached to compare".to_string());
+    };
+    if mirror.release < main.release {
+        return (MirrorHealth::Stale, format!("serves TeX Live {}, but {} is current", mirror.release, main.release));
+    }
+    if mirror.revision >= main.revision {
+        return (MirrorHealth::Fresh, "up to date with the CTAN main node".to_string());
+    }
+
+    let gap = main.revision - mirror.revision;
+    let behind = format!("{} revisions behind the CTAN main node", gap);
+    match (mirror.updated_at, main.updated_at) {
+        (Some(updated), Some(main_updated)) => {
+            let lag = main_updated.saturating_sub(updated);
+            let days = lag as f64 / 86_400.0;
+            if lag > MAX_MIRROR_LAG.as_secs() {
+                (MirrorHealth::Stale, format!("{}, last updated {:.1} days before it", behind, days))
+            } else {
+                (MirrorHealth::Fresh, format!("{}, which is normal between syncs", behind))
+            }
+        }
+        // Without dates only the size of the gap tells a missed sync from the next one
+        _ if gap > MAX_UNDATED_REVISION_GAP => {
+            (MirrorHealth::Stale, format!("{}, and the mirror sent no date", behind))
+        }
+        _ => (MirrorHealth::Unverified, format!("it is {} and sent no date to tell how long ago", behind)),
+    }
+}
+
+/// Unix time of an HTTP date such as "Sun, 06 Nov 1994 08:49:37 GMT"
+pub fn parse_http_date(date: &str) -> Option<u64> {
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = fields[..] else {
+        return None;
+    };
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let (day, year): (i64, i64) = (day.parse().ok()?, year.parse().ok()?);
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (clock.next()??, clock.next()??, clock.next()??);
+
+    // Civil date to days, after Howard Hinnant's algorithm
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+    Some(days * 86_400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_health() {
+        let tlpdb = "name 00texlive.config\ncategory Config\ndepend container_format/xz\ndepend release/2025\ndepend revision/75310\n\nname 00texlive.installation\n";
+        assert_eq!(parse_index_header(tlpdb), Some((2025, 75310)));
+        assert_eq!(parse_index_header("name siunitx\nrevision 1\n"), None);
+
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784_111_777));
+        assert_eq!(parse_http_date("Thu, 01 Oct 2026 00:00:00 GMT"), Some(1_790_812_800));
+        assert_eq!(parse_http_date("yesterday"), None);
+
+        let day = 86_400;
+        let snapshot = |release, revision, updated_at| IndexSnapshot { release, revision, updated_at };
+        let main = snapshot(2025, 75310, Some(10 * day));
+        assert_eq!(assess(&snapshot(2025, 75310, Some(10 * day)), Some(&main)).0, MirrorHealth::Fresh);
+        assert_eq!(assess(&snapshot(2025, 75290, Some(9 * day)), Some(&main)).0, MirrorHealth::Fresh);
+        assert_eq!(assess(&snapshot(2025, 74800, Some(3 * day)), Some(&main)).0, MirrorHealth::Stale);
+        assert_eq!(assess(&snapshot(2024, 73000, None), Some(&main)).0, MirrorHealth::Stale);
+        assert_eq!(assess(&snapshot(2025, 75300, None), Some(&main)).0, MirrorHealth::Unverified);
+        assert_eq!(assess(&snapshot(2025, 74800, None), Some(&main)).0, MirrorHealth::Stale);
+        assert_eq!(assess(&snapshot(2025, 75310, None), Some(&main)).0, MirrorHealth::Fresh);
+        assert_eq!(assess(&main, None).0, MirrorHealth::Unverified);
+    }
+}
@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use anyhow::Result;
-use std::path::PathBuf;
-use crate::config::Config;
+use std::path::{Path, PathBuf};
+use crate::config::{CacheConfig, Config, Repository};
+use crate::error::TpmgrError;
+use crate::repository::RemotePackage;
+use crate::tex_parser::TeXParser;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Package {
@@ -12,9 +15,11 @@ pub struct Package {
     pub dependencies: Vec<String>,
     pub files: Vec<String>,
     pub size: u64,
+    /// Repository the package would be installed from
+    pub repository: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct PackageInfo {
     pub name: String,
     pub version: String,
@@ -22,6 +27,200 @@ pub struct PackageInfo {
     pub dependencies: Vec<String>,
     pub download_url: String,
     pub checksum: String,
+    /// Repository the package would be installed from
+    pub repository: String,
+    /// Archive size in bytes, 0 when the repository does not say
+    #[serde(default)]
+    pub size: u64,
+    /// For a bibliography style installed on its own, the style file as the
+    /// package ships it (e.g. IEEEtran.bst); None installs the whole package
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style_file: Option<String>,
+}
+
+/// What installing one package would do, computed without touching disk
+#[derive(Debug, Clone)]
+pub struct InstallStep {
+    pub info: PackageInfo,
+    /// A verified archive is already in the cache, so nothing is downloaded
+    pub cached: bool,
+    /// Dependencies declared by the repository and whether they are installed
+    pub dependencies: Vec<(String, bool)>,
+    /// Files written to the install directory; None until the archive is downloaded
+    pub files: Option<Vec<PathBuf>>,
+    /// The distribution's tlmgr, when it installs the package instead of tpmgr
+    pub tlmgr: Option<PathBuf>,
+}
+
+impl InstallStep {
+    /// Bytes to download, 0 when the archive is cached or its size unknown
+    pub fn download_size(&self) -> u64 {
+        if self.cached { 0 } else { self.info.size }
+    }
+}
+
+/// An installed package as shown by `tpmgr list`
+#[derive(Debug, Clone)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    /// Total size of the installed files in bytes
+    pub size: u64,
+    /// Packages its .sty loads
+    pub requires: Vec<String>,
+}
+
+/// Where a package is available, as `tpmgr search` marks it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallStatus {
+    /// Installed in the project's package directory
+    Project,
+    /// Provided by a .sty/.cls file in the project
+    Local,
+    /// Installed in tpmgr's global package directory or the TeX distribution
+    Global,
+    NotInstalled,
+}
+
+impl InstallStatus {
+    /// The status of `name`, looking in the project first
+    pub fn of(name: &str, project: &HashSet<String>, local: &HashSet<String>, global: impl Fn(&str) -> bool) -> Self {
+        if project.contains(name) {
+            Self::Project
+        } else if local.contains(name) {
+            Self::Local
+        } else if global(name) {
+            Self::Global
+        } else {
+            Self::NotInstalled
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Project => "installed in project",
+            Self::Local => "provided by project files",
+            Self::Global => "installed globally",
+            Self::NotInstalled => "not installed",
+        }
+    }
+}
+
+/// A package in the registry (registry.json): its version and, when tpmgr
+/// unpacked it, the files written, relative to the install root with `/`.
+/// Registries of earlier versions and tlmgr installs hold only the version.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+enum RegistryEntry {
+    Version(String),
+    Installed { version: String, files: Vec<String> },
+}
+
+impl RegistryEntry {
+    fn version(&self) -> &str {
+        match self {
+            Self::Version(version) | Self::Installed { version, .. } => version,
+        }
+    }
+}
+
+/// TDS directories (relative to a TEXMF root) by file extension; each
+/// package gets its own subdirectory
+const TDS_DIRS: &[(&str, &str)] = &[
+    ("bst", "bibtex/bst"),
+    ("bib", "bibtex/bib"),
+    ("otf", "fonts/opentype/tpmgr"),
+    ("ttf", "fonts/truetype/tpmgr"),
+    ("tfm", "fonts/tfm/tpmgr"),
+    ("map", "fonts/map/dvips"),
+    ("enc", "fonts/enc/dvips"),
+];
+
+/// Bibliography style files that can be installed on their own, as
+/// `<style>-bst`, `<style>-bbx` or `<style>-cbx`
+const STYLE_EXTENSIONS: &[&str] = &["bst", "bbx", "cbx"];
+
+/// A bibliography style requested on its own, e.g. `ieeetran-bst` for the
+/// IEEEtran.bst of the ieeetran package
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleArtifact {
+    pub style: String,
+    pub extension: String,
+}
+
+impl StyleArtifact {
+    pub fn parse(name: &str) -> Option<Self> {
+        let (style, extension) = name.rsplit_once('-')?;
+        (!style.is_empty() && STYLE_EXTENSIONS.contains(&extension)).then(|| Self {
+            style: style.to_string(),
+            extension: extension.to_string(),
+        })
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.style, self.extension)
+    }
+
+    /// Whether `file_name` is the style's file; its case may differ from the
+    /// requested name (IEEEtran.bst for `ieeetran-bst`)
+    pub fn matches(&self, file_name: &std::ffi::OsStr) -> bool {
+        file_name.to_string_lossy().eq_ignore_ascii_case(&self.file_name())
+    }
+
+    /// Packages likely to ship the style when no file index says which does
+    fn candidate_packages(&self) -> Vec<String> {
+        let mut packages = Vec::new();
+        if self.extension != "bst" {
+            packages.push(format!("biblatex-{}", self.style));
+        }
+        packages.push(self.style.clone());
+        packages
+    }
+}
+
+/// How long an archive without a checksum is reused from the cache
+const UNVERIFIED_ARCHIVE_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Archive formats `unpack_archive` can read, by file name suffix
+const ARCHIVE_FORMATS: &[&str] = &[".tar.xz", ".tar.gz", ".tgz"];
+
+/// Whether the archive at `url` is in a format tpmgr can unpack
+pub fn is_unpackable(url: &str) -> bool {
+    ARCHIVE_FORMATS.iter().any(|format| url.ends_with(format))
+}
+
+/// Whether an archive entry belongs to the package; TeX Live archives also
+/// carry their tlpkg/ metadata, which is not installed
+fn is_package_file(path: &Path) -> bool {
+    !path.starts_with("tlpkg")
+}
+
+/// Top-level directories of a TEXMF tree that package archives fill
+const TDS_ROOTS: &[&str] = &["tex", "fonts", "bibtex", "metapost", "makeindex", "dvips", "scripts", "doc", "source"];
+
+/// Whether an archive entry is a path in TDS layout, e.g. tex/latex/siunitx/siunitx.sty
+fn is_tds_path(path: &Path) -> bool {
+    let mut components = path.components();
+    let root = components.next().and_then(|component| match component {
+        std::path::Component::Normal(root) => root.to_str(),
+        _ => None,
+    });
+    root.is_some_and(|root| TDS_ROOTS.contains(&root))
+        && components.all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+/// TDS directory of everything else (.sty, .cls, .def, biblatex styles, ...)
+const DEFAULT_TDS_DIR: &str = "tex/latex";
+
+/// The TDS directory of a package file, e.g. `tex/latex/siunitx`
+fn tds_dir(package_name: &str, file_name: &std::ffi::OsStr) -> PathBuf {
+    let extension = Path::new(file_name).extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    let base = TDS_DIRS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, dir)| *dir)
+        .unwrap_or(DEFAULT_TDS_DIR);
+    Path::new(base).join(package_name)
 }
 
 #[allow(dead_code)]
@@ -29,17 +228,42 @@ pub struct PackageManager {
     global: bool,
     config: Config,
     cache_dir: PathBuf,
+    /// Where the registry is kept, and for project installs the package files
     install_dir: PathBuf,
+    /// The TEXMF tree global installs are written to (TEXMFHOME), in TDS layout
+    texmf_root: Option<PathBuf>,
+    /// Repositories consulted in priority order
+    repositories: Vec<Repository>,
+    /// Mirror that downloads go through (see `Config::resolved_mirror`)
+    mirror_url: String,
+    cache_policy: CacheConfig,
+    /// For global installs into a distribution of the user's own (TinyTeX),
+    /// the tlmgr that installs packages instead of tpmgr
+    tlmgr: Option<PathBuf>,
 }
 
 impl PackageManager {
     pub fn new(global: bool) -> Result<Self> {
+        let manager = Self::read_only(global)?;
+        
+        // 只在非测试环境创建目录
+        #[cfg(not(test))]
+        {
+            std::fs::create_dir_all(&manager.cache_dir)?;
+            std::fs::create_dir_all(&manager.install_dir)?;
+        }
+        
+        Ok(manager)
+    }
+    
+    /// Like `new`, but without creating the cache and install directories (for dry runs)
+    pub fn read_only(global: bool) -> Result<Self> {
         let cache_dir = if global {
             dirs::cache_dir()
                 .unwrap_or_else(|| PathBuf::from("."))
                 .join("tpmgr")
         } else {
-            PathBuf::from(".tpmgr").join("cache")
+            crate::config::project_path(".tpmgr").join("cache")
         };
         
         let install_dir = if global {
@@ -48,53 +272,120 @@ impl PackageManager {
                 .join("tpmgr")
                 .join("packages")
         } else {
-            PathBuf::from("packages")
+            crate::config::project_path("packages")
         };
-        
-        let config = if std::path::Path::new("tpmgr.toml").exists() {
-            Config::load("tpmgr.toml")?
+        let texmf_root = if global {
+            Some(crate::environment::texmf_home()?)
         } else {
-            Config::new()
+            None
         };
         
-        // 只在非测试环境创建目录
-        #[cfg(not(test))]
-        {
-            std::fs::create_dir_all(&cache_dir)?;
-            std::fs::create_dir_all(&install_dir)?;
-        }
+        let config = Config::load_project_or_default()?;
+        let repositories = crate::repository::project_repositories(&config);
+        let mirror_url = config.resolved_mirror_url();
+        let cache_policy = config.cache_config();
+        let tlmgr = if global {
+            config.resolved_texlive_path().as_deref().and_then(crate::texlive::TeXLiveManager::user_tlmgr)
+        } else {
+            None
+        };
         
         Ok(Self {
             global,
             config,
             cache_dir,
             install_dir,
+            texmf_root,
+            repositories,
+            mirror_url,
+            cache_policy,
+            tlmgr,
         })
     }
     
-    pub async fn install(&self, package_name: &str) -> Result<()> {
+    /// Install a package, returning the files written (none if it was already installed)
+    pub async fn install(&self, package_name: &str) -> Result<Vec<PathBuf>> {
         println!("Resolving package: {}", package_name);
         
         // Check if package is already installed
         if self.is_installed(package_name).await? {
             println!("Package {} is already installed", package_name);
-            return Ok(());
+            return Ok(Vec::new());
+        }
+        if let Some(tlmgr) = &self.tlmgr {
+            run_tlmgr(tlmgr, &["install", package_name])?;
+            let revision = tlmgr_revision(tlmgr, package_name);
+            self.update_registry(|registry| {
+                registry.insert(package_name.to_string(), RegistryEntry::Version(revision));
+            })?;
+            println!("Successfully installed {} with {}", package_name, tlmgr.display());
+            return Ok(Vec::new());
         }
         
         // Get package information
         let package_info = self.fetch_package_info(package_name).await?;
+        Self::ensure_unpackable(&package_info)?;
         
-        // Download package
+        // Download package (or reuse the cached archive)
         let package_path = self.download_package(&package_info).await?;
         
         // Extract and install package
-        self.extract_package(&package_path, &package_info).await?;
+        let files = self.unpack_archive(&package_path, &package_info)?;
         
         // Update local package registry
-        self.register_package(&package_info).await?;
+        self.register_package(&package_info, &files).await?;
+        self.refresh_filename_database();
         
         println!("Successfully installed {}", package_name);
-        Ok(())
+        Ok(files)
+    }
+    
+    /// Work out what `install` would do; None if the package is already installed
+    pub async fn plan_install(&self, package_name: &str) -> Result<Option<InstallStep>> {
+        if self.is_installed(package_name).await? {
+            return Ok(None);
+        }
+        if let Some(tlmgr) = &self.tlmgr {
+            let info = PackageInfo { name: package_name.to_string(), repository: "tlmgr".to_string(), ..Default::default() };
+            return Ok(Some(InstallStep { info, cached: false, dependencies: Vec::new(), files: None, tlmgr: Some(tlmgr.clone()) }));
+        }
+        let info = self.fetch_package_info(package_name).await?;
+        Self::ensure_unpackable(&info)?;
+        
+        let package_path = self.archive_path(&info);
+        let cached = Self::is_reusable(&package_path, &info);
+        let files = if cached {
+            Some(
+                Self::archive_files(&package_path)?
+                    .iter()
+                    .filter_map(|entry_path| self.entry_destination(&info.name, entry_path))
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        
+        let mut dependencies = Vec::new();
+        for dependency in &info.dependencies {
+            dependencies.push((dependency.clone(), self.is_installed(dependency).await?));
+        }
+        
+        Ok(Some(InstallStep { info, cached, dependencies, files, tlmgr: None }))
+    }
+    
+    /// Files that `remove` would delete; None if the package is not installed
+    pub async fn plan_remove(&self, package_name: &str) -> Result<Option<Vec<PathBuf>>> {
+        if !self.is_installed(package_name).await? {
+            return Ok(None);
+        }
+        Ok(Some(self.installed_files(package_name)))
+    }
+    
+    /// The installed version of a package and the latest one available
+    pub async fn plan_update(&self, package_name: &str) -> Result<(String, PackageInfo)> {
+        let current_version = self.get_installed_version(package_name).await?;
+        let package_info = self.fetch_package_info(package_name).await?;
+        Ok((current_version, package_info))
     }
     
     pub async fn remove(&self, package_name: &str) -> Result<()> {
@@ -102,15 +393,29 @@ impl PackageManager {
             println!("Package {} is not installed", package_name);
             return Ok(());
         }
+        if let Some(tlmgr) = &self.tlmgr {
+            run_tlmgr(tlmgr, &["remove", package_name])?;
+            self.unregister_package(package_name).await?;
+            println!("Successfully removed {} with {}", package_name, tlmgr.display());
+            return Ok(());
+        }
 
-        // Remove package file directly from packages directory
-        let sty_file = self.install_dir.join(format!("{}.sty", package_name));
-        if sty_file.exists() {
-            std::fs::remove_file(&sty_file)?;
+        for file in self.installed_files(package_name) {
+            std::fs::remove_file(&file)?;
+            // Drop the package's directories once they are empty
+            let mut dir = file.parent();
+            while let Some(parent) = dir.filter(|parent| parent.starts_with(self.install_root()) && *parent != self.install_root()) {
+                if std::fs::read_dir(parent)?.next().is_some() {
+                    break;
+                }
+                std::fs::remove_dir(parent)?;
+                dir = parent.parent();
+            }
         }
 
         // Update package registry
         self.unregister_package(package_name).await?;
+        self.refresh_filename_database();
         
         println!("Successfully removed {}", package_name);
         Ok(())
@@ -119,6 +424,18 @@ impl PackageManager {
     pub async fn update(&self, package_name: &str) -> Result<()> {
         // Check current version
         let current_version = self.get_installed_version(package_name).await?;
+        if let Some(tlmgr) = &self.tlmgr {
+            run_tlmgr(tlmgr, &["update", package_name])?;
+            let revision = tlmgr_revision(tlmgr, package_name);
+            if revision == current_version {
+                println!("{} is already up to date", package_name);
+            } else {
+                self.update_registry(|registry| {
+                    registry.insert(package_name.to_string(), RegistryEntry::Version(revision));
+                })?;
+            }
+            return Ok(());
+        }
         
         // Get latest version info
         let package_info = self.fetch_package_info(package_name).await?;
@@ -130,7 +447,10 @@ impl PackageManager {
         
         // Remove old version and install new one
         self.remove(package_name).await?;
-        self.install(package_name).await?;
+        let files = self.install(package_name).await?;
+        for file in self.unfindable_files(&files).unwrap_or_default() {
+            println!("Warning: kpsewhich cannot find {} after the update", file.display());
+        }
         
         Ok(())
     }
@@ -148,62 +468,109 @@ impl PackageManager {
     }
     
     pub async fn list_installed(&self) -> Result<Vec<(String, String)>> {
-        let registry_path = self.install_dir.join("registry.json");
-        
-        if !registry_path.exists() {
-            return Ok(Vec::new());
-        }
-        
-        let content = std::fs::read_to_string(&registry_path)?;
-        let registry: HashMap<String, String> = serde_json::from_str(&content)?;
-        
-        Ok(registry.into_iter().collect())
+        Ok(self
+            .read_registry()?
+            .into_iter()
+            .map(|(name, entry)| (name, entry.version().to_string()))
+            .collect())
+    }
+    
+    /// Installed packages with their size and the packages they load, by name
+    pub async fn installed_packages(&self) -> Result<Vec<InstalledPackage>> {
+        let parser = TeXParser::new()?;
+        let mut packages: Vec<InstalledPackage> = self
+            .list_installed()
+            .await?
+            .into_iter()
+            .map(|(name, version)| InstalledPackage {
+                size: self
+                    .installed_files(&name)
+                    .iter()
+                    .filter_map(|file| std::fs::metadata(file).ok())
+                    .map(|metadata| metadata.len())
+                    .sum(),
+                requires: self.requires(&parser, &name),
+                name,
+                version,
+            })
+            .collect();
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packages)
     }
     
-    pub async fn search(&self, query: &str) -> Result<Vec<Package>> {
-        // This is a placeholder implementation
-        // In a real implementation, this would query package repositories
-        let mut results = Vec::new();
+    pub async fn search(&self, query: &crate::query::SearchQuery) -> Result<Vec<Package>> {
+        let results = crate::repository::search(&self.repositories, query).await;
         
-        // Simulate some search results
-        if query.contains("ams") {
-            results.push(Package {
-                name: "amsmath".to_string(),
-                version: "2.17".to_string(),
-                description: "AMS mathematical facilities for LaTeX".to_string(),
-                dependencies: vec![],
-                files: vec!["amsmath.sty".to_string()],
-                size: 45672,
-            });
+        Ok(results
+            .into_iter()
+            .map(|package| Package {
+                name: package.name,
+                version: package.version,
+                description: package.description,
+                dependencies: package.dependencies,
+                files: vec![],
+                size: package.size,
+                repository: package.repository,
+            })
+            .collect())
+    }
+    
+    pub async fn get_package_info(&self, package_name: &str) -> Result<PackageInfo> {
+        if let Some(package) = crate::repository::find_package(&self.repositories, &self.mirror_url, package_name).await {
+            return Self::package_info_from(package);
         }
-        
-        if query.contains("geometry") {
-            results.push(Package {
-                name: "geometry".to_string(),
-                version: "5.9".to_string(),
-                description: "Flexible and complete interface to document dimensions".to_string(),
-                dependencies: vec!["keyval".to_string()],
-                files: vec!["geometry.sty".to_string()],
-                size: 78234,
-            });
+        // A repository may publish a style under its artifact name; otherwise
+        // it comes from the package that ships it
+        if let Some(style) = StyleArtifact::parse(package_name) {
+            return self.style_package_info(package_name, &style).await;
         }
-        
-        Ok(results)
+        let names: Vec<&str> = self.repositories.iter().map(|r| r.name.as_str()).collect();
+        anyhow::bail!("Package '{}' not found in any repository ({})", package_name, names.join(", "))
     }
     
-    pub async fn get_package_info(&self, package_name: &str) -> Result<PackageInfo> {
-        // Placeholder implementation
+    /// Install information for a style artifact: the package shipping the
+    /// style file, according to the mirror's file index or else by name
+    async fn style_package_info(&self, package_name: &str, style: &StyleArtifact) -> Result<PackageInfo> {
+        let mut texlive = crate::texlive::TeXLiveManager::new();
+        let owner = match texlive.fetch_remote_file_index(&self.mirror_url).await {
+            Ok(()) => texlive.find_file_ignoring_case(&style.file_name()),
+            Err(_) => None,
+        };
+        let candidates = match owner {
+            Some((file_name, package)) => vec![(file_name.to_string(), package.to_string())],
+            None => style.candidate_packages().into_iter().map(|package| (style.file_name(), package)).collect(),
+        };
+        for (file_name, provider) in candidates {
+            if let Some(package) = crate::repository::find_package(&self.repositories, &self.mirror_url, &provider).await {
+                println!("{} is provided by package '{}'", file_name, provider);
+                let mut info = Self::package_info_from(package)?;
+                info.name = package_name.to_string();
+                info.style_file = Some(file_name);
+                return Ok(info);
+            }
+        }
+        anyhow::bail!("No repository has a package providing {}", style.file_name())
+    }
+    
+    fn package_info_from(package: RemotePackage) -> Result<PackageInfo> {
+        let download_url = package
+            .download_url
+            .ok_or_else(|| anyhow::anyhow!("Repository '{}' has no download for {}", package.repository, package.name))?;
         Ok(PackageInfo {
-            name: package_name.to_string(),
-            version: "1.0.0".to_string(),
-            description: format!("Description for {}", package_name),
-            dependencies: vec![],
-            download_url: format!("https://ctan.org/tex-archive/macros/latex/contrib/{}.tar.gz", package_name),
-            checksum: "sha256:placeholder".to_string(),
+            name: package.name,
+            version: package.version,
+            description: package.description,
+            dependencies: package.dependencies,
+            download_url,
+            checksum: package.checksum.unwrap_or_default(),
+            repository: package.repository,
+            size: package.size,
+            style_file: None,
         })
     }
     
     pub async fn clean_cache(&self) -> Result<()> {
+        let _lock = crate::lock::exclusive_async(&self.cache_lock_path()).await?;
         if self.cache_dir.exists() {
             std::fs::remove_dir_all(&self.cache_dir)?;
             std::fs::create_dir_all(&self.cache_dir)?;
@@ -211,6 +578,55 @@ impl PackageManager {
         Ok(())
     }
     
+    /// Run garbage collection on the package cache according to the cache policy
+    pub async fn collect_cache_garbage(&self) -> Result<crate::cache::GcReport> {
+        let _lock = crate::lock::exclusive_async(&self.cache_lock_path()).await?;
+        crate::cache::collect_garbage(&self.cache_dir, &self.cache_policy)
+    }
+    
+    /// Location, number of archives and total size of the package cache
+    pub fn cache_usage(&self) -> Result<(&Path, usize, u64)> {
+        let (files, bytes) = crate::cache::usage(&self.cache_dir)?;
+        Ok((&self.cache_dir, files, bytes))
+    }
+    
+    /// Installed packages that are neither in `used` nor loaded, directly or
+    /// indirectly, by an installed package that is
+    pub async fn find_unused(&self, used: &[String]) -> Result<Vec<String>> {
+        let parser = TeXParser::new()?;
+        let installed: Vec<String> = self.list_installed().await?.into_iter().map(|(name, _)| name).collect();
+        let mut unused = unused_packages(&installed, used, |name| self.requires(&parser, name));
+        unused.sort();
+        Ok(unused)
+    }
+    
+    /// Packages loaded by the .sty of an installed package
+    fn requires(&self, parser: &TeXParser, package_name: &str) -> Vec<String> {
+        let sty_file = self.file_path(package_name, format!("{}.sty", package_name).as_ref());
+        parser
+            .parse_file(&sty_file)
+            .map(|dependencies| TeXParser::get_unique_packages(&dependencies))
+            .unwrap_or_default()
+    }
+    
+    /// Apply the cache settings after installing: empty the cache when
+    /// `cache.keep = false`, otherwise trim it according to the GC policy
+    pub async fn apply_cache_policy(&self) -> Result<()> {
+        if !self.cache_policy.keep {
+            println!("Cleaning package cache (cache.keep = false)...");
+            self.clean_cache().await?;
+            println!("✓ Package cache cleaned");
+            return Ok(());
+        }
+        
+        let report = self.collect_cache_garbage().await?;
+        if report.removed_files > 0 {
+            println!("✓ Removed {} old archives from the package cache ({})",
+                report.removed_files, crate::cache::format_size(report.freed_bytes));
+        }
+        Ok(())
+    }
+    
     /// Check if a package is installed locally
     pub async fn is_package_installed(&self, package_name: &str) -> Result<bool> {
         self.is_installed(package_name).await
@@ -218,25 +634,13 @@ impl PackageManager {
     
     // Helper methods
     async fn is_installed(&self, package_name: &str) -> Result<bool> {
-        let registry_path = self.install_dir.join("registry.json");
-        
-        if !registry_path.exists() {
-            return Ok(false);
-        }
-        
-        let content = std::fs::read_to_string(&registry_path)?;
-        let registry: HashMap<String, String> = serde_json::from_str(&content)?;
-        
-        Ok(registry.contains_key(package_name))
+        Ok(self.read_registry()?.contains_key(package_name))
     }
     
     async fn get_installed_version(&self, package_name: &str) -> Result<String> {
-        let registry_path = self.install_dir.join("registry.json");
-        let content = std::fs::read_to_string(&registry_path)?;
-        let registry: HashMap<String, String> = serde_json::from_str(&content)?;
-        
-        registry.get(package_name)
-            .cloned()
+        self.read_registry()?
+            .get(package_name)
+            .map(|entry| entry.version().to_string())
             .ok_or_else(|| anyhow::anyhow!("Package not found"))
     }
     
@@ -245,186 +649,445 @@ impl PackageManager {
         self.get_package_info(package_name).await
     }
     
+    /// Lock file guarding the package cache; kept next to the cache directory
+    /// so that cleaning the cache does not delete it
+    fn cache_lock_path(&self) -> PathBuf {
+        self.cache_dir.with_extension("lock")
+    }
+    
+    /// Where the archive of a package is cached
+    fn archive_path(&self, package_info: &PackageInfo) -> PathBuf {
+        let extension = ARCHIVE_FORMATS
+            .iter()
+            .chain([".zip"].iter())
+            .find(|extension| package_info.download_url.ends_with(*extension))
+            .copied()
+            .unwrap_or(".tar.xz");
+        match package_info.version.as_str() {
+            "" => self.cache_dir.join(format!("{}{}", package_info.name, extension)),
+            version => self.cache_dir.join(format!("{}-{}{}", package_info.name, version, extension)),
+        }
+    }
+    
+    /// Whether a cached archive may be installed instead of downloading it
+    /// again. Without a checksum the archive cannot be told apart from a newer
+    /// one under the same name, so it is only reused shortly after the download.
+    fn is_reusable(package_path: &Path, package_info: &PackageInfo) -> bool {
+        if !package_path.exists() || !Self::checksum_matches(package_path, package_info) {
+            return false;
+        }
+        Self::has_checksum(package_info)
+            || std::fs::metadata(package_path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age < UNVERIFIED_ARCHIVE_MAX_AGE)
+    }
+    
+    fn has_checksum(package_info: &PackageInfo) -> bool {
+        package_info.checksum.contains(':')
+    }
+    
     async fn download_package(&self, package_info: &PackageInfo) -> Result<PathBuf> {
-        let filename = format!("{}-{}.tar.gz", package_info.name, package_info.version);
-        let package_path = self.cache_dir.join(&filename);
+        let package_path = self.archive_path(package_info);
+        
+        if Self::is_reusable(&package_path, package_info) {
+            println!("Using cached {}", package_path.file_name().unwrap_or_default().to_string_lossy());
+            // The age of an archive without a checksum is its download time
+            if Self::has_checksum(package_info) {
+                crate::cache::touch(&package_path)?;
+            }
+            return Ok(package_path);
+        }
+        
+        // Download into a file of its own so concurrent tpmgr processes do not
+        // wait for it; garbage collection skips the subdirectory
+        let partial_dir = self.cache_dir.join("partial");
+        std::fs::create_dir_all(&partial_dir)?;
+        let partial = tempfile::NamedTempFile::new_in(&partial_dir)?;
         
-        // Simulate download (in real implementation, use reqwest)
-        std::fs::write(&package_path, b"placeholder package data")?;
+        // http(s) mirrors are fetched directly, rsync-only mirrors with rsync
+        crate::download::fetch(&package_info.download_url, partial.path()).await?;
+        if !Self::checksum_matches(partial.path(), package_info) {
+            return Err(TpmgrError::IntegrityCheck { name: package_info.name.clone() }.into());
+        }
         
+        // Only moving the archive into the cache waits for cleaning and garbage collection
+        let _lock = crate::lock::exclusive_async(&self.cache_lock_path()).await?;
+        partial.persist(&package_path)?;
         Ok(package_path)
     }
     
-    async fn extract_package(&self, _package_path: &PathBuf, package_info: &PackageInfo) -> Result<()> {
-        // Create package file directly in packages directory (no subdirectory)
-        let sty_file = self.install_dir.join(format!("{}.sty", package_info.name));
-        let package_content = self.generate_package_content(&package_info.name);
-        std::fs::write(&sty_file, package_content)?;
-        
-        // Setup package environment
-        self.setup_package_environment(&package_info.name).await?;
+    /// Whether the archive matches its checksum; archives without a known
+    /// checksum always match
+    fn checksum_matches(package_path: &Path, package_info: &PackageInfo) -> bool {
+        use sha2::{Digest, Sha256, Sha512};
         
+        let Some((algorithm, expected)) = package_info.checksum.split_once(':') else {
+            return true;
+        };
+        let Ok(content) = std::fs::read(package_path) else {
+            return false;
+        };
+        let actual = match algorithm {
+            "sha256" => format!("{:x}", Sha256::digest(&content)),
+            "sha512" => format!("{:x}", Sha512::digest(&content)),
+            _ => return true,
+        };
+        actual.eq_ignore_ascii_case(expected)
+    }
+    
+    /// Fail before downloading when the archive is in a format tpmgr cannot unpack
+    fn ensure_unpackable(package_info: &PackageInfo) -> Result<()> {
+        if !is_unpackable(&package_info.download_url) {
+            anyhow::bail!(
+                "Cannot install {} from {}: only {} archives can be unpacked",
+                package_info.name,
+                package_info.download_url,
+                ARCHIVE_FORMATS.join(", ")
+            );
+        }
         Ok(())
     }
     
-    async fn register_package(&self, package_info: &PackageInfo) -> Result<()> {
-        let registry_path = self.install_dir.join("registry.json");
-        
-        let mut registry: HashMap<String, String> = if registry_path.exists() {
-            let content = std::fs::read_to_string(&registry_path)?;
-            serde_json::from_str(&content)?
+    /// The tar archive at `package_path`, decompressed according to its extension
+    fn open_archive(package_path: &Path) -> Result<tar::Archive<Box<dyn std::io::Read>>> {
+        let file = std::fs::File::open(package_path)?;
+        let name = package_path.to_string_lossy();
+        let reader: Box<dyn std::io::Read> = if name.ends_with(".tar.xz") {
+            Box::new(xz2::read::XzDecoder::new(file))
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Box::new(flate2::read::GzDecoder::new(file))
         } else {
-            HashMap::new()
+            anyhow::bail!("Cannot unpack {}: only {} archives are supported", package_path.display(), ARCHIVE_FORMATS.join(", "));
         };
+        Ok(tar::Archive::new(reader))
+    }
+    
+    /// Paths inside the archive of the files `unpack_archive` considers
+    fn archive_files(package_path: &Path) -> Result<Vec<PathBuf>> {
+        let mut archive = Self::open_archive(package_path)?;
+        let mut files = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let path = entry.path()?.into_owned();
+            if entry.header().entry_type().is_file() && is_package_file(&path) {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+    
+    /// Unpack the files of a .tar.xz or .tar.gz archive directly into the packages directory
+    fn unpack_archive(&self, package_path: &Path, package_info: &PackageInfo) -> Result<Vec<PathBuf>> {
+        let mut archive = Self::open_archive(package_path)?;
+        let mut files = Vec::new();
+        let style = StyleArtifact::parse(&package_info.name);
         
-        registry.insert(package_info.name.clone(), package_info.version.clone());
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() || !is_package_file(&entry.path()?) {
+                continue;
+            }
+            let entry_path = entry.path()?.into_owned();
+            let Some(path) = self.entry_destination(&package_info.name, &entry_path) else {
+                continue;
+            };
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            entry.unpack(&path)?;
+            files.push(path);
+        }
+        if let (Some(style), true) = (&style, files.is_empty()) {
+            anyhow::bail!("The archive of {} has no {}", package_info.name, style.file_name());
+        }
+        if files.is_empty() {
+            anyhow::bail!("The archive of {} contains no files to install", package_info.name);
+        }
         
-        let content = serde_json::to_string_pretty(&registry)?;
-        std::fs::write(&registry_path, content)?;
+        let destination = self.texmf_root.as_ref().unwrap_or(&self.install_dir);
+        println!("Package {} unpacked {} files to: {}", package_info.name, files.len(), destination.display());
+        Ok(files)
+    }
+    
+    /// Where an archive entry of a package is unpacked, None if it is left out.
+    /// Global installs keep the paths of archives in TDS layout (TeX Live's),
+    /// so their files land where the distribution's own copies would.
+    fn entry_destination(&self, package_name: &str, entry_path: &Path) -> Option<PathBuf> {
+        let file_name = entry_path.file_name()?;
+        // A style artifact takes only the style file from the package
+        if let Some(style) = StyleArtifact::parse(package_name) {
+            return style.matches(file_name).then(|| self.file_path(package_name, file_name));
+        }
+        match &self.texmf_root {
+            Some(texmf_root) if is_tds_path(entry_path) => Some(texmf_root.join(entry_path)),
+            _ => Some(self.file_path(package_name, file_name)),
+        }
+    }
+    
+    /// Where a file of a package is installed: flat in the project's packages
+    /// directory, or in its TDS directory of the TEXMF tree for global installs
+    fn file_path(&self, package_name: &str, file_name: &std::ffi::OsStr) -> PathBuf {
+        match &self.texmf_root {
+            Some(texmf_root) => texmf_root.join(tds_dir(package_name, file_name)).join(file_name),
+            None => self.install_dir.join(file_name),
+        }
+    }
+    
+    /// The directory registered file paths are relative to
+    fn install_root(&self) -> &Path {
+        self.texmf_root.as_deref().unwrap_or(&self.install_dir)
+    }
+    
+    /// Files of an installed package: those the registry records, or for
+    /// packages registered without a file list, the files found in its place
+    fn installed_files(&self, package_name: &str) -> Vec<PathBuf> {
+        if let Some(RegistryEntry::Installed { files, .. }) = self.read_registry().ok().and_then(|mut registry| registry.remove(package_name)) {
+            return files
+                .iter()
+                .map(|file| file.split('/').fold(self.install_root().to_path_buf(), |path, part| path.join(part)))
+                .filter(|path| path.is_file())
+                .collect();
+        }
+        let Some(texmf_root) = &self.texmf_root else {
+            if let Some(style) = StyleArtifact::parse(package_name) {
+                return std::fs::read_dir(&self.install_dir)
+                    .map(|entries| entries.flatten().map(|entry| entry.path()).collect::<Vec<_>>())
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|path| path.is_file() && path.file_name().is_some_and(|name| style.matches(name)))
+                    .collect();
+            }
+            let sty_file = self.install_dir.join(format!("{}.sty", package_name));
+            return if sty_file.exists() { vec![sty_file] } else { Vec::new() };
+        };
         
-        Ok(())
+        let mut files: Vec<PathBuf> = TDS_DIRS
+            .iter()
+            .map(|(_, dir)| texmf_root.join(dir).join(package_name))
+            .chain(std::iter::once(texmf_root.join(DEFAULT_TDS_DIR).join(package_name)))
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+    
+    /// Rebuild the ls-R database of the TEXMF tree after a global change.
+    /// Trees without one (TEXMFHOME usually) are searched directly by kpathsea.
+    fn refresh_filename_database(&self) {
+        let Some(texmf_root) = self.texmf_root.as_ref().filter(|root| root.join("ls-R").is_file()) else {
+            return;
+        };
+        match std::process::Command::new("mktexlsr").arg(texmf_root).output() {
+            Ok(output) if output.status.success() => {}
+            _ => println!("Warning: Failed to update {}; run 'mktexlsr {}'", texmf_root.join("ls-R").display(), texmf_root.display()),
+        }
+    }
+    
+    async fn register_package(&self, package_info: &PackageInfo, files: &[PathBuf]) -> Result<()> {
+        let files = files
+            .iter()
+            .filter_map(|file| file.strip_prefix(self.install_root()).ok())
+            .map(|file| file.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"))
+            .collect();
+        let entry = RegistryEntry::Installed { version: package_info.version.clone(), files };
+        self.update_registry(|registry| {
+            registry.insert(package_info.name.clone(), entry);
+        })
     }
     
     async fn unregister_package(&self, package_name: &str) -> Result<()> {
+        self.update_registry(|registry| {
+            registry.remove(package_name);
+        })
+    }
+    
+    /// Installed packages by name with their version, read under a shared lock
+    fn read_registry(&self) -> Result<HashMap<String, RegistryEntry>> {
         let registry_path = self.install_dir.join("registry.json");
-        
         if !registry_path.exists() {
-            return Ok(());
+            return Ok(HashMap::new());
         }
-        
-        let content = std::fs::read_to_string(&registry_path)?;
-        let mut registry: HashMap<String, String> = serde_json::from_str(&content)?;
-        
-        registry.remove(package_name);
-        
-        let content = serde_json::to_string_pretty(&registry)?;
-        std::fs::write(&registry_path, content)?;
-        
-        Ok(())
+        let _lock = crate::lock::shared(&self.install_dir.join("registry.lock"))?;
+        Self::load_registry(&registry_path)
     }
-
-    /// Setup package environment for LaTeX compilation
-    /// Instead of creating symlinks, we'll set TEXINPUTS environment variable
-    async fn setup_package_environment(&self, package_name: &str) -> Result<()> {
-        let sty_file = self.install_dir.join(format!("{}.sty", package_name));
-        if !sty_file.exists() {
-            return Err(anyhow::anyhow!("Package file not found: {}", sty_file.display()));
-        }
-
-        // The TEXINPUTS environment variable will be set by the compile command
-        // This method just verifies the package file exists
-        println!("Package {} is available at: {}", package_name, sty_file.display());
+    
+    /// Read, change and write back the registry under an exclusive lock, so
+    /// concurrent tpmgr processes do not lose each other's changes
+    fn update_registry(&self, change: impl FnOnce(&mut HashMap<String, RegistryEntry>)) -> Result<()> {
+        let registry_path = self.install_dir.join("registry.json");
+        let _lock = crate::lock::exclusive(&self.install_dir.join("registry.lock"))?;
+        let mut registry = Self::load_registry(&registry_path)?;
+        change(&mut registry);
         
+        // Readers without a lock (older tpmgr versions) never see a partial file
+        let temp_path = registry_path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_string_pretty(&registry)?)?;
+        std::fs::rename(&temp_path, &registry_path)?;
         Ok(())
     }
     
-    /// Get the TEXINPUTS path for this package manager
-    /// This should be used by the compile command to set environment variables
+    fn load_registry(registry_path: &Path) -> Result<HashMap<String, RegistryEntry>> {
+        if !registry_path.exists() {
+            return Ok(HashMap::new());
+        }
+        let content = std::fs::read_to_string(registry_path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
     pub fn get_texinputs_path(&self) -> String {
         // Simply return the packages directory path since all .sty files are directly in it
         self.install_dir.to_string_lossy().to_string()
     }
+    
+    /// The search path variable kpathsea looks a file up in (BSTINPUTS for
+    /// .bst, TEXINPUTS otherwise) and its value with the installed packages
+    pub fn search_path_for(&self, file: &Path) -> (&'static str, String) {
+        let variable = if file.extension().is_some_and(|extension| extension == "bst") { "BSTINPUTS" } else { "TEXINPUTS" };
+        (variable, crate::environment::search_path(variable, Path::new(&self.get_texinputs_path())))
+    }
+    
+    /// Installed TeX input files (.sty, .cls, ...) and bibliography styles
+    /// that kpsewhich cannot find with the packages directory on TEXINPUTS and
+    /// BSTINPUTS. Fails when kpsewhich is not available.
+    pub fn unfindable_files(&self, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        const TEX_INPUT_EXTENSIONS: &[&str] = &["sty", "cls", "clo", "def", "cfg", "fd", "bst", "bbx", "cbx", "lbx"];
+        
+        let mut unfindable = Vec::new();
+        for file in files {
+            let is_tex_input = file.extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| TEX_INPUT_EXTENSIONS.contains(&extension))
+                .unwrap_or(false);
+            let Some(file_name) = file.file_name().filter(|_| is_tex_input) else {
+                continue;
+            };
+            let (variable, search_path) = self.search_path_for(file);
+            let output = std::process::Command::new("kpsewhich")
+                .env(variable, search_path)
+                .arg(file_name)
+                .output()
+                .map_err(|e| anyhow::anyhow!("Failed to run kpsewhich: {}", e))?;
+            if !output.status.success() || String::from_utf8_lossy(&output.stdout).trim().is_empty() {
+                unfindable.push(file.clone());
+            }
+        }
+        Ok(unfindable)
+    }
+}
+
+/// Installed packages not reachable from `used` through the packages each
+/// installed package `requires`
+fn unused_packages(installed: &[String], used: &[String], requires: impl Fn(&str) -> Vec<String>) -> Vec<String> {
+    let mut needed = HashSet::new();
+    let mut pending = used.to_vec();
+    while let Some(name) = pending.pop() {
+        if installed.contains(&name) && !needed.contains(&name) {
+            pending.extend(requires(&name));
+        }
+        needed.insert(name);
+    }
+    installed.iter().filter(|name| !needed.contains(*name)).cloned().collect()
+}
+
+/// Run the tlmgr of a per-user distribution, failing with what it printed
+fn run_tlmgr(tlmgr: &Path, args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new(tlmgr).args(args).output()?;
+    if !output.status.success() {
+        anyhow::bail!("tlmgr {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// The installed revision of a package as tlmgr reports it, e.g. "r71234"
+/// ("tlmgr" when it does not say)
+fn tlmgr_revision(tlmgr: &Path, package_name: &str) -> String {
+    run_tlmgr(tlmgr, &["info", "--only-installed", "--data", "revision", package_name])
+        .ok()
+        .and_then(|output| output.lines().next().map(str::trim).filter(|revision| !revision.is_empty()).map(|revision| format!("r{}", revision)))
+        .unwrap_or_else(|| "tlmgr".to_string())
+}
+
+/// Sort installed packages by "name" or "size" (largest first)
+pub fn sort_installed(packages: &mut [InstalledPackage], key: &str) {
+    match key {
+        "size" => packages.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name))),
+        _ => packages.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+}
+
+/// Lines of a dependency tree of `packages`, in their order. Roots are the
+/// packages no other listed package requires; requirements are looked up in
+/// `installed`, and ones tpmgr did not install are shown as leaves.
+pub fn dependency_tree(packages: &[InstalledPackage], installed: &[InstalledPackage]) -> Vec<String> {
+    let required: HashSet<&str> = packages
+        .iter()
+        .flat_map(|package| package.requires.iter().map(String::as_str))
+        .collect();
+    let mut roots: HashSet<&str> = packages
+        .iter()
+        .map(|package| package.name.as_str())
+        .filter(|name| !required.contains(name))
+        .collect();
+    // Packages that only appear in cycles still need a root
+    let mut reachable = HashSet::new();
+    for root in &roots {
+        collect_reachable(root, installed, &mut reachable);
+    }
+    for package in packages {
+        if !reachable.contains(package.name.as_str()) {
+            collect_reachable(&package.name, installed, &mut reachable);
+            roots.insert(&package.name);
+        }
+    }
 
-    /// Generate appropriate package content based on package name
-    fn generate_package_content(&self, package_name: &str) -> String {
-        let mut content = self.get_package_header(package_name);
-        content.push_str(&self.get_package_specific_content(package_name));
-        content
-    }
-
-    fn get_package_header(&self, package_name: &str) -> String {
-        format!(
-            r#"% Simple {} package placeholder for testing
-\ProvidesPackage{{{}}}[2025/08/06 Test {} package]
-
-"#,
-            package_name, package_name, package_name
-        )
-    }
-
-    fn get_package_specific_content(&self, package_name: &str) -> String {
-        match package_name {
-            "inputenc" => self.get_basic_package_with_options() + 
-                r#"
-% Provide basic functionality
-\def\@inpenc@test#1{\relax}"#,
-            
-            "fontenc" | "geometry" | "fancyhdr" | "xcolor" | "hyperref" => 
-                self.get_basic_package_with_options() + &self.get_package_commands(package_name),
-            
-            "amssymb" => r#"% Define checkmark symbol
-\def\checkmark{$\surd$}"#.to_string(),
-            
-            "graphicx" => self.get_basic_package_with_options() + 
-                r#"
-% Define includegraphics command (placeholder)
-\newcommand{\includegraphics}[2][]{\textbf{[Image: #2]}}"#,
-            
-            "minted" => r#"% Define a simple minted environment for testing
-\newenvironment{minted}[1]{%
-    \begin{quote}%
-    \ttfamily%
-    \textbf{Code in #1:}\\%
-}{%
-    \end{quote}%
+    let mut lines = Vec::new();
+    for root in packages.iter().filter(|package| roots.contains(package.name.as_str())) {
+        lines.push(format!("{} ({})", root.name, root.version));
+        let mut path = vec![root.name.as_str()];
+        push_requirements(root, installed, "", &mut path, &mut lines);
+    }
+    lines
 }
 
-% Define other minted commands
-\newcommand{\mint}[2]{\texttt{#2}}
-\newcommand{\mintinline}[2]{\texttt{#2}}"#.to_string(),
-            
-            "tikz" => r#"% Define basic tikz environment
-\newenvironment{tikzpicture}{\begin{center}\textbf{[TikZ Picture]}}{\end{center}}
-\newcommand{\draw}[1]{\relax}"#.to_string(),
-            
-            "pgfplots" => r#"% Require tikz
-\RequirePackage{tikz}
-
-% Define basic commands
-\newcommand{\pgfplotsset}[1]{\relax}"#.to_string(),
-            
-            "subcaption" => r#"% Define subcaption environment
-\newenvironment{subfigure}[1]{\begin{minipage}{#1}}{\end{minipage}}"#.to_string(),
-            
-            "lipsum" => r#"% Define lipsum command
-\newcommand{\lipsum}[1][]{%
-    Lorem ipsum dolor sit amet, consectetur adipiscing elit. Sed do eiusmod tempor incididunt ut labore et dolore magna aliqua.%
-}"#.to_string(),
-            
-            _ => self.get_basic_package_with_options(),
-        }
-    }
-
-    fn get_basic_package_with_options(&self) -> String {
-        r#"% Accept and ignore options
-\DeclareOption*{\relax}
-\ProcessOptions\relax"#.to_string()
-    }
-
-    fn get_package_commands(&self, package_name: &str) -> String {
-        match package_name {
-            "geometry" => "\n% Provide basic geometry commands\n\\newcommand{\\geometry}[1]{\\relax}".to_string(),
-            "fancyhdr" => r#"
-% Define basic commands
-\newcommand{\fancyhf}[1]{\relax}
-\newcommand{\fancyhead}[1]{\relax}
-\newcommand{\fancyfoot}[1]{\relax}"#.to_string(),
-            "xcolor" => r#"
-% Define color commands
-\newcommand{\textcolor}[2]{#2}
-\newcommand{\colorbox}[2]{#2}
-\newcommand{\fcolorbox}[3]{#3}"#.to_string(),
-            "hyperref" => r#"
-% Define hyperref commands
-\newcommand{\href}[2]{#2}
-\newcommand{\url}[1]{\texttt{#1}}"#.to_string(),
-            "url" => "\n% Define url command\n\\newcommand{\\url}[1]{\\texttt{#1}}".to_string(),
-            "natbib" => r#"
-% Define citation commands
-\newcommand{\cite}[1]{[#1]}
-\newcommand{\citep}[1]{(#1)}
-\newcommand{\citet}[1]{#1}"#.to_string(),
-            _ => String::new(),
+fn collect_reachable<'a>(name: &'a str, installed: &'a [InstalledPackage], reachable: &mut HashSet<&'a str>) {
+    if !reachable.insert(name) {
+        return;
+    }
+    if let Some(package) = installed.iter().find(|package| package.name == name) {
+        for requirement in &package.requires {
+            collect_reachable(requirement, installed, reachable);
+        }
+    }
+}
+
+fn push_requirements<'a>(
+    package: &'a InstalledPackage,
+    installed: &'a [InstalledPackage],
+    indent: &str,
+    path: &mut Vec<&'a str>,
+    lines: &mut Vec<String>,
+) {
+    for (index, requirement) in package.requires.iter().enumerate() {
+        let last = index + 1 == package.requires.len();
+        let branch = if last { "└── " } else { "├── " };
+        let child = installed.iter().find(|candidate| &candidate.name == requirement);
+        match child {
+            Some(child) if path.contains(&child.name.as_str()) => {
+                lines.push(format!("{}{}{} ({}) (cycle)", indent, branch, child.name, child.version));
+            }
+            Some(child) => {
+                lines.push(format!("{}{}{} ({})", indent, branch, child.name, child.version));
+                let indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+                path.push(&child.name);
+                push_requirements(child, installed, &indent, path, lines);
+                path.pop();
+            }
+            None => lines.push(format!("{}{}{}", indent, branch, requirement)),
         }
     }
 }
@@ -433,6 +1096,35 @@ impl PackageManager {
 mod tests {
     use super::*;
 
+    /// Write a tar archive of the given entries, compressed by the extension of `path`
+    fn tar_archive(path: &Path, entries: &[(&str, &[u8])]) {
+        let file = std::fs::File::create(path).unwrap();
+        let writer: Box<dyn std::io::Write> = if path.to_string_lossy().ends_with(".tar.xz") {
+            Box::new(xz2::write::XzEncoder::new(file, 6))
+        } else {
+            Box::new(flate2::write::GzEncoder::new(file, flate2::Compression::default()))
+        };
+        let mut builder = tar::Builder::new(writer);
+        for (name, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        // Dropping the encoder finishes the compressed stream
+        drop(builder.into_inner().unwrap());
+    }
+
+    fn package_info(name: &str, url: &str) -> PackageInfo {
+        PackageInfo {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            download_url: url.to_string(),
+            repository: "lab".to_string(),
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn test_package_manager_basic() {
         // 测试 PackageManager 的基本创建和配置
@@ -445,22 +1137,241 @@ mod tests {
     }
 
     #[test]
-    fn test_package_content_generation() {
-        // 测试包内容生成功能（纯函数，无副作用）
-        let manager = PackageManager::new(false).unwrap();
+    fn test_unpack_tlnet_archive() {
+        let mut manager = PackageManager::new(false).unwrap();
+        assert_eq!(manager.get_texinputs_path(), "packages");
+
+        // TeX Live's archives are .tar.xz in TDS layout, with tlpkg/ metadata
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("siunitx-75310.tar.xz");
+        tar_archive(&archive_path, &[
+            ("tex/latex/siunitx/siunitx.sty", b"%tex"),
+            ("tex/latex/siunitx/siunitx-abbreviations.cfg", b"%tex"),
+            ("tlpkg/tlpobj/siunitx.tlpobj", b"%tex"),
+        ]);
+        assert_eq!(PackageManager::archive_files(&archive_path).unwrap(), vec![
+            PathBuf::from("tex/latex/siunitx/siunitx.sty"),
+            PathBuf::from("tex/latex/siunitx/siunitx-abbreviations.cfg"),
+        ]);
+
+        manager.install_dir = dir.path().join("packages");
+        let mut info = package_info("siunitx", "https://mirror.example.edu/tlnet/archive/siunitx.tar.xz");
+        assert!(PackageManager::ensure_unpackable(&info).is_ok());
+        let files = manager.unpack_archive(&archive_path, &info).unwrap();
+        assert_eq!(files, vec![dir.path().join("packages/siunitx.sty"), dir.path().join("packages/siunitx-abbreviations.cfg")]);
+        assert_eq!(std::fs::read_to_string(&files[0]).unwrap(), "%tex");
+
+        // Formats that cannot be unpacked fail instead of installing anything
+        info.download_url = "https://mirror.ctan.org/install/macros/latex/contrib/siunitx.zip".to_string();
+        assert!(PackageManager::ensure_unpackable(&info).is_err());
+        let zip_path = dir.path().join("siunitx-75310.zip");
+        std::fs::write(&zip_path, "PK").unwrap();
+        assert!(manager.unpack_archive(&zip_path, &info).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_registry_records_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("labstyle-1.0.tar.gz");
+        tar_archive(&archive_path, &[("labstyle/labstyle.sty", b""), ("labstyle/labstyle-fonts.def", b""), ("labstyle/labstyle.bst", b"")]);
+
+        let mut manager = PackageManager::read_only(false).unwrap();
+        manager.install_dir = dir.path().join("packages");
+        let info = package_info("labstyle", "https://example.com/labstyle-1.0.tar.gz");
+        let files = manager.unpack_archive(&archive_path, &info).unwrap();
+        manager.register_package(&info, &files).await.unwrap();
+        std::fs::write(manager.install_dir.join("other.sty"), "").unwrap();
+
+        let registry = std::fs::read_to_string(manager.install_dir.join("registry.json")).unwrap();
+        assert!(registry.contains("\"labstyle-fonts.def\""));
+        assert_eq!(manager.list_installed().await.unwrap(), vec![("labstyle".to_string(), "1.0".to_string())]);
+        let mut installed = manager.installed_files("labstyle");
+        installed.sort();
+        let mut expected = files.clone();
+        expected.sort();
+        assert_eq!(installed, expected);
+
+        // Removing deletes exactly the recorded files
+        manager.remove("labstyle").await.unwrap();
+        assert!(files.iter().all(|file| !file.exists()));
+        assert!(manager.install_dir.join("other.sty").exists());
+
+        // Registries of earlier versions record only the version; their files are looked up
+        std::fs::write(manager.install_dir.join("registry.json"), r#"{"other": "2.0"}"#).unwrap();
+        assert_eq!(manager.get_installed_version("other").await.unwrap(), "2.0");
+        assert_eq!(manager.installed_files("other"), vec![manager.install_dir.join("other.sty")]);
+    }
+
+    #[test]
+    fn test_unused_packages() {
+        let installed: Vec<String> = ["tikz", "pgf", "xcolor", "lipsum"].iter().map(|s| s.to_string()).collect();
+        let requires = |name: &str| match name {
+            "tikz" => vec!["pgf".to_string()],
+            "pgf" => vec!["xcolor".to_string(), "tikz".to_string()],
+            _ => Vec::new(),
+        };
+        assert_eq!(unused_packages(&installed, &["tikz".to_string()], requires), vec!["lipsum"]);
+        assert_eq!(unused_packages(&installed, &[], requires).len(), 4);
+    }
+
+    #[test]
+    fn test_dependency_tree() {
+        let package = |name: &str, size: u64, requires: &[&str]| InstalledPackage {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            size,
+            requires: requires.iter().map(|s| s.to_string()).collect(),
+        };
+        let mut installed = vec![
+            package("tikz", 300, &["pgf", "xcolor"]),
+            package("pgf", 900, &["tikz"]),
+            package("lipsum", 100, &[]),
+            package("loopa", 10, &["loopb"]),
+            package("loopb", 10, &["loopa"]),
+        ];
+
+        sort_installed(&mut installed, "size");
+        let names: Vec<&str> = installed.iter().map(|package| package.name.as_str()).collect();
+        assert_eq!(names, vec!["pgf", "tikz", "lipsum", "loopa", "loopb"]);
+        sort_installed(&mut installed, "name");
+
+        assert_eq!(dependency_tree(&installed, &installed), vec![
+            "lipsum (1.0)",
+            "loopa (1.0)",
+            "└── loopb (1.0)",
+            "    └── loopa (1.0) (cycle)",
+            "pgf (1.0)",
+            "└── tikz (1.0)",
+            "    ├── pgf (1.0) (cycle)",
+            "    └── xcolor",
+        ]);
+
+        // Filtering to one package still shows what it loads
+        let tikz: Vec<InstalledPackage> = installed.iter().filter(|package| package.name == "tikz").cloned().collect();
+        assert_eq!(dependency_tree(&tikz, &installed), vec![
+            "tikz (1.0)",
+            "├── pgf (1.0)",
+            "│   └── tikz (1.0) (cycle)",
+            "└── xcolor",
+        ]);
+    }
+
+    #[test]
+    fn test_archive_files_and_checksum() {
+        use sha2::{Digest, Sha256};
         
-        // 测试基本包内容生成
-        let content = manager.generate_package_content("amsmath");
-        assert!(content.contains("\\ProvidesPackage{amsmath}"));
-        assert!(content.contains("Test amsmath package"));
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("demo-1.0.tar.gz");
+        tar_archive(&archive_path, &[("demo/demo.sty", b""), ("demo/README", b"")]);
         
-        // 测试特殊包内容生成
-        let graphicx_content = manager.generate_package_content("graphicx");
-        assert!(graphicx_content.contains("\\ProvidesPackage{graphicx}"));
-        assert!(graphicx_content.contains("includegraphics"));
+        let files = PackageManager::archive_files(&archive_path).unwrap();
+        assert_eq!(files, vec![PathBuf::from("demo/demo.sty"), PathBuf::from("demo/README")]);
         
-        // 测试获取 TEXINPUTS 路径
-        let texinputs = manager.get_texinputs_path();
-        assert_eq!(texinputs, "packages");
+        let content = std::fs::read(&archive_path).unwrap();
+        let mut info = PackageInfo {
+            checksum: format!("sha256:{:x}", Sha256::digest(&content)),
+            size: content.len() as u64,
+            ..package_info("demo", "https://example.com/demo-1.0.tar.gz")
+        };
+        assert!(PackageManager::checksum_matches(&archive_path, &info));
+        let checksum = info.checksum.clone();
+        info.checksum = "sha256:0000".to_string();
+        assert!(!PackageManager::checksum_matches(&archive_path, &info));
+        assert!(!PackageManager::is_reusable(&archive_path, &info));
+
+        // Archives without a checksum expire from the cache a day after the download
+        info.checksum = String::new();
+        assert!(PackageManager::is_reusable(&archive_path, &info));
+        let two_days_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 24 * 60 * 60);
+        std::fs::File::options().write(true).open(&archive_path).unwrap().set_modified(two_days_ago).unwrap();
+        assert!(!PackageManager::is_reusable(&archive_path, &info));
+        info.checksum = checksum;
+        assert!(PackageManager::is_reusable(&archive_path, &info));
+
+        let mut manager = PackageManager::read_only(false).unwrap();
+        manager.cache_dir = dir.path().to_path_buf();
+        assert_eq!(manager.archive_path(&info), dir.path().join("demo-1.0.tar.gz"));
+        info.version = String::new();
+        info.download_url = "https://mirror.ctan.org/install/macros/latex/contrib/demo.zip".to_string();
+        assert_eq!(manager.archive_path(&info), dir.path().join("demo.zip"));
+    }
+
+    #[test]
+    fn test_global_install_layout() {
+        let texmf = tempfile::tempdir().unwrap();
+        let mut manager = PackageManager::read_only(false).unwrap();
+        manager.texmf_root = Some(texmf.path().to_path_buf());
+
+        let sty = manager.file_path("labstyle", "labstyle.sty".as_ref());
+        let bst = manager.file_path("labstyle", "labstyle.bst".as_ref());
+        assert_eq!(sty, texmf.path().join("tex/latex/labstyle/labstyle.sty"));
+        assert_eq!(bst, texmf.path().join("bibtex/bst/labstyle/labstyle.bst"));
+        assert_eq!(tds_dir("apa", "apa.bbx".as_ref()), Path::new("tex/latex/apa"));
+
+        // TeX Live archives keep their TDS paths; flat archives are sorted by extension
+        let font = Path::new("fonts/type1/public/lm/lmr10.pfb");
+        assert_eq!(manager.entry_destination("lm", font), Some(texmf.path().join(font)));
+        assert_eq!(manager.entry_destination("labstyle", "labstyle/labstyle.sty".as_ref()), Some(sty.clone()));
+        assert_eq!(manager.entry_destination("evil", "tex/../../outside.sty".as_ref()), Some(texmf.path().join("tex/latex/evil/outside.sty")));
+        assert_eq!(manager.entry_destination("ieeetran-bst", "bibtex/bst/ieeetran/IEEEtran.cls".as_ref()), None);
+
+        for file in [&sty, &bst] {
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, "").unwrap();
+        }
+        assert_eq!(manager.installed_files("labstyle"), vec![bst, sty]);
+        assert!(manager.installed_files("other").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_plan_tlmgr_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = PackageManager::read_only(false).unwrap();
+        manager.install_dir = dir.path().to_path_buf();
+        manager.tlmgr = Some(PathBuf::from("/opt/TinyTeX/bin/tlmgr"));
+
+        let step = manager.plan_install("siunitx").await.unwrap().unwrap();
+        assert_eq!(step.tlmgr, manager.tlmgr);
+        assert_eq!(step.download_size(), 0);
+        assert!(step.files.is_none() && step.dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_style_artifacts() {
+        let style = StyleArtifact::parse("ieeetran-bst").unwrap();
+        assert_eq!(style.file_name(), "ieeetran.bst");
+        assert!(style.matches("IEEEtran.bst".as_ref()));
+        assert!(!style.matches("IEEEtran.cls".as_ref()));
+        assert_eq!(style.candidate_packages(), vec!["ieeetran"]);
+        assert_eq!(StyleArtifact::parse("apa-bbx").unwrap().candidate_packages(), vec!["biblatex-apa", "apa"]);
+        assert_eq!(StyleArtifact::parse("amsmath"), None);
+        assert_eq!(StyleArtifact::parse("-bst"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive_path = dir.path().join("ieeetran-bst-1.0.tar.gz");
+        tar_archive(&archive_path, &[("ieeetran/IEEEtran.cls", b""), ("ieeetran/IEEEtran.bst", b""), ("ieeetran/README", b"")]);
+
+        let mut manager = PackageManager::read_only(false).unwrap();
+        manager.install_dir = dir.path().join("packages");
+        let info = PackageInfo {
+            style_file: Some("IEEEtran.bst".to_string()),
+            ..package_info("ieeetran-bst", "https://example.com/ieeetran-1.0.tar.gz")
+        };
+        let bst = dir.path().join("packages/IEEEtran.bst");
+        assert_eq!(manager.unpack_archive(&archive_path, &info).unwrap(), vec![bst.clone()]);
+        assert_eq!(manager.installed_files("ieeetran-bst"), vec![bst]);
+        assert_eq!(manager.search_path_for("IEEEtran.bst".as_ref()).0, "BSTINPUTS");
+    }
+
+    #[test]
+    fn test_install_status() {
+        let project: HashSet<String> = ["siunitx".to_string(), "mystyle".to_string()].into();
+        let local: HashSet<String> = ["mystyle".to_string(), "labreport".to_string()].into();
+        let global = |name: &str| name == "amsmath" || name == "labreport";
+
+        assert_eq!(InstallStatus::of("mystyle", &project, &local, global), InstallStatus::Project);
+        assert_eq!(InstallStatus::of("labreport", &project, &local, global), InstallStatus::Local);
+        assert_eq!(InstallStatus::of("amsmath", &project, &local, global), InstallStatus::Global);
+        assert_eq!(InstallStatus::of("minted", &project, &local, global), InstallStatus::NotInstalled);
     }
 }
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::{PostAction, PostStep};
+
+/// Ghostscript `-dPDFSETTINGS` presets accepted by `quality`
+const QUALITY_PRESETS: &[&str] = &["screen", "ebook", "printer", "prepress", "default"];
+
+impl PostStep {
+    /// Why the step cannot run, if it cannot
+    pub fn problem(&self) -> Option<String> {
+        match self.action {
+            PostAction::Compress => self
+                .quality
+                .as_deref()
+                .filter(|quality| !QUALITY_PRESETS.contains(quality))
+                .map(|quality| format!("unknown compress quality \"{}\" (use {})", quality, QUALITY_PRESETS.join(", "))),
+            PostAction::Pdfa => self
+                .level
+                .filter(|level| !(1..=3).contains(level))
+                .map(|level| format!("PDF/A level {} does not exist (use 1, 2 or 3)", level)),
+            PostAction::Command if self.tool.as_deref().is_none_or(|tool| tool.trim().is_empty()) => {
+                Some("action = \"command\" needs a tool".to_string())
+            }
+            _ => None,
+        }
+    }
+
+    /// Where the step writes its result for `pdf`: `output` (with `${NAME}`
+    /// replaced by the document's name) next to the PDF, or None to replace
+    /// the input
+    pub fn output_path(&self, pdf: &Path) -> Option<PathBuf> {
+        let output = self.output.as_deref()?;
+        let name = pdf.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let output = output.replace("${NAME}", &name);
+        Some(pdf.parent().unwrap_or(Path::new("")).join(output))
+    }
+
+    /// The command that turns `input` into `output`
+    pub fn command_line(&self, input: &Path, output: &Path) -> Vec<String> {
+        let input = input.to_string_lossy().to_string();
+        let output = output.to_string_lossy().to_string();
+        let ghostscript = |options: Vec<String>| {
+            let mut command = vec!["gs".to_string(), "-sDEVICE=pdfwrite".to_string()];
+            command.extend(options);
+            command.extend(["-dNOPAUSE", "-dQUIET", "-dBATCH"].map(str::to_string));
+            command.push(format!("-sOutputFile={}", output));
+            command.push(input.clone());
+            command
+        };
+        match self.action {
+            PostAction::Compress => ghostscript(vec![
+                "-dCompatibilityLevel=1.5".to_string(),
+                format!("-dPDFSETTINGS=/{}", self.quality.as_deref().unwrap_or("ebook")),
+            ]),
+            PostAction::Pdfa => ghostscript(vec![
+                format!("-dPDFA={}", self.level.unwrap_or(2)),
+                "-dPDFACompatibilityPolicy=1".to_string(),
+                "-sColorConversionStrategy=RGB".to_string(),
+            ]),
+            PostAction::Linearize => vec!["qpdf".to_string(), "--linearize".to_string(), input, output],
+            PostAction::Command => {
+                let mut command = vec![self.tool.clone().unwrap_or_default()];
+                command.extend(
+                    self.args
+                        .iter()
+                        .map(|arg| arg.replace("${INPUT}", &input).replace("${OUTPUT}", &output)),
+                );
+                command
+            }
+        }
+    }
+}
+
+/// Every file the post-processing steps write for `pdf`
+pub fn post_outputs(steps: &[PostStep], pdf: &Path) -> Vec<PathBuf> {
+    steps.iter().filter_map(|step| step.output_path(pdf)).collect()
+}
+
+/// The file holding the result of the last step: the last `output`, or
+/// `pdf` itself when every step replaces its input
+pub fn final_output(steps: &[PostStep], pdf: &Path) -> PathBuf {
+    post_outputs(steps, pdf).pop().unwrap_or_else(|| pdf.to_path_buf())
+}
+
+/// Run the post-processing steps on a freshly built `pdf`. Each step reads
+/// the previous step's result (the PDF itself for the first one); the files
+/// written are returned.
+pub fn run_post_steps(steps: &[PostStep], pdf: &Path, environment: &[(String, String)]) -> Result<Vec<PathBuf>> {
+    let mut current = pdf.to_path_buf();
+    let mut written = Vec::new();
+    for (i, step) in steps.iter().enumerate() {
+        if let Some(problem) = step.problem() {
+            anyhow::bail!("Post-processing step {}: {}", i + 1, problem);
+        }
+        let target = step.output_path(pdf);
+        // In-place steps write next to their input first, so a failure leaves it intact
+        let output = target.clone().unwrap_or_else(|| current.with_extension("tpmgr-post.pdf"));
+        let command = step.command_line(&current, &output);
+        let status = Command::new(&command[0])
+            .args(&command[1..])
+            .current_dir(pdf.parent().unwrap_or(Path::new(".")))
+            .envs(environment.iter().cloned())
+            .stdin(Stdio::null())
+            .status()
+            .map_err(|e| anyhow::anyhow!("Failed to execute {}: {}", command[0], e))?;
+        if !status.success() || !output.is_file() {
+            let _ = std::fs::remove_file(&output);
+            anyhow::bail!("Post-processing step {} ({}) failed with exit code: {:?}", i + 1, command[0], status.code());
+        }
+        match target {
+            Some(target) => {
+                println!("  ✓ {} → {}", step.action, target.display());
+                current = target;
+            }
+            None => {
+                std::fs::rename(&output, &current)?;
+                println!("  ✓ {} → {} (replaced)", step.action, current.display());
+            }
+        }
+        if !written.contains(&current) {
+            written.push(current.clone());
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(action: PostAction, output: Option<&str>) -> PostStep {
+        PostStep {
+            action,
+            output: output.map(str::to_string),
+            quality: None,
+            level: None,
+            tool: None,
+            args: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_post_step_commands() {
+        let pdf = Path::new("/work/paper/main.pdf");
+        let compress = step(PostAction::Compress, Some("${NAME}-small.pdf"));
+        let small = compress.output_path(pdf).unwrap();
+        assert_eq!(small, Path::new("/work/paper/main-small.pdf"));
+        let command = compress.command_line(pdf, &small);
+        assert_eq!(command[0], "gs");
+        assert!(command.contains(&"-dPDFSETTINGS=/ebook".to_string()));
+        assert!(command.contains(&"-sOutputFile=/work/paper/main-small.pdf".to_string()));
+        assert_eq!(command.last().unwrap(), "/work/paper/main.pdf");
+
+        let linearize = step(PostAction::Linearize, None);
+        assert_eq!(linearize.output_path(pdf), None);
+        assert_eq!(linearize.command_line(pdf, Path::new("out.pdf")), vec!["qpdf", "--linearize", "/work/paper/main.pdf", "out.pdf"]);
+
+        let mut custom = step(PostAction::Command, Some("signed.pdf"));
+        assert!(custom.problem().is_some());
+        custom.tool = Some("pdfsig".to_string());
+        custom.args = vec!["-in".to_string(), "${INPUT}".to_string(), "${OUTPUT}".to_string()];
+        assert_eq!(custom.problem(), None);
+        assert_eq!(custom.command_line(pdf, Path::new("signed.pdf")), vec!["pdfsig", "-in", "/work/paper/main.pdf", "signed.pdf"]);
+
+        let mut pdfa = step(PostAction::Pdfa, Some("main-pdfa.pdf"));
+        pdfa.level = Some(4);
+        assert!(pdfa.problem().is_some());
+        assert_eq!(
+            post_outputs(&[compress, linearize, pdfa], pdf),
+            vec![PathBuf::from("/work/paper/main-small.pdf"), PathBuf::from("/work/paper/main-pdfa.pdf")]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_post_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let pdf = dir.path().join("main.pdf");
+        std::fs::write(&pdf, "original").unwrap();
+        let mut copy = step(PostAction::Command, Some("${NAME}-copy.pdf"));
+        copy.tool = Some("cp".to_string());
+        copy.args = vec!["${INPUT}".to_string(), "${OUTPUT}".to_string()];
+        let mut in_place = step(PostAction::Command, None);
+        in_place.tool = Some("sh".to_string());
+        in_place.args = vec!["-c".to_string(), "echo changed > \"$1\"".to_string(), "sh".to_string(), "${OUTPUT}".to_string()];
+
+        let written = run_post_steps(&[copy.clone(), in_place], &pdf, &[]).unwrap();
+        let copy_path = dir.path().join("main-copy.pdf");
+        assert_eq!(written, vec![copy_path.clone()]);
+        assert_eq!(std::fs::read_to_string(&pdf).unwrap(), "original");
+        assert_eq!(std::fs::read_to_string(&copy_path).unwrap().trim(), "changed");
+
+        let mut failing = step(PostAction::Command, None);
+        failing.tool = Some("false".to_string());
+        assert!(run_post_steps(&[failing], &pdf, &[]).is_err());
+        assert_eq!(std::fs::read_to_string(&pdf).unwrap(), "original");
+    }
+}
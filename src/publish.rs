@@ -0,0 +1,197 @@
+use crate::repository::{IndexEntry, RepositoryIndex, INDEX_FILE};
+use crate::tex_parser::TeXParser;
+use anyhow::Result;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Files that make up a LaTeX package and are included in published archives
+const PACKAGE_FILE_EXTENSIONS: &[&str] = &[
+    "sty", "cls", "clo", "def", "cfg", "fd", "dtx", "ins", "bst", "bbx", "cbx", "lbx",
+];
+
+/// Directory of a self-hosted repository that holds the archives
+const ARCHIVE_DIR: &str = "archives";
+
+/// Package the .sty/.cls files in `source` as `<name>-<version>.tar.gz` in
+/// `repository` and add the package to the repository's index.
+///
+/// The name defaults to the only .sty (or .cls) file, the version and
+/// description to its \ProvidesPackage/\ProvidesClass line.
+pub fn publish(source: &Path, repository: &Path, name: Option<&str>, version: Option<&str>) -> Result<IndexEntry> {
+    let files = package_files(source)?;
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => default_name(&files)?,
+    };
+
+    let main_file = ["sty", "cls"]
+        .iter()
+        .map(|extension| source.join(format!("{}.{}", name, extension)))
+        .find(|path| path.is_file());
+    let (provided_version, description) = match &main_file {
+        Some(path) => parse_provides(&TeXParser::read_source(path)?),
+        None => (None, String::new()),
+    };
+    let version = version
+        .map(str::to_string)
+        .or(provided_version)
+        .ok_or_else(|| anyhow::anyhow!("Could not determine the version of '{}'; pass --version", name))?;
+
+    let dependencies = package_dependencies(&files)?;
+
+    let archive = format!("{}/{}-{}.tar.gz", ARCHIVE_DIR, name, version);
+    let archive_path = repository.join(&archive);
+    std::fs::create_dir_all(repository.join(ARCHIVE_DIR))?;
+    write_archive(&archive_path, &name, &files)?;
+
+    let content = std::fs::read(&archive_path)?;
+    let entry = IndexEntry {
+        name,
+        version,
+        description,
+        dependencies,
+        archive,
+        sha256: format!("{:x}", Sha256::digest(&content)),
+        size: content.len() as u64,
+    };
+
+    let index_path = repository.join(INDEX_FILE);
+    let mut index = RepositoryIndex::load(&index_path)?;
+    index.upsert(entry.clone());
+    index.save(&index_path)?;
+
+    Ok(entry)
+}
+
+/// Package files at the top level of the source directory
+fn package_files(source: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(source)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+            let is_readme = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_lowercase().starts_with("readme"))
+                .unwrap_or(false);
+            PACKAGE_FILE_EXTENSIONS.contains(&extension) || is_readme
+        })
+        .collect();
+    files.sort();
+
+    if !files.iter().any(|path| has_extension(path, "sty") || has_extension(path, "cls")) {
+        anyhow::bail!("No .sty or .cls files found in {}", source.display());
+    }
+    Ok(files)
+}
+
+/// The stem of the only .sty file, or of the only .cls file
+fn default_name(files: &[PathBuf]) -> Result<String> {
+    for extension in ["sty", "cls"] {
+        let stems: Vec<String> = files
+            .iter()
+            .filter(|path| has_extension(path, extension))
+            .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+            .collect();
+        match stems.len() {
+            0 => continue,
+            1 => return Ok(stems[0].clone()),
+            _ => anyhow::bail!("Several .{} files found ({}); pass --name", extension, stems.join(", ")),
+        }
+    }
+    anyhow::bail!("No .sty or .cls files found")
+}
+
+/// Version and description from `\ProvidesPackage{name}[2024/05/01 v1.2 Description]`
+fn parse_provides(content: &str) -> (Option<String>, String) {
+    let provides_regex = Regex::new(r"\\Provides(?:Package|Class|File)\{[^}]*\}\s*\[([^\]]*)\]").expect("valid regex");
+    let Some(caps) = provides_regex.captures(content) else {
+        return (None, String::new());
+    };
+
+    let mut version = None;
+    let mut description = Vec::new();
+    for (index, word) in caps[1].split_whitespace().enumerate() {
+        let is_date = index == 0 && word.chars().all(|c| c.is_ascii_digit() || c == '/' || c == '-');
+        if is_date {
+            continue;
+        }
+        let number = word.strip_prefix('v').unwrap_or(word);
+        if version.is_none() && description.is_empty() && number.starts_with(|c: char| c.is_ascii_digit()) {
+            version = Some(number.to_string());
+        } else {
+            description.push(word);
+        }
+    }
+    (version, description.join(" "))
+}
+
+/// Packages loaded by the published files, excluding the files themselves
+fn package_dependencies(files: &[PathBuf]) -> Result<Vec<String>> {
+    let parser = TeXParser::new()?;
+    let own: Vec<String> = files
+        .iter()
+        .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+        .collect();
+
+    let mut dependencies = Vec::new();
+    for file in files.iter().filter(|path| has_extension(path, "sty") || has_extension(path, "cls")) {
+        dependencies.extend(parser.parse_file(file)?);
+    }
+    Ok(TeXParser::get_unique_packages(&dependencies)
+        .into_iter()
+        .filter(|package| !own.contains(package))
+        .collect())
+}
+
+fn write_archive(archive_path: &Path, name: &str, files: &[PathBuf]) -> Result<()> {
+    let encoder = flate2::write::GzEncoder::new(std::fs::File::create(archive_path)?, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for file in files {
+        if let Some(file_name) = file.file_name() {
+            builder.append_path_with_name(file, Path::new(name).join(file_name))?;
+        }
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().map(|ext| ext == extension).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_publish_updates_index() {
+        let source = tempfile::tempdir().unwrap();
+        let repository = tempfile::tempdir().unwrap();
+        fs::write(
+            source.path().join("labstyle.sty"),
+            "\\ProvidesPackage{labstyle}[2024/05/01 v1.2 Lab report style]\n\\RequirePackage{xcolor}\n\\RequirePackage{labstyle-fonts}\n",
+        )
+        .unwrap();
+        fs::write(source.path().join("labstyle-fonts.def"), "% fonts\n").unwrap();
+        fs::write(source.path().join("notes.txt"), "not packaged").unwrap();
+
+        let entry = publish(source.path(), repository.path(), None, None).unwrap();
+        assert_eq!(entry.name, "labstyle");
+        assert_eq!(entry.version, "1.2");
+        assert_eq!(entry.description, "Lab report style");
+        assert_eq!(entry.dependencies, vec!["xcolor"]);
+        assert_eq!(entry.archive, "archives/labstyle-1.2.tar.gz");
+
+        let content = fs::read(repository.path().join(&entry.archive)).unwrap();
+        assert_eq!(entry.sha256, format!("{:x}", Sha256::digest(&content)));
+
+        // Publishing again replaces the entry
+        publish(source.path(), repository.path(), None, Some("1.3")).unwrap();
+        let index = RepositoryIndex::load(&repository.path().join(INDEX_FILE)).unwrap();
+        assert_eq!(index.packages.len(), 1);
+        assert_eq!(index.packages[0].version, "1.3");
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+
+use crate::repository::RemotePackage;
+
+/// Field prefixes accepted in search queries, e.g. `name:tikz desc:chemistry`
+const FIELD_PREFIXES: &[(&str, Field)] = &[
+    ("name:", Field::Name),
+    ("desc:", Field::Description),
+    ("description:", Field::Description),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Any,
+    Name,
+    Description,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    /// Lowercase substring
+    Text(String),
+    Regex(Regex),
+}
+
+#[derive(Debug)]
+struct Term {
+    field: Field,
+    matcher: Matcher,
+}
+
+/// A parsed `tpmgr search` query. Text before the first field prefix is
+/// matched against name and description; every term must match.
+#[derive(Debug)]
+pub struct SearchQuery {
+    terms: Vec<Term>,
+}
+
+impl SearchQuery {
+    /// Parse a query; with `regex` each term is a case-insensitive regular
+    /// expression instead of a substring
+    pub fn parse(query: &str, regex: bool) -> Result<Self> {
+        let mut terms = Vec::new();
+        for (field, text) in split_fields(query) {
+            let matcher = if regex {
+                let pattern = RegexBuilder::new(text)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| anyhow::anyhow!("Invalid regular expression '{}': {}", text, e))?;
+                Matcher::Regex(pattern)
+            } else {
+                Matcher::Text(text.to_lowercase())
+            };
+            terms.push(Term { field, matcher });
+        }
+        if terms.is_empty() {
+            anyhow::bail!("Empty search query");
+        }
+        Ok(Self { terms })
+    }
+
+    /// How well a package matches, lower is better (see `term_rank`); a
+    /// package ranks by its weakest term. None if a term does not match.
+    pub fn rank(&self, package: &RemotePackage) -> Option<u8> {
+        self.terms
+            .iter()
+            .map(|term| term_rank(term, package))
+            .try_fold(0, |worst, rank| rank.map(|rank| rank.max(worst)))
+    }
+}
+
+/// Split a query at its field prefixes into (field, text) pairs, dropping
+/// empty texts
+fn split_fields(query: &str) -> Vec<(Field, &str)> {
+    let mut starts: Vec<(usize, usize, Field)> = Vec::new();
+    for (index, _) in query.char_indices() {
+        if index > 0 && !query[..index].ends_with(char::is_whitespace) {
+            continue;
+        }
+        if let Some((prefix, field)) = FIELD_PREFIXES.iter().find(|(prefix, _)| query[index..].starts_with(prefix)) {
+            starts.push((index, index + prefix.len(), *field));
+        }
+    }
+
+    let mut fields = vec![(Field::Any, &query[..starts.first().map_or(query.len(), |start| start.0)])];
+    for (position, (_, text_start, field)) in starts.iter().enumerate() {
+        let end = starts.get(position + 1).map_or(query.len(), |next| next.0);
+        fields.push((*field, &query[*text_start..end]));
+    }
+    fields
+        .into_iter()
+        .map(|(field, text)| (field, text.trim()))
+        .filter(|(_, text)| !text.is_empty())
+        .collect()
+}
+
+/// 0 for the exact name, 1 for a name prefix, 2 for a name substring (or
+/// regex match) and 3 for a description hit
+fn term_rank(term: &Term, package: &RemotePackage) -> Option<u8> {
+    let name_rank = if term.field == Field::Description {
+        None
+    } else {
+        match &term.matcher {
+            Matcher::Text(text) => {
+                let name = package.name.to_lowercase();
+                if name == *text {
+                    Some(0)
+                } else if name.starts_with(text.as_str()) {
+                    Some(1)
+                } else {
+                    name.contains(text.as_str()).then_some(2)
+                }
+            }
+            Matcher::Regex(pattern) => pattern.is_match(&package.name).then_some(2),
+        }
+    };
+    if name_rank.is_some() || term.field == Field::Name {
+        return name_rank;
+    }
+
+    let described = match &term.matcher {
+        Matcher::Text(text) => package.description.to_lowercase().contains(text.as_str()),
+        Matcher::Regex(pattern) => pattern.is_match(&package.description),
+    };
+    described.then_some(3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, description: &str) -> RemotePackage {
+        RemotePackage {
+            name: name.to_string(),
+            version: String::new(),
+            description: description.to_string(),
+            dependencies: Vec::new(),
+            download_url: None,
+            checksum: None,
+            size: 0,
+            repository: "ctan".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let query = SearchQuery::parse("tikz", false).unwrap();
+        assert_eq!(query.rank(&package("TikZ", "")), Some(0));
+        assert_eq!(query.rank(&package("tikz-cd", "")), Some(1));
+        assert_eq!(query.rank(&package("circuitikz", "Draw circuits with TikZ")), Some(2));
+        assert_eq!(query.rank(&package("pgfplots", "Plots for TikZ")), Some(3));
+        assert_eq!(query.rank(&package("pgfplots", "Plots")), None);
+    }
+
+    #[test]
+    fn test_field_scoped_and_regex_queries() {
+        assert_eq!(split_fields("name:tikz desc:chemical formulas"), vec![
+            (Field::Name, "tikz"),
+            (Field::Description, "chemical formulas"),
+        ]);
+        assert_eq!(split_fields("units desc:SI"), vec![(Field::Any, "units"), (Field::Description, "SI")]);
+        assert_eq!(split_fields("rename:x"), vec![(Field::Any, "rename:x")]);
+
+        let chemfig = package("chemfig", "Draw molecules with an easy syntax");
+        let mhchem = package("mhchem", "Typeset chemical formulae and equations");
+        let query = SearchQuery::parse("desc:chemical", false).unwrap();
+        assert_eq!(query.rank(&chemfig), None);
+        assert_eq!(query.rank(&mhchem), Some(3));
+        let query = SearchQuery::parse("name:chem desc:equations", false).unwrap();
+        assert_eq!(query.rank(&mhchem), Some(3));
+        assert_eq!(query.rank(&chemfig), None);
+
+        let query = SearchQuery::parse("name:^chem", true).unwrap();
+        assert_eq!(query.rank(&chemfig), Some(2));
+        assert_eq!(query.rank(&mhchem), None);
+        assert!(SearchQuery::parse("name:(", true).is_err());
+        assert!(SearchQuery::parse("desc: ", false).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// TeX wraps log lines at this many characters
+const LOG_LINE_WIDTH: usize = 79;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReferenceKind {
+    Citation,
+    Reference,
+}
+
+/// A citation or cross-reference that LaTeX could not resolve
+#[derive(Debug, Clone)]
+pub struct UndefinedReference {
+    pub kind: ReferenceKind,
+    pub key: String,
+    /// Input lines reported in the log
+    pub log_lines: Vec<usize>,
+    /// Places in the sources where the key is used
+    pub locations: Vec<(PathBuf, usize)>,
+}
+
+/// Collect undefined citations and references from a compile log and,
+/// when available, the .aux file (citations without a \bibcite entry)
+pub fn find_undefined_references(log: &str, aux: Option<&str>) -> Vec<UndefinedReference> {
+    let mut found: BTreeMap<(ReferenceKind, String), Vec<usize>> = BTreeMap::new();
+
+    let warning_regex = Regex::new(
+        r"(Citation|Reference) `([^']+)'(?: on page \S+)? undefined(?: on input line (\d+))?",
+    )
+    .expect("valid regex");
+    for caps in warning_regex.captures_iter(&unwrap_log_lines(log)) {
+        let kind = if &caps[1] == "Citation" { ReferenceKind::Citation } else { ReferenceKind::Reference };
+        let lines = found.entry((kind, caps[2].to_string())).or_default();
+        if let Some(line) = caps.get(3).and_then(|line| line.as_str().parse().ok()) {
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+    }
+
+    if let Some(aux) = aux {
+        let citation_regex = Regex::new(r"\\citation\{([^}]*)\}").expect("valid regex");
+        let bibcite_regex = Regex::new(r"\\bibcite\{([^}]*)\}").expect("valid regex");
+        let defined: Vec<&str> = bibcite_regex
+            .captures_iter(aux)
+            .filter_map(|caps| caps.get(1).map(|key| key.as_str()))
+            .collect();
+        for caps in citation_regex.captures_iter(aux) {
+            for key in caps[1].split(',').map(str::trim) {
+                if !key.is_empty() && key != "*" && !defined.contains(&key) {
+                    found.entry((ReferenceKind::Citation, key.to_string())).or_default();
+                }
+            }
+        }
+    }
+
+    found
+        .into_iter()
+        .map(|((kind, key), log_lines)| UndefinedReference {
+            kind,
+            key,
+            log_lines,
+            locations: Vec::new(),
+        })
+        .collect()
+}
+
+/// Fill in where each undefined key is used in the given source files
+pub fn locate_in_sources(references: &mut [UndefinedReference], files: &[PathBuf]) {
+    let citation_regex = Regex::new(r"\\[A-Za-z]*cite[A-Za-z]*\*?(?:\[[^\]]*\])*\{([^}]*)\}").expect("valid regex");
+    let reference_regex = Regex::new(r"\\[A-Za-z]*ref\*?\{([^}]*)\}").expect("valid regex");
+
+    for file in files {
+        let Ok(content) = crate::tex_parser::TeXParser::read_source(file) else {
+            continue;
+        };
+        for (index, line) in content.lines().enumerate() {
+            for reference in references.iter_mut() {
+                let regex = match reference.kind {
+                    ReferenceKind::Citation => &citation_regex,
+                    ReferenceKind::Reference => &reference_regex,
+                };
+                let used = regex
+                    .captures_iter(line)
+                    .any(|caps| caps[1].split(',').any(|key| key.trim() == reference.key));
+                if used {
+                    reference.locations.push((file.clone(), index + 1));
+                }
+            }
+        }
+    }
+}
+
+/// Print the report grouped by kind; returns the number of undefined keys
+pub fn print_report(references: &[UndefinedReference], project_root: &Path) -> usize {
+    for (kind, title) in [
+        (ReferenceKind::Citation, "Undefined citations"),
+        (ReferenceKind::Reference, "Undefined references"),
+    ] {
+        let group: Vec<&UndefinedReference> = references.iter().filter(|r| r.kind == kind).collect();
+        if group.is_empty() {
+            continue;
+        }
+
+        println!("\n⚠️  {} ({}):", title, group.len());
+        for reference in group {
+            let locations: Vec<String> = if reference.locations.is_empty() {
+                reference.log_lines.iter().map(|line| format!("input line {}", line)).collect()
+            } else {
+                reference
+                    .locations
+                    .iter()
+                    .map(|(file, line)| {
+                        format!("{}:{}", file.strip_prefix(project_root).unwrap_or(file).display(), line)
+                    })
+                    .collect()
+            };
+
+            if locations.is_empty() {
+                println!("  ✗ {}", reference.key);
+            } else {
+                println!("  ✗ {} ({})", reference.key, locations.join(", "));
+            }
+        }
+    }
+
+    references.len()
+}
+
+/// Join the lines TeX broke at the log width back together
+fn unwrap_log_lines(log: &str) -> String {
+    let mut result = String::with_capacity(log.len());
+    for line in log.lines() {
+        result.push_str(line);
+        if line.chars().count() != LOG_LINE_WIDTH {
+            result.push('\n');
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_undefined_references() {
+        let log = "LaTeX Warning: Citation `knuth84' on page 1 undefined on input line 12.\n\
+                   LaTeX Warning: Reference `fig:setup' on page 2 undefined on input line 30.\n\
+                   LaTeX Warning: Citation `knuth84' on page 3 undefined on input line 40.\n\
+                   LaTeX Warning: There were undefined references.\n";
+        let aux = "\\citation{knuth84,lamport94}\n\\citation{tufte}\n\\bibcite{tufte}{1}\n";
+
+        let references = find_undefined_references(log, Some(aux));
+        let keys: Vec<(ReferenceKind, &str)> = references.iter().map(|r| (r.kind, r.key.as_str())).collect();
+
+        assert_eq!(
+            keys,
+            vec![
+                (ReferenceKind::Citation, "knuth84"),
+                (ReferenceKind::Citation, "lamport94"),
+                (ReferenceKind::Reference, "fig:setup"),
+            ]
+        );
+        assert_eq!(references[0].log_lines, vec![12, 40]);
+    }
+
+    #[test]
+    fn test_wrapped_log_warning() {
+        let warning = "LaTeX Warning: Citation `a-rather-long-citation-key-that-wraps' on page 1 undefined on input line 5.";
+        let (first, rest) = warning.split_at(LOG_LINE_WIDTH);
+        let log = format!("{}\n{}\n", first, rest);
+
+        let references = find_undefined_references(&log, None);
+        assert_eq!(references[0].key, "a-rather-long-citation-key-that-wraps");
+        assert_eq!(references[0].log_lines, vec![5]);
+    }
+}
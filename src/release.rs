@@ -0,0 +1,131 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::ReleaseConfig;
+
+/// What the `${...}` variables of a release name stand for
+pub struct ReleaseInfo {
+    pub project: String,
+    pub version: String,
+    /// The day of the release, YYYY-MM-DD
+    pub date: String,
+    /// `git describe --tags --always --dirty`, when the project is in git
+    pub git: Option<String>,
+}
+
+impl ReleaseInfo {
+    pub fn collect(project: &str, version: &str, project_root: &Path) -> Self {
+        Self {
+            project: project.to_string(),
+            version: version.to_string(),
+            date: date_from_timestamp(crate::mirror::unix_timestamp()),
+            git: git_describe(project_root),
+        }
+    }
+
+    /// The release file name (without extension) of the document `name`
+    pub fn file_name(&self, template: &str, name: &str) -> Result<String> {
+        if template.contains("${GIT}") && self.git.is_none() {
+            anyhow::bail!("The release name uses ${{GIT}}, but the project is not a git repository with commits");
+        }
+        let file_name = template
+            .replace("${NAME}", name)
+            .replace("${PROJECT}", &self.project)
+            .replace("${VERSION}", &self.version)
+            .replace("${DATE}", &self.date)
+            .replace("${GIT}", self.git.as_deref().unwrap_or_default());
+        if file_name.contains(['/', '\\']) || file_name.trim().is_empty() {
+            anyhow::bail!("The release name \"{}\" is not a file name", file_name);
+        }
+        Ok(file_name)
+    }
+}
+
+/// YYYY-MM-DD of a Unix timestamp (UTC)
+pub fn date_from_timestamp(timestamp: u64) -> String {
+    // Days to civil date, after Howard Hinnant's algorithm
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+pub fn git_describe(project_root: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["describe", "--tags", "--always", "--dirty"])
+        .current_dir(project_root)
+        .output()
+        .ok()?;
+    let described = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !described.is_empty()).then_some(described)
+}
+
+/// Where the release `file_name` is stored
+pub fn target(config: &ReleaseConfig, project_root: &Path, file_name: &str) -> PathBuf {
+    project_root.join(&config.dir).join(format!("{}.pdf", file_name))
+}
+
+/// Copy `pdf` into the release directory as `<file_name>.pdf` with a
+/// `.sha256` file next to it (in `sha256sum` format) and the package registry
+/// the build used as `<file_name>.registry.json`. Returns the files written.
+pub fn store(config: &ReleaseConfig, project_root: &Path, pdf: &Path, file_name: &str, registry: Option<&Path>, overwrite: bool) -> Result<Vec<PathBuf>> {
+    let dir = project_root.join(&config.dir);
+    let target = target(config, project_root, file_name);
+    if target.exists() && !overwrite {
+        anyhow::bail!("{} already exists; use --force to replace it", target.display());
+    }
+    std::fs::create_dir_all(&dir)?;
+
+    let content = std::fs::read(pdf)?;
+    std::fs::write(&target, &content)?;
+    let checksum = dir.join(format!("{}.pdf.sha256", file_name));
+    std::fs::write(&checksum, format!("{:x}  {}.pdf\n", Sha256::digest(&content), file_name))?;
+    let mut written = vec![target, checksum];
+    if let Some(registry) = registry.filter(|registry| registry.is_file()) {
+        let copy = dir.join(format!("{}.registry.json", file_name));
+        std::fs::copy(registry, &copy)?;
+        written.push(copy);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_release_name() {
+        assert_eq!(date_from_timestamp(0), "1970-01-01");
+        assert_eq!(date_from_timestamp(951_782_400), "2000-02-29");
+        assert_eq!(date_from_timestamp(1_790_812_800), "2026-10-01");
+
+        let mut info = ReleaseInfo {
+            project: "thesis".to_string(),
+            version: "1.2.0".to_string(),
+            date: "2026-10-01".to_string(),
+            git: None,
+        };
+        assert_eq!(info.file_name(&ReleaseConfig::default().name, "main").unwrap(), "main-1.2.0-2026-10-01");
+        assert!(info.file_name("${PROJECT}-${GIT}", "main").is_err());
+        info.git = Some("v1.2.0-3-gabc1234".to_string());
+        assert_eq!(info.file_name("${PROJECT}-${GIT}", "main").unwrap(), "thesis-v1.2.0-3-gabc1234");
+        assert!(info.file_name("out/${NAME}", "main").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.pdf"), "%PDF-1.5").unwrap();
+        let config = ReleaseConfig::default();
+        let written = store(&config, dir.path(), &dir.path().join("main.pdf"), "main-1.2.0", None, false).unwrap();
+        assert_eq!(written.len(), 2);
+        let checksum = std::fs::read_to_string(dir.path().join("releases/main-1.2.0.pdf.sha256")).unwrap();
+        assert!(checksum.ends_with("  main-1.2.0.pdf\n"));
+        assert!(store(&config, dir.path(), &dir.path().join("main.pdf"), "main-1.2.0", None, false).is_err());
+    }
+}
@@ -0,0 +1,331 @@
+use anyhow::Result;
+use serde::Deserialize;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::tex_parser::TeXParser;
+
+/// Where `compile --remote` sends a project
+#[derive(Debug, PartialEq)]
+pub enum RemoteTarget {
+    /// A build server speaking the protocol described in the README
+    Http(String),
+    /// A host reached with the `ssh` binary; tpmgr must be installed there
+    Ssh { host: SshHost, dir: Option<String> },
+}
+
+/// An SSH destination: `[user@]host`, and a port when not the default
+#[derive(Debug, PartialEq)]
+pub struct SshHost {
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl SshHost {
+    /// An `ssh` invocation reaching the host, ready for the remote command
+    fn command(&self) -> Command {
+        let mut command = Command::new("ssh");
+        if let Some(port) = self.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg(&self.host);
+        command
+    }
+}
+
+impl RemoteTarget {
+    /// `http(s)://server[:port][/prefix]` or `ssh://[user@]host[:port][/dir]`
+    pub fn parse(url: &str) -> Result<Self> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Self::Http(url.trim_end_matches('/').to_string()));
+        }
+        let Some(rest) = url.strip_prefix("ssh://") else {
+            anyhow::bail!("Unsupported remote '{}': use http(s)://... or ssh://[user@]host[:port][/dir]", url);
+        };
+        let (host, dir) = match rest.split_once('/') {
+            Some((host, dir)) => (host, Some(dir.trim_end_matches('/')).filter(|dir| !dir.is_empty())),
+            None => (rest, None),
+        };
+        // ssh takes the port as -p, not as part of the host name
+        let (host, port) = match host.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| anyhow::anyhow!("Remote '{}' has an invalid port '{}'", url, port))?;
+                (host, Some(port))
+            }
+            None => (host, None),
+        };
+        if host.is_empty() || host.ends_with('@') {
+            anyhow::bail!("Remote '{}' names no host", url);
+        }
+        Ok(Self::Ssh {
+            host: SshHost { host: host.to_string(), port },
+            dir: dir.map(str::to_string),
+        })
+    }
+}
+
+/// One line of the build server's response stream
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ServerEvent {
+    Log { log: String },
+    Done { success: bool, job: String, outputs: Vec<String> },
+}
+
+/// The files shipped to a remote build, relative to the project root: the
+/// project's files (build output left out) and its installed packages with
+/// their registry
+pub fn bundle_files(project_root: &Path, package_dir: &str, excluded: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    TeXParser::collect_project_files(project_root, &mut files)?;
+    files.retain(|file| !excluded.contains(file));
+    collect_all_files(&project_root.join(package_dir), &mut files)?;
+
+    let mut relative: Vec<PathBuf> = files
+        .into_iter()
+        .filter_map(|file| file.strip_prefix(project_root).ok().map(Path::to_path_buf))
+        .collect();
+    relative.sort();
+    relative.dedup();
+    Ok(relative)
+}
+
+fn collect_all_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_all_files(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Write `files` (relative to `project_root`) as a .tar.gz
+pub fn write_bundle(project_root: &Path, files: &[PathBuf], writer: impl Write) -> Result<()> {
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    for file in files {
+        builder.append_path_with_name(project_root.join(file), file)?;
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Compile on a build server: POST the bundle, print the log as it streams
+/// in, then download the outputs into the project. True if the build succeeded.
+pub async fn compile_over_http(server: &str, bundle: Vec<u8>, target: Option<&str>, profile: Option<&str>, project_root: &Path) -> Result<bool> {
+    let client = crate::download::http_client()?;
+    let mut request = client
+        .post(format!("{}/compile", server))
+        .header(reqwest::header::CONTENT_TYPE, "application/gzip")
+        .body(bundle);
+    if let Some(target) = target {
+        request = request.query(&[("target", target)]);
+    }
+    if let Some(profile) = profile {
+        request = request.query(&[("profile", profile)]);
+    }
+    let mut response = request.send().await?;
+    if !response.status().is_success() {
+        anyhow::bail!("Build server answered {}", response.status());
+    }
+
+    let mut pending = Vec::new();
+    let mut result = None;
+    while let Some(chunk) = response.chunk().await? {
+        pending.extend_from_slice(&chunk);
+        while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<ServerEvent>(&line) {
+                Ok(ServerEvent::Log { log }) => {
+                    print!("{}", log);
+                    if !log.ends_with('\n') {
+                        ::std::println!();
+                    }
+                }
+                Ok(ServerEvent::Done { success, job, outputs }) => result = Some((success, job, outputs)),
+                Err(e) => anyhow::bail!("Unexpected response from the build server: {}", e),
+            }
+        }
+    }
+    let Some((success, job, outputs)) = result else {
+        anyhow::bail!("The build server closed the connection before the build finished");
+    };
+
+    for output in outputs {
+        let path = safe_relative_path(&output)?;
+        let response = client.get(format!("{}/jobs/{}/{}", server, job, output)).send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!("Failed to download {}: {}", output, response.status());
+        }
+        let content = response.bytes().await?;
+        write_output(project_root, &path, &content)?;
+        println!("📥 {}", output);
+    }
+    Ok(success)
+}
+
+/// The `tpmgr compile` command line run on an SSH host
+pub fn remote_compile_command(target: Option<&str>, profile: Option<&str>) -> String {
+    let mut compile = "tpmgr --non-interactive".to_string();
+    if let Some(profile) = profile {
+        compile.push_str(&format!(" --profile {}", shell_quote(profile)));
+    }
+    compile.push_str(" compile");
+    if let Some(target) = target {
+        compile.push_str(&format!(" --path {}", shell_quote(target)));
+    }
+    compile
+}
+
+/// Compile on an SSH host: unpack the bundle in `dir` (by default
+/// ~/.cache/tpmgr-remote/<project>), run `compile` (see
+/// `remote_compile_command`) there with its output shown here, then copy
+/// `outputs` back. True if the build succeeded.
+pub fn compile_over_ssh(
+    host: &SshHost,
+    dir: Option<&str>,
+    project_name: &str,
+    bundle: &[u8],
+    compile: &str,
+    outputs: &[PathBuf],
+    project_root: &Path,
+) -> Result<bool> {
+    let dir = match dir {
+        Some(dir) => shell_quote(dir),
+        None => format!("\"$HOME\"/.cache/tpmgr-remote/{}", shell_quote(project_name)),
+    };
+    let script = format!("mkdir -p {dir} && tar xzf - -C {dir} && cd {dir} && {compile}");
+
+    let mut child = host
+        .command()
+        .arg(script)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("Failed to run ssh: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(bundle)?;
+    }
+    let success = child.wait()?.success();
+
+    for output in outputs {
+        let name = output.to_string_lossy().replace('\\', "/");
+        let fetched = host
+            .command()
+            .arg(format!("cd {} && cat {}", dir, shell_quote(&name)))
+            .stderr(Stdio::null())
+            .output()?;
+        if fetched.status.success() {
+            write_output(project_root, output, &fetched.stdout)?;
+            println!("📥 {}", name);
+        }
+    }
+    Ok(success)
+}
+
+fn write_output(project_root: &Path, relative: &Path, content: &[u8]) -> Result<()> {
+    let path = project_root.join(relative);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// A server-supplied output path, refused if it could leave the project
+fn safe_relative_path(path: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(path);
+    if path.as_os_str().is_empty() || !path.components().all(|component| matches!(component, Component::Normal(_))) {
+        anyhow::bail!("The build server returned an unsafe output path: {}", path.display());
+    }
+    Ok(path)
+}
+
+/// Quote a word for a POSIX shell
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_remote_targets_and_bundle() {
+        assert_eq!(RemoteTarget::parse("https://build.example.edu/").unwrap(), RemoteTarget::Http("https://build.example.edu".to_string()));
+        assert_eq!(RemoteTarget::parse("ssh://me@lab-server/work/paper").unwrap(), RemoteTarget::Ssh {
+            host: SshHost { host: "me@lab-server".to_string(), port: None },
+            dir: Some("work/paper".to_string()),
+        });
+        assert_eq!(RemoteTarget::parse("ssh://lab-server:2222").unwrap(), RemoteTarget::Ssh {
+            host: SshHost { host: "lab-server".to_string(), port: Some(2222) },
+            dir: None,
+        });
+        assert!(RemoteTarget::parse("ssh://lab-server:ssh/work").is_err());
+        assert!(RemoteTarget::parse("ssh://me@:22").is_err());
+        let args: Vec<String> = SshHost { host: "me@lab-server".to_string(), port: Some(2222) }
+            .command()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(args, vec!["-p", "2222", "me@lab-server"]);
+        assert!(RemoteTarget::parse("ftp://lab-server").is_err());
+        assert!(safe_relative_path("../.bashrc").is_err());
+        assert!(safe_relative_path("/etc/passwd").is_err());
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(remote_compile_command(Some("ch1.tex"), Some("archive")), "tpmgr --non-interactive --profile 'archive' compile --path 'ch1.tex'");
+
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("packages/mypkg")).unwrap();
+        std::fs::create_dir_all(root.join("figures")).unwrap();
+        for file in ["main.tex", "main.pdf", "tpmgr.toml", "figures/plot.png", "packages/registry.json", "packages/mypkg/mypkg.sty"] {
+            std::fs::write(root.join(file), file).unwrap();
+        }
+        let files = bundle_files(root, "packages", &[root.join("main.pdf")]).unwrap();
+        let names: Vec<String> = files.iter().map(|file| file.to_string_lossy().replace('\\', "/")).collect();
+        assert_eq!(names, vec!["figures/plot.png", "main.tex", "packages/mypkg/mypkg.sty", "packages/registry.json", "tpmgr.toml"]);
+
+        let mut bundle = Vec::new();
+        write_bundle(root, &files, &mut bundle).unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bundle.as_slice()));
+        assert_eq!(archive.entries().unwrap().count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_compile_over_http() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let responses = [
+                "{\"log\":\"This is pdfTeX\\n\"}\n{\"success\":true,\"job\":\"7\",\"outputs\":[\"main.pdf\"]}\n",
+                "%PDF-1.5",
+            ];
+            for body in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 64 * 1024];
+                let _ = stream.read(&mut request).await.unwrap();
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let success = compile_over_http(&server, b"bundle".to_vec(), None, None, dir.path()).await.unwrap();
+        assert!(success);
+        assert_eq!(std::fs::read_to_string(dir.path().join("main.pdf")).unwrap(), "%PDF-1.5");
+    }
+}
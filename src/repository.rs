@@ -0,0 +1,533 @@
+use crate::config::{Config, GlobalConfig, Repository, RepositoryKind};
+use crate::download;
+use crate::query::SearchQuery;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long a downloaded CTAN package list is reused
+const CTAN_PACKAGE_LIST_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where CTAN package archives are downloaded from
+const CTAN_DOWNLOAD_URL: &str = "https://mirrors.ctan.org";
+
+/// How long the index of a remote self-hosted repository is reused
+const INDEX_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Index file at the root of a self-hosted repository
+pub const INDEX_FILE: &str = "index.json";
+
+/// Version of the index format written by `tpmgr publish`
+pub const INDEX_FORMAT: u32 = 1;
+
+/// Index of a self-hosted repository: `index.json` next to the archives it lists.
+/// The repository may live in a local directory or be served over HTTP(S)
+/// (a web server or object store bucket).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RepositoryIndex {
+    pub format: u32,
+    #[serde(default)]
+    pub packages: Vec<IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+    /// Archive path relative to the index, e.g. archives/mystyle-1.0.tar.gz
+    pub archive: String,
+    /// Hex SHA-256 of the archive
+    pub sha256: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl RepositoryIndex {
+    /// Load an index file; a missing file is an empty repository
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self {
+                format: INDEX_FORMAT,
+                packages: Vec::new(),
+            });
+        }
+        let index: Self = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if index.format > INDEX_FORMAT {
+            anyhow::bail!("{} uses index format {}, this tpmgr supports up to {}", path.display(), index.format, INDEX_FORMAT);
+        }
+        Ok(index)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Add a package, replacing an earlier entry with the same name
+    pub fn upsert(&mut self, entry: IndexEntry) {
+        self.packages.retain(|package| package.name != entry.name);
+        self.packages.push(entry);
+        self.packages.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+}
+
+/// A package as offered by a repository
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemotePackage {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub dependencies: Vec<String>,
+    /// Archive URL; None when the package is only known from a listing
+    pub download_url: Option<String>,
+    pub checksum: Option<String>,
+    pub size: u64,
+    /// Name of the repository that provides the package
+    pub repository: String,
+}
+
+/// Merge global and project repositories and order them by priority.
+/// Project entries replace global ones with the same name; the TeX Live
+/// default (the CTAN redirector) is replaced by the selected mirror, with
+/// the mirror's archive template if it has one.
+pub fn resolve_repositories(global: &[Repository], project: &[Repository], mirror_url: &str, archive_template: Option<&str>) -> Vec<Repository> {
+    let mut repositories: Vec<Repository> = global
+        .iter()
+        .filter(|repository| !project.iter().any(|p| p.name == repository.name))
+        .cloned()
+        .collect();
+    repositories.extend(project.iter().cloned());
+    if repositories.is_empty() {
+        repositories = Config::default_repositories();
+    }
+
+    let default_url = crate::mirror::DEFAULT_TLNET_URL.trim_end_matches('/');
+    for repository in &mut repositories {
+        if repository.kind() == RepositoryKind::TexLive && repository.url.trim_end_matches('/') == default_url {
+            repository.url = mirror_url.to_string();
+            repository.archive_template = archive_template.map(str::to_string);
+        }
+    }
+
+    repositories.sort_by_key(|repository| repository.priority);
+    repositories
+}
+
+/// The repositories of a project, in the order they are consulted
+pub fn project_repositories(config: &Config) -> Vec<Repository> {
+    let global = GlobalConfig::load().unwrap_or_else(|_| GlobalConfig::new());
+    let mirror_url = config.resolved_mirror_url();
+    let archive_template = mirror_archive_template(&global, &mirror_url);
+    resolve_repositories(&global.repositories, &config.repositories, &mirror_url, archive_template.as_deref())
+}
+
+/// The archive template of the mirror serving `mirror_url`, wherever that URL
+/// was configured: the selected mirror's, or that of a custom mirror
+fn mirror_archive_template(global: &GlobalConfig, mirror_url: &str) -> Option<String> {
+    let same_url = |url: &str| url.trim_end_matches('/') == mirror_url.trim_end_matches('/');
+    if let (Some(url), Some(selection)) = (&global.mirror_url, &global.mirror) {
+        if same_url(url) {
+            return selection.archive_template.clone();
+        }
+    }
+    global
+        .custom_mirrors
+        .iter()
+        .map(crate::mirror::Mirror::from_custom)
+        .find(|mirror| same_url(&mirror.tlnet_url()) && !mirror.has_standard_archives())
+        .map(|mirror| mirror.archive_template())
+}
+
+/// Find a package in the first repository that provides it as an archive
+/// tpmgr can unpack; one only offered otherwise (such as a CTAN .zip) is
+/// returned from the first repository that has it. Archives named by the
+/// CTAN catalogue are downloaded from `mirror_url`'s CTAN tree.
+pub async fn find_package(repositories: &[Repository], mirror_url: &str, name: &str) -> Option<RemotePackage> {
+    let mut uninstallable = None;
+    for repository in repositories {
+        let found = match repository.kind() {
+            RepositoryKind::TexLive => texlive_packages(repository)
+                .await
+                .map(|packages| packages.into_iter().find(|package| package.name == name)),
+            RepositoryKind::Ctan => ctan_package(repository, &ctan_download_root(mirror_url), name).await,
+            RepositoryKind::Index => index_packages(repository)
+                .await
+                .map(|packages| packages.into_iter().find(|package| package.name == name)),
+        };
+        match found {
+            Ok(Some(package)) if package.download_url.as_deref().is_some_and(crate::package::is_unpackable) => return Some(package),
+            Ok(Some(package)) => {
+                uninstallable.get_or_insert(package);
+            }
+            Ok(None) => {}
+            Err(e) => println!("Warning: Repository '{}' is unavailable: {}", repository.name, e),
+        }
+    }
+    uninstallable
+}
+
+/// Search the name and description of the packages in all repositories,
+/// best matches first (see `SearchQuery::rank`); a package offered by
+/// several repositories is reported from the first one
+pub async fn search(repositories: &[Repository], query: &SearchQuery) -> Vec<RemotePackage> {
+    let mut results: Vec<(u8, RemotePackage)> = Vec::new();
+
+    for repository in repositories {
+        let packages = match repository.kind() {
+            RepositoryKind::TexLive => texlive_packages(repository).await,
+            RepositoryKind::Ctan => ctan_packages(repository).await,
+            RepositoryKind::Index => index_packages(repository).await,
+        };
+        let packages = match packages {
+            Ok(packages) => packages,
+            Err(e) => {
+                println!("Warning: Repository '{}' is unavailable: {}", repository.name, e);
+                continue;
+            }
+        };
+
+        for package in packages {
+            let Some(rank) = query.rank(&package) else {
+                continue;
+            };
+            if !results.iter().any(|(_, result)| result.name == package.name) {
+                results.push((rank, package));
+            }
+        }
+    }
+
+    results.sort_by(|(a_rank, a), (b_rank, b)| a_rank.cmp(b_rank).then_with(|| a.name.cmp(&b.name)));
+    results.into_iter().map(|(_, package)| package).collect()
+}
+
+async fn texlive_packages(repository: &Repository) -> Result<Vec<RemotePackage>> {
+    let cache_path = download::index_dir()?.join(format!("{}.tlpdb", repository.name));
+    let url = format!("{}/tlpkg/texlive.tlpdb", repository.url.trim_end_matches('/'));
+    let _progress = crate::ui::Progress::start(&format!("Loading the package index of {}", repository.name), "");
+    download::fetch_cached(&url, &cache_path, crate::texlive::REMOTE_TLPDB_MAX_AGE).await?;
+
+    let content = std::fs::read_to_string(&cache_path)?;
+    Ok(parse_tlpdb_packages(&content, repository))
+}
+
+/// Read the package records of a texlive.tlpdb. Platform-specific binary
+/// packages (name.arch) and installer internals (00texlive.*) are skipped.
+pub fn parse_tlpdb_packages(content: &str, repository: &Repository) -> Vec<RemotePackage> {
+    let base_url = repository.url.trim_end_matches('/');
+    let mut packages = Vec::new();
+
+    for record in content.split("\n\n") {
+        let mut package = RemotePackage {
+            name: String::new(),
+            version: String::new(),
+            description: String::new(),
+            dependencies: Vec::new(),
+            download_url: None,
+            checksum: None,
+            size: 0,
+            repository: repository.name.clone(),
+        };
+        let mut revision = String::new();
+
+        for line in record.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            match key {
+                "name" => package.name = value.trim().to_string(),
+                "revision" => revision = value.trim().to_string(),
+                "catalogue-version" => package.version = value.trim().to_string(),
+                "shortdesc" => package.description = value.trim().to_string(),
+                "depend" if !value.contains(".ARCH") => package.dependencies.push(value.trim().to_string()),
+                "containersize" => package.size = value.trim().parse().unwrap_or(0),
+                "containerchecksum" => package.checksum = Some(format!("sha512:{}", value.trim())),
+                _ => {}
+            }
+        }
+
+        if package.name.is_empty() || package.name.contains('.') || package.name.starts_with("00texlive") {
+            continue;
+        }
+        if package.version.is_empty() && !revision.is_empty() {
+            package.version = format!("r{}", revision);
+        }
+        package.download_url = Some(match &repository.archive_template {
+            Some(template) => template.replace("{package}", &package.name),
+            None => format!("{}/archive/{}.tar.xz", base_url, package.name),
+        });
+        packages.push(package);
+    }
+
+    packages
+}
+
+/// Packages of a self-hosted repository; remote indexes are cached briefly
+async fn index_packages(repository: &Repository) -> Result<Vec<RemotePackage>> {
+    let base = repository.url.trim_end_matches('/');
+    let base = base.strip_suffix(INDEX_FILE).unwrap_or(base).trim_end_matches('/');
+
+    if base.starts_with("http://") || base.starts_with("https://") {
+        let cache_path = download::index_dir()?.join(format!("{}-{}", repository.name, INDEX_FILE));
+        download::fetch_cached(&format!("{}/{}", base, INDEX_FILE), &cache_path, INDEX_MAX_AGE).await?;
+        let index = RepositoryIndex::load(&cache_path)?;
+        Ok(packages_from_index(index, repository, |archive| format!("{}/{}", base, archive)))
+    } else {
+        // Absolute without canonicalizing, which on Windows gives \\?\ paths
+        let root = PathBuf::from(base.strip_prefix("file://").unwrap_or(base));
+        let root = std::path::absolute(&root).unwrap_or(root);
+        let index = RepositoryIndex::load(&root.join(INDEX_FILE))?;
+        Ok(packages_from_index(index, repository, |archive| {
+            let path = archive.split('/').fold(root.clone(), |path, part| path.join(part));
+            format!("file://{}", path.display())
+        }))
+    }
+}
+
+/// Packages of a repository index; `archive_url` turns an archive path
+/// relative to the index into its download URL
+fn packages_from_index(index: RepositoryIndex, repository: &Repository, archive_url: impl Fn(&str) -> String) -> Vec<RemotePackage> {
+    index
+        .packages
+        .into_iter()
+        .map(|entry| RemotePackage {
+            download_url: Some(archive_url(entry.archive.trim_start_matches('/'))),
+            checksum: Some(format!("sha256:{}", entry.sha256)),
+            name: entry.name,
+            version: entry.version,
+            description: entry.description,
+            dependencies: entry.dependencies,
+            size: entry.size,
+            repository: repository.name.clone(),
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct CtanListEntry {
+    key: String,
+    #[serde(default)]
+    caption: String,
+}
+
+#[derive(Deserialize)]
+struct CtanPackage {
+    id: String,
+    #[serde(default)]
+    caption: String,
+    version: Option<CtanVersion>,
+    ctan: Option<CtanLocation>,
+}
+
+#[derive(Deserialize)]
+struct CtanVersion {
+    #[serde(default)]
+    number: String,
+}
+
+#[derive(Deserialize)]
+struct CtanLocation {
+    path: String,
+    #[serde(default)]
+    file: bool,
+}
+
+/// All packages in the CTAN catalogue, from the cached package list
+async fn ctan_packages(repository: &Repository) -> Result<Vec<RemotePackage>> {
+    let cache_path = download::index_dir()?.join(format!("{}-packages.json", repository.name));
+    let url = format!("{}/json/2.0/packages", repository.url.trim_end_matches('/'));
+    download::fetch_cached(&url, &cache_path, CTAN_PACKAGE_LIST_MAX_AGE).await?;
+
+    let entries: Vec<CtanListEntry> = serde_json::from_str(&std::fs::read_to_string(&cache_path)?)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| RemotePackage {
+            name: entry.key,
+            version: String::new(),
+            description: entry.caption,
+            dependencies: Vec::new(),
+            download_url: None,
+            checksum: None,
+            size: 0,
+            repository: repository.name.clone(),
+        })
+        .collect())
+}
+
+/// The CTAN root of a TeX Live mirror URL (`<root>/systems/texlive/tlnet`);
+/// the CTAN redirector when the URL is not laid out like a CTAN mirror
+fn ctan_download_root(mirror_url: &str) -> String {
+    mirror_url
+        .trim_end_matches('/')
+        .strip_suffix("/systems/texlive/tlnet")
+        .unwrap_or(CTAN_DOWNLOAD_URL)
+        .to_string()
+}
+
+/// Look up one package in the CTAN catalogue; archives are downloaded from
+/// the CTAN tree at `download_root`
+async fn ctan_package(repository: &Repository, download_root: &str, name: &str) -> Result<Option<RemotePackage>> {
+    let url = format!("{}/json/2.0/pkg/{}", repository.url.trim_end_matches('/'), name);
+    let response = crate::download::http_client()?.get(&url).send().await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let answer: serde_json::Value = response.error_for_status()?.json().await?;
+    // Unknown packages are answered with an error object
+    if answer.get("errors").is_some() {
+        return Ok(None);
+    }
+    let package: CtanPackage =
+        serde_json::from_value(answer).map_err(|e| anyhow::anyhow!("Unexpected answer from {}: {}", url, e))?;
+
+    let download_url = package.ctan.map(|location| {
+        if location.file {
+            format!("{}{}", download_root, location.path)
+        } else {
+            format!("{}{}.zip", download_root, location.path.trim_end_matches('/'))
+        }
+    });
+    Ok(Some(RemotePackage {
+        name: package.id,
+        version: package.version.map(|version| version.number).unwrap_or_default(),
+        description: package.caption,
+        dependencies: Vec::new(),
+        download_url,
+        checksum: None,
+        size: 0,
+        repository: repository.name.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repository(name: &str, url: &str, priority: u8) -> Repository {
+        Repository {
+            name: name.to_string(),
+            url: url.to_string(),
+            priority,
+            kind: None,
+            archive_template: None,
+        }
+    }
+
+    #[test]
+    fn test_resolve_repositories() {
+        let global = vec![
+            repository("campus", "https://tex.example.edu/tlnet/", 1),
+            repository("ctan", "https://ctan.org/", 5),
+        ];
+        let project = vec![
+            repository("ctan", "https://ctan.org/", 3),
+            repository("texlive", crate::mirror::DEFAULT_TLNET_URL, 2),
+        ];
+        let mirror = "https://mirrors.ustc.edu.cn/CTAN/systems/texlive/tlnet/";
+
+        let resolved = resolve_repositories(&global, &project, mirror, Some("https://mirrors.ustc.edu.cn/texlive-archive/{package}.tar.xz"));
+        let order: Vec<(&str, u8)> = resolved.iter().map(|r| (r.name.as_str(), r.priority)).collect();
+        assert_eq!(order, vec![("campus", 1), ("texlive", 2), ("ctan", 3)]);
+        assert_eq!(resolved[1].url, mirror);
+        assert!(resolved[1].archive_template.is_some() && resolved[0].archive_template.is_none());
+        let packages = parse_tlpdb_packages("name siunitx\nrevision 1\n", &resolved[1]);
+        assert_eq!(packages[0].download_url.as_deref(), Some("https://mirrors.ustc.edu.cn/texlive-archive/siunitx.tar.xz"));
+        assert_eq!(resolved[2].kind(), RepositoryKind::Ctan);
+        assert_eq!(resolved[0].kind(), RepositoryKind::TexLive);
+
+        assert_eq!(resolve_repositories(&[], &[], mirror, None).len(), 2);
+    }
+
+    #[test]
+    fn test_ctan_download_root() {
+        assert_eq!(ctan_download_root("https://mirrors.ustc.edu.cn/CTAN/systems/texlive/tlnet/"), "https://mirrors.ustc.edu.cn/CTAN");
+        assert_eq!(ctan_download_root(crate::mirror::DEFAULT_TLNET_URL), "https://mirror.ctan.org");
+        assert_eq!(ctan_download_root("https://tex.example.edu/tlnet"), CTAN_DOWNLOAD_URL);
+
+        // The redirector is replaced by the selected mirror with or without a trailing slash
+        let project = vec![repository("texlive", "https://mirror.ctan.org/systems/texlive/tlnet", 1)];
+        let mirror = "https://mirrors.ustc.edu.cn/CTAN/systems/texlive/tlnet/";
+        assert_eq!(resolve_repositories(&[], &project, mirror, None)[0].url, mirror);
+    }
+
+    #[tokio::test]
+    async fn test_find_package_prefers_installable_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut repositories = Vec::new();
+        for (name, archive) in [("zips", "mystyle.zip"), ("tarballs", "archives/mystyle-1.0.tar.gz")] {
+            let root = dir.path().join(name);
+            std::fs::create_dir_all(&root).unwrap();
+            let index = format!(
+                r#"{{"format": 1, "packages": [{{"name": "mystyle", "version": "1.0", "archive": "{}", "sha256": "00"}}]}}"#,
+                archive
+            );
+            std::fs::write(root.join(INDEX_FILE), index).unwrap();
+            repositories.push(repository(name, &root.to_string_lossy(), 1));
+        }
+
+        let found = find_package(&repositories, crate::mirror::DEFAULT_TLNET_URL, "mystyle").await.unwrap();
+        assert_eq!(found.repository, "tarballs");
+        // Local archives are joined as paths, so download::fetch can copy them on any platform
+        let archive = dir.path().join("tarballs").join("archives").join("mystyle-1.0.tar.gz");
+        assert_eq!(found.download_url, Some(format!("file://{}", archive.display())));
+        let found = find_package(&repositories[..1], crate::mirror::DEFAULT_TLNET_URL, "mystyle").await.unwrap();
+        assert_eq!(found.repository, "zips");
+        assert!(find_package(&repositories, crate::mirror::DEFAULT_TLNET_URL, "other").await.is_none());
+    }
+
+    #[test]
+    fn test_mirror_archive_template() {
+        let mut global = GlobalConfig::new();
+        global.custom_mirrors.push(crate::config::CustomMirror {
+            name: "lab".to_string(),
+            url: "https://tex.example.edu/tlnet".to_string(),
+            index_template: None,
+            archive_template: Some("https://files.example.edu/{package}.tar.xz".to_string()),
+        });
+        global.custom_mirrors.push(crate::config::CustomMirror {
+            name: "plain".to_string(),
+            url: "https://plain.example.edu/tlnet".to_string(),
+            index_template: None,
+            archive_template: None,
+        });
+
+        // A project mirror_url keeps the template of the mirror it points to
+        let template = mirror_archive_template(&global, "https://tex.example.edu/tlnet/");
+        assert_eq!(template.as_deref(), Some("https://files.example.edu/{package}.tar.xz"));
+        assert_eq!(mirror_archive_template(&global, "https://plain.example.edu/tlnet/"), None);
+        assert_eq!(mirror_archive_template(&global, crate::mirror::DEFAULT_TLNET_URL), None);
+
+        global.mirror_url = Some("https://sel.example.edu/tlnet/".to_string());
+        global.mirror = Some(crate::config::MirrorSelection {
+            name: "sel".to_string(),
+            url: "https://sel.example.edu".to_string(),
+            latency_ms: None,
+            archive_template: Some("https://sel.example.edu/pkgs/{package}.tar.xz".to_string()),
+        });
+        let template = mirror_archive_template(&global, "https://sel.example.edu/tlnet");
+        assert_eq!(template.as_deref(), Some("https://sel.example.edu/pkgs/{package}.tar.xz"));
+    }
+
+    #[test]
+    fn test_parse_tlpdb_packages() {
+        let content = "name 00texlive.config\ncategory TLCore\n\n\
+                       name siunitx\ncategory Package\nrevision 69784\nshortdesc A comprehensive (SI) units package\n\
+                       depend l3kernel\ncontainersize 123456\ncontainerchecksum abc\ncatalogue-version 3.3.19\n\n\
+                       name xetex\ncategory TLCore\nrevision 70000\ndepend xetex.ARCH\n\n\
+                       name xetex.x86_64-linux\ncategory TLCore\n";
+        let packages = parse_tlpdb_packages(content, &repository("texlive", "https://example.org/tlnet/", 1));
+
+        let names: Vec<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["siunitx", "xetex"]);
+        assert_eq!(packages[0].version, "3.3.19");
+        assert_eq!(packages[0].dependencies, vec!["l3kernel"]);
+        assert_eq!(packages[0].download_url.as_deref(), Some("https://example.org/tlnet/archive/siunitx.tar.xz"));
+        assert_eq!(packages[0].size, 123456);
+        assert_eq!(packages[1].version, "r70000");
+        assert!(packages[1].dependencies.is_empty());
+    }
+}
@@ -33,7 +33,7 @@ impl DependencyResolver {
     pub fn add_package(&mut self, package: ResolvedPackage) {
         self.packages
             .entry(package.name.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(package);
     }
     
@@ -84,7 +84,7 @@ impl DependencyResolver {
         }
     }
     
-    fn sort_by_dependencies(&self, packages: &mut Vec<ResolvedPackage>) -> Result<()> {
+    fn sort_by_dependencies(&self, packages: &mut [ResolvedPackage]) -> Result<()> {
         // Topological sort to ensure dependencies are installed before dependents
         let mut graph: HashMap<String, Vec<String>> = HashMap::new();
         let mut in_degree: HashMap<String, usize> = HashMap::new();
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::diagnostics::BufferAnalyzer;
+use crate::package::PackageManager;
+
+/// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(error: anyhow::Error) -> Self {
+        Self::new(SERVER_ERROR, error.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct AnalyzeParams {
+    file: PathBuf,
+    /// Unsaved contents of the file; read from disk when absent
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct InstallParams {
+    packages: Vec<String>,
+    #[serde(default)]
+    global: bool,
+}
+
+/// Answers JSON-RPC requests (one JSON object per line): `analyze`,
+/// `install`, `status` and `shutdown`
+pub struct Server {
+    analyzer: BufferAnalyzer,
+    shutdown: bool,
+}
+
+impl Server {
+    pub fn new(analyzer: BufferAnalyzer) -> Self {
+        Self { analyzer, shutdown: false }
+    }
+
+    /// The response to one request line; None for notifications (no id)
+    pub async fn handle_line(&mut self, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, RpcError::new(PARSE_ERROR, format!("Parse error: {}", e)))),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            return Some(error_response(id.unwrap_or(Value::Null), RpcError::new(INVALID_REQUEST, "Request has no method")));
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = self.call(method, params).await;
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => error_response(id, error),
+        })
+    }
+
+    async fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "analyze" => {
+                let params: AnalyzeParams = parse_params(params)?;
+                let text = match params.text {
+                    Some(text) => text,
+                    None => crate::tex_parser::TeXParser::read_source(&params.file)?,
+                };
+                let report = self.analyzer.analyze(&params.file, &text).await?;
+                Ok(serde_json::to_value(report).map_err(anyhow::Error::from)?)
+            }
+            "install" => {
+                let params: InstallParams = parse_params(params)?;
+                install(&params.packages, params.global).await
+            }
+            "status" => self.status().await,
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+        }
+    }
+
+    /// The project root, TeX Live and the project's installed packages
+    async fn status(&self) -> Result<Value, RpcError> {
+        let mut packages = PackageManager::read_only(false)?.list_installed().await?;
+        packages.sort();
+        let texlive = self.analyzer.texlive().and_then(|texlive| texlive.get_texlive_info()).map(|info| {
+            json!({ "version": info.version, "path": info.install_path, "distribution": info.distribution })
+        });
+        Ok(json!({
+            "project_root": crate::config::project_root(),
+            "texlive": texlive,
+            "packages": packages
+                .into_iter()
+                .map(|(name, version)| json!({ "name": name, "version": version }))
+                .collect::<Vec<_>>(),
+        }))
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, format!("Invalid params: {}", e)))
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": error.code, "message": error.message } })
+}
+
+/// Install packages, reporting each failure instead of stopping at the first
+async fn install(packages: &[String], global: bool) -> Result<Value, RpcError> {
+    let manager = PackageManager::new(global)?;
+    let mut installed = Vec::new();
+    let mut failed = Vec::new();
+    for package in packages {
+        match manager.install(package).await {
+            Ok(_) => installed.push(package.clone()),
+            Err(e) => failed.push(json!({ "package": package, "error": e.to_string() })),
+        }
+    }
+    if !installed.is_empty() {
+        if let Err(e) = manager.apply_cache_policy().await {
+            println!("Warning: Failed to clean cache: {}", e);
+        }
+    }
+    Ok(json!({ "installed": installed, "failed": failed }))
+}
+
+/// Serve requests read line by line from `reader` until it ends or the
+/// client sends "shutdown"
+pub async fn serve_stream<R, W>(server: &mut Server, reader: R, mut writer: W) -> Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = reader.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = server.handle_line(&line).await {
+            writer.write_all(format!("{}\n", response).as_bytes()).await?;
+            writer.flush().await?;
+        }
+        if server.shutdown {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Serve clients connecting to a Unix socket at `path`, one at a time,
+/// until one sends "shutdown"
+#[cfg(unix)]
+pub async fn serve_socket(server: &mut Server, path: &Path) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by a server that did not shut down cleanly
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", path.display(), e))?;
+    println!("Listening on {}", path.display());
+
+    while !server.shutdown {
+        let (stream, _) = listener.accept().await?;
+        let (reader, writer) = stream.into_split();
+        if let Err(e) = serve_stream(server, tokio::io::BufReader::new(reader), writer).await {
+            println!("Warning: Connection closed: {}", e);
+        }
+    }
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn serve_socket(_server: &mut Server, _path: &Path) -> Result<()> {
+    anyhow::bail!("--socket needs Unix domain sockets; use stdio on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_json_rpc_requests() {
+        let analyzer = BufferAnalyzer::with_texlive(crate::tex_parser::TeXParser::new().unwrap(), None);
+        let mut server = Server::new(analyzer);
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("chapter3.tex");
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "analyze",
+            "params": { "file": file, "text": "\\usepackage{tpmgrtestpkg}\n" },
+        });
+        let response = server.handle_line(&request.to_string()).await.unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["missing"], json!(["tpmgrtestpkg"]));
+        assert_eq!(response["result"]["diagnostics"][0]["column"], 13);
+
+        let response = server.handle_line(r#"{"jsonrpc":"2.0","id":2,"method":"compile"}"#).await.unwrap();
+        assert_eq!(response["error"]["code"], METHOD_NOT_FOUND);
+        let response = server.handle_line(r#"{"jsonrpc":"2.0","id":3,"method":"install","params":{}}"#).await.unwrap();
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        let response = server.handle_line("{not json").await.unwrap();
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+
+        // Notifications get no response
+        assert!(server.handle_line(r#"{"jsonrpc":"2.0","method":"shutdown"}"#).await.is_none());
+        assert!(server.shutdown);
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Where a copy of a package is installed, in the order kpathsea searches
+/// them: the project's packages/ comes first in TEXINPUTS, and TEXMFHOME
+/// (global installs) precedes the TeX Live tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Project,
+    Global,
+    TeXLive,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Project => write!(f, "project"),
+            Scope::Global => write!(f, "global"),
+            Scope::TeXLive => write!(f, "TeX Live"),
+        }
+    }
+}
+
+/// A package installed in more than one scope
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shadowing {
+    pub name: String,
+    /// Scope and version of each copy in search order; the first one is used
+    pub copies: Vec<(Scope, String)>,
+}
+
+impl Shadowing {
+    /// The copy TeX loads
+    pub fn winner(&self) -> &(Scope, String) {
+        &self.copies[0]
+    }
+
+    /// The copies in search order, e.g. "project 1.1 > TeX Live 2.0"
+    pub fn describe(&self) -> String {
+        self.copies
+            .iter()
+            .map(|(scope, version)| match version.as_str() {
+                "unknown" => scope.to_string(),
+                _ => format!("{} {}", scope, version),
+            })
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+
+    /// Whether the copies have different known versions
+    pub fn has_version_skew(&self) -> bool {
+        let mut versions = self.copies.iter().map(|(_, version)| version).filter(|version| *version != "unknown");
+        let Some(first) = versions.next() else {
+            return false;
+        };
+        versions.any(|version| version != first)
+    }
+}
+
+/// Packages installed in more than one scope, by name. `texlive_version`
+/// gives the version of a package in TeX Live, if TeX Live has it.
+pub fn find_shadowed(
+    project: &[(String, String)],
+    global: &[(String, String)],
+    texlive_version: impl Fn(&str) -> Option<String>,
+) -> Vec<Shadowing> {
+    let mut copies: HashMap<&str, Vec<(Scope, String)>> = HashMap::new();
+    for (scope, packages) in [(Scope::Project, project), (Scope::Global, global)] {
+        for (name, version) in packages {
+            copies.entry(name).or_default().push((scope, version.clone()));
+        }
+    }
+
+    let mut shadowed: Vec<Shadowing> = copies
+        .into_iter()
+        .filter_map(|(name, mut copies)| {
+            if let Some(version) = texlive_version(name) {
+                copies.push((Scope::TeXLive, version));
+            }
+            copies.sort_by_key(|(scope, _)| *scope);
+            (copies.len() > 1).then(|| Shadowing { name: name.to_string(), copies })
+        })
+        .collect();
+    shadowed.sort_by(|a, b| a.name.cmp(&b.name));
+    shadowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_shadowed() {
+        let project = vec![("siunitx".to_string(), "3.2".to_string()), ("labstyle".to_string(), "1.0".to_string())];
+        let global = vec![("siunitx".to_string(), "3.1".to_string()), ("tikz".to_string(), "3.1".to_string())];
+        let texlive = |name: &str| match name {
+            "siunitx" => Some("3.3".to_string()),
+            "tikz" => Some("unknown".to_string()),
+            _ => None,
+        };
+
+        let shadowed = find_shadowed(&project, &global, texlive);
+        assert_eq!(shadowed.len(), 2);
+
+        assert_eq!(shadowed[0].name, "siunitx");
+        assert_eq!(shadowed[0].winner(), &(Scope::Project, "3.2".to_string()));
+        assert_eq!(shadowed[0].copies.iter().map(|(scope, _)| *scope).collect::<Vec<_>>(), vec![Scope::Project, Scope::Global, Scope::TeXLive]);
+        assert!(shadowed[0].has_version_skew());
+        assert_eq!(shadowed[0].describe(), "project 3.2 > global 3.1 > TeX Live 3.3");
+
+        assert_eq!(shadowed[1].name, "tikz");
+        assert_eq!(shadowed[1].winner().0, Scope::Global);
+        assert!(!shadowed[1].has_version_skew());
+    }
+}
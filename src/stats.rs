@@ -0,0 +1,130 @@
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::manifest::BuildManifest;
+use crate::tex_parser::{DependencyType, TeXDependency};
+
+/// What the project's sources contain, as counted by `tpmgr stats`
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct SourceStats {
+    pub source_files: usize,
+    /// Files with a \documentclass
+    pub documents: usize,
+    /// Distinct \input/\include/\subfile/\import targets
+    pub included_files: usize,
+    /// Distinct \includegraphics targets
+    pub figures: usize,
+    /// Distinct citation keys
+    pub citations: usize,
+    pub bibliographies: usize,
+}
+
+impl SourceStats {
+    pub fn count(dependencies: &[TeXDependency], source_files: usize) -> Self {
+        let distinct = |types: &[DependencyType]| {
+            dependencies
+                .iter()
+                .filter(|dep| types.contains(&dep.dependency_type))
+                .map(|dep| dep.package_name.as_str())
+                .collect::<BTreeSet<_>>()
+                .len()
+        };
+        let documents: BTreeSet<&Path> = dependencies
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::DocumentClass)
+            .filter_map(|dep| dep.source_file.as_deref())
+            .collect();
+
+        Self {
+            source_files,
+            documents: documents.len(),
+            included_files: distinct(&[DependencyType::Input, DependencyType::Include, DependencyType::Subfile, DependencyType::Import]),
+            figures: distinct(&[DependencyType::Graphic]),
+            citations: distinct(&[DependencyType::Citation]),
+            bibliographies: distinct(&[DependencyType::Bibliography]),
+        }
+    }
+}
+
+/// The last successful build, from its build manifest
+#[derive(Serialize, Debug, PartialEq)]
+pub struct LastBuild {
+    pub built_at: u64,
+    pub duration_seconds: f64,
+    pub cached: bool,
+    pub outputs: usize,
+    /// Total size of the outputs in bytes
+    pub output_size: u64,
+    /// Sources changed after the build finished
+    pub changed_sources: usize,
+}
+
+impl LastBuild {
+    /// Read the build manifest of `project_root`, if the project was built
+    pub fn load(project_root: &Path, sources: &[PathBuf]) -> Option<Self> {
+        let manifest = BuildManifest::load(project_root)?;
+        let changed_sources = sources
+            .iter()
+            .filter(|source| modified_at(source).is_some_and(|modified| modified > manifest.built_at))
+            .count();
+        Some(Self {
+            built_at: manifest.built_at,
+            duration_seconds: manifest.duration_seconds,
+            cached: manifest.cached,
+            outputs: manifest.outputs.len(),
+            output_size: manifest.outputs.iter().map(|output| output.size).sum(),
+            changed_sources,
+        })
+    }
+}
+
+/// Unix time `path` was last modified
+fn modified_at(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tex_parser::TeXParser;
+
+    #[test]
+    fn test_project_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.tex");
+        std::fs::write(
+            &main,
+            "\\documentclass{article}\n\\usepackage{graphicx}\n\\begin{document}\n\\input{intro}\n\\include{chapter}\n\\input{intro}\n\\includegraphics{fig/a}\n\\includegraphics{fig/b}\n\\cite{knuth84,lamport94}\n\\cite{knuth84}\n\\bibliography{refs}\n\\end{document}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("intro.tex"), "\\includegraphics{fig/a}\n").unwrap();
+
+        let parser = TeXParser::new().unwrap();
+        let dependencies = parser.parse_project(dir.path()).unwrap();
+        assert_eq!(
+            SourceStats::count(&dependencies, 2),
+            SourceStats {
+                source_files: 2,
+                documents: 1,
+                included_files: 2,
+                figures: 2,
+                citations: 2,
+                bibliographies: 1,
+            }
+        );
+
+        let sources = vec![main];
+        assert_eq!(LastBuild::load(dir.path(), &sources), None);
+        std::fs::create_dir_all(dir.path().join(".tpmgr")).unwrap();
+        std::fs::write(
+            BuildManifest::path(dir.path()),
+            r#"{"project":"p","version":"0.1.0","built_at":1,"duration_seconds":2.5,"cached":false,"chain":[],"outputs":[{"path":"main.pdf","sha256":"00","size":1024}],"tools":{},"registry_sha256":null,"git":null}"#,
+        )
+        .unwrap();
+        let build = LastBuild::load(dir.path(), &sources).unwrap();
+        assert_eq!((build.outputs, build.output_size, build.changed_sources), (1, 1024, 1));
+    }
+}
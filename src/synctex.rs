@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::process::Command;
+
+/// Viewers `tpmgr synctex view --viewer` knows how to drive
+pub const VIEWERS: &[&str] = &["zathura", "okular", "sumatrapdf", "skim", "qpdfview"];
+
+/// Where Skim keeps its forward-search helper
+const SKIM_DISPLAYLINE: &str = "/Applications/Skim.app/Contents/SharedSupport/displayline";
+
+/// A spot in the PDF that a source line was typeset at
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PdfLocation {
+    pub page: u32,
+    /// Coordinates in big points from the top left corner of the page
+    pub x: f64,
+    pub y: f64,
+}
+
+/// The records `synctex view` prints, one `Page:`/`x:`/`y:` group each
+pub fn parse_view_output(output: &str) -> Vec<PdfLocation> {
+    let mut locations = Vec::new();
+    let mut page = None;
+    let mut x = None;
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key {
+            "Page" => page = value.trim().parse().ok(),
+            "x" => x = value.trim().parse().ok(),
+            "y" => {
+                if let (Some(page), Some(x), Ok(y)) = (page.take(), x.take(), value.trim().parse()) {
+                    locations.push(PdfLocation { page, x, y });
+                }
+            }
+            _ => {}
+        }
+    }
+    locations
+}
+
+/// Ask TeX Live's `synctex` tool where `line` of `source` is in `pdf`
+pub fn locate(pdf: &Path, source: &Path, line: u32, column: u32, project_root: &Path, environment: &[(String, String)]) -> Result<Vec<PdfLocation>> {
+    let output = Command::new("synctex")
+        .arg("view")
+        .arg("-i")
+        .arg(format!("{}:{}:{}", line, column, source.display()))
+        .arg("-o")
+        .arg(pdf)
+        .current_dir(project_root)
+        .envs(environment.iter().cloned())
+        .output()
+        .context("Failed to run synctex; it comes with TeX Live and must be on PATH")?;
+    if !output.status.success() {
+        anyhow::bail!("synctex failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(parse_view_output(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// The command that makes `viewer` show `line` of `source` in `pdf`
+pub fn forward_search_command(viewer: &str, pdf: &Path, source: &Path, line: u32, column: u32) -> Result<Vec<String>> {
+    let pdf = pdf.display().to_string();
+    let source = source.display().to_string();
+    let line = line.to_string();
+    Ok(match viewer {
+        "zathura" => vec!["zathura".to_string(), "--synctex-forward".to_string(), format!("{}:{}:{}", line, column, source), pdf],
+        "okular" => vec!["okular".to_string(), "--unique".to_string(), format!("{}#src:{}{}", pdf, line, source)],
+        "sumatrapdf" => vec!["SumatraPDF".to_string(), "-reuse-instance".to_string(), pdf, "-forward-search".to_string(), source, line],
+        "skim" => vec![SKIM_DISPLAYLINE.to_string(), line, pdf, source],
+        "qpdfview" => vec!["qpdfview".to_string(), "--unique".to_string(), format!("{}#src:{}:{}:{}", pdf, source, line, column)],
+        _ => anyhow::bail!("Unknown viewer '{}' (use {})", viewer, VIEWERS.join(", ")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_view_output() {
+        let output = "This is SyncTeX command line utility, version 1.5\nSyncTeX result begin\nOutput:/tmp/main.pdf\nPage:2\nx:133.768356\ny:420.108063\nh:133.768356\nv:422.100006\nW:343.711060\nH:9.962640\nbefore:\noffset:0\nmiddle:\nafter:\nOutput:/tmp/main.pdf\nPage:3\nx:72.0\ny:100.5\nSyncTeX result end\n";
+        assert_eq!(
+            parse_view_output(output),
+            vec![
+                PdfLocation { page: 2, x: 133.768356, y: 420.108063 },
+                PdfLocation { page: 3, x: 72.0, y: 100.5 },
+            ]
+        );
+        assert!(parse_view_output("SyncTeX result begin\nSyncTeX result end\n").is_empty());
+
+        let command = forward_search_command("okular", Path::new("/p/main.pdf"), Path::new("/p/ch1.tex"), 42, 0).unwrap();
+        assert_eq!(command[2], "/p/main.pdf#src:42/p/ch1.tex");
+        assert!(forward_search_command("acrobat", Path::new("main.pdf"), Path::new("main.tex"), 1, 0).is_err());
+    }
+}
@@ -1,9 +1,170 @@
 ﻿use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use anyhow::Result;
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::process::{Command, Stdio};
+use crate::command_db::CommandPackageMap;
+use crate::ignore::IgnoreRules;
+
+/// TikZ libraries that are not part of the pgf distribution, mapped to the
+/// CTAN package that ships them. Libraries bundled with pgf itself are
+/// satisfied by loading tikz and do not appear here.
+const TIKZ_LIBRARY_PACKAGES: &[(&str, &str)] = &[
+    ("cd", "tikz-cd"),
+    ("feynman", "tikz-feynman"),
+    ("bayesnet", "tikz-bayesnet"),
+    ("optics", "tikz-optics"),
+    ("3dtools", "tikz-3dtools"),
+    ("tikzmark", "tikzmark"),
+    ("hobby", "hobby"),
+    ("spath3", "spath3"),
+    ("knots", "spath3"),
+    ("calligraphy", "spath3"),
+    ("lake", "tikz-lake-fig"),
+    ("karnaugh", "tikz-karnaugh"),
+    ("trackschematic", "tikz-trackschematic"),
+    ("timing", "tikz-timing"),
+    ("quantikz", "quantikz"),
+    ("quantikz2", "quantikz"),
+    ("tqft", "tqft"),
+];
+
+/// Library name prefixes whose whole family is provided by a single package
+const TIKZ_LIBRARY_PREFIXES: &[(&str, &str)] = &[
+    ("ext.", "tikz-ext"),
+    ("pgfplots.", "pgfplots"),
+];
+
+/// Themes that ship with beamer, by theme kind ("" for \usetheme)
+const BEAMER_BUILTIN_THEMES: &[(&str, &[&str])] = &[
+    ("", &[
+        "default", "AnnArbor", "Antibes", "Bergen", "Berkeley", "Berlin", "Boadilla", "boxes",
+        "CambridgeUS", "Copenhagen", "Darmstadt", "Dresden", "EastLansing", "Frankfurt",
+        "Goettingen", "Hannover", "Ilmenau", "JuanLesPins", "Luebeck", "Madrid", "Malmoe",
+        "Marburg", "Montpellier", "PaloAlto", "Pittsburgh", "Rochester", "Singapore", "Szeged",
+        "Warsaw",
+    ]),
+    ("color", &[
+        "default", "albatross", "beaver", "beetle", "crane", "dolphin", "dove", "fly", "lily",
+        "monarch", "orchid", "rose", "seagull", "seahorse", "sidebartab", "spruce", "structure",
+        "whale", "wolverine",
+    ]),
+    ("font", &[
+        "default", "professionalfonts", "serif", "structurebold", "structureitalicserif",
+        "structuresmallcapsserif",
+    ]),
+    ("inner", &["default", "circles", "inmargin", "rectangles", "rounded"]),
+    ("outer", &[
+        "default", "infolines", "miniframes", "shadow", "sidebar", "smoothbars", "smoothtree",
+        "split", "tree",
+    ]),
+];
+
+/// Third-party beamer themes, by theme file name, mapped to their CTAN package.
+/// Themes not listed here are resolved through the package index by file name.
+const BEAMER_THEME_PACKAGES: &[(&str, &str)] = &[
+    ("beamerthememetropolis", "beamertheme-metropolis"),
+    ("beamerthememoloch", "moloch"),
+    ("beamerthemefocus", "beamertheme-focus"),
+    ("beamerthemetrigon", "beamertheme-trigon"),
+    ("beamerthemesaintpetersburg", "beamertheme-saintpetersburg"),
+    ("beamerthemenpbt", "beamertheme-npbt"),
+    ("beamerthemecuerna", "beamertheme-cuerna"),
+    ("beamerthemelight", "beamertheme-light"),
+    ("beamerthemetcolorbox", "beamertheme-tcolorbox"),
+    ("beamerthemeFeather", "beamertheme-feather"),
+    ("beamerthemedetlevcm", "beamertheme-detlevcm"),
+    ("beamerthemeepyt", "beamertheme-epyt"),
+    ("beamerthemephnompenh", "beamertheme-phnompenh"),
+    ("beamerthemeArguelles", "arguelles"),
+    ("beamerthemepureminimalistic", "pure-minimalistic"),
+    ("beamercolorthemeowl", "beamercolortheme-owl"),
+];
+
+/// Language names (babel options and polyglossia languages) mapped to the
+/// TeX Live packages holding their babel definitions and hyphenation patterns
+const LANGUAGE_PACKAGES: &[(&str, &[&str])] = &[
+    ("english", &["babel-english", "hyphen-english"]),
+    ("american", &["babel-english", "hyphen-english"]),
+    ("british", &["babel-english", "hyphen-english"]),
+    ("USenglish", &["babel-english", "hyphen-english"]),
+    ("UKenglish", &["babel-english", "hyphen-english"]),
+    ("canadian", &["babel-english", "hyphen-english"]),
+    ("australian", &["babel-english", "hyphen-english"]),
+    ("newzealand", &["babel-english", "hyphen-english"]),
+    ("german", &["babel-german", "hyphen-german"]),
+    ("ngerman", &["babel-german", "hyphen-german"]),
+    ("austrian", &["babel-german", "hyphen-german"]),
+    ("naustrian", &["babel-german", "hyphen-german"]),
+    ("swissgerman", &["babel-german", "hyphen-german"]),
+    ("nswissgerman", &["babel-german", "hyphen-german"]),
+    ("french", &["babel-french", "hyphen-french"]),
+    ("francais", &["babel-french", "hyphen-french"]),
+    ("acadian", &["babel-french", "hyphen-french"]),
+    ("spanish", &["babel-spanish", "hyphen-spanish"]),
+    ("italian", &["babel-italian", "hyphen-italian"]),
+    ("portuguese", &["babel-portuges", "hyphen-portuguese"]),
+    ("portuges", &["babel-portuges", "hyphen-portuguese"]),
+    ("brazil", &["babel-portuges", "hyphen-portuguese"]),
+    ("brazilian", &["babel-portuges", "hyphen-portuguese"]),
+    ("dutch", &["babel-dutch", "hyphen-dutch"]),
+    ("russian", &["babel-russian", "hyphen-russian"]),
+    ("ukrainian", &["babel-ukrainian", "hyphen-ukrainian"]),
+    ("polish", &["babel-polish", "hyphen-polish"]),
+    ("czech", &["babel-czech", "hyphen-czech"]),
+    ("slovak", &["babel-slovak", "hyphen-slovak"]),
+    ("greek", &["babel-greek", "hyphen-greek"]),
+    ("swedish", &["babel-swedish", "hyphen-swedish"]),
+    ("danish", &["babel-danish", "hyphen-danish"]),
+    ("norsk", &["babel-norsk", "hyphen-norwegian"]),
+    ("norwegian", &["babel-norsk", "hyphen-norwegian"]),
+    ("nynorsk", &["babel-norsk", "hyphen-norwegian"]),
+    ("finnish", &["babel-finnish", "hyphen-finnish"]),
+    ("hungarian", &["babel-hungarian", "hyphen-hungarian"]),
+    ("magyar", &["babel-hungarian", "hyphen-hungarian"]),
+    ("turkish", &["babel-turkish", "hyphen-turkish"]),
+    ("catalan", &["babel-catalan", "hyphen-catalan"]),
+    ("croatian", &["babel-croatian", "hyphen-croatian"]),
+    ("latin", &["babel-latin", "hyphen-latin"]),
+    ("vietnamese", &["babel-vietnamese", "hyphen-vietnamese"]),
+    ("indonesian", &["babel-indonesian", "hyphen-indonesian"]),
+    ("japanese", &["babel-japanese"]),
+    ("hebrew", &["babel-hebrew"]),
+];
+
+/// Packages that load other packages themselves, so commands from the latter
+/// are available without an explicit \usepackage
+const IMPLIED_PACKAGES: &[(&str, &[&str])] = &[
+    ("beamer", &["amsmath", "amssymb", "amsthm", "graphicx", "hyperref", "xcolor"]),
+    ("tikz", &["graphicx", "xcolor"]),
+    ("pgfplots", &["tikz"]),
+    ("tcolorbox", &["tikz"]),
+    ("mathtools", &["amsmath"]),
+    ("physics", &["amsmath"]),
+    ("amssymb", &["amsfonts"]),
+    ("algpseudocode", &["algorithmicx"]),
+    ("glossaries-extra", &["glossaries"]),
+];
+
+/// Prefix marking a font requested by name (fontspec) in missing-package lists
+pub const FONT_NAME_PREFIX: &str = "font:";
+
+/// Directories holding build output, installed packages or tool caches
+/// rather than sources; the project's `output_dir` is skipped as well
+const SKIPPED_DIRECTORIES: &[&str] = &[
+    "packages", "build", "_build", "out", "output", "auto", "svg-inkscape",
+    "node_modules", "__pycache__",
+];
+
+/// Prefixes of generated directory names (hidden dirs, minted caches)
+const SKIPPED_DIRECTORY_PREFIXES: &[&str] = &[".", "_minted"];
+
+/// Extensions tried by graphicx when \includegraphics omits one
+const GRAPHICS_EXTENSIONS: &[&str] = &[
+    "pdf", "png", "jpg", "jpeg", "mps", "jbig2", "jb2", "eps", "ps",
+    "PDF", "PNG", "JPG", "JPEG", "EPS",
+];
 
 #[derive(Debug, Clone)]
 pub struct TeXDependency {
@@ -11,6 +172,49 @@ pub struct TeXDependency {
     pub dependency_type: DependencyType,
     pub line_number: usize,
     pub context: String,
+    /// File the dependency was declared in (None for parsed content)
+    pub source_file: Option<PathBuf>,
+    /// Options of a \usepackage or \RequirePackage, without spaces
+    pub options: Vec<String>,
+}
+
+impl TeXDependency {
+    fn new(package_name: String, dependency_type: DependencyType, line_number: usize, line: &str) -> Self {
+        Self {
+            package_name,
+            dependency_type,
+            line_number,
+            context: line.trim().to_string(),
+            source_file: None,
+            options: Vec::new(),
+        }
+    }
+
+    fn with_options(mut self, options: Option<&str>) -> Self {
+        self.options = options
+            .map(|options| options.split(',').map(|option| option.split_whitespace().collect::<String>()).filter(|option| !option.is_empty()).collect())
+            .unwrap_or_default();
+        self
+    }
+}
+
+/// A package loaded again with options its first load did not have, which
+/// stops LaTeX with "Option clash for package ..."
+#[derive(Debug, Clone)]
+pub struct OptionClash {
+    pub package: String,
+    pub first: TeXDependency,
+    pub clashing: TeXDependency,
+}
+
+/// A command or environment used without loading the package that provides it
+#[derive(Debug, Clone)]
+pub struct PackageSuggestion {
+    pub package: String,
+    /// Command (with backslash) or environment name
+    pub name: String,
+    pub file: Option<PathBuf>,
+    pub line_number: usize,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -23,6 +227,123 @@ pub enum DependencyType {
     Include,         // \include{...}
     Bibliography,    // \bibliography{...}
     BibliographyStyle, // \bibliographystyle{...}
+    TikzLibrary,     // \usetikzlibrary{...} / \usepgfplotslibrary{...}
+    Graphic,         // \includegraphics{...}
+    GraphicsPath,    // \graphicspath{{...}{...}}
+    Language,        // \usepackage[lang]{babel} / \setmainlanguage{lang}
+    Subfile,         // \subfile{...} / \includestandalone{...}
+    Import,          // \import{dir}{file} / \subimport{dir}{file}
+    IncludeOnly,     // \includeonly{...}
+    BeamerTheme,     // \usetheme{...} / \usecolortheme{...} / ...
+    Citation,        // \cite{...} / \citep{...} / \autocite{...} / \nocite{...}
+    Index,           // \makeindex / \makeindex[name=...] / \makeindex{name}
+    Provides,        // \ProvidesPackage{...} / \ProvidesClass{...} in a .sty/.cls
+}
+
+impl DependencyType {
+    /// Whether the dependency names a package or class rather than a file
+    pub fn loads_package(&self) -> bool {
+        matches!(
+            self,
+            DependencyType::UsePackage
+                | DependencyType::RequirePackage
+                | DependencyType::DocumentClass
+                | DependencyType::LoadClass
+                | DependencyType::TikzLibrary
+                | DependencyType::Language
+                | DependencyType::BeamerTheme
+        )
+    }
+}
+
+/// A place where a source loads a package that is not installed, located
+/// for editors (lines and columns are 1-based, columns count characters)
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct MissingPackageDiagnostic {
+    pub package: String,
+    pub line: usize,
+    pub column: usize,
+    pub end_column: usize,
+    pub severity: &'static str,
+    pub message: String,
+}
+
+/// State of a recursive source file scan
+struct SourceScan {
+    root: PathBuf,
+    ignore_rules: IgnoreRules,
+    output_dir: Option<PathBuf>,
+    /// Canonical directories already scanned, so symlink loops end
+    visited: HashSet<PathBuf>,
+    /// Collect every file instead of only TeX sources
+    all_files: bool,
+}
+
+impl SourceScan {
+    fn walk(&mut self, dir_path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        if !dir_path.is_dir() {
+            return Ok(());
+        }
+
+        let canonical = dir_path.canonicalize()?;
+        if self.output_dir.as_ref() == Some(&canonical) || !self.visited.insert(canonical) {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(dir_path)? {
+            let entry = entry?;
+            let path = entry.path();
+            let relative_path = path.strip_prefix(&self.root).unwrap_or(&path).to_path_buf();
+
+            if path.is_dir() {
+                // Skip certain directories
+                if let Some(dir_name) = path.file_name() {
+                    let dir_name = dir_name.to_string_lossy();
+                    let is_generated = SKIPPED_DIRECTORIES.contains(&dir_name.as_ref())
+                        || SKIPPED_DIRECTORY_PREFIXES.iter().any(|prefix| dir_name.starts_with(prefix));
+                    if is_generated {
+                        continue;
+                    }
+                }
+                if self.ignore_rules.is_ignored(&relative_path, true) {
+                    continue;
+                }
+                if let Err(e) = self.walk(&path, files) {
+                    println!("Warning: Failed to scan {}: {}", path.display(), e);
+                }
+            } else if path.is_file() {
+                if self.ignore_rules.is_ignored(&relative_path, false) {
+                    continue;
+                }
+                if self.all_files {
+                    files.push(path);
+                } else if let Some(extension) = path.extension() {
+                    let ext = extension.to_string_lossy().to_lowercase();
+                    if ext == "tex" || ext == "latex" || ext == "sty" || ext == "cls" {
+                        files.push(path);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Environments whose contents TeX does not read as code
+const VERBATIM_ENVIRONMENTS: &[&str] = &[
+    "verbatim", "verbatim*", "Verbatim", "Verbatim*", "BVerbatim", "LVerbatim",
+    "lstlisting", "minted", "comment", "spverbatim",
+];
+
+/// Where a line-by-line scan is: inside a verbatim-like environment or an
+/// \iffalse branch, whose lines are not code
+#[derive(Debug, Default)]
+struct CodeState {
+    /// The verbatim environment whose \end the scan waits for
+    verbatim: Option<String>,
+    /// Open conditionals since the \iffalse (0 outside one)
+    skipped_conditionals: usize,
 }
 
 pub struct TeXParser {
@@ -30,164 +351,454 @@ pub struct TeXParser {
     requirepackage_regex: Regex,
     documentclass_regex: Regex,
     loadclass_regex: Regex,
+    provides_regex: Regex,
     input_regex: Regex,
     include_regex: Regex,
     bibliography_regex: Regex,
     bibliographystyle_regex: Regex,
+    tikzlibrary_regex: Regex,
+    pgfplotslibrary_regex: Regex,
+    includegraphics_regex: Regex,
+    graphicspath_regex: Regex,
+    braced_group_regex: Regex,
+    babel_regex: Regex,
+    polyglossia_language_regex: Regex,
+    subfile_regex: Regex,
+    import_regex: Regex,
+    includeonly_regex: Regex,
+    beamertheme_regex: Regex,
+    cite_regex: Regex,
+    makeindex_regex: Regex,
+    command_regex: Regex,
+    code_switch_regex: Regex,
+    begin_environment_regex: Regex,
+    command_definition_regex: Regex,
+    environment_definition_regex: Regex,
+    command_packages: CommandPackageMap,
 }
 
 impl TeXParser {
     pub fn new() -> Result<Self> {
         Ok(Self {
             // Match \usepackage[options]{package1,package2}
-            usepackage_regex: Regex::new(r"\\usepackage(?:\[[^\]]*\])?\{([^}]+)\}")?,
-            // Match \RequirePackage[options]{package}
-            requirepackage_regex: Regex::new(r"\\RequirePackage(?:\[[^\]]*\])?\{([^}]+)\}")?,
+            usepackage_regex: Regex::new(r"\\usepackage(?:\[([^\]]*)\])?\{([^}]+)\}")?,
+            // Match \RequirePackage[options]{package} and \RequirePackageWithOptions{package}
+            requirepackage_regex: Regex::new(r"\\RequirePackage(?:WithOptions)?(?:\[([^\]]*)\])?\{([^}]+)\}")?,
             // Match \documentclass[options]{class}
-            documentclass_regex: Regex::new(r"\\documentclass(?:\[[^\]]*\])?\{([^}]+)\}")?,
-            // Match \LoadClass[options]{class}
-            loadclass_regex: Regex::new(r"\\LoadClass(?:\[[^\]]*\])?\{([^}]+)\}")?,
+            documentclass_regex: Regex::new(r"\\documentclass(?:\[([^\]]*)\])?\{([^}]+)\}")?,
+            // Match \LoadClass[options]{class} and \LoadClassWithOptions{class}
+            loadclass_regex: Regex::new(r"\\LoadClass(?:WithOptions)?(?:\[[^\]]*\])?\{([^}]+)\}")?,
+            // Match \ProvidesPackage{name} and \ProvidesClass{name}
+            provides_regex: Regex::new(r"\\Provides(?:Package|Class)\s*\{([^}]+)\}")?,
             // Match \input{file}
             input_regex: Regex::new(r"\\input\{([^}]+)\}")?,
             // Match \include{file}
             include_regex: Regex::new(r"\\include\{([^}]+)\}")?,
-            // Match \bibliography{files}
-            bibliography_regex: Regex::new(r"\\bibliography\{([^}]+)\}")?,
+            // Match \bibliography{files} and biblatex's \addbibresource[options]{file.bib}
+            bibliography_regex: Regex::new(r"\\(?:bibliography|addbibresource)(?:\[[^\]]*\])?\{([^}]+)\}")?,
             // Match \bibliographystyle{style}
             bibliographystyle_regex: Regex::new(r"\\bibliographystyle\{([^}]+)\}")?,
+            // Match \usetikzlibrary{lib1,lib2}
+            tikzlibrary_regex: Regex::new(r"\\usetikzlibrary\{([^}]+)\}")?,
+            // Match \usepgfplotslibrary{lib1,lib2}
+            pgfplotslibrary_regex: Regex::new(r"\\usepgfplotslibrary\{([^}]+)\}")?,
+            // Match \includegraphics*[options]{file}
+            includegraphics_regex: Regex::new(r"\\includegraphics\*?(?:\[[^\]]*\])*\{([^}]+)\}")?,
+            // Match \graphicspath{{dir1/}{dir2/}}
+            graphicspath_regex: Regex::new(r"\\graphicspath\s*\{((?:\s*\{[^}]*\})+)\s*\}")?,
+            // Match a single {...} group
+            braced_group_regex: Regex::new(r"\{([^}]*)\}")?,
+            // Match \usepackage[lang1,lang2]{babel}
+            babel_regex: Regex::new(r"\\usepackage\[([^\]]*)\]\{babel\}")?,
+            // Match \setdefaultlanguage[options]{lang} / \setmainlanguage{lang} / \setotherlanguages{lang1,lang2}
+            polyglossia_language_regex: Regex::new(r"\\set(?:default|main|other)languages?(?:\[[^\]]*\])?\{([^}]+)\}")?,
+            // Match \subfile{file} and \includestandalone[options]{file}
+            subfile_regex: Regex::new(r"\\(?:subfile|includestandalone)(?:\[[^\]]*\])?\{([^}]+)\}")?,
+            // Match \import{dir}{file}, \subimport*{dir}{file}, \inputfrom{dir}{file}, ...
+            import_regex: Regex::new(r"\\(?:sub)?(?:import|inputfrom|includefrom)\*?\{([^}]*)\}\{([^}]+)\}")?,
+            // Match \includeonly{file1,file2}
+            includeonly_regex: Regex::new(r"\\includeonly\{([^}]*)\}")?,
+            // Match \usetheme[options]{name}, \usecolortheme{name}, \useinnertheme{name}, ...
+            beamertheme_regex: Regex::new(r"\\use(|color|font|inner|outer)theme(?:\[[^\]]*\])?\{([^}]+)\}")?,
+            // Match \cite[pre][post]{key1,key2}, \citep*, \textcite, \autocite, \nocite, ...
+            cite_regex: Regex::new(r"\\([A-Za-z]*cite[A-Za-z]*)\*?(?:\[[^\]]*\]){0,2}\{([^}]*)\}")?,
+            // Match \makeindex, imakeidx's \makeindex[name=...] and multind's \makeindex{name}
+            makeindex_regex: Regex::new(r"\\makeindex\b(?:\[([^\]]*)\])?(?:\{([^}]*)\})?")?,
+            // Match any control word \name
+            command_regex: Regex::new(r"\\([A-Za-z]+)")?,
+            // Match \begin{verbatim}-like environments, \iffalse, and \verb|...| / \lstinline|...|
+            code_switch_regex: Regex::new(r"\\begin\s*\{([A-Za-z]+\*?)\}|\\iffalse\b|\\(?:verb\*?|lstinline)([^A-Za-z\s{\[])")?,
+            // Match \begin{env}
+            begin_environment_regex: Regex::new(r"\\begin\{([^}]+)\}")?,
+            // Match \newcommand{\name}, \def\name, \DeclareMathOperator{\name}, ...
+            command_definition_regex: Regex::new(
+                r"\\(?:(?:re)?newcommand|providecommand|DeclareRobustCommand|(?:New|Renew|Provide|Declare)DocumentCommand|DeclareMathOperator|[egx]?def|let)\*?\s*\{?\s*\\([A-Za-z@]+)",
+            )?,
+            // Match \newenvironment{name}, \newtheorem{name}, ...
+            environment_definition_regex: Regex::new(
+                r"\\(?:(?:re)?newenvironment|(?:New|Renew|Provide|Declare)DocumentEnvironment|newtheorem|(?:new|renew)tcolorbox|newfloat)\*?\s*(?:\[[^\]]*\])?\{([^}]+)\}",
+            )?,
+            command_packages: CommandPackageMap::embedded(),
         })
     }
 
+    /// Add user-defined command/environment mappings on top of the built-in table
+    pub fn extend_command_packages(&mut self, overrides: &CommandPackageMap) {
+        self.command_packages.extend(overrides);
+    }
+
     /// Parse dependencies of a single TeX file
     pub fn parse_file(&self, file_path: &Path) -> Result<Vec<TeXDependency>> {
-        let content = fs::read_to_string(file_path)?;
-        self.parse_content(&content)
+        let content = Self::read_source(file_path)?;
+        let mut dependencies = self.parse_content(&content)?;
+        for dep in &mut dependencies {
+            dep.source_file = Some(file_path.to_path_buf());
+        }
+        Ok(dependencies)
+    }
+
+    /// Read a TeX source file, decoding legacy encodings (latin-1, GBK) when
+    /// the file is not valid UTF-8
+    pub fn read_source(file_path: &Path) -> Result<String> {
+        let bytes = fs::read(file_path)?;
+        let (content, legacy_encoding) = Self::decode_source(&bytes);
+        if let Some(encoding) = legacy_encoding {
+            println!("Warning: {} is not valid UTF-8, decoded as {}", file_path.display(), encoding);
+        }
+        Ok(content)
+    }
+
+    /// Decode source bytes, returning the legacy encoding used if not UTF-8.
+    /// The encoding is taken from \usepackage[...]{inputenc} or CJK package hints,
+    /// otherwise GBK is tried strictly before falling back to Windows-1252.
+    fn decode_source(bytes: &[u8]) -> (String, Option<&'static str>) {
+        if let Ok(content) = std::str::from_utf8(bytes) {
+            return (content.trim_start_matches('\u{feff}').to_string(), None);
+        }
+
+        // Markup is ASCII, so hints can be read from a lossy decode
+        let lossy = String::from_utf8_lossy(bytes);
+        let declared = Regex::new(r"\\usepackage\[([^\]]*)\]\{inputenc\}")
+            .ok()
+            .and_then(|regex| regex.captures(&lossy).map(|caps| caps[1].to_lowercase()));
+        let mentions_cjk = ["{CJK}", "{CJKutf8}", "{ctex", "{xeCJK}", "GBK", "gbk", "GB2312"]
+            .iter()
+            .any(|hint| lossy.contains(hint));
+
+        let encoding = match declared.as_deref() {
+            Some(option) if option.contains("latin") || option.contains("cp1252") || option.contains("ansinew") => {
+                encoding_rs::WINDOWS_1252
+            }
+            Some(option) if option.contains("gbk") || option.contains("gb2312") => encoding_rs::GBK,
+            _ if mentions_cjk => encoding_rs::GBK,
+            _ if encoding_rs::GBK
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .is_some() =>
+            {
+                encoding_rs::GBK
+            }
+            _ => encoding_rs::WINDOWS_1252,
+        };
+
+        let (content, _) = encoding.decode_without_bom_handling(bytes);
+        (content.into_owned(), Some(encoding.name()))
     }
 
     /// Parse dependencies of TeX content
     pub fn parse_content(&self, content: &str) -> Result<Vec<TeXDependency>> {
         let mut dependencies = Vec::new();
 
+        let mut state = CodeState::default();
         for (line_number, line) in content.lines().enumerate() {
             let line_number = line_number + 1;
             
-            // Handle comments: process only the part before comments
-            let effective_line = if let Some(comment_pos) = line.find('%') {
-                if comment_pos > 0 && line.chars().nth(comment_pos - 1) == Some('\\') {
-                    line
-                } else {
-                    let before_comment = &line[..comment_pos];
-                    if before_comment.trim().is_empty() {
-                        continue;
-                    }
-                    before_comment
-                }
-            } else {
-                line
+            // Handle comments, verbatim text and \iffalse blocks: process only code
+            let Some(effective_line) = self.code_of_line(line, &mut state) else {
+                continue;
             };
 
             // Check various dependency types
-            self.extract_dependencies(effective_line, line_number, &mut dependencies);
+            self.extract_dependencies(&effective_line, line_number, &mut dependencies);
         }
+        Self::add_class_option_languages(&mut dependencies);
 
         Ok(dependencies)
     }
 
+    /// Get the part of a line before its comment, or None if nothing is left.
+    /// A % escaped as \% does not start a comment, but one after \\ does.
+    fn strip_comment(line: &str) -> Option<&str> {
+        let mut escaped = false;
+        let end = line
+            .char_indices()
+            .find(|&(_, c)| {
+                let starts_comment = c == '%' && !escaped;
+                escaped = c == '\\' && !escaped;
+                starts_comment
+            })
+            .map_or(line.len(), |(index, _)| index);
+        let before_comment = &line[..end];
+        (!before_comment.trim().is_empty()).then_some(before_comment)
+    }
+
+    /// The parts of `line` TeX reads as code, without comments, verbatim text
+    /// and \iffalse branches. `state` carries environments and conditionals
+    /// that span lines.
+    fn code_of_line(&self, line: &str, state: &mut CodeState) -> Option<String> {
+        let mut code = String::new();
+        let mut rest = if state.verbatim.is_some() { line } else { Self::strip_comment(line).unwrap_or_default() };
+        loop {
+            if let Some(environment) = &state.verbatim {
+                let end = format!("\\end{{{}}}", environment);
+                let Some(position) = rest.find(&end) else {
+                    break;
+                };
+                code.push_str(&end);
+                rest = Self::strip_comment(&rest[position + end.len()..]).unwrap_or_default();
+                state.verbatim = None;
+            } else if state.skipped_conditionals > 0 {
+                let mut resume = None;
+                for caps in self.command_regex.captures_iter(rest) {
+                    let name = &caps[1];
+                    if name == "fi" {
+                        state.skipped_conditionals -= 1;
+                    } else if name == "else" && state.skipped_conditionals == 1 {
+                        // The \else branch of \iffalse is read; its \fi is harmless
+                        state.skipped_conditionals = 0;
+                    } else if name.starts_with("if") && name != "ifthenelse" && name != "ifboolexpr" {
+                        state.skipped_conditionals += 1;
+                    }
+                    if state.skipped_conditionals == 0 {
+                        resume = Some(caps.get(0).unwrap().end());
+                        break;
+                    }
+                }
+                let Some(resume) = resume else {
+                    break;
+                };
+                rest = &rest[resume..];
+            } else {
+                let Some(caps) = self.code_switch_regex.captures(rest) else {
+                    code.push_str(rest);
+                    break;
+                };
+                let found = caps.get(0).unwrap();
+                if let Some(environment) = caps.get(1) {
+                    code.push_str(&rest[..found.end()]);
+                    if VERBATIM_ENVIRONMENTS.contains(&environment.as_str()) {
+                        state.verbatim = Some(environment.as_str().to_string());
+                    }
+                    rest = &rest[found.end()..];
+                } else if let Some(delimiter) = caps.get(2) {
+                    code.push_str(&rest[..found.start()]);
+                    let text = &rest[delimiter.end()..];
+                    rest = text.find(delimiter.as_str()).map_or("", |end| &text[end + delimiter.len()..]);
+                } else {
+                    code.push_str(&rest[..found.start()]);
+                    state.skipped_conditionals = 1;
+                    rest = &rest[found.end()..];
+                }
+            }
+        }
+        (!code.trim().is_empty()).then_some(code)
+    }
+
     /// Extract dependencies from a line
     fn extract_dependencies(&self, line: &str, line_number: usize, dependencies: &mut Vec<TeXDependency>) {
         // \usepackage{...}
         for caps in self.usepackage_regex.captures_iter(line) {
-            let packages = &caps[1];
+            let packages = &caps[2];
             for package in self.split_package_list(packages) {
-                dependencies.push(TeXDependency {
-                    package_name: package,
-                    dependency_type: DependencyType::UsePackage,
-                    line_number,
-                    context: line.trim().to_string(),
-                });
+                dependencies.push(TeXDependency::new(package, DependencyType::UsePackage, line_number, line).with_options(caps.get(1).map(|m| m.as_str())));
             }
         }
 
         // \RequirePackage{...}
         for caps in self.requirepackage_regex.captures_iter(line) {
-            let packages = &caps[1];
+            let packages = &caps[2];
             for package in self.split_package_list(packages) {
-                dependencies.push(TeXDependency {
-                    package_name: package,
-                    dependency_type: DependencyType::RequirePackage,
-                    line_number,
-                    context: line.trim().to_string(),
-                });
+                dependencies.push(TeXDependency::new(package, DependencyType::RequirePackage, line_number, line).with_options(caps.get(1).map(|m| m.as_str())));
             }
         }
 
         // \documentclass{...}
         for caps in self.documentclass_regex.captures_iter(line) {
-            let class = caps[1].trim().to_string();
-            dependencies.push(TeXDependency {
-                package_name: class,
-                dependency_type: DependencyType::DocumentClass,
-                line_number,
-                context: line.trim().to_string(),
-            });
+            let class = caps[2].trim().to_string();
+            dependencies.push(TeXDependency::new(class, DependencyType::DocumentClass, line_number, line).with_options(caps.get(1).map(|m| m.as_str())));
         }
 
         // \LoadClass{...}
         for caps in self.loadclass_regex.captures_iter(line) {
             let class = caps[1].trim().to_string();
-            dependencies.push(TeXDependency {
-                package_name: class,
-                dependency_type: DependencyType::LoadClass,
-                line_number,
-                context: line.trim().to_string(),
-            });
+            dependencies.push(TeXDependency::new(class, DependencyType::LoadClass, line_number, line));
+        }
+
+        // \ProvidesPackage{...}: what the file defines, not what it needs
+        for caps in self.provides_regex.captures_iter(line) {
+            let name = caps[1].trim().to_string();
+            dependencies.push(TeXDependency::new(name, DependencyType::Provides, line_number, line));
         }
 
         // \input{...}
         for caps in self.input_regex.captures_iter(line) {
             let file = caps[1].trim().to_string();
-            dependencies.push(TeXDependency {
-                package_name: file,
-                dependency_type: DependencyType::Input,
-                line_number,
-                context: line.trim().to_string(),
-            });
+            dependencies.push(TeXDependency::new(file, DependencyType::Input, line_number, line));
         }
 
         // \include{...}
         for caps in self.include_regex.captures_iter(line) {
             let file = caps[1].trim().to_string();
-            dependencies.push(TeXDependency {
-                package_name: file,
-                dependency_type: DependencyType::Include,
-                line_number,
-                context: line.trim().to_string(),
-            });
+            dependencies.push(TeXDependency::new(file, DependencyType::Include, line_number, line));
         }
 
         // \bibliography{...}
         for caps in self.bibliography_regex.captures_iter(line) {
             let files = &caps[1];
             for file in self.split_package_list(files) {
-                dependencies.push(TeXDependency {
-                    package_name: file,
-                    dependency_type: DependencyType::Bibliography,
-                    line_number,
-                    context: line.trim().to_string(),
-                });
+                let file = file.strip_suffix(".bib").map(str::to_string).unwrap_or(file);
+                dependencies.push(TeXDependency::new(file, DependencyType::Bibliography, line_number, line));
             }
         }
 
         // \bibliographystyle{...}
         for caps in self.bibliographystyle_regex.captures_iter(line) {
             let style = caps[1].trim().to_string();
-            dependencies.push(TeXDependency {
-                package_name: style,
-                dependency_type: DependencyType::BibliographyStyle,
-                line_number,
-                context: line.trim().to_string(),
+            dependencies.push(TeXDependency::new(style, DependencyType::BibliographyStyle, line_number, line));
+        }
+
+        // \usetikzlibrary{...}
+        for caps in self.tikzlibrary_regex.captures_iter(line) {
+            for library in self.split_package_list(&caps[1]) {
+                if let Some(package) = Self::tikz_library_package(&library) {
+                    dependencies.push(TeXDependency::new(package.to_string(), DependencyType::TikzLibrary, line_number, line));
+                }
+            }
+        }
+
+        // \usepgfplotslibrary{...}: every pgfplots library ships with pgfplots
+        if self.pgfplotslibrary_regex.is_match(line) {
+            dependencies.push(TeXDependency::new("pgfplots".to_string(), DependencyType::TikzLibrary, line_number, line));
+        }
+
+        // \usetheme{...} and friends: third-party themes come from their own packages
+        for caps in self.beamertheme_regex.captures_iter(line) {
+            for theme in self.split_package_list(&caps[2]) {
+                let package = Self::beamer_theme_package(&caps[1], &theme);
+                dependencies.push(TeXDependency::new(package, DependencyType::BeamerTheme, line_number, line));
+            }
+        }
+
+        // \includegraphics{...}
+        for caps in self.includegraphics_regex.captures_iter(line) {
+            dependencies.push(TeXDependency::new(caps[1].trim().to_string(), DependencyType::Graphic, line_number, line));
+        }
+
+        // \usepackage[...]{babel}: each language option needs its own packages
+        for caps in self.babel_regex.captures_iter(line) {
+            for option in self.split_package_list(&caps[1]) {
+                let language = option.strip_prefix("main=").unwrap_or(&option);
+                for package in Self::language_packages(language) {
+                    dependencies.push(TeXDependency::new(package.to_string(), DependencyType::Language, line_number, line));
+                }
+            }
+        }
+
+        // polyglossia ships its own gloss files, so only hyphenation is needed
+        for caps in self.polyglossia_language_regex.captures_iter(line) {
+            for language in self.split_package_list(&caps[1]) {
+                for package in Self::language_packages(&language) {
+                    if package.starts_with("hyphen-") {
+                        dependencies.push(TeXDependency::new(package.to_string(), DependencyType::Language, line_number, line));
+                    }
+                }
+            }
+        }
+
+        // \subfile{...} / \includestandalone{...}
+        for caps in self.subfile_regex.captures_iter(line) {
+            dependencies.push(TeXDependency::new(caps[1].trim().to_string(), DependencyType::Subfile, line_number, line));
+        }
+
+        // \import{dir}{file} and friends: the file is relative to dir
+        for caps in self.import_regex.captures_iter(line) {
+            let file = format!("{}{}", caps[1].trim(), caps[2].trim());
+            dependencies.push(TeXDependency::new(file, DependencyType::Import, line_number, line));
+        }
+
+        // \includeonly{...}
+        for caps in self.includeonly_regex.captures_iter(line) {
+            for file in self.split_package_list(&caps[1]) {
+                dependencies.push(TeXDependency::new(file, DependencyType::IncludeOnly, line_number, line));
+            }
+        }
+
+        // \cite{...} and friends; \citestyle names a natbib style, not keys
+        for caps in self.cite_regex.captures_iter(line) {
+            if &caps[1] == "citestyle" {
+                continue;
+            }
+            for key in self.split_package_list(&caps[2]) {
+                dependencies.push(TeXDependency::new(key, DependencyType::Citation, line_number, line));
+            }
+        }
+
+        // \makeindex: the index is named after the job unless the document
+        // keeps several
+        for caps in self.makeindex_regex.captures_iter(line) {
+            let named = caps.get(1).and_then(|options| {
+                options.as_str().split(',').find_map(|option| {
+                    let (key, value) = option.split_once('=')?;
+                    (key.trim() == "name").then(|| value.trim().to_string())
+                })
             });
+            let name = named
+                .or_else(|| caps.get(2).map(|name| name.as_str().trim().to_string()))
+                .filter(|name| !name.is_empty() && name != "\\jobname")
+                .unwrap_or_else(|| "\\jobname".to_string());
+            dependencies.push(TeXDependency::new(name, DependencyType::Index, line_number, line));
+        }
+
+        // \graphicspath{{...}{...}}
+        for caps in self.graphicspath_regex.captures_iter(line) {
+            for dir in self.braced_group_regex.captures_iter(&caps[1]) {
+                let dir = dir[1].trim();
+                if !dir.is_empty() {
+                    dependencies.push(TeXDependency::new(dir.to_string(), DependencyType::GraphicsPath, line_number, line));
+                }
+            }
+        }
+    }
+
+    /// Map a TikZ library name to the CTAN package providing it.
+    /// Returns None for libraries that ship with pgf itself.
+    pub fn tikz_library_package(library: &str) -> Option<&'static str> {
+        if let Some((_, package)) = TIKZ_LIBRARY_PACKAGES.iter().find(|(name, _)| *name == library) {
+            return Some(package);
+        }
+
+        TIKZ_LIBRARY_PREFIXES
+            .iter()
+            .find(|(prefix, _)| library.starts_with(prefix))
+            .map(|(_, package)| *package)
+    }
+
+    /// Map a beamer theme to the package providing it: "beamer" for built-in
+    /// themes, the CTAN package for known third-party themes, otherwise the
+    /// theme file name (e.g. beamerthemeFoo) for lookup in the package index
+    pub fn beamer_theme_package(kind: &str, theme: &str) -> String {
+        let is_builtin = BEAMER_BUILTIN_THEMES
+            .iter()
+            .any(|(builtin_kind, themes)| *builtin_kind == kind && themes.contains(&theme));
+        if is_builtin {
+            return "beamer".to_string();
         }
+
+        let file_stem = format!("beamer{}theme{}", kind, theme);
+        BEAMER_THEME_PACKAGES
+            .iter()
+            .find(|(stem, _)| *stem == file_stem)
+            .map(|(_, package)| package.to_string())
+            .unwrap_or(file_stem)
     }
 
     /// Split package list (handle comma-separated package names)
@@ -202,53 +813,210 @@ impl TeXParser {
     /// Recursively parse all TeX files in the project
     pub fn parse_project(&self, project_path: &Path) -> Result<Vec<TeXDependency>> {
         let mut all_dependencies = Vec::new();
-        let mut visited_files = HashSet::new();
+        let mut files = Vec::new();
 
-        self.parse_directory_recursive(project_path, &mut all_dependencies, &mut visited_files)?;
+        let progress = crate::ui::Progress::start("Scanning project sources", "files");
+        Self::collect_source_files(project_path, &mut files)?;
+
+        for path in files {
+            match self.parse_file(&path) {
+                Ok(mut file_deps) => all_dependencies.append(&mut file_deps),
+                Err(e) => println!("Warning: Failed to parse {}: {}", path.display(), e),
+            }
+            progress.inc();
+        }
         
         Ok(all_dependencies)
     }
 
-    /// Recursively parse directory
-    fn parse_directory_recursive(
-        &self,
-        dir_path: &Path,
-        dependencies: &mut Vec<TeXDependency>,
-        visited: &mut HashSet<std::path::PathBuf>,
-    ) -> Result<()> {
-        if !dir_path.is_dir() {
-            return Ok(());
-        }
+    /// Recursively collect TeX source files (.tex, .latex, .sty, .cls) in a directory,
+    /// honoring the directory's .tpmgrignore and skipping build output
+    pub fn collect_source_files(dir_path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let mut scan = SourceScan {
+            root: dir_path.to_path_buf(),
+            ignore_rules: IgnoreRules::load(dir_path)?,
+            output_dir: Self::configured_output_dir(dir_path),
+            visited: HashSet::new(),
+            all_files: false,
+        };
+        scan.walk(dir_path, files)
+    }
 
-        for entry in fs::read_dir(dir_path)? {
-            let entry = entry?;
-            let path = entry.path();
+    /// Recursively collect all files in a directory, skipping the same
+    /// directories as `collect_source_files`
+    pub fn collect_project_files(dir_path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        let mut scan = SourceScan {
+            root: dir_path.to_path_buf(),
+            ignore_rules: IgnoreRules::load(dir_path)?,
+            output_dir: Self::configured_output_dir(dir_path),
+            visited: HashSet::new(),
+            all_files: true,
+        };
+        scan.walk(dir_path, files)
+    }
 
-            if path.is_dir() {
-                // Skip certain directories
-                if let Some(dir_name) = path.file_name() {
-                    let dir_name = dir_name.to_string_lossy();
-                    if dir_name == "packages" || dir_name == ".git" || dir_name.starts_with('.') {
-                        continue;
+    /// The project's configured output directory, canonicalized
+    fn configured_output_dir(project_path: &Path) -> Option<PathBuf> {
+        let config_path = [project_path.join(crate::config::PROJECT_CONFIG_FILE), crate::config::project_config_path()]
+            .into_iter()
+            .find(|path| path.is_file())?;
+        let config = crate::config::Config::load(&config_path).ok()?;
+        let output_dir = config.project.output_dir?;
+        config_path.parent()?.join(output_dir).canonicalize().ok()
+    }
+
+    /// Find packages and classes provided by .sty/.cls files in the project tree
+    pub fn find_local_packages(project_path: &Path) -> Result<HashMap<String, PathBuf>> {
+        let mut files = Vec::new();
+        Self::collect_source_files(project_path, &mut files)?;
+
+        Ok(files
+            .into_iter()
+            .filter(|path| {
+                path.extension()
+                    .map(|ext| ext.eq_ignore_ascii_case("sty") || ext.eq_ignore_ascii_case("cls"))
+                    .unwrap_or(false)
+            })
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_string_lossy().to_string();
+                Some((stem, path))
+            })
+            .collect())
+    }
+
+    /// Parse the local .sty/.cls files that the dependencies refer to, following
+    /// their own \RequirePackage/\LoadClass chains, and append what they need.
+    /// Classes and packages given by path (\documentclass{../template/thesis})
+    /// are added to `local_packages` when the file exists, and so are the
+    /// packages a loaded local file declares with \ProvidesPackage, since
+    /// LaTeX then treats them as loaded.
+    pub fn add_local_package_dependencies(
+        &self,
+        dependencies: &mut Vec<TeXDependency>,
+        local_packages: &mut HashMap<String, PathBuf>,
+    ) {
+        let mut parsed: HashSet<PathBuf> = dependencies
+            .iter()
+            .filter_map(|dep| dep.source_file.clone())
+            .collect();
+
+        loop {
+            for dep in dependencies.iter() {
+                if !local_packages.contains_key(&dep.package_name) {
+                    if let Some(file) = Self::local_file_by_path(dep) {
+                        local_packages.insert(dep.package_name.clone(), file);
                     }
                 }
-                self.parse_directory_recursive(&path, dependencies, visited)?;
-            } else if path.is_file() {
-                if let Some(extension) = path.extension() {
-                    let ext = extension.to_string_lossy().to_lowercase();
-                    if (ext == "tex" || ext == "latex" || ext == "sty" || ext == "cls") 
-                        && !visited.contains(&path) {
-                        visited.insert(path.clone());
-                        match self.parse_file(&path) {
-                            Ok(mut file_deps) => dependencies.append(&mut file_deps),
-                            Err(e) => println!("Warning: Failed to parse {}: {}", path.display(), e),
-                        }
-                    }
+            }
+
+            let loaded_files: HashSet<PathBuf> = Self::get_unique_packages(dependencies)
+                .iter()
+                .filter_map(|package| local_packages.get(package).cloned())
+                .collect();
+            for dep in dependencies.iter().filter(|dep| dep.dependency_type == DependencyType::Provides) {
+                if let Some(file) = dep.source_file.as_ref().filter(|file| loaded_files.contains(*file)) {
+                    local_packages.entry(dep.package_name.clone()).or_insert_with(|| file.clone());
+                }
+            }
+
+            let pending: Vec<PathBuf> = loaded_files.into_iter().filter(|file| !parsed.contains(file)).collect();
+
+            if pending.is_empty() {
+                break;
+            }
+
+            for file in pending {
+                match self.parse_file(&file) {
+                    Ok(mut file_deps) => dependencies.append(&mut file_deps),
+                    Err(e) => println!("Warning: Failed to parse {}: {}", file.display(), e),
                 }
+                parsed.insert(file);
             }
         }
+    }
 
-        Ok(())
+    /// Resolve a package or class named by a relative path to its file
+    fn local_file_by_path(dep: &TeXDependency) -> Option<PathBuf> {
+        if !dep.package_name.contains('/') {
+            return None;
+        }
+        let extension = match dep.dependency_type {
+            DependencyType::DocumentClass | DependencyType::LoadClass => "cls",
+            DependencyType::UsePackage | DependencyType::RequirePackage => "sty",
+            _ => return None,
+        };
+        let base_dir = dep.source_file.as_deref().and_then(Path::parent).unwrap_or(Path::new("."));
+        let file = base_dir.join(format!("{}.{}", dep.package_name, extension));
+        file.is_file().then_some(file)
+    }
+
+    /// Packages and classes that a project .sty/.cls file loads itself
+    pub fn packages_required_by(dependencies: &[TeXDependency], file: &Path) -> Vec<String> {
+        let own: Vec<TeXDependency> = dependencies
+            .iter()
+            .filter(|dep| dep.source_file.as_deref() == Some(file))
+            .cloned()
+            .collect();
+        Self::get_unique_packages(&own)
+    }
+
+    /// Restrict dependencies to the chapters selected by \includeonly.
+    /// Files pulled in by an \include that is not listed (and anything they
+    /// input in turn) are dropped. Without \includeonly everything is kept.
+    pub fn filter_included_only(dependencies: &[TeXDependency], base_dir: &Path) -> Vec<TeXDependency> {
+        let selected: HashSet<String> = dependencies
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::IncludeOnly)
+            .map(|dep| dep.package_name.trim_end_matches(".tex").to_string())
+            .collect();
+
+        if selected.is_empty() {
+            return dependencies.to_vec();
+        }
+
+        let resolve = |name: &str| {
+            let path = if Path::new(name).extension().is_some() {
+                base_dir.join(name)
+            } else {
+                base_dir.join(format!("{}.tex", name))
+            };
+            path.canonicalize().unwrap_or(path)
+        };
+        let source_of = |dep: &TeXDependency| {
+            dep.source_file.as_ref().map(|file| file.canonicalize().unwrap_or_else(|_| file.clone()))
+        };
+
+        let mut excluded: HashSet<PathBuf> = dependencies
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::Include)
+            .filter(|dep| !selected.contains(dep.package_name.trim_end_matches(".tex")))
+            .map(|dep| resolve(&dep.package_name))
+            .collect();
+
+        // Follow \input chains out of excluded chapters until nothing changes
+        loop {
+            let nested: Vec<PathBuf> = dependencies
+                .iter()
+                .filter(|dep| matches!(
+                    dep.dependency_type,
+                    DependencyType::Input | DependencyType::Include | DependencyType::Subfile | DependencyType::Import
+                ))
+                .filter(|dep| source_of(dep).is_some_and(|file| excluded.contains(&file)))
+                .map(|dep| resolve(&dep.package_name))
+                .filter(|path| !excluded.contains(path))
+                .collect();
+
+            if nested.is_empty() {
+                break;
+            }
+            excluded.extend(nested);
+        }
+
+        dependencies
+            .iter()
+            .filter(|dep| !source_of(dep).is_some_and(|file| excluded.contains(&file)))
+            .cloned()
+            .collect()
     }
 
     /// Get unique list of package dependencies
@@ -257,14 +1025,8 @@ impl TeXParser {
         
         for dep in dependencies {
             // Only include actual package dependencies, skip file includes
-            match dep.dependency_type {
-                DependencyType::UsePackage | 
-                DependencyType::RequirePackage |
-                DependencyType::DocumentClass |
-                DependencyType::LoadClass => {
-                    packages.insert(dep.package_name.clone());
-                }
-                _ => {} // Skip file dependencies
+            if dep.dependency_type.loads_package() {
+                packages.insert(dep.package_name.clone());
             }
         }
 
@@ -273,6 +1035,271 @@ impl TeXParser {
         result
     }
 
+    /// Suggest packages for commands and environments that are used in the TeX
+    /// sources under `path` but neither defined there nor provided by a loaded package
+    pub fn suggest_packages(&self, path: &Path, loaded_packages: &[String]) -> Result<Vec<PackageSuggestion>> {
+        let mut files = Vec::new();
+        if path.is_dir() {
+            Self::collect_source_files(path, &mut files)?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+
+        let mut sources = Vec::new();
+        for file in files {
+            match Self::read_source(&file) {
+                Ok(content) => sources.push((file, content)),
+                Err(e) => println!("Warning: Failed to read {}: {}", file.display(), e),
+            }
+        }
+
+        // Macros defined anywhere in the project (including local .sty files) count as available
+        let mut defined_commands = HashSet::new();
+        let mut defined_environments = HashSet::new();
+        for (_, content) in &sources {
+            let mut state = CodeState::default();
+            for line in content.lines().filter_map(|line| self.code_of_line(line, &mut state)) {
+                for caps in self.command_definition_regex.captures_iter(&line) {
+                    defined_commands.insert(caps[1].to_string());
+                }
+                for caps in self.environment_definition_regex.captures_iter(&line) {
+                    defined_environments.insert(caps[1].trim().to_string());
+                }
+            }
+        }
+
+        let loaded = Self::with_implied_packages(loaded_packages);
+        let is_satisfied = |packages: &[String]| packages.iter().any(|pkg| loaded.contains(pkg));
+
+        let mut seen = HashSet::new();
+        let mut suggestions = Vec::new();
+        for (file, content) in &sources {
+            let is_document_source = file
+                .extension()
+                .map(|ext| ext.eq_ignore_ascii_case("tex") || ext.eq_ignore_ascii_case("latex"))
+                .unwrap_or(false);
+            if !is_document_source {
+                continue;
+            }
+
+            let mut state = CodeState::default();
+            for (line_number, line) in content.lines().enumerate() {
+                let Some(line) = self.code_of_line(line, &mut state) else {
+                    continue;
+                };
+
+                let commands = self.command_regex.captures_iter(&line).filter_map(|caps| {
+                    let name = caps[1].to_string();
+                    if defined_commands.contains(&name) {
+                        return None;
+                    }
+                    let packages = self.command_packages.packages_for_command(&name)?;
+                    Some((format!("\\{}", name), packages))
+                });
+                let environments = self.begin_environment_regex.captures_iter(&line).filter_map(|caps| {
+                    let name = caps[1].trim().to_string();
+                    if defined_environments.contains(&name) {
+                        return None;
+                    }
+                    let packages = self.command_packages.packages_for_environment(&name)?;
+                    Some((name, packages))
+                });
+
+                for (name, packages) in commands.chain(environments) {
+                    if is_satisfied(packages) || !seen.insert(name.clone()) {
+                        continue;
+                    }
+                    suggestions.push(PackageSuggestion {
+                        package: packages[0].clone(),
+                        name,
+                        file: Some(file.clone()),
+                        line_number: line_number + 1,
+                    });
+                }
+            }
+        }
+
+        Ok(suggestions)
+    }
+
+    /// Expand a package list with the packages they load themselves
+    fn with_implied_packages(packages: &[String]) -> HashSet<String> {
+        let mut loaded: HashSet<String> = packages.iter().cloned().collect();
+        let mut pending: Vec<String> = packages.to_vec();
+
+        while let Some(package) = pending.pop() {
+            if let Some((_, implied)) = IMPLIED_PACKAGES.iter().find(|(name, _)| *name == package) {
+                for implied_package in implied.iter() {
+                    if loaded.insert(implied_package.to_string()) {
+                        pending.push(implied_package.to_string());
+                    }
+                }
+            }
+        }
+
+        loaded
+    }
+
+    /// Get the subfiles/standalone child documents, which can be compiled on their own
+    pub fn find_child_documents(dependencies: &[TeXDependency], main: Option<&Path>) -> Vec<PathBuf> {
+        let mut children: Vec<PathBuf> = dependencies
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::DocumentClass)
+            .filter(|dep| dep.package_name == "subfiles" || dep.package_name == "standalone")
+            .filter_map(|dep| dep.source_file.clone())
+            .filter(|file| Some(file.as_path()) != main)
+            .collect();
+        children.sort();
+        children.dedup();
+        children
+    }
+
+    /// The \usepackage and \RequirePackage loads of each document in load
+    /// order, following its \input, \include and \import files; files no
+    /// document reaches get a sequence of their own
+    pub fn load_sequences(dependencies: &[TeXDependency]) -> Vec<Vec<&TeXDependency>> {
+        let files: HashSet<&PathBuf> = dependencies.iter().filter_map(|dep| dep.source_file.as_ref()).collect();
+        let mut reached = HashSet::new();
+        let mut sequences = Vec::new();
+        for dep in dependencies.iter().filter(|dep| dep.dependency_type == DependencyType::DocumentClass) {
+            if let Some(document) = &dep.source_file {
+                let mut visited = HashSet::new();
+                let mut loads = Vec::new();
+                Self::expand_loads(dependencies, &files, document, &mut visited, &mut loads);
+                reached.extend(visited);
+                sequences.push(loads);
+            }
+        }
+        let mut unreached: Vec<(Option<&PathBuf>, Vec<&TeXDependency>)> = Vec::new();
+        for dep in dependencies.iter().filter(|dep| dep.source_file.as_ref().is_none_or(|file| !reached.contains(file))) {
+            match unreached.iter_mut().find(|(file, _)| *file == dep.source_file.as_ref()) {
+                Some((_, deps)) => deps.push(dep),
+                None => unreached.push((dep.source_file.as_ref(), vec![dep])),
+            }
+        }
+        sequences.extend(unreached.into_iter().map(|(_, deps)| deps.into_iter().filter(|dep| Self::is_load(dep)).collect()));
+        sequences
+    }
+
+    /// Find packages loaded again with options the first load did not have,
+    /// in the load order of each document
+    pub fn find_option_clashes(dependencies: &[TeXDependency]) -> Vec<OptionClash> {
+        let mut clashes: Vec<OptionClash> = Vec::new();
+        for loads in Self::load_sequences(dependencies) {
+            for (index, load) in loads.iter().enumerate() {
+                let Some(first) = loads[..index].iter().find(|earlier| earlier.package_name == load.package_name) else {
+                    continue;
+                };
+                let known = clashes.iter().any(|clash| {
+                    (clash.clashing.source_file.as_ref(), clash.clashing.line_number) == (load.source_file.as_ref(), load.line_number)
+                        && clash.package == load.package_name
+                });
+                if !known && load.options.iter().any(|option| !first.options.contains(option)) {
+                    clashes.push(OptionClash {
+                        package: load.package_name.clone(),
+                        first: (*first).clone(),
+                        clashing: (*load).clone(),
+                    });
+                }
+            }
+        }
+        clashes
+    }
+
+    fn is_load(dep: &TeXDependency) -> bool {
+        matches!(dep.dependency_type, DependencyType::UsePackage | DependencyType::RequirePackage)
+    }
+
+    /// Append the package loads of `file` to `loads`, descending into the
+    /// project files it inputs where they are input
+    fn expand_loads<'a>(
+        dependencies: &'a [TeXDependency],
+        files: &HashSet<&PathBuf>,
+        file: &PathBuf,
+        visited: &mut HashSet<PathBuf>,
+        loads: &mut Vec<&'a TeXDependency>,
+    ) {
+        if !visited.insert(file.clone()) {
+            return;
+        }
+        for dep in dependencies.iter().filter(|dep| dep.source_file.as_ref() == Some(file)) {
+            if Self::is_load(dep) {
+                loads.push(dep);
+            } else if matches!(dep.dependency_type, DependencyType::Input | DependencyType::Include | DependencyType::Import) {
+                let name = dep.package_name.trim_start_matches("./");
+                let name = name.strip_suffix(".tex").unwrap_or(name);
+                if let Some(input) = files.iter().find(|source| source.with_extension("").ends_with(name)) {
+                    Self::expand_loads(dependencies, files, input, visited, loads);
+                }
+            }
+        }
+    }
+
+    /// Check whether a file is a complete document (has \documentclass)
+    pub fn is_document(&self, file_path: &Path) -> bool {
+        self.parse_file(file_path)
+            .map(|deps| deps.iter().any(|dep| dep.dependency_type == DependencyType::DocumentClass))
+            .unwrap_or(false)
+    }
+
+    /// Class options are global, so babel also takes its languages from
+    /// \documentclass[ngerman]{...}; add their packages when babel is loaded
+    fn add_class_option_languages(dependencies: &mut Vec<TeXDependency>) {
+        if !dependencies.iter().any(|dep| Self::is_load(dep) && dep.package_name == "babel") {
+            return;
+        }
+        let mut languages = Vec::new();
+        for class in dependencies.iter().filter(|dep| dep.dependency_type == DependencyType::DocumentClass) {
+            for option in &class.options {
+                for package in Self::language_packages(option) {
+                    languages.push(TeXDependency::new(package.to_string(), DependencyType::Language, class.line_number, &class.context));
+                }
+            }
+        }
+        dependencies.extend(languages);
+    }
+
+    /// Map a babel/polyglossia language name to the packages it requires
+    pub fn language_packages(language: &str) -> &'static [&'static str] {
+        LANGUAGE_PACKAGES
+            .iter()
+            .find(|(name, _)| *name == language)
+            .map(|(_, packages)| *packages)
+            .unwrap_or(&[])
+    }
+
+    /// Find \includegraphics references that do not resolve to a file on disk.
+    /// Figures are looked up relative to `base_dir` and every \graphicspath entry,
+    /// trying the graphicx default extensions for extension-less references.
+    pub fn find_missing_figures(dependencies: &[TeXDependency], base_dir: &Path) -> Vec<TeXDependency> {
+        let mut search_dirs = vec![base_dir.to_path_buf()];
+        for dep in dependencies {
+            if dep.dependency_type == DependencyType::GraphicsPath {
+                search_dirs.push(base_dir.join(&dep.package_name));
+            }
+        }
+
+        dependencies
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::Graphic)
+            // Names built from macros cannot be resolved statically
+            .filter(|dep| !dep.package_name.contains('\\') && !dep.package_name.contains('#'))
+            .filter(|dep| !search_dirs.iter().any(|dir| Self::figure_exists(dir, &dep.package_name)))
+            .cloned()
+            .collect()
+    }
+
+    fn figure_exists(dir: &Path, name: &str) -> bool {
+        let candidate = dir.join(name);
+        if candidate.is_file() {
+            return true;
+        }
+
+        GRAPHICS_EXTENSIONS
+            .iter()
+            .any(|ext| dir.join(format!("{}.{}", name, ext)).is_file())
+    }
+
     /// Filter out LaTeX core packages (do not need separate installation)
     pub fn filter_core_packages(packages: &[String]) -> Vec<String> {
         let core_packages = [
@@ -289,6 +1316,36 @@ impl TeXParser {
             .collect()
     }
 
+    /// Diagnostics for every place in `content` where one of the `missing`
+    /// packages is loaded. The range covers the package name, or the whole
+    /// line when the name does not appear in it (e.g. TikZ libraries).
+    pub fn missing_package_diagnostics(
+        content: &str,
+        dependencies: &[TeXDependency],
+        missing: &[String],
+    ) -> Vec<MissingPackageDiagnostic> {
+        let lines: Vec<&str> = content.lines().collect();
+        dependencies
+            .iter()
+            .filter(|dep| dep.dependency_type.loads_package() && missing.contains(&dep.package_name))
+            .map(|dep| {
+                let line = lines.get(dep.line_number - 1).copied().unwrap_or_default();
+                let (start, end) = match line.find(&dep.package_name) {
+                    Some(start) => (start, start + dep.package_name.len()),
+                    None => (line.len() - line.trim_start().len(), line.trim_end().len()),
+                };
+                MissingPackageDiagnostic {
+                    package: dep.package_name.clone(),
+                    line: dep.line_number,
+                    column: line[..start].chars().count() + 1,
+                    end_column: line[..end].chars().count() + 1,
+                    severity: "error",
+                    message: format!("Package '{}' is not installed; run 'tpmgr install {}'", dep.package_name, dep.package_name),
+                }
+            })
+            .collect()
+    }
+
     /// Display dependency analysis results
     pub fn print_dependency_analysis(dependencies: &[TeXDependency]) {
         if dependencies.is_empty() {
@@ -298,7 +1355,7 @@ impl TeXParser {
 
         println!("Found {} dependencies:", dependencies.len());
         
-        let mut by_type: std::collections::HashMap<&str, Vec<&TeXDependency>> = std::collections::HashMap::new();
+        let mut by_type: HashMap<&str, Vec<&TeXDependency>> = HashMap::new();
         
         for dep in dependencies {
             let type_name = match dep.dependency_type {
@@ -310,15 +1367,29 @@ impl TeXParser {
                 DependencyType::Include => "Included Files",
                 DependencyType::Bibliography => "Bibliography Files",
                 DependencyType::BibliographyStyle => "Bibliography Styles",
+                DependencyType::TikzLibrary => "TikZ/PGF Libraries",
+                DependencyType::Graphic => "Graphics",
+                DependencyType::GraphicsPath => "Graphics Paths",
+                DependencyType::Language => "Language Support",
+                DependencyType::Subfile => "Child Documents",
+                DependencyType::Import => "Imported Files",
+                DependencyType::IncludeOnly => "Included Only",
+                DependencyType::BeamerTheme => "Beamer Themes",
+                DependencyType::Citation => "Citations",
+                DependencyType::Index => "Indexes",
+                DependencyType::Provides => "Provided Packages",
             };
             
-            by_type.entry(type_name).or_insert_with(Vec::new).push(dep);
+            by_type.entry(type_name).or_default().push(dep);
         }
 
         for (type_name, deps) in by_type {
             println!("\n{}:", type_name);
             for dep in deps {
-                println!("  {} (line {}): {}", dep.package_name, dep.line_number, dep.context);
+                match &dep.source_file {
+                    Some(file) => println!("  {} ({}:{}): {}", dep.package_name, file.display(), dep.line_number, dep.context),
+                    None => println!("  {} (line {}): {}", dep.package_name, dep.line_number, dep.context),
+                }
             }
         }
     }
@@ -329,13 +1400,15 @@ impl TeXParser {
         compile_cmd: &crate::config::CompileCommand,
         project_root: &Path,
     ) -> Result<Vec<String>> {
+        let config = crate::config::Config::load_project_in(project_root)?.unwrap_or_else(crate::config::Config::new);
         // Parse compile command chain and magic variables
-        let resolved_commands = compile_cmd.resolve_variables(project_root)?;
+        let resolved_commands = compile_cmd.resolve_variables(&config.chain_variables(project_root))?;
         
         if resolved_commands.is_empty() {
             return Err(anyhow::anyhow!("Empty resolved compile command chain"));
         }
 
+        let environment = crate::environment::project_environment(&config, project_root)?;
         let mut missing_packages = Vec::new();
         
         // Execute each command in the compilation chain
@@ -352,6 +1425,7 @@ impl TeXParser {
                 .stdout(Stdio::piped())
                 .stderr(Stdio::piped())
                 .current_dir(project_root)
+                .envs(environment.iter().cloned())
                 .output()?;
 
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -457,7 +1531,7 @@ impl TeXParser {
     }
 
     /// 解析编译错误输出，提取缺失的包名
-    fn parse_compilation_errors(&self, error_output: &str) -> Vec<String> {
+    pub fn parse_compilation_errors(&self, error_output: &str) -> Vec<String> {
         let mut missing_packages = HashSet::new();
         
         // 常见的TeX错误模式
@@ -495,50 +1569,97 @@ impl TeXParser {
 
         // 特殊处理一些常见情况
         let lines: Vec<&str> = error_output.lines().collect();
-        for line in &lines {
-            // 处理 "! Undefined control sequence" 后跟包名的情况
+        for (index, line) in lines.iter().enumerate() {
+            // 处理 "! Undefined control sequence"，未定义的命令出现在随后的 "l.<n>" 行末尾
             if line.contains("Undefined control sequence") {
-                // 查找可能的包名提示
-                if let Some(package_hint) = self.extract_package_from_undefined_command(line) {
+                if let Some(package_hint) = self.extract_package_from_undefined_command(&lines[index..]) {
                     missing_packages.insert(package_hint);
                 }
             }
         }
 
+        // Missing fonts are reported as font files (or fontspec font names) to look up in the file index
+        let missing_fonts = Self::parse_missing_fonts(error_output);
+        if missing_fonts.iter().any(|font| font.starts_with(FONT_NAME_PREFIX)) {
+            // "Package fontspec Error: The font ... cannot be found" is not a missing fontspec
+            missing_packages.remove("fontspec");
+        }
+        missing_packages.extend(missing_fonts);
+
+        // ! LaTeX Error: Environment xyz undefined.
+        if let Ok(regex) = Regex::new(r"Environment (\S+) undefined") {
+            for captures in regex.captures_iter(error_output) {
+                if let Some(packages) = self.command_packages.packages_for_environment(&captures[1]) {
+                    missing_packages.insert(packages[0].clone());
+                }
+            }
+        }
+
         let mut result: Vec<String> = missing_packages.into_iter().collect();
         result.sort();
         result
     }
 
-    /// 从未定义命令错误中提取可能的包名
-    fn extract_package_from_undefined_command(&self, error_line: &str) -> Option<String> {
-        // 一些常见的命令到包的映射
-        let command_to_package = [
-            (r"\\includegraphics", "graphicx"),
-            (r"\\url", "url"),
-            (r"\\href", "hyperref"),
-            (r"\\textcolor", "xcolor"),
-            (r"\\colorbox", "xcolor"),
-            (r"\\fcolorbox", "xcolor"),
-            (r"\\begin\{figure\}", "graphicx"),
-            (r"\\begin\{table\}", "array"),
-            (r"\\toprule", "booktabs"),
-            (r"\\midrule", "booktabs"),
-            (r"\\bottomrule", "booktabs"),
-            (r"\\multicolumn", "array"),
-            (r"\\multirow", "multirow"), 
-            (r"\\footnotesize", "geometry")
-        ];
+    /// Extract missing fonts from compiler output. TFM metrics and font definition
+    /// files are returned as file names (ecrm1000.tfm, t1phv.fd); fonts requested
+    /// by name through fontspec as `font:<name>`.
+    pub fn parse_missing_fonts(error_output: &str) -> Vec<String> {
+        let mut fonts = Vec::new();
+        let mut add = |font: String| {
+            if !fonts.contains(&font) {
+                fonts.push(font);
+            }
+        };
 
-        for (pattern, package) in &command_to_package {
+        // ! Font \T1/phv/m/n/10=phvr8t at 10pt not loadable: Metric (TFM) file not found.
+        // ** ERROR ** Could not locate a virtual/physical font for TFM "phvr8t".
+        let tfm_patterns = [
+            r"Font \\[^=\s]+=([^\s]+)(?: at [^\s]+)? not loadable: Metric \(TFM\) file not found",
+            r#"Could not locate a virtual/physical font for TFM "([^"]+)""#,
+        ];
+        for pattern in &tfm_patterns {
             if let Ok(regex) = Regex::new(pattern) {
-                if regex.is_match(error_line) {
-                    return Some(package.to_string());
+                for captures in regex.captures_iter(error_output) {
+                    add(format!("{}.tfm", &captures[1]));
                 }
             }
         }
 
-        None
+        // LaTeX Font Warning: Font shape `T1/xyz/m/n' undefined
+        // Only the medium upright shape is taken as a missing family; other shapes
+        // are usually substitutions within an installed family
+        if let Ok(regex) = Regex::new(r"Font shape `([^/']+)/([^/']+)/m/n' undefined") {
+            for captures in regex.captures_iter(error_output) {
+                add(format!("{}{}.fd", captures[1].to_lowercase(), &captures[2]));
+            }
+        }
+
+        // Package fontspec Error: The font "Fira Sans" cannot be found.
+        if let Ok(regex) = Regex::new(r#"The font "([^"]+)" cannot be found"#) {
+            for captures in regex.captures_iter(error_output) {
+                add(format!("{}{}", FONT_NAME_PREFIX, &captures[1]));
+            }
+        }
+
+        fonts
+    }
+
+    /// 从未定义命令错误中提取可能的包名
+    /// `lines` starts at the error line; TeX prints the input up to the offending
+    /// command on the following "l.<n>" line, so the last control word there is it
+    fn extract_package_from_undefined_command(&self, lines: &[&str]) -> Option<String> {
+        let context = lines
+            .iter()
+            .take(4)
+            .find(|line| line.starts_with("l."))
+            .or_else(|| lines.first())?;
+
+        let command_regex = Regex::new(r"\\([A-Za-z@]+)").ok()?;
+        let command = command_regex.captures_iter(context).last()?;
+
+        self.command_packages
+            .packages_for_command(&command[1])
+            .map(|packages| packages[0].clone())
     }
 
     /// 判断编译错误是否与包相关
@@ -550,6 +1671,8 @@ impl TeXParser {
             r"Package.*Error",
             r"Package.*Warning",
             r"Unknown option.*for package",
+            r"not loadable: Metric",
+            r"The font .* cannot be found",
             r"Undefined control sequence.*\\usepackage",
             r"File.*\.sty.*not found",
             r"File.*\.cls.*not found",
@@ -617,6 +1740,36 @@ mod tests {
         assert_eq!(deps[1].package_name, "article");
     }
 
+    #[test]
+    fn test_skip_verbatim_and_disabled_code() {
+        let parser = TeXParser::new().unwrap();
+        let content = r"\documentclass{article}
+\usepackage{listings,comment}
+\begin{lstlisting}[language=TeX]
+\usepackage{inlisting}
+\end{lstlisting} \usepackage{afterlisting}
+\begin{comment}
+\usepackage{incomment}
+\end{comment}
+\iffalse
+\usepackage{infalse}
+\ifdefined\foo \usepackage{nested}\fi
+\else \usepackage{inelse}
+\fi
+Use \verb|\usepackage{inverb}| or 50\% % \usepackage{commented}
+\iffalse \usepackage{inline} \fi\usepackage{afterfi}
+\begin{verbatim*}\usepackage{sameline}\end{verbatim*}\usepackage{sameline2}";
+        let deps = parser.parse_content(content).unwrap();
+        let packages: Vec<&str> = deps
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::UsePackage)
+            .map(|dep| dep.package_name.as_str())
+            .collect();
+
+        assert_eq!(packages, vec!["listings", "comment", "afterlisting", "inelse", "afterfi", "sameline2"]);
+        assert_eq!(deps.iter().find(|dep| dep.package_name == "inelse").unwrap().line_number, 12);
+    }
+
     #[test]
     fn test_parse_compilation_errors() {
         let parser = TeXParser::new().unwrap();
@@ -626,6 +1779,311 @@ mod tests {
         assert_eq!(missing, vec!["minted"]);
     }
 
+    #[test]
+    fn test_undefined_command_errors() {
+        let parser = TeXParser::new().unwrap();
+        let error = "! Undefined control sequence.\nl.12 The length is \\SI\n                   {3}{\\metre}\n\
+                     ! LaTeX Error: Environment algorithm undefined.";
+        let missing = parser.parse_compilation_errors(error);
+
+        assert_eq!(missing, vec!["algorithm2e", "siunitx"]);
+    }
+
+    #[test]
+    fn test_suggest_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.tex");
+        fs::write(
+            &main,
+            "\\usepackage{graphicx}\n\\newcommand{\\toprule}{\\hline}\n\\SI{3}{m} and \\cref{fig}\n\
+             \\includegraphics{a}\\toprule\n\\begin{align*}x\\end{align*}\n% \\lipsum\n",
+        )
+        .unwrap();
+
+        let parser = TeXParser::new().unwrap();
+        let suggestions = parser.suggest_packages(&main, &["graphicx".to_string()]).unwrap();
+        let found: Vec<(&str, &str)> = suggestions
+            .iter()
+            .map(|s| (s.name.as_str(), s.package.as_str()))
+            .collect();
+
+        assert_eq!(found, vec![("\\SI", "siunitx"), ("\\cref", "cleveref"), ("align*", "amsmath")]);
+        assert_eq!(suggestions[0].line_number, 3);
+
+        let suggestions = parser
+            .suggest_packages(&main, &["graphicx", "mathtools", "siunitx", "cleveref"].map(String::from))
+            .unwrap();
+        assert!(suggestions.is_empty());
+    }
+
+    #[test]
+    fn test_decode_legacy_sources() {
+        let latin1 = b"\\usepackage[latin1]{inputenc}\n\\usepackage{amsmath} % caf\xe9\n";
+        let (content, encoding) = TeXParser::decode_source(latin1);
+        assert_eq!(encoding, Some("windows-1252"));
+        assert!(content.contains("caf\u{e9}"));
+
+        let gbk = b"\\usepackage{CJK}\n% \xd6\xd0\xce\xc4\n";
+        let (content, encoding) = TeXParser::decode_source(gbk);
+        assert_eq!(encoding, Some("GBK"));
+        assert!(content.contains("\u{4e2d}\u{6587}"));
+
+        let (content, encoding) = TeXParser::decode_source("\u{feff}\\usepackage{amsmath}".as_bytes());
+        assert_eq!(encoding, None);
+        assert_eq!(content, "\\usepackage{amsmath}");
+    }
+
+    #[test]
+    fn test_scan_skips_output_and_symlink_loops() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for sub in ["chapters", "build", "out", "pdf", "_minted-main"] {
+            fs::create_dir(root.join(sub)).unwrap();
+            fs::write(root.join(sub).join("part.tex"), "\\usepackage{amsmath}").unwrap();
+        }
+        fs::write(root.join("main.tex"), "\\documentclass{article}").unwrap();
+
+        let mut config = crate::config::Config::new();
+        config.project.output_dir = Some("pdf".to_string());
+        config.save(root.join("tpmgr.toml")).unwrap();
+
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root, root.join("chapters").join("loop")).unwrap();
+
+        let mut files = Vec::new();
+        TeXParser::collect_source_files(root, &mut files).unwrap();
+        files.sort();
+
+        assert_eq!(files, vec![root.join("chapters").join("part.tex"), root.join("main.tex")]);
+    }
+
+    #[test]
+    fn test_collect_project_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for sub in ["sections", "pdf", "drafts"] {
+            fs::create_dir(root.join(sub)).unwrap();
+            fs::write(root.join(sub).join("ch1.aux"), "").unwrap();
+        }
+        fs::write(root.join(crate::ignore::IGNORE_FILE), "drafts/\n").unwrap();
+
+        let mut config = crate::config::Config::new();
+        config.project.output_dir = Some("pdf".to_string());
+        config.save(root.join("tpmgr.toml")).unwrap();
+
+        let mut files = Vec::new();
+        TeXParser::collect_project_files(root, &mut files).unwrap();
+        files.sort();
+
+        assert_eq!(files, vec![
+            root.join(crate::ignore::IGNORE_FILE),
+            root.join("sections").join("ch1.aux"),
+            root.join("tpmgr.toml"),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_missing_fonts() {
+        let parser = TeXParser::new().unwrap();
+        let error = "! Font \\T1/phv/m/n/10=phvr8t at 10pt not loadable: Metric (TFM) file not found.\n\
+                     LaTeX Font Warning: Font shape `T1/xyz/m/n' undefined\n\
+                     LaTeX Font Warning: Font shape `T1/cmr/m/scit' undefined\n\
+                     ! Package fontspec Error: The font \"Fira Sans\" cannot be found.";
+        let missing = parser.parse_compilation_errors(error);
+
+        assert_eq!(missing, vec!["font:Fira Sans", "phvr8t.tfm", "t1xyz.fd"]);
+    }
+
+    #[test]
+    fn test_parse_beamer_themes() {
+        let parser = TeXParser::new().unwrap();
+        let content = "\\usetheme[progressbar=frametitle]{metropolis}\n\\usetheme{Madrid}\n\\usecolortheme{owl}\n\\useoutertheme{fancy}";
+        let deps = parser.parse_content(content).unwrap();
+        let packages = TeXParser::get_unique_packages(&deps);
+
+        assert_eq!(packages, vec!["beamer", "beamercolortheme-owl", "beamerouterthemefancy", "beamertheme-metropolis"]);
+    }
+
+    #[test]
+    fn test_parse_tikz_libraries() {
+        let parser = TeXParser::new().unwrap();
+        let content = "\\usetikzlibrary{positioning,cd,ext.paths.ortho}\n\\usepgfplotslibrary{fillbetween}";
+        let deps = parser.parse_content(content).unwrap();
+        let packages = TeXParser::get_unique_packages(&deps);
+
+        assert_eq!(packages, vec!["pgfplots", "tikz-cd", "tikz-ext"]);
+    }
+
+    #[test]
+    fn test_parse_citations() {
+        let parser = TeXParser::new().unwrap();
+        let content = "As shown~\\cite{knuth1974, lamport1994} and \\citep[see][p.~3]{vaswani2017}.\n\\textcite{smith2020}\\nocite{*}\n\\citestyle{aa} % \\cite{commented}";
+        let deps = parser.parse_content(content).unwrap();
+        let keys: Vec<&str> = deps
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::Citation)
+            .map(|dep| dep.package_name.as_str())
+            .collect();
+
+        assert_eq!(keys, vec!["knuth1974", "lamport1994", "vaswani2017", "smith2020", "*"]);
+        assert!(TeXParser::get_unique_packages(&deps).is_empty());
+    }
+
+    #[test]
+    fn test_parse_indexes() {
+        let parser = TeXParser::new().unwrap();
+        let content = "\\usepackage{imakeidx}\n\\makeindex\n\\makeindex[name=persons, title=Index of Names]\n\\makeindex{symbols}\n\\printindex";
+        let deps = parser.parse_content(content).unwrap();
+        let indexes: Vec<&str> = deps
+            .iter()
+            .filter(|dep| dep.dependency_type == DependencyType::Index)
+            .map(|dep| dep.package_name.as_str())
+            .collect();
+
+        assert_eq!(indexes, vec!["\\jobname", "persons", "symbols"]);
+    }
+
+    #[test]
+    fn test_parse_language_options() {
+        let parser = TeXParser::new().unwrap();
+        let content = "\\usepackage[main=ngerman,french]{babel}\n\\setotherlanguages{russian}";
+        let deps = parser.parse_content(content).unwrap();
+        let packages = TeXParser::get_unique_packages(&deps);
+
+        assert_eq!(packages, vec![
+            "babel", "babel-french", "babel-german",
+            "hyphen-french", "hyphen-german", "hyphen-russian",
+        ]);
+
+        let deps = parser.parse_content("\\usepackage{fontspec,polyglossia}\n\\setdefaultlanguage[variant=british]{english}\n").unwrap();
+        let packages = TeXParser::get_unique_packages(&deps);
+        assert_eq!(packages, vec!["fontspec", "hyphen-english", "polyglossia"]);
+    }
+
+    #[test]
+    fn test_class_option_languages() {
+        let parser = TeXParser::new().unwrap();
+        let deps = parser.parse_content("\\documentclass[12pt,ngerman,english]{article}\n\\usepackage{babel}\n").unwrap();
+        let packages = TeXParser::get_unique_packages(&deps);
+        assert_eq!(packages, vec![
+            "article", "babel", "babel-english", "babel-german", "hyphen-english", "hyphen-german",
+        ]);
+
+        // Without babel the options are left to the class
+        let deps = parser.parse_content("\\documentclass[ngerman]{article}\n").unwrap();
+        assert_eq!(TeXParser::get_unique_packages(&deps), vec!["article"]);
+    }
+
+    #[test]
+    fn test_child_document_attribution() {
+        let parser = TeXParser::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.tex");
+        let child = dir.path().join("chapter.tex");
+        fs::write(&main, "\\documentclass{article}\n\\usepackage{subfiles}\n\\subfile{chapter}\n\\subimport{parts/}{intro}").unwrap();
+        fs::write(&child, "\\documentclass[main.tex]{subfiles}\n\\usepackage{siunitx}").unwrap();
+
+        let deps = parser.parse_project(dir.path()).unwrap();
+        let siunitx = deps.iter().find(|dep| dep.package_name == "siunitx").unwrap();
+        assert_eq!(siunitx.source_file.as_deref(), Some(child.as_path()));
+        assert!(deps.iter().any(|dep| dep.dependency_type == DependencyType::Import && dep.package_name == "parts/intro"));
+        assert_eq!(TeXParser::find_child_documents(&deps, Some(&main)), vec![child]);
+    }
+
+    #[test]
+    fn test_filter_included_only() {
+        let parser = TeXParser::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("main.tex"), "\\includeonly{ch1}\n\\include{ch1}\n\\include{ch2}").unwrap();
+        fs::write(dir.path().join("ch1.tex"), "\\usepackage{booktabs}").unwrap();
+        fs::write(dir.path().join("ch2.tex"), "\\usepackage{siunitx}\n\\input{ch2-table}").unwrap();
+        fs::write(dir.path().join("ch2-table.tex"), "\\usepackage{longtable}").unwrap();
+
+        let deps = parser.parse_project(dir.path()).unwrap();
+        let active = TeXParser::filter_included_only(&deps, dir.path());
+
+        assert_eq!(TeXParser::get_unique_packages(&active), vec!["booktabs"]);
+        assert_eq!(TeXParser::get_unique_packages(&deps).len(), 3);
+    }
+
+    #[test]
+    fn test_local_packages() {
+        let parser = TeXParser::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("styles")).unwrap();
+        let main = dir.path().join("main.tex");
+        fs::write(&main, "\\usepackage{mystyle}").unwrap();
+        fs::write(dir.path().join("styles").join("mystyle.sty"), "\\RequirePackage{xcolor}").unwrap();
+
+        let mut local = TeXParser::find_local_packages(dir.path()).unwrap();
+        assert!(local.contains_key("mystyle"));
+
+        let mut deps = parser.parse_file(&main).unwrap();
+        parser.add_local_package_dependencies(&mut deps, &mut local);
+        assert_eq!(TeXParser::get_unique_packages(&deps), vec!["mystyle", "xcolor"]);
+    }
+
+    #[test]
+    fn test_provided_packages() {
+        let parser = TeXParser::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.tex");
+        fs::write(&main, "\\documentclass{thesis}\n\\usepackage{thesis-fonts}\n\\usepackage{draftstyle}\n").unwrap();
+        // The class bundles thesis-fonts; draftstyle is only provided by a file nothing loads
+        fs::write(dir.path().join("thesis.cls"), "\\ProvidesClass{thesis}\n\\LoadClass{report}\n\\ProvidesPackage{thesis-fonts}\n\\RequirePackage{fontspec}\n").unwrap();
+        fs::write(dir.path().join("unused.sty"), "\\ProvidesPackage{draftstyle}\n").unwrap();
+
+        let mut local = TeXParser::find_local_packages(dir.path()).unwrap();
+        let mut deps = parser.parse_project(dir.path()).unwrap();
+        parser.add_local_package_dependencies(&mut deps, &mut local);
+
+        assert_eq!(local.get("thesis-fonts"), Some(&dir.path().join("thesis.cls")));
+        assert!(!local.contains_key("draftstyle"));
+        let packages = TeXParser::get_unique_packages(&deps);
+        assert_eq!(packages, vec!["draftstyle", "fontspec", "report", "thesis", "thesis-fonts"]);
+        let needed: Vec<&String> = packages.iter().filter(|package| !local.contains_key(*package)).collect();
+        assert_eq!(needed, vec!["draftstyle", "fontspec", "report"]);
+    }
+
+    #[test]
+    fn test_local_class_chain() {
+        let parser = TeXParser::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("paper");
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(dir.path().join("template")).unwrap();
+        let main = project.join("main.tex");
+        fs::write(&main, "\\documentclass{../template/thesis}").unwrap();
+        let class = dir.path().join("template").join("thesis.cls");
+        fs::write(&class, "\\LoadClassWithOptions{report}\n\\RequirePackageWithOptions{geometry}\n\\RequirePackage{fancyhdr}").unwrap();
+
+        let mut local = TeXParser::find_local_packages(&project).unwrap();
+        let mut deps = parser.parse_file(&main).unwrap();
+        parser.add_local_package_dependencies(&mut deps, &mut local);
+
+        assert_eq!(local.get("../template/thesis"), Some(&project.join("../template/thesis.cls")));
+        assert_eq!(
+            TeXParser::packages_required_by(&deps, &project.join("../template/thesis.cls")),
+            vec!["fancyhdr", "geometry", "report"]
+        );
+    }
+
+    #[test]
+    fn test_find_missing_figures() {
+        let parser = TeXParser::new().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("figures")).unwrap();
+        fs::write(dir.path().join("figures").join("plot.pdf"), b"").unwrap();
+
+        let content = "\\graphicspath{{figures/}}\n\\includegraphics[width=5cm]{plot}\n\\includegraphics{missing.png}";
+        let deps = parser.parse_content(content).unwrap();
+        let missing = TeXParser::find_missing_figures(&deps, dir.path());
+
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].package_name, "missing.png");
+        assert_eq!(missing[0].line_number, 3);
+    }
+
     #[test]
     fn test_filter_core_packages() {
         let packages = vec!["amsmath".to_string(), "article".to_string()];
@@ -633,6 +2091,43 @@ mod tests {
         
         assert_eq!(filtered, vec!["amsmath"]);
     }
+
+    #[test]
+    fn test_missing_package_diagnostics() {
+        let parser = TeXParser::new().unwrap();
+        let content = "\\documentclass{article}\n  \\usepackage{amsmath,siunitx} % µ\n\\input{siunitx}\n";
+        let deps = parser.parse_content(content).unwrap();
+        let diagnostics = TeXParser::missing_package_diagnostics(content, &deps, &["siunitx".to_string()]);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!((diagnostics[0].column, diagnostics[0].end_column), (23, 30));
+        assert_eq!(diagnostics[0].severity, "error");
+    }
+
+    #[test]
+    fn test_option_clashes() {
+        let parser = TeXParser::new().unwrap();
+        let with_file = |content: &str, file: &str| {
+            let mut deps = parser.parse_content(content).unwrap();
+            for dep in &mut deps {
+                dep.source_file = Some(PathBuf::from(file));
+            }
+            deps
+        };
+        let mut deps = with_file("\\documentclass{article}\n\\usepackage[margin=1in, a4paper]{geometry}\n\\usepackage{geometry}\n\\usepackage[utf8]{inputenc}\n\\input{preamble}\n", "main.tex");
+        assert_eq!(deps[1].options, vec!["margin=1in", "a4paper"]);
+        deps.extend(with_file("\\usepackage[a4paper]{geometry}\n\\usepackage[latin1]{inputenc}\n", "preamble.tex"));
+        // A separate document may load packages with its own options
+        deps.extend(with_file("\\documentclass{beamer}\n\\usepackage[margin=2cm]{geometry}\n", "slides.tex"));
+        deps.extend(with_file("\\usepackage[final]{geometry}\n", "unused.tex"));
+
+        let clashes = TeXParser::find_option_clashes(&deps);
+        assert_eq!(clashes.len(), 1);
+        assert_eq!(clashes[0].package, "inputenc");
+        assert_eq!((clashes[0].first.line_number, clashes[0].clashing.line_number), (4, 2));
+        assert_eq!(clashes[0].clashing.source_file, Some(PathBuf::from("preamble.tex")));
+    }
 }
 
 
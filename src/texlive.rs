@@ -2,7 +2,13 @@
 use std::process::Command;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::BufRead;
+use std::time::Duration;
+
+/// How long a downloaded copy of the remote package database is reused
+pub const REMOTE_TLPDB_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TeXLiveInfo {
@@ -11,21 +17,266 @@ pub struct TeXLiveInfo {
     pub texmf_dist: PathBuf,
     pub texmf_local: PathBuf,
     pub texmf_home: PathBuf,
+    #[serde(default)]
+    pub distribution: Distribution,
+}
+
+/// The TeX distribution an installation belongs to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Distribution {
+    #[default]
+    TeXLive,
+    /// The small TeX Live scheme for macOS, installed next to MacTeX's
+    /// releases as e.g. /usr/local/texlive/2024basic
+    BasicTeX,
+    MiKTeX,
+    /// The per-user TeX Live of the TinyTeX project, e.g. ~/.TinyTeX
+    TinyTeX,
+}
+
+impl Distribution {
+    /// Tell the distribution from the layout of its root directory: MiKTeX
+    /// keeps its executables in `miktex/bin`
+    pub fn of(root: &Path) -> Self {
+        let name = root.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        if root.join("miktex").join("bin").is_dir() {
+            Self::MiKTeX
+        } else if name.trim_start_matches('.') == "TinyTeX" {
+            Self::TinyTeX
+        } else if name.ends_with("basic") {
+            Self::BasicTeX
+        } else {
+            Self::TeXLive
+        }
+    }
+
+    /// The texmf tree holding the distribution's packages
+    pub fn texmf_dist(self, root: &Path) -> PathBuf {
+        match self {
+            Self::TeXLive | Self::BasicTeX | Self::TinyTeX => root.join("texmf-dist"),
+            // MiKTeX's install root is itself a texmf tree
+            Self::MiKTeX => root.to_path_buf(),
+        }
+    }
+
+    /// Whether the distribution manages its packages with tlmgr
+    pub fn has_tlmgr(self) -> bool {
+        self != Self::MiKTeX
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::TeXLive => "TeX Live",
+            Self::BasicTeX => "BasicTeX",
+            Self::MiKTeX => "MiKTeX",
+            Self::TinyTeX => "TinyTeX",
+        })
+    }
+}
+
+/// A key of `reg query` output with its values
+#[derive(Debug, Default)]
+struct RegistryKey {
+    path: String,
+    values: HashMap<String, String>,
+}
+
+/// Bumped when `PackageIndexCache` changes, so older caches are reparsed
+const PACKAGE_INDEX_FORMAT: u32 = 2;
+
+/// The parsed package database of an installation, kept between runs
+#[derive(Serialize, Deserialize)]
+struct PackageIndexCache {
+    format: u32,
+    /// Size and modification time (nanoseconds since the epoch) of the tlpdb
+    /// it was parsed from
+    tlpdb_size: u64,
+    tlpdb_modified: u128,
+    packages: HashMap<String, InstalledPackage>,
+    file_owners: FileIndex,
+}
+
+impl PackageIndexCache {
+    /// The size and modification time identifying a version of `tlpdb_path`
+    fn key(tlpdb_path: &Path) -> Option<(u64, u128)> {
+        let metadata = std::fs::metadata(tlpdb_path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(std::time::UNIX_EPOCH).ok()?;
+        Some((metadata.len(), modified.as_nanos()))
+    }
+
+    /// Where the index parsed from `tlpdb_path` is cached: one file per
+    /// installation in the index directory
+    fn path(tlpdb_path: &Path) -> Result<PathBuf> {
+        use sha2::{Digest, Sha256};
+        let digest = format!("{:x}", Sha256::digest(tlpdb_path.to_string_lossy().as_bytes()));
+        Ok(crate::download::index_dir()?.join(format!("installed-{}.json", &digest[..16])))
+    }
+
+    /// The cache at `cache_path`, if it was made from the current `tlpdb_path`
+    fn load(cache_path: &Path, tlpdb_path: &Path) -> Option<Self> {
+        let (size, modified) = Self::key(tlpdb_path)?;
+        let cache: Self = serde_json::from_slice(&std::fs::read(cache_path).ok()?).ok()?;
+        (cache.format == PACKAGE_INDEX_FORMAT && cache.tlpdb_size == size && cache.tlpdb_modified == modified).then_some(cache)
+    }
+
+    fn save(cache_path: &Path, tlpdb_path: &Path, packages: &HashMap<String, InstalledPackage>, file_owners: &FileIndex) -> Result<()> {
+        let Some((tlpdb_size, tlpdb_modified)) = Self::key(tlpdb_path) else {
+            return Ok(());
+        };
+        let cache = Self {
+            format: PACKAGE_INDEX_FORMAT,
+            tlpdb_size,
+            tlpdb_modified,
+            packages: packages.clone(),
+            file_owners: file_owners.clone(),
+        };
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let temp_path = cache_path.with_extension("json.tmp");
+        std::fs::write(&temp_path, serde_json::to_vec(&cache)?)?;
+        std::fs::rename(&temp_path, cache_path)?;
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone)]
+/// MacTeX's link to the executables of the selected distribution
+const MACOS_TEXBIN: &str = "/Library/TeX/texbin";
+
+/// Where macOS keeps a `<name>.texdist` bundle per TeX distribution
+const MACOS_TEXDIST_DIR: &str = "/Library/TeX/Distributions";
+
+/// The uninstall entries Windows keeps for machine-wide and per-user installs
+const UNINSTALL_KEYS: &[&str] = &[
+    "HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall",
+    "HKLM\\SOFTWARE\\WOW6432Node\\Microsoft\\Windows\\CurrentVersion\\Uninstall",
+    "HKCU\\Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct InstalledPackage {
     pub name: String,
     pub version: String,
     pub description: String,
-    pub files: Vec<PathBuf>,
     pub install_path: PathBuf,
 }
 
+/// Runtime file name (e.g. bm.sty) to the package that ships it, with each
+/// package name stored once however many files it has
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FileIndex {
+    packages: Vec<String>,
+    owners: HashMap<String, u32>,
+}
+
+impl FileIndex {
+    /// Store a package name, returning the id files refer to it by
+    fn intern(&mut self, package: &str) -> u32 {
+        self.packages.push(package.to_string());
+        (self.packages.len() - 1) as u32
+    }
+
+    /// Record the owner of `file_name` unless it already has one
+    fn insert(&mut self, file_name: &str, package: u32) {
+        if !self.owners.contains_key(file_name) {
+            self.owners.insert(file_name.to_string(), package);
+        }
+    }
+
+    fn get(&self, file_name: &str) -> Option<&str> {
+        self.owners.get(file_name).map(|package| self.packages[*package as usize].as_str())
+    }
+
+    fn get_key_value(&self, file_name: &str) -> Option<(&str, &str)> {
+        let (file_name, package) = self.owners.get_key_value(file_name)?;
+        Some((file_name.as_str(), self.packages[*package as usize].as_str()))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.owners.iter().map(|(file_name, package)| (file_name.as_str(), self.packages[*package as usize].as_str()))
+    }
+
+    /// Add the files of `other` that have no owner yet
+    fn merge(&mut self, other: FileIndex) {
+        let offset = self.packages.len() as u32;
+        self.packages.extend(other.packages);
+        for (file_name, package) in other.owners {
+            self.owners.entry(file_name).or_insert(package + offset);
+        }
+    }
+}
+
+/// The fields of a tlpdb package record the installed package list keeps
+#[derive(Debug, Default)]
+struct TlpdbRecord {
+    name: String,
+    description: String,
+    version: Option<String>,
+}
+
+/// Stream a tlpdb line by line, passing each package record to `on_package`
+/// and adding its runtime files to `file_index`
+fn read_tlpdb(mut reader: impl BufRead, file_index: &mut FileIndex, mut on_package: impl FnMut(TlpdbRecord)) -> Result<()> {
+    let mut buffer = String::new();
+    let mut record: Option<TlpdbRecord> = None;
+    // The interned name of the current package, once it has a runtime file
+    let mut package_id = None;
+    let mut in_runfiles = false;
+
+    loop {
+        buffer.clear();
+        if reader.read_line(&mut buffer)? == 0 {
+            break;
+        }
+        let line = buffer.trim_end_matches(['\n', '\r']);
+        if let Some(file_path) = line.strip_prefix(' ') {
+            // 文件路径 (relative to the installation root, e.g. texmf-dist/tex/...)
+            let (true, Some(record)) = (in_runfiles, &record) else {
+                continue;
+            };
+            let Some(file_name) = file_path.split_whitespace().next().and_then(|path| path.rsplit('/').next()) else {
+                continue;
+            };
+            let package = *package_id.get_or_insert_with(|| file_index.intern(&record.name));
+            file_index.insert(file_name, package);
+        } else if let Some(name) = line.strip_prefix("name ") {
+            // 保存前一个包，开始新包
+            if let Some(record) = record.take() {
+                on_package(record);
+            }
+            record = Some(TlpdbRecord { name: name.trim().to_string(), ..Default::default() });
+            package_id = None;
+            in_runfiles = false;
+        } else {
+            in_runfiles = line.starts_with("runfiles");
+            let Some(record) = &mut record else {
+                continue;
+            };
+            if let Some(description) = line.strip_prefix("shortdesc ") {
+                record.description = description.to_string();
+            } else if let Some(version) = line.strip_prefix("catalogue-version ") {
+                record.version = Some(version.trim().to_string());
+            }
+        }
+    }
+
+    // 保存最后一个包
+    if let Some(record) = record {
+        on_package(record);
+    }
+    Ok(())
+}
+
 pub struct TeXLiveManager {
     texlive_info: Option<TeXLiveInfo>,
     installed_packages: HashMap<String, InstalledPackage>,
+    file_owners: FileIndex,
+    /// Packages kpsewhich found although the database may not list them
+    /// (see `check_with_kpsewhich`)
+    found_by_kpsewhich: HashSet<String>,
 }
 
 impl TeXLiveManager {
@@ -33,22 +284,27 @@ impl TeXLiveManager {
         Self {
             texlive_info: None,
             installed_packages: HashMap::new(),
+            file_owners: FileIndex::default(),
+            found_by_kpsewhich: HashSet::new(),
         }
     }
 
     /// Automatically detect TeXLive installation
     pub fn detect_texlive(&mut self) -> Result<()> {
         println!("Detecting TeXLive installation...");
+        let progress = crate::ui::Progress::start("Detecting TeXLive installation", "");
 
         let texmf_root = self.find_texlive_root()?;
+        let distribution = Distribution::of(&texmf_root);
         
         // Temporarily set texlive_info for version detection methods
         self.texlive_info = Some(TeXLiveInfo {
             version: "Unknown".to_string(),
             install_path: texmf_root.clone(),
-            texmf_dist: texmf_root.join("texmf-dist"),
+            texmf_dist: distribution.texmf_dist(&texmf_root),
             texmf_local: texmf_root.join("texmf-local"),
             texmf_home: self.get_texmf_home()?,
+            distribution,
         });
         
         let version = self.get_texlive_version()?;
@@ -57,16 +313,67 @@ impl TeXLiveManager {
         let texlive_info = TeXLiveInfo {
             version: version.clone(),
             install_path: texmf_root.clone(),
-            texmf_dist: texmf_root.join("texmf-dist"),
+            texmf_dist: distribution.texmf_dist(&texmf_root),
             texmf_local: texmf_root.join("texmf-local"),
             texmf_home: self.get_texmf_home()?,
+            distribution,
         };
 
-        println!("Found TeXLive {} at: {}", version, texmf_root.display());
+        drop(progress);
+        println!("Found {} {} at: {}", distribution, version, texmf_root.display());
+        if distribution == Distribution::BasicTeX {
+            println!("BasicTeX ships few packages; `tpmgr install` adds the ones a project is missing");
+        }
+        if Self::user_tlmgr(&texmf_root).is_some() {
+            println!("This {} belongs to you, so `tpmgr install --global` installs through its tlmgr", distribution);
+        }
         self.texlive_info = Some(texlive_info);
         Ok(())
     }
 
+    /// Use the TeX Live installation at `texlive_root` instead of searching for one
+    pub fn at(texlive_root: &Path) -> Result<Self> {
+        let mut manager = Self::new();
+        let version = Self::release_year(texlive_root)
+            .map(|year| year.to_string())
+            .unwrap_or_else(|| "Unknown".to_string());
+        let distribution = Distribution::of(texlive_root);
+        manager.texlive_info = Some(TeXLiveInfo {
+            version,
+            install_path: texlive_root.to_path_buf(),
+            texmf_dist: distribution.texmf_dist(texlive_root),
+            texmf_local: texlive_root.join("texmf-local"),
+            texmf_home: manager.get_texmf_home()?,
+            distribution,
+        });
+        Ok(manager)
+    }
+
+    /// The release year of a TeX Live root named after it, e.g. /usr/local/texlive/2024
+    pub fn release_year(texlive_root: &Path) -> Option<u32> {
+        let name = texlive_root.file_name()?.to_str()?;
+        Self::texlive_year(name)
+    }
+
+    /// Newer TeX Live releases installed next to `texlive_root`, newest first
+    pub fn newer_releases(texlive_root: &Path) -> Vec<(u32, PathBuf)> {
+        let (Some(year), Some(parent)) = (Self::release_year(texlive_root), texlive_root.parent()) else {
+            return Vec::new();
+        };
+        let Ok(entries) = std::fs::read_dir(parent) else {
+            return Vec::new();
+        };
+        let mut releases: Vec<(u32, PathBuf)> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.join("texmf-dist").is_dir())
+            .filter_map(|path| Some((Self::release_year(&path)?, path)))
+            .filter(|(release, _)| *release > year)
+            .collect();
+        releases.sort_by_key(|(release, _)| std::cmp::Reverse(*release));
+        releases
+    }
+
     /// Unified entry point for finding TeXLive root directory
     fn find_texlive_root(&self) -> Result<PathBuf> {
         // 1. First check environment variables
@@ -79,7 +386,12 @@ impl TeXLiveManager {
             return Ok(path);
         }
 
-        // 3. Search common installation paths
+        // 3. Look for per-user installations such as TinyTeX
+        if let Ok(path) = self.scan_texlive_directories(&Self::user_local_dirs()) {
+            return Ok(path);
+        }
+
+        // 4. Search common installation paths
         self.find_texlive_in_common_paths()
     }
 
@@ -132,19 +444,39 @@ impl TeXLiveManager {
     /// Find TeXLive in common paths
     fn find_texlive_in_common_paths(&self) -> Result<PathBuf> {
         if cfg!(windows) {
-            // Windows: First try registry
+            // Windows: First try registry, then the tex on PATH and MiKTeX's own report
             if let Ok(path) = self.find_texlive_from_registry() {
                 return Ok(path);
             }
+            if let Ok(path) = self.find_texlive_from_where() {
+                return Ok(path);
+            }
+            if let Ok(path) = self.find_miktex_from_report() {
+                return Ok(path);
+            }
             
             // Then scan common root directories
+            let local_app_data = std::env::var("LOCALAPPDATA").unwrap_or_default();
             self.scan_texlive_directories(&[
                 "C:\\texlive",
                 "C:\\Program Files\\texlive",
                 "C:\\Program Files (x86)\\texlive",
+                "C:\\Program Files\\MiKTeX",
+                &format!("{}\\Programs\\MiKTeX", local_app_data),
             ])
         } else if cfg!(target_os = "macos") {
-            // macOS: Check common paths and Homebrew installation
+            // macOS: MacTeX's texbin link and TeX Distribution data first
+            if let Ok(path) = self.find_texlive_from_texbin() {
+                return Ok(path);
+            }
+            if let Some(path) = Self::texdist_roots(Path::new(MACOS_TEXDIST_DIR))
+                .iter()
+                .find_map(|root| self.installation_containing(root))
+            {
+                return Ok(path);
+            }
+
+            // Then check common paths and Homebrew installation
             self.scan_texlive_directories(&[
                 "/usr/local/texlive",
                 "/opt/homebrew/texlive",
@@ -157,57 +489,180 @@ impl TeXLiveManager {
                 "/usr/local/texlive",
                 "/opt/texlive",
                 "/usr/share/texlive",
-                &format!("{}/texlive", std::env::var("HOME").unwrap_or_default()),
             ])
         }
     }
 
-    /// Windows注册表查找TeXLive
-    #[cfg(windows)]
+    /// Where per-user distributions are installed: TinyTeX's directory on
+    /// each platform and TeX Live installed into the home directory
+    fn user_local_dirs() -> Vec<PathBuf> {
+        let home = dirs::home_dir().unwrap_or_default();
+        let app_data = std::env::var("APPDATA").map(PathBuf::from).unwrap_or_else(|_| home.join("AppData").join("Roaming"));
+        if cfg!(windows) {
+            vec![app_data.join("TinyTeX"), home.join("texlive")]
+        } else if cfg!(target_os = "macos") {
+            vec![home.join("Library").join("TinyTeX"), home.join(".TinyTeX"), home.join("texlive")]
+        } else {
+            vec![home.join(".TinyTeX"), home.join("texlive"), home.join(".local").join("texlive")]
+        }
+    }
+
+    /// Find MacTeX through /Library/TeX/texbin, which links to the `bin`
+    /// directory of the default distribution
+    fn find_texlive_from_texbin(&self) -> Result<PathBuf> {
+        let texbin = Path::new(MACOS_TEXBIN).canonicalize()?;
+        self.installation_containing(&texbin)
+            .ok_or_else(|| anyhow::anyhow!("{} does not lead to a TeX installation", MACOS_TEXBIN))
+    }
+
+    /// The roots of the TeX distributions macOS knows (the texdist mechanism
+    /// of the TeX Distribution preference pane): the default one, then the
+    /// others, newest name first
+    fn texdist_roots(distributions_dir: &Path) -> Vec<PathBuf> {
+        let root_of = |texdist: &Path| texdist.join("Contents").join("Root").canonicalize().ok();
+        let mut roots: Vec<PathBuf> = root_of(&distributions_dir.join(".DefaultTeX")).into_iter().collect();
+        let mut texdists: Vec<PathBuf> = std::fs::read_dir(distributions_dir)
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        texdists.retain(|path| path.extension().is_some_and(|extension| extension == "texdist"));
+        texdists.sort_by(|a, b| b.cmp(a));
+        for root in texdists.iter().filter_map(|texdist| root_of(texdist)) {
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+        roots
+    }
+
+    /// Windows注册表查找TeXLive（以及MiKTeX）的卸载信息
     fn find_texlive_from_registry(&self) -> Result<PathBuf> {
-        use std::process::Command;
-        
-        // 查询注册表中的TeXLive安装信息
-        let output = Command::new("reg")
-            .args([
-                "query",
-                "HKLM\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall",
-                "/s",
-                "/f",
-                "TeXLive",
-                "/t",
-                "REG_SZ",
-            ])
-            .output()?;
+        for uninstall_key in UNINSTALL_KEYS {
+            let Ok(output) = Command::new("reg").args(["query", uninstall_key, "/s"]).output() else {
+                continue;
+            };
+            if !output.status.success() {
+                continue;
+            }
+            let keys = Self::parse_registry(&String::from_utf8_lossy(&output.stdout));
+            if let Some(path) = Self::registry_install_locations(&keys)
+                .iter()
+                .find_map(|location| self.installation_containing(location))
+            {
+                return Ok(path);
+            }
+        }
+
+        anyhow::bail!("TeXLive not found in Windows registry")
+    }
+
+    /// Parse `reg query` output: a line naming a key, then one indented
+    /// `<name>    <REG_type>    <data>` line per value
+    fn parse_registry(output: &str) -> Vec<RegistryKey> {
+        let mut keys: Vec<RegistryKey> = Vec::new();
+        for line in output.lines() {
+            if line.starts_with("HKEY_") {
+                keys.push(RegistryKey { path: line.trim().to_string(), ..Default::default() });
+                continue;
+            }
+            let (Some(key), Some(type_start)) = (keys.last_mut(), line.find("    REG_")) else {
+                continue;
+            };
+            let name = line[..type_start].trim();
+            let typed = line[type_start..].trim_start();
+            // The data follows the type after whitespace, and may contain spaces itself
+            let data = typed.split_once(char::is_whitespace).map(|(_, data)| data.trim()).unwrap_or_default();
+            key.values.insert(name.to_string(), data.to_string());
+        }
+        keys
+    }
+
+    /// Where the TeX Live and MiKTeX entries among uninstall `keys` say the
+    /// distribution lives: InstallLocation, else the uninstaller's directory
+    fn registry_install_locations(keys: &[RegistryKey]) -> Vec<PathBuf> {
+        keys.iter()
+            .filter(|key| {
+                let name = key.values.get("DisplayName").unwrap_or(&key.path);
+                ["TeX Live", "TeXLive", "MiKTeX"].iter().any(|distribution| name.contains(distribution))
+            })
+            .filter_map(|key| {
+                if let Some(location) = key.values.get("InstallLocation").filter(|location| !location.is_empty()) {
+                    return Some(PathBuf::from(location.trim_matches('"')));
+                }
+                let uninstaller = key.values.get("UninstallString")?;
+                // "C:\texlive\2024\tlpkg\installer\uninst.bat" /quiet
+                let program = match uninstaller.strip_prefix('"') {
+                    Some(quoted) => quoted.split('"').next()?,
+                    None => uninstaller.split(' ').next()?,
+                };
+                Some(PathBuf::from(program))
+            })
+            .collect()
+    }
 
+    /// The installation `path` is in (or is), looking a few levels up as
+    /// executables sit in e.g. `<root>\bin\windows` or `<root>\miktex\bin\x64`
+    fn installation_containing(&self, path: &Path) -> Option<PathBuf> {
+        path.ancestors()
+            .take(5)
+            .find(|dir| dir.parent().is_some() && self.is_valid_texlive_installation(dir))
+            .map(Path::to_path_buf)
+    }
+
+    /// Find the distribution whose tex `where tex` reports first
+    fn find_texlive_from_where(&self) -> Result<PathBuf> {
+        let output = Command::new("where").arg("tex").output()?;
         if output.status.success() {
             let output_str = String::from_utf8_lossy(&output.stdout);
-            // 解析注册表输出，查找InstallLocation
-            for line in output_str.lines() {
-                if line.contains("InstallLocation") {
-                    if let Some(path_start) = line.find("REG_SZ") {
-                        let path_str = line[path_start + 6..].trim();
-                        let path = PathBuf::from(path_str);
-                        if self.is_valid_texlive_installation(&path) {
-                            return Ok(path);
-                        }
-                    }
-                }
+            if let Some(path) = output_str
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .find_map(|line| self.installation_containing(Path::new(line)))
+            {
+                return Ok(path);
             }
         }
 
-        anyhow::bail!("TeXLive not found in Windows registry")
+        anyhow::bail!("No TeX distribution found via where tex")
     }
 
-    #[cfg(not(windows))]
-    fn find_texlive_from_registry(&self) -> Result<PathBuf> {
-        anyhow::bail!("Registry lookup not available on this platform")
+    /// Find MiKTeX through `initexmf --report`, which names its install roots
+    fn find_miktex_from_report(&self) -> Result<PathBuf> {
+        let output = Command::new("initexmf").arg("--report").output()?;
+        if output.status.success() {
+            let report = String::from_utf8_lossy(&output.stdout);
+            if let Some(path) = Self::parse_miktex_report(&report)
+                .into_iter()
+                .find(|root| self.is_valid_texlive_installation(root))
+            {
+                return Ok(path);
+            }
+        }
+
+        anyhow::bail!("MiKTeX not found via initexmf")
+    }
+
+    /// The install roots of an `initexmf --report`, per-user install first
+    fn parse_miktex_report(report: &str) -> Vec<PathBuf> {
+        let mut roots = Vec::new();
+        for field in ["UserInstall", "CommonInstall"] {
+            let root = report
+                .lines()
+                .filter_map(|line| line.trim().split_once(':'))
+                .find(|(name, _)| name.trim() == field)
+                .map(|(_, value)| value.trim())
+                .filter(|value| !value.is_empty());
+            if let Some(root) = root.map(PathBuf::from).filter(|root| !roots.contains(root)) {
+                roots.push(root);
+            }
+        }
+        roots
     }
 
     /// 扫描目录查找TeXLive安装
-    fn scan_texlive_directories(&self, base_paths: &[&str]) -> Result<PathBuf> {
+    fn scan_texlive_directories<P: AsRef<Path>>(&self, base_paths: &[P]) -> Result<PathBuf> {
         for base_path in base_paths {
-            let base = PathBuf::from(base_path);
+            let base = base_path.as_ref().to_path_buf();
             if !base.exists() {
                 continue;
             }
@@ -222,16 +677,16 @@ impl TeXLiveManager {
                         if let Some(dir_name) = path.file_name() {
                             let name = dir_name.to_string_lossy();
                             // Check if matches year pattern (2020-2030)
-                            if Self::is_texlive_year_dir(&name) && self.is_valid_texlive_installation(&path) {
-                                found_versions.push((name.to_string(), path));
+                            if let Some(year) = Self::texlive_year(&name).filter(|_| self.is_valid_texlive_installation(&path)) {
+                                found_versions.push(((year, Distribution::of(&path) != Distribution::BasicTeX), path));
                             }
                         }
                     }
                 }
 
-                // 返回最新版本
+                // 返回最新版本，同一年份优先完整安装而非 BasicTeX
                 if !found_versions.is_empty() {
-                    found_versions.sort_by(|a, b| b.0.cmp(&a.0)); // 降序排列
+                    found_versions.sort_by_key(|(key, _)| std::cmp::Reverse(*key)); // 降序排列
                     return Ok(found_versions[0].1.clone());
                 }
             }
@@ -245,17 +700,20 @@ impl TeXLiveManager {
         anyhow::bail!("TeXLive installation not found in common directories")
     }
 
-    /// Check if directory name matches TeXLive year pattern
-    fn is_texlive_year_dir(name: &str) -> bool {
-        if let Ok(year) = name.parse::<u32>() {
-            year >= 2015 && year <= 2030 // 合理的TeXLive版本年份范围
-        } else {
-            false
-        }
+    /// The release year of a TeXLive year directory name, e.g. 2024 (BasicTeX
+    /// installs into e.g. 2024basic)
+    fn texlive_year(name: &str) -> Option<u32> {
+        let year = name.strip_suffix("basic").unwrap_or(name).parse::<u32>().ok()?;
+        (2015..=2030).contains(&year).then_some(year) // 合理的TeXLive版本年份范围
     }
 
-    /// Validate if path is a valid TeXLive installation
-    fn is_valid_texlive_installation(&self, path: &PathBuf) -> bool {
+    /// Validate if path is a valid TeXLive installation (or a MiKTeX one)
+    fn is_valid_texlive_installation(&self, path: &Path) -> bool {
+        if Distribution::of(path) == Distribution::MiKTeX {
+            return true;
+        }
+
+
         // Check if key directories and files exist
         let _required_dirs = ["texmf-dist", "bin"];
         let _optional_dirs = ["tlpkg", "texmf-local"];
@@ -318,11 +776,14 @@ impl TeXLiveManager {
 
         if output.status.success() {
             let version_output = String::from_utf8_lossy(&output.stdout);
-            // 解析版本信息，通常格式为 "TeX 3.141592653 (TeX Live 2023)"
-            if let Some(start) = version_output.find("TeX Live ") {
-                let version_part = &version_output[start + 9..];
-                if let Some(end) = version_part.find(')') {
-                    return Ok(version_part[..end].to_string());
+            // 解析版本信息，通常格式为 "TeX 3.141592653 (TeX Live 2023)"，
+            // MiKTeX 为 "MiKTeX-TeX 4.10 (MiKTeX 24.1)"
+            for marker in ["TeX Live ", "(MiKTeX "] {
+                if let Some(start) = version_output.find(marker) {
+                    let version_part = &version_output[start + marker.len()..];
+                    if let Some(end) = version_part.find(')') {
+                        return Ok(version_part[..end].to_string());
+                    }
                 }
             }
         }
@@ -338,8 +799,8 @@ impl TeXLiveManager {
             // 尝试从路径中提取年份
             for component in info.install_path.components() {
                 if let Some(component_str) = component.as_os_str().to_str() {
-                    if Self::is_texlive_year_dir(component_str) {
-                        return Ok(component_str.to_string());
+                    if let Some(year) = Self::texlive_year(component_str) {
+                        return Ok(year.to_string());
                     }
                 }
             }
@@ -379,8 +840,34 @@ impl TeXLiveManager {
         anyhow::bail!("Could not get version from TLPDB")
     }
 
+    /// The directory holding the executables of a TeX Live installation,
+    /// e.g. `<root>/bin/x86_64-linux` (`<root>/miktex/bin/x64` for MiKTeX)
+    pub fn bin_dir(texlive_root: &Path) -> Option<PathBuf> {
+        let tex = if cfg!(windows) { "tex.exe" } else { "tex" };
+        let miktex_bin = texlive_root.join("miktex").join("bin");
+        [texlive_root.join("bin"), miktex_bin.clone()]
+            .iter()
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+            .chain(std::iter::once(miktex_bin))
+            .find(|path| path.join(tex).is_file())
+    }
+
+    /// The tlmgr of a distribution the user installed for themselves
+    /// (TinyTeX, or a TeX Live in the home directory), which can install
+    /// packages into it without administrator rights
+    pub fn user_tlmgr(texlive_root: &Path) -> Option<PathBuf> {
+        let distribution = Distribution::of(texlive_root);
+        let in_home = dirs::home_dir().is_some_and(|home| texlive_root.starts_with(home));
+        if !distribution.has_tlmgr() || (distribution != Distribution::TinyTeX && !in_home) {
+            return None;
+        }
+        let tlmgr = Self::bin_dir(texlive_root)?.join(if cfg!(windows) { "tlmgr.bat" } else { "tlmgr" });
+        tlmgr.is_file().then_some(tlmgr)
+    }
+
     /// 获取用户的TEXMF目录
-    fn get_texmf_home(&self) -> Result<PathBuf> {
+    pub fn get_texmf_home(&self) -> Result<PathBuf> {
         let output = Command::new("kpsewhich")
             .args(["--var-value", "TEXMFHOME"])
             .output();
@@ -405,15 +892,13 @@ impl TeXLiveManager {
             self.detect_texlive()?;
         }
 
-        let texlive_info = self.texlive_info.as_ref().unwrap();
         println!("Scanning installed packages...");
-
-        // 读取TeXLive包数据库
-        let tlpdb_path = texlive_info.install_path.join("tlpkg/texlive.tlpdb");
-        
-        if tlpdb_path.exists() {
-            self.parse_tlpdb(&tlpdb_path)?;
-        } else {
+        let progress = crate::ui::Progress::start("Indexing the TeXLive package database", "packages");
+        let found_database = self.load_package_database(&progress)?;
+        drop(progress);
+        if !found_database {
+            let texlive_info = self.texlive_info.as_ref().unwrap();
+            let tlpdb_path = texlive_info.install_path.join("tlpkg/texlive.tlpdb");
             println!("Warning: TeXLive package database not found at {}", tlpdb_path.display());
             // 作为备选方案，扫描文件系统
             self.scan_filesystem_packages()?;
@@ -423,52 +908,175 @@ impl TeXLiveManager {
         Ok(())
     }
 
-    /// 解析TeXLive包数据库
-    fn parse_tlpdb(&mut self, tlpdb_path: &Path) -> Result<()> {
-        let content = std::fs::read_to_string(tlpdb_path)?;
-        let mut current_package: Option<String> = None;
-        let mut current_description = String::new();
-        let mut current_files = Vec::new();
+    /// Load the installed packages from the package database without any
+    /// output; false when the installation has no database
+    pub fn read_package_database(&mut self) -> Result<bool> {
+        self.load_package_database(&crate::ui::Progress::hidden())
+    }
 
-        for line in content.lines() {
-            if line.starts_with("name ") {
-                // 保存前一个包
-                if let Some(name) = current_package.take() {
-                    self.add_package_from_tlpdb(name, current_description.clone(), current_files.clone());
-                }
+    /// Load the package database, counting the packages read on `progress`
+    fn load_package_database(&mut self, progress: &crate::ui::Progress) -> Result<bool> {
+        let Some(texlive_info) = &self.texlive_info else {
+            anyhow::bail!("TeXLive not detected");
+        };
+        // 读取TeXLive包数据库
+        let tlpdb_path = texlive_info.install_path.join("tlpkg/texlive.tlpdb");
+        if !tlpdb_path.exists() {
+            return Ok(false);
+        }
+        match PackageIndexCache::path(&tlpdb_path) {
+            Ok(cache_path) => self.load_package_index(&tlpdb_path, &cache_path, progress)?,
+            Err(_) => self.parse_tlpdb(&tlpdb_path, progress)?,
+        }
+        Ok(true)
+    }
+
+    /// Read the index cached at `cache_path` while the tlpdb is unchanged,
+    /// else parse the tlpdb and cache the result
+    fn load_package_index(&mut self, tlpdb_path: &Path, cache_path: &Path, progress: &crate::ui::Progress) -> Result<()> {
+        if let Some(cache) = PackageIndexCache::load(cache_path, tlpdb_path) {
+            self.installed_packages.extend(cache.packages);
+            self.file_owners.merge(cache.file_owners);
+            return Ok(());
+        }
+        // Parse into empty maps so the cache holds only what the tlpdb says
+        let earlier_packages = std::mem::take(&mut self.installed_packages);
+        let earlier_owners = std::mem::take(&mut self.file_owners);
+        self.parse_tlpdb(tlpdb_path, progress)?;
+        // The cache only saves time; a read-only data directory is no error
+        let _ = PackageIndexCache::save(cache_path, tlpdb_path, &self.installed_packages, &self.file_owners);
+        for (name, package) in earlier_packages {
+            self.installed_packages.entry(name).or_insert(package);
+        }
+        let parsed_owners = std::mem::replace(&mut self.file_owners, earlier_owners);
+        self.file_owners.merge(parsed_owners);
+        Ok(())
+    }
+
+    /// 解析TeXLive包数据库, streaming it instead of reading it whole; file
+    /// lists are not kept (see `package_files`)
+    fn parse_tlpdb(&mut self, tlpdb_path: &Path, progress: &crate::ui::Progress) -> Result<()> {
+        let reader = std::io::BufReader::new(std::fs::File::open(tlpdb_path)?);
+        let texmf_dist = self.texlive_info.as_ref().map(|info| info.texmf_dist.clone()).unwrap_or_default();
+        let installed_packages = &mut self.installed_packages;
+        read_tlpdb(reader, &mut self.file_owners, |record| {
+            let package = InstalledPackage {
+                name: record.name.clone(),
+                // The CTAN version, when the catalogue records one
+                version: record.version.unwrap_or_else(|| "unknown".to_string()),
+                description: record.description,
+                install_path: texmf_dist.clone(),
+            };
+            installed_packages.insert(record.name, package);
+            progress.inc();
+        })
+    }
 
-                // 开始新包
-                current_package = Some(line[5..].to_string());
-                current_description.clear();
-                current_files.clear();
-            } else if line.starts_with("shortdesc ") {
-                current_description = line[10..].to_string();
-            } else if line.starts_with(" ") && line.contains('/') {
-                // 文件路径
-                let file_path = line.trim();
-                if let Some(texlive_info) = &self.texlive_info {
-                    current_files.push(texlive_info.texmf_dist.join(file_path));
+    /// Build a runtime file name → package index from tlpdb content
+    fn parse_file_index(reader: impl BufRead) -> Result<FileIndex> {
+        let mut index = FileIndex::default();
+        read_tlpdb(reader, &mut index, |_| {})?;
+        Ok(index)
+    }
+
+    /// Add the file index of another tlpdb (e.g. the remote one) without
+    /// marking its packages as installed
+    pub fn load_file_index(&mut self, tlpdb_path: &Path) -> Result<()> {
+        let index = Self::parse_file_index(std::io::BufReader::new(std::fs::File::open(tlpdb_path)?))?;
+        self.file_owners.merge(index);
+        Ok(())
+    }
+
+    /// The files of an installed package, read from the package database on
+    /// demand as the parsed index does not keep them
+    pub fn package_files(&self, package_name: &str) -> Result<Vec<PathBuf>> {
+        let Some(texlive_info) = &self.texlive_info else {
+            anyhow::bail!("TeXLive not detected");
+        };
+        let tlpdb_path = texlive_info.install_path.join("tlpkg/texlive.tlpdb");
+        let reader = std::io::BufReader::new(std::fs::File::open(&tlpdb_path)?);
+        let mut files = Vec::new();
+        let mut in_package = false;
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(name) = line.strip_prefix("name ") {
+                if in_package {
+                    break;
+                }
+                in_package = name.trim() == package_name;
+            } else if let (true, Some(file_path)) = (in_package, line.strip_prefix(' ')) {
+                if let Some(file_path) = file_path.split_whitespace().next() {
+                    files.push(texlive_info.install_path.join(file_path));
                 }
             }
         }
+        Ok(files)
+    }
+
+    /// Load the file index of the mirror's package database, downloading it
+    /// at most once a day
+    pub async fn fetch_remote_file_index(&mut self, mirror_url: &str) -> Result<()> {
+        let cache_path = crate::download::index_dir()?.join("texlive.tlpdb");
+        let url = format!("{}/tlpkg/texlive.tlpdb", mirror_url.trim_end_matches('/'));
+        crate::download::fetch_cached(&url, &cache_path, REMOTE_TLPDB_MAX_AGE).await?;
+
+        self.load_file_index(&cache_path)
+    }
 
-        // 保存最后一个包
-        if let Some(name) = current_package {
-            self.add_package_from_tlpdb(name, current_description, current_files);
+    /// Find the package that ships a runtime file such as bm.sty
+    pub fn package_for_file(&self, file_name: &str) -> Option<&str> {
+        self.file_owners.get(file_name)
+    }
+
+    /// Like `package_for_file`, ignoring case; returns the file name as the
+    /// package ships it (IEEEtran.bst for ieeetran.bst) with the package
+    pub fn find_file_ignoring_case(&self, file_name: &str) -> Option<(&str, &str)> {
+        if let Some(found) = self.file_owners.get_key_value(file_name) {
+            return Some(found);
         }
+        self.file_owners
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(file_name))
+            .min()
+    }
 
-        Ok(())
+    /// Resolve a \usepackage/\documentclass name to the package that ships its
+    /// .sty/.cls file, falling back to the name itself. Names with an extension
+    /// (ecrm1000.tfm, t1phv.fd) are looked up as file names directly.
+    pub fn resolve_package_name(&self, name: &str) -> String {
+        let owner = if Path::new(name).extension().is_some() {
+            self.package_for_file(name)
+        } else {
+            self.package_for_file(&format!("{}.sty", name))
+                .or_else(|| self.package_for_file(&format!("{}.cls", name)))
+        };
+        owner.unwrap_or(name).to_string()
     }
 
-    fn add_package_from_tlpdb(&mut self, name: String, description: String, files: Vec<PathBuf>) {
-        let package = InstalledPackage {
-            name: name.clone(),
-            version: "unknown".to_string(), // TLPDB通常不包含版本信息
-            description,
-            files,
-            install_path: self.texlive_info.as_ref().unwrap().texmf_dist.clone(),
+    /// Find the package shipping an OpenType/TrueType/Type 1 font by its family
+    /// name, e.g. "TeX Gyre Termes" -> texgyretermes-regular.otf -> tex-gyre
+    pub fn package_for_font_name(&self, font_name: &str) -> Option<&str> {
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
         };
-        self.installed_packages.insert(name, package);
+        let wanted = normalize(font_name);
+        if wanted.is_empty() {
+            return None;
+        }
+
+        self.file_owners
+            .iter()
+            .filter_map(|(file_name, package)| {
+                let (stem, extension) = file_name.rsplit_once('.')?;
+                let is_font = ["otf", "ttf", "ttc", "pfb"].contains(&extension.to_ascii_lowercase().as_str());
+                let stem = normalize(stem);
+                (is_font && stem.starts_with(&wanted)).then_some((stem.len(), file_name, package))
+            })
+            .min()
+            .map(|(_, _, package)| package)
     }
 
     /// 备选方案：扫描文件系统中的包
@@ -478,13 +1086,79 @@ impl TeXLiveManager {
         Ok(())
     }
 
-    /// Check if a package is installed
+    /// Check if a package is installed: listed in the database, or found by
+    /// `check_with_kpsewhich`
     pub fn is_package_installed(&self, package_name: &str) -> bool {
-        self.installed_packages.contains_key(package_name)
+        self.installed_packages.contains_key(package_name) || self.found_by_kpsewhich.contains(package_name)
+    }
+
+    /// The names among `names` (\usepackage/\documentclass names) whose .sty
+    /// or .cls kpsewhich finds, asking for all of them in one run; None when
+    /// kpsewhich cannot be run
+    pub fn kpsewhich_lookup(names: &[String]) -> Option<HashSet<String>> {
+        if names.is_empty() {
+            return Some(HashSet::new());
+        }
+        let files: Vec<String> = names
+            .iter()
+            .flat_map(|name| [format!("{}.sty", name), format!("{}.cls", name)])
+            .collect();
+        // Exits with 1 when any file is missing, still printing the ones found
+        let output = Command::new("kpsewhich").args(&files).output().ok()?;
+        let found_files: HashSet<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| Path::new(line.trim()).file_name().map(|name| name.to_string_lossy().to_string()))
+            .collect();
+        Some(Self::names_with_files(names, &found_files))
+    }
+
+    /// The names whose .sty or .cls is among `files`
+    fn names_with_files(names: &[String], files: &HashSet<String>) -> HashSet<String> {
+        names
+            .iter()
+            .filter(|name| files.contains(&format!("{}.sty", name)) || files.contains(&format!("{}.cls", name)))
+            .cloned()
+            .collect()
+    }
+
+    /// Cross-check `names` against kpsewhich: the ones it finds count as
+    /// installed, even when the database misses them (files copied into
+    /// TEXMFHOME by hand). Returns the files the database lists but
+    /// kpsewhich cannot find, a sign of a stale ls-R database.
+    pub fn check_with_kpsewhich(&mut self, names: &[String]) -> Vec<PathBuf> {
+        let Some(found) = Self::kpsewhich_lookup(names) else {
+            return Vec::new();
+        };
+        let stale = self.record_kpsewhich(names, &found);
+        stale
+            .into_iter()
+            .filter_map(|name| {
+                let files = [format!("{}.sty", name), format!("{}.cls", name)];
+                let package_files = self.package_files(&self.resolve_package_name(&name)).ok()?;
+                package_files.into_iter().find(|file| file.file_name().is_some_and(|file_name| files.iter().any(|file| file_name == file.as_str())))
+            })
+            .collect()
+    }
+
+    fn record_kpsewhich(&mut self, names: &[String], found: &HashSet<String>) -> Vec<String> {
+        let mut stale = Vec::new();
+        for name in names {
+            let package = self.resolve_package_name(name);
+            if found.contains(name) {
+                self.found_by_kpsewhich.insert(name.clone());
+                self.found_by_kpsewhich.insert(package);
+                continue;
+            }
+            let listed = self.installed_packages.contains_key(&package)
+                && [format!("{}.sty", name), format!("{}.cls", name)].iter().any(|file| self.file_owners.get(file) == Some(package.as_str()));
+            if listed {
+                stale.push(name.clone());
+            }
+        }
+        stale
     }
 
     /// 获取已安装包的信息
-    #[allow(dead_code)]
     pub fn get_installed_package(&self, package_name: &str) -> Option<&InstalledPackage> {
         self.installed_packages.get(package_name)
     }
@@ -512,7 +1186,7 @@ impl TeXLiveManager {
             }
         } else {
             // 项目本地路径
-            Ok(PathBuf::from("packages"))
+            Ok(crate::config::project_path("packages"))
         }
     }
 
@@ -539,3 +1213,256 @@ impl TeXLiveManager {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_for_font_name() {
+        let tlpdb = "name tex-gyre\nrunfiles size=1\n texmf-dist/fonts/opentype/public/tex-gyre/texgyretermes-regular.otf\n \
+                     texmf-dist/fonts/tfm/public/tex-gyre/ec-qtmr.tfm\n\
+                     name helvetic\nrunfiles size=1\n texmf-dist/fonts/tfm/adobe/helvetic/phvr8t.tfm\n";
+        let mut texlive = TeXLiveManager::new();
+        texlive.file_owners = TeXLiveManager::parse_file_index(tlpdb.as_bytes()).unwrap();
+
+        assert_eq!(texlive.package_for_font_name("TeX Gyre Termes"), Some("tex-gyre"));
+        assert_eq!(texlive.package_for_font_name("Fira Sans"), None);
+        assert_eq!(texlive.resolve_package_name("phvr8t.tfm"), "helvetic");
+        assert_eq!(texlive.resolve_package_name("ec-qtmr.tfm"), "tex-gyre");
+    }
+
+    #[test]
+    fn test_bin_dir() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(TeXLiveManager::bin_dir(root.path()), None);
+
+        let bin_dir = root.path().join("bin").join("x86_64-linux");
+        std::fs::create_dir_all(&bin_dir).unwrap();
+        std::fs::create_dir_all(root.path().join("bin").join("win64")).unwrap();
+        std::fs::write(bin_dir.join(if cfg!(windows) { "tex.exe" } else { "tex" }), "").unwrap();
+        assert_eq!(TeXLiveManager::bin_dir(root.path()), Some(bin_dir));
+    }
+
+    #[test]
+    fn test_windows_detection() {
+        let output = "\r\nHKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\7-Zip\r\n    \
+                      DisplayName    REG_SZ    7-Zip 23.01 (x64)\r\n\r\n\
+                      HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\TeXLive2024\r\n    \
+                      DisplayName    REG_SZ    TeX Live 2024\r\n    \
+                      UninstallString    REG_EXPAND_SZ    \"C:\\texlive\\2024\\tlpkg\\installer\\uninst.bat\" /quiet\r\n\r\n\
+                      HKEY_CURRENT_USER\\Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\MiKTeX\r\n    \
+                      DisplayName    REG_SZ    MiKTeX\r\n    \
+                      InstallLocation    REG_SZ    C:\\Users\\Jo Doe\\AppData\\Local\\Programs\\MiKTeX\r\n";
+        let keys = TeXLiveManager::parse_registry(output);
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[1].values["DisplayName"], "TeX Live 2024");
+        assert_eq!(
+            TeXLiveManager::registry_install_locations(&keys),
+            vec![
+                PathBuf::from("C:\\texlive\\2024\\tlpkg\\installer\\uninst.bat"),
+                PathBuf::from("C:\\Users\\Jo Doe\\AppData\\Local\\Programs\\MiKTeX"),
+            ]
+        );
+
+        let report = "MiKTeX: 24.1\nSharedSetup: yes\nUserInstall: \nCommonInstall: C:\\Program Files\\MiKTeX\nCommonData: C:\\ProgramData\\MiKTeX\n";
+        assert_eq!(TeXLiveManager::parse_miktex_report(report), vec![PathBuf::from("C:\\Program Files\\MiKTeX")]);
+
+        let root = tempfile::tempdir().unwrap();
+        let bin_dir = root.path().join("miktex").join("bin").join("x64");
+        std::fs::create_dir_all(&bin_dir).unwrap();
+        std::fs::write(bin_dir.join(if cfg!(windows) { "tex.exe" } else { "tex" }), "").unwrap();
+        assert_eq!(Distribution::of(root.path()), Distribution::MiKTeX);
+        assert_eq!(TeXLiveManager::bin_dir(root.path()), Some(bin_dir.clone()));
+        let texlive = TeXLiveManager::new();
+        assert_eq!(texlive.installation_containing(&bin_dir.join("tex.exe")), Some(root.path().to_path_buf()));
+    }
+
+    #[test]
+    fn test_macos_detection() {
+        let base = tempfile::tempdir().unwrap();
+        let texlive = base.path().join("texlive");
+        for release in ["2023", "2024", "2024basic"] {
+            std::fs::create_dir_all(texlive.join(release).join("texmf-dist")).unwrap();
+        }
+        assert_eq!(TeXLiveManager::release_year(&texlive.join("2024basic")), Some(2024));
+        assert_eq!(Distribution::of(&texlive.join("2024basic")), Distribution::BasicTeX);
+        assert_eq!(Distribution::of(&texlive.join("2024")), Distribution::TeXLive);
+        let manager = TeXLiveManager::new();
+        assert_eq!(manager.scan_texlive_directories(&[texlive.to_str().unwrap()]).unwrap(), texlive.join("2024"));
+
+        // The bundles hold the distribution's root; .DefaultTeX is the selected one
+        let distributions = base.path().join("Distributions");
+        for (texdist, root) in [("TeXLive-2023.texdist", "2023"), ("TeXLive-2024.texdist", "2024"), (".DefaultTeX", "2024basic")] {
+            std::fs::create_dir_all(distributions.join(texdist).join("Contents")).unwrap();
+            std::fs::rename(texlive.join(root), distributions.join(texdist).join("Contents").join("Root")).unwrap();
+        }
+        let root = |texdist: &str| distributions.join(texdist).join("Contents").join("Root").canonicalize().unwrap();
+        assert_eq!(
+            TeXLiveManager::texdist_roots(&distributions),
+            vec![root(".DefaultTeX"), root("TeXLive-2024.texdist"), root("TeXLive-2023.texdist")]
+        );
+        assert!(TeXLiveManager::texdist_roots(&base.path().join("missing")).is_empty());
+    }
+
+    #[test]
+    fn test_user_local_distributions() {
+        let base = tempfile::tempdir().unwrap();
+        let executable = |name: &str| if cfg!(windows) { format!("{}{}", name, if name == "tlmgr" { ".bat" } else { ".exe" }) } else { name.to_string() };
+        for root in [".TinyTeX", "2024"] {
+            let bin_dir = base.path().join(root).join("bin").join("x86_64-linux");
+            std::fs::create_dir_all(base.path().join(root).join("texmf-dist")).unwrap();
+            std::fs::create_dir_all(&bin_dir).unwrap();
+            std::fs::write(bin_dir.join(executable("tex")), "").unwrap();
+            std::fs::write(bin_dir.join(executable("tlmgr")), "").unwrap();
+        }
+        let tinytex = base.path().join(".TinyTeX");
+        assert_eq!(Distribution::of(&tinytex), Distribution::TinyTeX);
+        assert_eq!(TeXLiveManager::new().scan_texlive_directories(&[&tinytex]).unwrap(), tinytex);
+        assert_eq!(
+            TeXLiveManager::user_tlmgr(&tinytex),
+            Some(tinytex.join("bin").join("x86_64-linux").join(executable("tlmgr")))
+        );
+        // A system-wide TeX Live needs administrator rights to change
+        assert_eq!(TeXLiveManager::user_tlmgr(&base.path().join("2024")), None);
+
+        let info = TeXLiveManager::at(&tinytex).unwrap().get_texlive_info().cloned().unwrap();
+        assert_eq!(info.distribution, Distribution::TinyTeX);
+        assert!(info.distribution.has_tlmgr());
+        assert!(!Distribution::MiKTeX.has_tlmgr());
+    }
+
+    #[test]
+    fn test_kpsewhich_cross_check() {
+        let tlpdb = "name tools\nrunfiles size=1\n texmf-dist/tex/latex/tools/bm.sty\n texmf-dist/tex/latex/tools/array.sty\n";
+        let mut texlive = TeXLiveManager::new();
+        texlive.file_owners = TeXLiveManager::parse_file_index(tlpdb.as_bytes()).unwrap();
+        texlive.installed_packages.insert("tools".to_string(), InstalledPackage {
+            name: "tools".to_string(),
+            version: "unknown".to_string(),
+            description: String::new(),
+            install_path: PathBuf::from("/tl/texmf-dist"),
+        });
+
+        let names: Vec<String> = ["bm", "array", "mystyle", "thesis"].iter().map(|name| name.to_string()).collect();
+        let files: HashSet<String> = ["bm.sty", "mystyle.sty", "thesis.cls"].iter().map(|file| file.to_string()).collect();
+        let found = TeXLiveManager::names_with_files(&names, &files);
+        assert_eq!(found.len(), 3);
+        assert!(!found.contains("array"));
+
+        // array.sty is in the database, but kpsewhich did not find it
+        assert_eq!(texlive.record_kpsewhich(&names, &found), vec!["array".to_string()]);
+        assert!(texlive.is_package_installed("mystyle"));
+        assert!(texlive.is_package_installed("thesis"));
+        assert!(!texlive.is_package_installed("siunitx"));
+    }
+
+    #[test]
+    fn test_package_index_cache() {
+        let root = tempfile::tempdir().unwrap();
+        let tlpdb_path = root.path().join("tlpkg").join("texlive.tlpdb");
+        std::fs::create_dir_all(tlpdb_path.parent().unwrap()).unwrap();
+        std::fs::write(&tlpdb_path, "name tools\nshortdesc The LaTeX standard tools bundle\nrunfiles size=1\n texmf-dist/tex/latex/tools/bm.sty\n").unwrap();
+        let cache_path = root.path().join("index").join("installed.json");
+        let progress = crate::ui::Progress::hidden();
+
+        let mut texlive = TeXLiveManager::at(root.path()).unwrap();
+        texlive.load_package_index(&tlpdb_path, &cache_path, &progress).unwrap();
+        assert!(cache_path.is_file());
+
+        // Unchanged, the tlpdb is read back from the cache
+        let cached = PackageIndexCache::load(&cache_path, &tlpdb_path).unwrap();
+        assert_eq!(cached.packages["tools"].description, "The LaTeX standard tools bundle");
+        let mut texlive = TeXLiveManager::at(root.path()).unwrap();
+        texlive.load_package_index(&tlpdb_path, &cache_path, &progress).unwrap();
+        assert!(texlive.is_package_installed("tools"));
+        assert_eq!(texlive.package_for_file("bm.sty"), Some("tools"));
+
+        // A changed tlpdb is parsed again
+        std::fs::write(&tlpdb_path, "name amsmath\nrunfiles size=1\n texmf-dist/tex/latex/amsmath/amsmath.sty\n").unwrap();
+        assert!(PackageIndexCache::load(&cache_path, &tlpdb_path).is_none());
+        let mut texlive = TeXLiveManager::at(root.path()).unwrap();
+        texlive.load_package_index(&tlpdb_path, &cache_path, &progress).unwrap();
+        assert!(texlive.is_package_installed("amsmath"));
+        assert!(!texlive.is_package_installed("tools"));
+    }
+
+    #[test]
+    fn test_streaming_tlpdb() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("tlpkg")).unwrap();
+        std::fs::write(
+            root.path().join("tlpkg/texlive.tlpdb"),
+            "name tools\r\nshortdesc The LaTeX standard tools bundle\r\ncatalogue-version 2024\r\nrunfiles size=2\r\n \
+             texmf-dist/tex/latex/tools/bm.sty\r\n texmf-dist/tex/latex/tools/array.sty\r\ndocfiles size=1\r\n \
+             texmf-dist/doc/latex/tools/bm.pdf details=\"Doc\"\r\n\r\nname amsmath\r\nrunfiles size=1\r\n texmf-dist/tex/latex/amsmath/amsmath.sty\r\n",
+        )
+        .unwrap();
+        let mut texlive = TeXLiveManager::at(root.path()).unwrap();
+        texlive.parse_tlpdb(&root.path().join("tlpkg/texlive.tlpdb"), &crate::ui::Progress::hidden()).unwrap();
+
+        let tools = texlive.get_installed_package("tools").unwrap();
+        assert_eq!(tools.description, "The LaTeX standard tools bundle");
+        assert_eq!(tools.version, "2024");
+        assert_eq!(texlive.get_installed_package("amsmath").unwrap().version, "unknown");
+        // Each package name is stored once in the file index
+        assert_eq!(texlive.file_owners.packages, vec!["tools", "amsmath"]);
+        assert_eq!(texlive.package_for_file("array.sty"), Some("tools"));
+        assert_eq!(texlive.package_for_file("bm.pdf"), None);
+
+        // File lists are read on demand
+        assert_eq!(
+            texlive.package_files("tools").unwrap(),
+            vec![
+                root.path().join("texmf-dist/tex/latex/tools/bm.sty"),
+                root.path().join("texmf-dist/tex/latex/tools/array.sty"),
+                root.path().join("texmf-dist/doc/latex/tools/bm.pdf"),
+            ]
+        );
+        assert!(texlive.package_files("siunitx").unwrap().is_empty());
+
+        // Files of another index only fill in the ones without an owner
+        texlive.file_owners.merge(TeXLiveManager::parse_file_index(&b"name oberdiek\nrunfiles size=1\n texmf-dist/tex/latex/oberdiek/bm.sty\n texmf-dist/tex/latex/oberdiek/hypcap.sty\n"[..]).unwrap());
+        assert_eq!(texlive.package_for_file("bm.sty"), Some("tools"));
+        assert_eq!(texlive.package_for_file("hypcap.sty"), Some("oberdiek"));
+    }
+
+    #[test]
+    fn test_parse_file_index() {
+        let tlpdb = "name tools\ncategory Package\nrunfiles size=100\n texmf-dist/tex/latex/tools/bm.sty\n texmf-dist/tex/latex/tools/array.sty\n\
+                     docfiles size=10\n texmf-dist/doc/latex/tools/bm.pdf details=\"Doc\"\n\n\
+                     name amscls\nrunfiles size=50\n texmf-dist/tex/latex/amscls/amsart.cls\n";
+        let index = TeXLiveManager::parse_file_index(tlpdb.as_bytes()).unwrap();
+
+        assert_eq!(index.get("bm.sty"), Some("tools"));
+        assert_eq!(index.get("amsart.cls"), Some("amscls"));
+        assert_eq!(index.get("bm.pdf"), None);
+
+        let mut texlive = TeXLiveManager::new();
+        texlive.file_owners = index;
+        assert_eq!(texlive.resolve_package_name("bm"), "tools");
+        assert_eq!(texlive.resolve_package_name("amsart"), "amscls");
+        assert_eq!(texlive.resolve_package_name("siunitx"), "siunitx");
+        assert_eq!(texlive.resolve_package_name("amsart.cls"), "amscls");
+    }
+
+    #[test]
+    fn test_newer_releases() {
+        let base = tempfile::tempdir().unwrap();
+        for release in ["2023", "2024", "2025"] {
+            std::fs::create_dir_all(base.path().join(release).join("texmf-dist")).unwrap();
+        }
+        // Not an installation, or not a release
+        std::fs::create_dir_all(base.path().join("2026")).unwrap();
+        std::fs::create_dir_all(base.path().join("texmf-local").join("texmf-dist")).unwrap();
+
+        let current = base.path().join("2023");
+        assert_eq!(TeXLiveManager::release_year(&current), Some(2023));
+        assert_eq!(
+            TeXLiveManager::newer_releases(&current),
+            vec![(2025, base.path().join("2025")), (2024, base.path().join("2024"))]
+        );
+        assert!(TeXLiveManager::newer_releases(&base.path().join("2025")).is_empty());
+        assert!(TeXLiveManager::newer_releases(Path::new("/usr")).is_empty());
+    }
+}
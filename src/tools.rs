@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+/// An external program that a LaTeX package needs at compile time
+#[derive(Debug, Clone)]
+pub struct ToolRequirement {
+    pub package: &'static str,
+    pub tool: &'static str,
+    pub needs_shell_escape: bool,
+    pub install_hint: &'static str,
+}
+
+/// Packages that call out to external programs during compilation
+const TOOL_REQUIREMENTS: &[ToolRequirement] = &[
+    ToolRequirement {
+        package: "minted",
+        tool: "pygmentize",
+        needs_shell_escape: true,
+        install_hint: "pip install Pygments",
+    },
+    ToolRequirement {
+        package: "svg",
+        tool: "inkscape",
+        needs_shell_escape: true,
+        install_hint: "install Inkscape from https://inkscape.org",
+    },
+    ToolRequirement {
+        package: "gnuplottex",
+        tool: "gnuplot",
+        needs_shell_escape: true,
+        install_hint: "install gnuplot from http://www.gnuplot.info",
+    },
+    ToolRequirement {
+        package: "pythontex",
+        tool: "pythontex",
+        needs_shell_escape: false,
+        install_hint: "tlmgr install pythontex, then run it between two LaTeX runs: tpmgr config set compile \"pdflatex main.tex | pythontex main | pdflatex main.tex\"",
+    },
+];
+
+/// Get the external tool requirements of the given packages
+pub fn requirements_for(packages: &[String]) -> Vec<&'static ToolRequirement> {
+    TOOL_REQUIREMENTS
+        .iter()
+        .filter(|req| packages.iter().any(|pkg| pkg == req.package))
+        .collect()
+}
+
+/// Locate an executable on PATH
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let path_var = std::env::var_os("PATH")?;
+
+    for dir in std::env::split_paths(&path_var) {
+        if let Some(found) = executable_in_dir(&dir, name) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+fn executable_in_dir(dir: &Path, name: &str) -> Option<PathBuf> {
+    let candidate = dir.join(name);
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+
+    if cfg!(windows) {
+        for ext in ["exe", "bat", "cmd"] {
+            let candidate = dir.join(format!("{}.{}", name, ext));
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requirements_for() {
+        let packages = vec!["amsmath".to_string(), "minted".to_string(), "pythontex".to_string()];
+        let requirements = requirements_for(&packages);
+        let tools: Vec<&str> = requirements.iter().map(|req| req.tool).collect();
+        assert_eq!(tools, vec!["pygmentize", "pythontex"]);
+        assert!(requirements[0].needs_shell_escape);
+        assert!(!requirements[1].needs_shell_escape);
+
+        assert!(requirements_for(&["graphicx".to_string()]).is_empty());
+        assert!(requirements_for(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_find_executable_in_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("pygmentize"), "").unwrap();
+        assert_eq!(executable_in_dir(dir.path(), "pygmentize"), Some(dir.path().join("pygmentize")));
+        assert_eq!(executable_in_dir(dir.path(), "inkscape"), None);
+    }
+}
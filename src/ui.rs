@@ -0,0 +1,259 @@
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+static PLAIN: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Whether a spinner currently occupies the last line of stderr
+static SPINNING: AtomicBool = AtomicBool::new(false);
+
+const SPINNER_FRAMES: &[&str] = &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const PLAIN_SPINNER_FRAMES: &[&str] = &["|", "/", "-", "\\"];
+const SPINNER_INTERVAL: Duration = Duration::from_millis(80);
+
+/// Environment variables set by common CI services
+const CI_VARIABLES: &[&str] = &[
+    "CI", "GITHUB_ACTIONS", "GITLAB_CI", "BUILDKITE", "CIRCLECI", "TRAVIS", "TF_BUILD", "JENKINS_URL",
+];
+
+/// ASCII replacements for the markers used in tpmgr's output
+const PLAIN_MARKERS: &[(&str, &str)] = &[
+    ("✅", "[ok]"),
+    ("✓", "[ok]"),
+    ("🎉", "[ok]"),
+    ("❌", "[error]"),
+    ("✗", "[error]"),
+    ("💥", "[error]"),
+    ("⚠️", "[warn]"),
+    ("⚠", "[warn]"),
+    ("ℹ️", "[info]"),
+    ("→", "->"),
+];
+
+/// ASCII replacements for the tree drawing of `list --tree`, used by `--plain`
+const PLAIN_TREE: &[(&str, &str)] = &[("├──", "|--"), ("└──", "`--"), ("│", "|")];
+
+/// Shadows std's `println!` for the whole crate so that every line of output
+/// goes through `print_line`
+macro_rules! println {
+    () => {
+        ::std::println!()
+    };
+    ($($arg:tt)*) => {
+        $crate::ui::print_line(&format!($($arg)*))
+    };
+}
+
+/// Whether a CI service is detected from its environment variables
+pub fn detect_ci() -> bool {
+    CI_VARIABLES.iter().any(|variable| {
+        std::env::var(variable)
+            .map(|value| !value.is_empty() && value != "0" && value != "false")
+            .unwrap_or(false)
+    })
+}
+
+/// Non-interactive mode (`--non-interactive` or CI): no prompts, plain output,
+/// JSON summaries and failing exit codes
+pub fn set_non_interactive(enabled: bool) {
+    NON_INTERACTIVE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// JSON output mode (editor integrations): stdout carries only JSON, and
+/// the usual output goes to stderr as plain text
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+/// Plain output (`--plain` or `ui.emoji = false`): ASCII markers instead of
+/// emoji and tree drawing, for logs, limited terminals and screen readers
+pub fn set_plain(enabled: bool) {
+    PLAIN.store(enabled, Ordering::Relaxed);
+}
+
+/// Quiet mode (`--quiet`): no progress spinners
+pub fn set_quiet(enabled: bool) {
+    QUIET.store(enabled, Ordering::Relaxed);
+}
+
+pub fn print_line(text: &str) {
+    if SPINNING.load(Ordering::Relaxed) {
+        // The spinner redraws itself below the printed line
+        eprint!("\r\x1b[2K");
+    }
+    if PLAIN.load(Ordering::Relaxed) {
+        let text = ascii_text(text);
+        if JSON_OUTPUT.load(Ordering::Relaxed) {
+            eprintln!("{}", text);
+        } else {
+            ::std::println!("{}", text);
+        }
+    } else if JSON_OUTPUT.load(Ordering::Relaxed) {
+        eprintln!("{}", plain_text(text));
+    } else if is_non_interactive() {
+        ::std::println!("{}", plain_text(text));
+    } else {
+        ::std::println!("{}", text);
+    }
+}
+
+/// Print a JSON value as one line on stdout
+pub fn print_json(value: &impl serde::Serialize) {
+    match serde_json::to_string(value) {
+        Ok(json) => ::std::println!("{}", json),
+        Err(e) => eprintln!("Failed to serialize output: {}", e),
+    }
+}
+
+/// Print a one-line JSON summary of a command's result in non-interactive mode
+pub fn summary(value: serde_json::Value) {
+    if is_non_interactive() {
+        ::std::println!("{}", value);
+    }
+}
+
+/// A spinner with a counter on stderr while a long scan runs, cleared when
+/// dropped. It is only drawn on a terminal, and never in quiet,
+/// non-interactive or JSON output mode.
+pub struct Progress {
+    count: Arc<AtomicUsize>,
+    spinner: Option<(Arc<AtomicBool>, std::thread::JoinHandle<()>)>,
+}
+
+impl Progress {
+    /// Start a spinner showing `message` and, unless `unit` is empty, the
+    /// count of `unit`s done so far
+    pub fn start(message: &str, unit: &'static str) -> Self {
+        let count = Arc::new(AtomicUsize::new(0));
+        let visible = !QUIET.load(Ordering::Relaxed)
+            && !is_non_interactive()
+            && !JSON_OUTPUT.load(Ordering::Relaxed)
+            && std::io::stderr().is_terminal();
+        // Only one spinner at a time; nested scans just count
+        if !visible || SPINNING.swap(true, Ordering::Relaxed) {
+            return Self { count, spinner: None };
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let frames = if PLAIN.load(Ordering::Relaxed) { PLAIN_SPINNER_FRAMES } else { SPINNER_FRAMES };
+        let message = message.to_string();
+        let handle = {
+            let count = count.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                for frame in frames.iter().cycle() {
+                    if done.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    eprint!("\r\x1b[2K{}", progress_line(frame, &message, count.load(Ordering::Relaxed), unit));
+                    let _ = std::io::stderr().flush();
+                    std::thread::park_timeout(SPINNER_INTERVAL);
+                }
+                eprint!("\r\x1b[2K");
+                let _ = std::io::stderr().flush();
+            })
+        };
+        Self { count, spinner: Some((done, handle)) }
+    }
+
+    /// A progress that is never drawn, for callers that must stay silent
+    pub fn hidden() -> Self {
+        Self { count: Arc::new(AtomicUsize::new(0)), spinner: None }
+    }
+
+    /// Count one more unit done
+    pub fn inc(&self) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some((done, handle)) = self.spinner.take() {
+            done.store(true, Ordering::Relaxed);
+            handle.thread().unpark();
+            let _ = handle.join();
+            SPINNING.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+/// One frame of a spinner, e.g. "⠋ Scanning sources (12 files)"
+fn progress_line(frame: &str, message: &str, count: usize, unit: &str) -> String {
+    if unit.is_empty() {
+        format!("{} {}", frame, message)
+    } else {
+        format!("{} {} ({} {})", frame, message, count, unit)
+    }
+}
+
+/// Show a desktop notification with notify-send (or osascript on macOS);
+/// nothing happens where neither is available
+pub fn desktop_notification(message: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("osascript");
+        command.arg("-e").arg(format!("display notification {:?} with title \"tpmgr\"", message));
+        command
+    } else {
+        let mut command = std::process::Command::new("notify-send");
+        command.arg("tpmgr").arg(message);
+        command
+    };
+    let _ = command
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+}
+
+/// `text` with known markers replaced by ASCII and other emoji removed
+pub fn plain_text(text: &str) -> String {
+    let mut text = text.to_string();
+    for (marker, replacement) in PLAIN_MARKERS {
+        text = text.replace(marker, replacement);
+    }
+    // A leading emoji takes its padding with it
+    let without_icon = text.trim_start_matches(is_emoji);
+    let text = if without_icon.len() == text.len() { &text } else { without_icon.trim_start() };
+    text.chars().filter(|c| !is_emoji(*c)).collect()
+}
+
+/// `plain_text` with tree drawing replaced by ASCII as well
+pub fn ascii_text(text: &str) -> String {
+    let mut text = plain_text(text);
+    for (marker, replacement) in PLAIN_TREE {
+        text = text.replace(marker, replacement);
+    }
+    text
+}
+
+/// Pictographs, dingbats and the joiners that combine them; box drawing
+/// (used by tree output) and letters of any script are kept
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x2190..=0x24FF | 0x25A0..=0x2BFF | 0x1F000..=0x1FAFF | 0xFE0F | 0x200D)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(plain_text("✅ Step 1/2 completed"), "[ok] Step 1/2 completed");
+        assert_eq!(plain_text("⚠️  No tpmgr.toml found"), "[warn]  No tpmgr.toml found");
+        assert_eq!(plain_text("  ✗ amsmath"), "  [error] amsmath");
+        assert_eq!(plain_text("🧹 Cleaning intermediate files..."), "Cleaning intermediate files...");
+        assert_eq!(plain_text("  tikz 1.0 → 1.1"), "  tikz 1.0 -> 1.1");
+        assert_eq!(plain_text("├── 中文 ctex"), "├── 中文 ctex");
+        assert_eq!(progress_line("⠋", "Scanning sources", 12, "files"), "⠋ Scanning sources (12 files)");
+        assert_eq!(progress_line("|", "Detecting TeXLive installation", 0, ""), "| Detecting TeXLive installation");
+        assert_eq!(ascii_text("│   └── ✓ xcolor (cycle)"), "|   `-- [ok] xcolor (cycle)");
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{Validation, Validator};
+
+/// PDF/A flavours veraPDF validates against
+pub const PDFA_FLAVOURS: &[&str] = &["1a", "1b", "2a", "2b", "2u", "3a", "3b", "3u", "4", "4e", "4f"];
+
+/// The outcome of validating one PDF
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ValidationReport {
+    pub file: PathBuf,
+    pub validator: String,
+    /// The PDF/A flavour checked, e.g. "2b"
+    pub flavour: String,
+    pub passed: bool,
+    pub failures: Vec<RuleFailure>,
+    /// Problems that do not fail the validation
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// A requirement the PDF does not meet
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RuleFailure {
+    /// Clause of the standard (veraPDF) or the kind of check (qpdf)
+    pub rule: String,
+    pub description: String,
+    /// How many objects failed the rule
+    pub count: usize,
+}
+
+/// Validate `pdf` as configured, running the tools in `dir`
+pub fn validate_pdf(pdf: &Path, validation: &Validation, dir: &Path, environment: &[(String, String)]) -> Result<ValidationReport> {
+    let validator = match validation.validator {
+        Validator::Auto if crate::tools::find_executable("verapdf").is_some() => Validator::Verapdf,
+        Validator::Auto if crate::tools::find_executable("qpdf").is_some() => Validator::Qpdf,
+        Validator::Auto => anyhow::bail!("No PDF validator found: install veraPDF (https://verapdf.org) or qpdf"),
+        validator => validator,
+    };
+    let (tool, args) = match validator {
+        Validator::Qpdf => ("qpdf", vec!["--check".to_string()]),
+        _ => ("verapdf", vec!["--format".to_string(), "mrr".to_string(), "--flavour".to_string(), validation.pdfa.clone()]),
+    };
+    let output = Command::new(tool)
+        .args(&args)
+        .arg(pdf)
+        .current_dir(dir)
+        .envs(environment.iter().cloned())
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to execute {}: {}", tool, e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let mut report = ValidationReport {
+        file: pdf.to_path_buf(),
+        validator: tool.to_string(),
+        flavour: validation.pdfa.clone(),
+        passed: false,
+        failures: Vec::new(),
+        warnings: Vec::new(),
+    };
+    match validator {
+        Validator::Qpdf => {
+            // qpdf exits with 2 on errors and 3 on warnings only
+            let (failures, warnings) = parse_qpdf_check(&stdout, output.status.code());
+            report.failures = failures;
+            report.warnings = warnings;
+            report.failures.extend(check_pdfa_identification(&std::fs::read(pdf)?, &validation.pdfa));
+            report.passed = report.failures.is_empty();
+        }
+        _ => {
+            let Some((compliant, failures)) = parse_verapdf_report(&stdout) else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let reason = stderr.lines().chain(stdout.lines()).find(|line| !line.trim().is_empty()).unwrap_or("no report");
+                anyhow::bail!("veraPDF could not validate {}: {}", pdf.display(), reason.trim());
+            };
+            report.passed = compliant;
+            report.failures = failures;
+        }
+    }
+    Ok(report)
+}
+
+/// Whether the file is compliant and the rules it fails, from veraPDF's
+/// machine-readable (`--format mrr`) report; None when there is no report
+pub fn parse_verapdf_report(xml: &str) -> Option<(bool, Vec<RuleFailure>)> {
+    let report = Regex::new(r#"<validationReport\b[^>]*\bisCompliant="(true|false)""#).unwrap();
+    let compliant = report.captures(xml)?[1] == *"true";
+
+    let rule = Regex::new(r"(?s)<rule\b([^>]*)>(.*?)</rule>").unwrap();
+    let attribute = |attributes: &str, name: &str| {
+        Regex::new(&format!(r#"\b{}="([^"]*)""#, name))
+            .unwrap()
+            .captures(attributes)
+            .map(|captures| captures[1].to_string())
+    };
+    let description = Regex::new(r"(?s)<description>(.*?)</description>").unwrap();
+    let failures = rule
+        .captures_iter(xml)
+        .filter(|captures| attribute(&captures[1], "status").as_deref() == Some("failed"))
+        .map(|captures| {
+            let clause = attribute(&captures[1], "clause").unwrap_or_default();
+            let test = attribute(&captures[1], "testNumber").map(|test| format!("-{}", test)).unwrap_or_default();
+            RuleFailure {
+                rule: format!("{}{}", clause, test),
+                description: description
+                    .captures(&captures[2])
+                    .map(|captures| unescape_xml(captures[1].trim()))
+                    .unwrap_or_default(),
+                count: attribute(&captures[1], "failedChecks").and_then(|count| count.parse().ok()).unwrap_or(1),
+            }
+        })
+        .collect();
+    Some((compliant, failures))
+}
+
+/// Errors and warnings from `qpdf --check`
+fn parse_qpdf_check(output: &str, exit_code: Option<i32>) -> (Vec<RuleFailure>, Vec<String>) {
+    let mut failures = Vec::new();
+    let mut warnings = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if let Some(warning) = line.strip_prefix("WARNING:") {
+            warnings.push(warning.trim().to_string());
+        } else if line.starts_with("ERROR") || line.contains(": error") {
+            failures.push(RuleFailure {
+                rule: "structure".to_string(),
+                description: line.to_string(),
+                count: 1,
+            });
+        }
+    }
+    if failures.is_empty() && !matches!(exit_code, Some(0) | Some(3)) {
+        failures.push(RuleFailure {
+            rule: "structure".to_string(),
+            description: format!("qpdf --check failed with exit code: {:?}", exit_code),
+            count: 1,
+        });
+    }
+    (failures, warnings)
+}
+
+/// Compare the PDF/A part and conformance level declared in the XMP metadata
+/// with `flavour`
+fn check_pdfa_identification(content: &[u8], flavour: &str) -> Option<RuleFailure> {
+    let content = String::from_utf8_lossy(content);
+    let declared = |name: &str| {
+        Regex::new(&format!(r#"pdfaid:{}(?:="|>)\s*([0-9A-Za-z]+)"#, name))
+            .unwrap()
+            .captures(&content)
+            .map(|captures| captures[1].to_lowercase())
+    };
+    let expected_part: String = flavour.chars().take_while(char::is_ascii_digit).collect();
+    let expected_conformance = &flavour[expected_part.len()..];
+    let description = match (declared("part"), declared("conformance")) {
+        (None, _) => "the XMP metadata declares no PDF/A part".to_string(),
+        (Some(part), conformance) => {
+            let conformance = conformance.unwrap_or_default();
+            if part == expected_part && (expected_conformance.is_empty() || conformance == expected_conformance) {
+                return None;
+            }
+            format!("the XMP metadata declares PDF/A-{}{}, not PDF/A-{}", part, conformance, flavour)
+        }
+    };
+    Some(RuleFailure {
+        rule: "pdfa-identification".to_string(),
+        description,
+        count: 1,
+    })
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_verapdf_report() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<report>
+  <jobs>
+    <job>
+      <item size="20480"><name>/work/main.pdf</name></item>
+      <validationReport jobEndStatus="normal" profileName="PDF/A-2B validation profile" isCompliant="false">
+        <details passedRules="141" failedRules="2" passedChecks="2012" failedChecks="4">
+          <rule specification="ISO 19005-2:2011" clause="6.2.11.4.1" testNumber="1" status="failed" failedChecks="3">
+            <description>The font programs for all fonts used within a conforming file shall be embedded</description>
+            <check status="failed"><context>root/document[0]/pages[0]</context></check>
+          </rule>
+          <rule specification="ISO 19005-2:2011" clause="6.6.2.1" testNumber="1" status="failed" failedChecks="1">
+            <description>The Catalog dictionary &amp; its Metadata key</description>
+          </rule>
+          <rule specification="ISO 19005-2:2011" clause="6.1.3" testNumber="2" status="passed" passedChecks="1">
+            <description>Passing rules are listed with --verbose</description>
+          </rule>
+        </details>
+      </validationReport>
+    </job>
+  </jobs>
+</report>"#;
+        let (compliant, failures) = parse_verapdf_report(xml).unwrap();
+        assert!(!compliant);
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].rule, "6.2.11.4.1-1");
+        assert_eq!(failures[0].count, 3);
+        assert_eq!(failures[1].description, "The Catalog dictionary & its Metadata key");
+
+        let passing = r#"<report><validationReport profileName="PDF/A-2B" isCompliant="true"><details failedRules="0"/></validationReport></report>"#;
+        assert_eq!(parse_verapdf_report(passing), Some((true, Vec::new())));
+        assert_eq!(parse_verapdf_report("<report><taskException/></report>"), None);
+    }
+
+    #[test]
+    fn test_qpdf_checks() {
+        let (failures, warnings) = parse_qpdf_check("checking main.pdf\nWARNING: main.pdf: file is damaged\n", Some(3));
+        assert!(failures.is_empty());
+        assert_eq!(warnings, vec!["main.pdf: file is damaged"]);
+        let (failures, _) = parse_qpdf_check("checking main.pdf\n", Some(2));
+        assert_eq!(failures[0].rule, "structure");
+
+        let metadata = b"<rdf:Description pdfaid:part=\"2\" pdfaid:conformance=\"B\"/>";
+        assert_eq!(check_pdfa_identification(metadata, "2b"), None);
+        assert!(check_pdfa_identification(metadata, "1b").unwrap().description.contains("PDF/A-2b"));
+        assert_eq!(check_pdfa_identification(b"<pdfaid:part>4</pdfaid:part>", "4"), None);
+        assert!(check_pdfa_identification(b"%PDF-1.5", "2b").is_some());
+    }
+}
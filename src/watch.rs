@@ -0,0 +1,152 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::tex_parser::{TeXDependency, TeXParser};
+
+/// Modification times of a project's TeX sources
+pub type Snapshot = HashMap<PathBuf, SystemTime>;
+
+/// The TeX sources of a project (as found by `TeXParser::collect_source_files`)
+/// with their modification times
+pub fn snapshot(project_root: &Path) -> Result<Snapshot> {
+    let mut files = Vec::new();
+    TeXParser::collect_source_files(project_root, &mut files)?;
+    Ok(files
+        .into_iter()
+        .filter_map(|file| {
+            let modified = std::fs::metadata(&file).and_then(|metadata| metadata.modified()).ok()?;
+            Some((file, modified))
+        })
+        .collect())
+}
+
+/// Files added, modified or deleted between two snapshots, sorted
+pub fn changed_files(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = new
+        .iter()
+        .filter(|(file, modified)| old.get(*file) != Some(*modified))
+        .map(|(file, _)| file.clone())
+        .chain(old.keys().filter(|file| !new.contains_key(*file)).cloned())
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// Dependencies of a project kept per file, so that a change only re-parses
+/// the files that changed
+#[derive(Default)]
+pub struct IncrementalAnalysis {
+    dependencies: HashMap<PathBuf, Vec<TeXDependency>>,
+}
+
+impl IncrementalAnalysis {
+    /// Re-parse `changed` files; files no longer in `snapshot` are forgotten
+    pub fn update(&mut self, parser: &TeXParser, changed: &[PathBuf], snapshot: &Snapshot) {
+        for file in changed {
+            if !snapshot.contains_key(file) {
+                self.dependencies.remove(file);
+                continue;
+            }
+            match parser.parse_file(file) {
+                Ok(dependencies) => {
+                    self.dependencies.insert(file.clone(), dependencies);
+                }
+                Err(e) => println!("Warning: Failed to parse {}: {}", file.display(), e),
+            }
+        }
+    }
+
+    /// External packages the project at `project_root` needs: core packages
+    /// and packages provided by local .sty/.cls files are left out
+    pub fn required_packages(&self, parser: &TeXParser, project_root: &Path) -> Result<Vec<String>> {
+        let mut dependencies: Vec<TeXDependency> = self.dependencies.values().flatten().cloned().collect();
+        let mut local_packages = TeXParser::find_local_packages(project_root)?;
+        parser.add_local_package_dependencies(&mut dependencies, &mut local_packages);
+        Ok(TeXParser::filter_core_packages(&TeXParser::get_unique_packages(&dependencies))
+            .into_iter()
+            .filter(|package| !local_packages.contains_key(package))
+            .collect())
+    }
+
+    /// The first file that loads `package`, for notifications
+    pub fn file_using(&self, package: &str) -> Option<&Path> {
+        let mut files: Vec<&PathBuf> = self
+            .dependencies
+            .iter()
+            .filter(|(_, dependencies)| dependencies.iter().any(|dependency| dependency.package_name == package))
+            .map(|(file, _)| file)
+            .collect();
+        files.sort();
+        files.first().map(|file| file.as_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_incremental_analysis() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.tex");
+        let chapter = dir.path().join("chapter.tex");
+        std::fs::write(&main, "\\documentclass{article}\n\\usepackage{siunitx}\n\\usepackage{labstyle}\n").unwrap();
+        std::fs::write(&chapter, "\\usepackage{booktabs}\n").unwrap();
+        std::fs::write(dir.path().join("labstyle.sty"), "\\RequirePackage{xcolor}\n").unwrap();
+
+        let parser = TeXParser::new().unwrap();
+        let mut analysis = IncrementalAnalysis::default();
+        let first = snapshot(dir.path()).unwrap();
+        let changed = changed_files(&Snapshot::new(), &first);
+        assert_eq!(changed.len(), 3);
+        analysis.update(&parser, &changed, &first);
+
+        let mut required = analysis.required_packages(&parser, dir.path()).unwrap();
+        required.sort();
+        assert!(required.contains(&"siunitx".to_string()));
+        assert!(required.contains(&"booktabs".to_string()));
+        assert!(!required.contains(&"labstyle".to_string()));
+        assert_eq!(analysis.file_using("booktabs"), Some(chapter.as_path()));
+
+        // Only the edited file and the deleted one show up as changed
+        let mut second = first.clone();
+        second.insert(main.clone(), first[&main] + Duration::from_secs(1));
+        second.remove(&chapter);
+        assert_eq!(changed_files(&first, &second), vec![chapter.clone(), main.clone()]);
+        std::fs::write(&main, "\\documentclass{article}\n\\usepackage{tikz}\n").unwrap();
+        analysis.update(&parser, &changed_files(&first, &second), &second);
+        assert!(analysis.required_packages(&parser, dir.path()).unwrap().contains(&"tikz".to_string()));
+        assert!(!analysis.required_packages(&parser, dir.path()).unwrap().contains(&"booktabs".to_string()));
+        assert_eq!(analysis.file_using("siunitx"), None);
+    }
+
+    #[test]
+    fn test_required_packages_from_local_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        let template = dir.path().join("template");
+        std::fs::create_dir_all(&project).unwrap();
+        std::fs::create_dir_all(&template).unwrap();
+        std::fs::write(
+            project.join("main.tex"),
+            "\\documentclass{../template/thesis}\n\\usepackage{labstyle}\n\\usepackage{lab-colors}\n\\usepackage{siunitx}\n",
+        )
+        .unwrap();
+        // labstyle.sty also declares lab-colors, a name no file carries
+        std::fs::write(project.join("labstyle.sty"), "\\ProvidesPackage{labstyle}\n\\ProvidesPackage{lab-colors}\n\\RequirePackage{xcolor}\n").unwrap();
+        // A class loaded by path from outside the project
+        std::fs::write(template.join("thesis.cls"), "\\ProvidesClass{thesis}\n\\RequirePackage{fontspec}\n").unwrap();
+
+        let parser = TeXParser::new().unwrap();
+        let mut analysis = IncrementalAnalysis::default();
+        let current = snapshot(&project).unwrap();
+        analysis.update(&parser, &changed_files(&Snapshot::new(), &current), &current);
+
+        let mut required = analysis.required_packages(&parser, &project).unwrap();
+        required.sort();
+        assert_eq!(required, vec!["fontspec", "siunitx", "xcolor"]);
+    }
+}